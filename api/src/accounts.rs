@@ -0,0 +1,436 @@
+//! User accounts and login sessions.
+//!
+//! Accounts are kept in the `accounts` document collection, and a `usernames`
+//! collection maps each username to its account so that names stay unique.
+//! Logging in issues an opaque bearer token. Only the SHA-256 hash of the token
+//! is stored, in the `sessions` collection.
+use crate::models::{
+    generic_response::ResponseMessage,
+    user::{Credentials, Session, TokenResponse, User},
+};
+use crate::store::{self, from_document, to_document, DocumentStore};
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use axum::{http::StatusCode, response::IntoResponse};
+use sha2::{Digest, Sha256};
+use std::sync::OnceLock;
+
+const ACCOUNTS: &str = "accounts";
+const USERNAMES: &str = "usernames";
+const SESSIONS: &str = "sessions";
+
+/// How long a login session lasts, in seconds.
+const SESSION_LIFETIME: u64 = 30 * 24 * 60 * 60;
+
+const MIN_PASSWORD_LENGTH: usize = 8;
+
+/// Errors that can occur when managing accounts.
+#[derive(Debug)]
+pub enum Error {
+    InvalidUsername,
+    WeakPassword,
+    UsernameTaken,
+    InvalidCredentials,
+    Store(store::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl std::error::Error for Error {}
+
+/// Allow Error to be displayed.
+impl std::fmt::Display for Error {
+    /// Display Error.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::InvalidUsername => write!(
+                f,
+                "Username must be 3 to 32 lowercase letters, digits, '-' or '_'"
+            ),
+            Error::WeakPassword => write!(
+                f,
+                "Password must be at least {MIN_PASSWORD_LENGTH} characters"
+            ),
+            Error::UsernameTaken => write!(f, "Username is taken"),
+            Error::InvalidCredentials => write!(f, "Invalid username or password"),
+            Error::Store(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::convert::From<store::Error> for Error {
+    fn from(value: store::Error) -> Self {
+        Error::Store(value)
+    }
+}
+
+impl std::convert::From<Error> for axum::response::Response {
+    fn from(value: Error) -> Self {
+        let status = match value {
+            Error::InvalidUsername | Error::WeakPassword => StatusCode::BAD_REQUEST,
+            Error::UsernameTaken => StatusCode::CONFLICT,
+            Error::InvalidCredentials => StatusCode::UNAUTHORIZED,
+            Error::Store(e) => return e.into(),
+        };
+
+        ResponseMessage::from(value.to_string())
+            .with_status(status)
+            .into_response()
+    }
+}
+
+/// Create a new account.
+///
+/// Usernames are case insensitive and stored in lowercase.
+///
+/// # Errors
+///
+/// Returns an error if the username or password is not acceptable, or if the
+/// username is already taken.
+pub async fn register(docs: &dyn DocumentStore, credentials: Credentials) -> Result<User> {
+    let username = credentials.username.trim().to_lowercase();
+    if !valid_username(&username) {
+        return Err(Error::InvalidUsername);
+    }
+    if credentials.password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(Error::WeakPassword);
+    }
+
+    let user = User {
+        id: crate::random::id(),
+        username,
+        password_hash: hash_password(credentials.password).await?,
+        created: crate::time::now(),
+    };
+
+    // Claim the username first, so two concurrent registrations cannot both win.
+    match docs
+        .insert_document(USERNAMES, &user.username, to_document(&user.id)?)
+        .await
+    {
+        Err(store::Error::Conflict) => return Err(Error::UsernameTaken),
+        result => result?,
+    }
+    docs.put_document(ACCOUNTS, &user.id, to_document(&user)?)
+        .await?;
+
+    Ok(user)
+}
+
+/// Create an account for a user who signs in elsewhere, such as with an
+/// identity provider. The account has no password.
+///
+/// # Arguments
+///
+/// * `hint` - What to base the username on. It is made into a valid username,
+///   with a random suffix if it is taken.
+/// * `reserved` - Usernames that are treated as taken, whatever the case.
+pub async fn register_external(
+    docs: &dyn DocumentStore,
+    hint: &str,
+    reserved: &[String],
+) -> Result<User> {
+    let base: String = hint
+        .to_lowercase()
+        .chars()
+        .filter(|&c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+        .take(24)
+        .collect();
+    let base = match base.len() {
+        0..=2 => format!("user{base}"),
+        _ => base,
+    };
+
+    let suffixed = || format!("{base}-{}", &crate::random::id()[..6]);
+    let mut user = User {
+        id: crate::random::id(),
+        username: match reserved.iter().any(|name| name.eq_ignore_ascii_case(&base)) {
+            true => suffixed(),
+            false => base.clone(),
+        },
+        password_hash: String::new(),
+        created: crate::time::now(),
+    };
+    loop {
+        match docs
+            .insert_document(USERNAMES, &user.username, to_document(&user.id)?)
+            .await
+        {
+            Err(store::Error::Conflict) => user.username = suffixed(),
+            result => break result?,
+        }
+    }
+    docs.put_document(ACCOUNTS, &user.id, to_document(&user)?)
+        .await?;
+
+    Ok(user)
+}
+
+/// Remove a user's account and free their username.
+///
+/// Only for accounts that were never used, such as one created for an
+/// identity that turned out to be linked already; nothing else the user owns
+/// is removed.
+pub async fn delete_user(docs: &dyn DocumentStore, user: &User) -> Result<()> {
+    docs.delete_document(ACCOUNTS, &user.id).await?;
+    docs.delete_document(USERNAMES, &user.username).await?;
+
+    Ok(())
+}
+
+/// Check a username and password, and start a new session.
+///
+/// # Returns
+///
+/// The session's bearer token and its expiry.
+///
+/// # Errors
+///
+/// Returns `Error::InvalidCredentials` if there is no such user or the password
+/// is wrong.
+pub async fn login(docs: &dyn DocumentStore, credentials: Credentials) -> Result<TokenResponse> {
+    let user = check_credentials(docs, credentials).await?;
+
+    let token = crate::random::token();
+    let now = crate::time::now();
+    let session = Session {
+        user_id: user.id,
+        created: now,
+        expires: now + SESSION_LIFETIME,
+    };
+    docs.put_document(SESSIONS, &token_key(&token), to_document(&session)?)
+        .await?;
+
+    Ok(TokenResponse {
+        token,
+        expires: session.expires,
+    })
+}
+
+/// Check a username and password.
+///
+/// # Returns
+///
+/// The user the credentials belong to.
+///
+/// # Errors
+///
+/// Returns `Error::InvalidCredentials` if there is no such user or the password
+/// is wrong.
+pub async fn check_credentials(docs: &dyn DocumentStore, credentials: Credentials) -> Result<User> {
+    let username = credentials.username.trim().to_lowercase();
+    let user = match docs.get_document(USERNAMES, &username).await? {
+        Some(id) => get_user(docs, &from_document::<String>(id)?).await?,
+        None => None,
+    };
+
+    // Verify against a dummy hash when the user does not exist, so the response
+    // time does not reveal which usernames are registered.
+    let hash = match &user {
+        Some(user) => user.password_hash.clone(),
+        None => dummy_hash().to_string(),
+    };
+    let verified = verify_password(credentials.password, hash).await?;
+
+    user.filter(|_| verified).ok_or(Error::InvalidCredentials)
+}
+
+/// End the session a token belongs to.
+pub async fn logout(docs: &dyn DocumentStore, token: &str) -> Result<()> {
+    Ok(docs.delete_document(SESSIONS, &token_key(token)).await?)
+}
+
+/// Find the user a session token belongs to.
+///
+/// # Returns
+///
+/// The user, or `None` if the token is unknown or its session has expired.
+pub async fn authenticate(docs: &dyn DocumentStore, token: &str) -> Result<Option<User>> {
+    let key = token_key(token);
+    let Some(session) = docs.get_document(SESSIONS, &key).await? else {
+        return Ok(None);
+    };
+    let session: Session = from_document(session)?;
+
+    if session.expires <= crate::time::now() {
+        docs.delete_document(SESSIONS, &key).await?;
+        return Ok(None);
+    }
+
+    get_user(docs, &session.user_id).await
+}
+
+/// Get an account by id.
+pub async fn get_user(docs: &dyn DocumentStore, id: &str) -> Result<Option<User>> {
+    Ok(docs
+        .get_document(ACCOUNTS, id)
+        .await?
+        .map(from_document)
+        .transpose()?)
+}
+
+/// Get the ids of every account.
+pub async fn user_ids(docs: &dyn DocumentStore) -> store::Result<Vec<String>> {
+    Ok(docs
+        .list_documents(ACCOUNTS)
+        .await?
+        .into_iter()
+        .map(|(id, _)| id)
+        .collect())
+}
+
+/// The reminders scope of a user.
+pub fn scope(user_id: &str) -> String {
+    format!("users/{user_id}")
+}
+
+/// Whether a (lowercased) username is acceptable.
+///
+/// Usernames are used as document ids, so they are limited to characters that
+/// are safe in every backend's keys.
+fn valid_username(username: &str) -> bool {
+    (3..=32).contains(&username.len())
+        && username
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+}
+
+/// Hash a password with Argon2 on the blocking thread pool.
+async fn hash_password(password: String) -> Result<String> {
+    tokio::task::spawn_blocking(move || {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+            .map_err(|e| store::Error::Backend(e.to_string()))
+    })
+    .await
+    .map_err(|e| store::Error::Backend(e.to_string()))?
+    .map_err(Error::Store)
+}
+
+/// Check a password against an Argon2 hash on the blocking thread pool.
+async fn verify_password(password: String, hash: String) -> Result<bool> {
+    tokio::task::spawn_blocking(move || {
+        PasswordHash::new(&hash)
+            .map(|hash| {
+                Argon2::default()
+                    .verify_password(password.as_bytes(), &hash)
+                    .is_ok()
+            })
+            .unwrap_or(false)
+    })
+    .await
+    .map_err(|e| Error::Store(store::Error::Backend(e.to_string())))
+}
+
+/// A hash of a random password, used to check passwords of unknown users.
+fn dummy_hash() -> &'static str {
+    static HASH: OnceLock<String> = OnceLock::new();
+
+    HASH.get_or_init(|| {
+        let salt = SaltString::generate(&mut OsRng);
+        Argon2::default()
+            .hash_password(crate::random::token().as_bytes(), &salt)
+            .expect("hashing a random password succeeds")
+            .to_string()
+    })
+}
+
+/// The key a token is stored under: the hex SHA-256 hash of the token.
+pub fn token_key(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::accounts::{self, valid_username, Error};
+    use crate::models::user::Credentials;
+    use crate::store::memory::Memory;
+
+    fn credentials(username: &str, password: &str) -> Credentials {
+        Credentials {
+            username: username.into(),
+            password: password.into(),
+        }
+    }
+
+    /// Test which usernames are accepted.
+    #[test]
+    fn test_valid_username() {
+        assert!(valid_username("sam"));
+        assert!(valid_username("sam_k-2"));
+        assert!(!valid_username("sa"));
+        assert!(!valid_username("sam.k"));
+        assert!(!valid_username("sam/k"));
+        assert!(!valid_username(&"a".repeat(33)));
+    }
+
+    /// Test registering, logging in and out.
+    #[tokio::test]
+    async fn test_register_and_login() {
+        let docs = Memory::default();
+
+        let user = accounts::register(&docs, credentials(" Sam ", "correct horse"))
+            .await
+            .unwrap();
+        assert_eq!(user.username, "sam");
+        assert_ne!(user.password_hash, "correct horse");
+
+        let duplicate = accounts::register(&docs, credentials("SAM", "battery staple")).await;
+        assert!(matches!(duplicate, Err(Error::UsernameTaken)));
+
+        let wrong = accounts::login(&docs, credentials("sam", "wrong password")).await;
+        assert!(matches!(wrong, Err(Error::InvalidCredentials)));
+        let unknown = accounts::login(&docs, credentials("alex", "correct horse")).await;
+        assert!(matches!(unknown, Err(Error::InvalidCredentials)));
+
+        let token = accounts::login(&docs, credentials("Sam", "correct horse"))
+            .await
+            .unwrap()
+            .token;
+        let authenticated = accounts::authenticate(&docs, &token).await.unwrap();
+        assert_eq!(authenticated.unwrap().id, user.id);
+
+        accounts::logout(&docs, &token).await.unwrap();
+        assert!(accounts::authenticate(&docs, &token)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    /// Test that bad usernames and short passwords are rejected.
+    #[tokio::test]
+    async fn test_register_invalid() {
+        let docs = Memory::default();
+
+        let result = accounts::register(&docs, credentials("no spaces", "long enough")).await;
+        assert!(matches!(result, Err(Error::InvalidUsername)));
+
+        let result = accounts::register(&docs, credentials("sam", "short")).await;
+        assert!(matches!(result, Err(Error::WeakPassword)));
+    }
+
+    /// Test that removing an external user frees their username.
+    #[tokio::test]
+    async fn test_delete_user() {
+        let docs = Memory::default();
+
+        let user = accounts::register_external(&docs, "Sam.K", &[])
+            .await
+            .unwrap();
+        assert_eq!(user.username, "samk");
+        accounts::delete_user(&docs, &user).await.unwrap();
+        assert!(accounts::get_user(&docs, &user.id).await.unwrap().is_none());
+
+        let again = accounts::register_external(&docs, "Sam.K", &[])
+            .await
+            .unwrap();
+        assert_eq!(again.username, "samk");
+    }
+}
@@ -0,0 +1,304 @@
+//! API keys for scripts and integrations.
+//!
+//! An API key acts as a user, but only for the routes its scopes allow. Keys
+//! are created, listed and revoked by admins, and are kept in the `api_keys`
+//! document collection under their id. Only the SHA-256 hash of a key is
+//! stored.
+//!
+//! A key has the form `rk_<id>_<secret>`, so it can be looked up by id.
+use crate::accounts;
+use crate::middleware::auth::{AuthUser, SHARED_USER};
+use crate::models::{
+    api_key::{ApiKey, ApiKeyResponse, CreatedApiKey, NewApiKey, Scope},
+    generic_response::ResponseMessage,
+};
+use crate::store::{self, from_document, to_document, DocumentStore};
+use axum::{
+    http::{Method, StatusCode},
+    response::IntoResponse,
+};
+
+const API_KEYS: &str = "api_keys";
+
+/// Prefix of every API key, so they are easy to tell apart from other tokens.
+pub const PREFIX: &str = "rk_";
+
+const MAX_NAME_LENGTH: usize = 100;
+
+/// Errors that can occur when managing API keys.
+#[derive(Debug)]
+pub enum Error {
+    NotFound,
+    InvalidName,
+    NoScopes,
+    UnknownUser,
+    Store(store::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl std::error::Error for Error {}
+
+/// Allow Error to be displayed.
+impl std::fmt::Display for Error {
+    /// Display Error.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::NotFound => write!(f, "API key not found"),
+            Error::InvalidName => write!(f, "Name must be 1 to {MAX_NAME_LENGTH} characters"),
+            Error::NoScopes => write!(f, "API key must have at least one scope"),
+            Error::UnknownUser => write!(f, "User does not exist"),
+            Error::Store(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::convert::From<store::Error> for Error {
+    fn from(value: store::Error) -> Self {
+        Error::Store(value)
+    }
+}
+
+impl std::convert::From<accounts::Error> for Error {
+    fn from(value: accounts::Error) -> Self {
+        match value {
+            accounts::Error::Store(e) => Error::Store(e),
+            _ => Error::UnknownUser,
+        }
+    }
+}
+
+impl std::convert::From<Error> for axum::response::Response {
+    fn from(value: Error) -> Self {
+        let status = match value {
+            Error::NotFound => StatusCode::NOT_FOUND,
+            Error::InvalidName | Error::NoScopes | Error::UnknownUser => StatusCode::BAD_REQUEST,
+            Error::Store(e) => return e.into(),
+        };
+
+        ResponseMessage::from(value.to_string())
+            .with_status(status)
+            .into_response()
+    }
+}
+
+/// Create an API key.
+///
+/// # Arguments
+///
+/// * `creator` - The admin creating the key, who it acts as unless `new.user_id` is set.
+/// * `new` - The key's name, scopes and user.
+///
+/// # Returns
+///
+/// The new key. This is the only time the key itself is available.
+pub async fn create(
+    docs: &dyn DocumentStore,
+    creator: &AuthUser,
+    new: NewApiKey,
+) -> Result<CreatedApiKey> {
+    let name = new.name.trim();
+    if !(1..=MAX_NAME_LENGTH).contains(&name.chars().count()) {
+        return Err(Error::InvalidName);
+    }
+
+    let mut scopes = Vec::new();
+    for scope in new.scopes {
+        if !scopes.contains(&scope) {
+            scopes.push(scope);
+        }
+    }
+    if scopes.is_empty() {
+        return Err(Error::NoScopes);
+    }
+
+    let (user_id, username) = match new.user_id {
+        None => (creator.id.clone(), creator.username.clone()),
+        Some(id) if id == creator.id || id == SHARED_USER => (id.clone(), id),
+        Some(id) => {
+            let user = accounts::get_user(docs, &id)
+                .await?
+                .ok_or(Error::UnknownUser)?;
+            (user.id, user.username)
+        }
+    };
+
+    let id = crate::random::id();
+    let key = format!("{PREFIX}{id}_{}", crate::random::token());
+    let api_key = ApiKey {
+        id,
+        name: name.into(),
+        user_id,
+        username,
+        scopes,
+        hash: accounts::token_key(&key),
+        created: crate::time::now(),
+    };
+    docs.put_document(API_KEYS, &api_key.id, to_document(&api_key)?)
+        .await?;
+
+    Ok(CreatedApiKey {
+        key,
+        api_key: api_key.into(),
+    })
+}
+
+/// Get every API key.
+pub async fn list(docs: &dyn DocumentStore) -> Result<Vec<ApiKeyResponse>> {
+    let mut keys = Vec::new();
+    for (_, key) in docs.list_documents(API_KEYS).await? {
+        keys.push(from_document::<ApiKey>(key)?.into());
+    }
+    keys.sort_by_key(|key: &ApiKeyResponse| key.created);
+
+    Ok(keys)
+}
+
+/// Revoke an API key.
+///
+/// # Errors
+///
+/// Returns `Error::NotFound` if there is no key with the id.
+pub async fn revoke(docs: &dyn DocumentStore, id: &str) -> Result<()> {
+    if docs.get_document(API_KEYS, id).await?.is_none() {
+        return Err(Error::NotFound);
+    }
+
+    Ok(docs.delete_document(API_KEYS, id).await?)
+}
+
+/// Find the API key a bearer token is.
+///
+/// # Returns
+///
+/// The key, or `None` if the token is not a known key.
+pub async fn authenticate(docs: &dyn DocumentStore, token: &str) -> store::Result<Option<ApiKey>> {
+    let Some((id, _)) = token
+        .strip_prefix(PREFIX)
+        .and_then(|rest| rest.split_once('_'))
+    else {
+        return Ok(None);
+    };
+
+    let Some(key) = docs.get_document(API_KEYS, id).await? else {
+        return Ok(None);
+    };
+    let key: ApiKey = from_document(key)?;
+
+    Ok((key.hash == accounts::token_key(token)).then_some(key))
+}
+
+/// The scope an API key needs to call a route.
+///
+/// # Arguments
+///
+/// * `path` - The route's path pattern, such as `/reminders/v2/:id`.
+/// * `method` - The request method.
+///
+/// # Returns
+///
+/// The scope, or `None` if API keys may not call the route at all.
+pub fn required_scope(path: &str, method: &Method) -> Option<Scope> {
+    let reminders = path.starts_with("/reminders/v2/")
+        || (path.starts_with("/households/") && path.contains("/reminders/"));
+    if !reminders {
+        return None;
+    }
+
+    Some(match *method {
+        Method::GET | Method::HEAD => Scope::RemindersRead,
+        _ => Scope::RemindersWrite,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api_keys::{self, required_scope, Error};
+    use crate::middleware::auth::AuthUser;
+    use crate::models::api_key::{NewApiKey, Scope};
+    use crate::store::memory::Memory;
+    use axum::http::Method;
+
+    /// Test which scope each route needs.
+    #[test]
+    fn test_required_scope() {
+        let read = required_scope("/reminders/v2/", &Method::GET);
+        assert_eq!(read, Some(Scope::RemindersRead));
+        let write = required_scope("/reminders/v2/:id/complete", &Method::POST);
+        assert_eq!(write, Some(Scope::RemindersWrite));
+        let list = "/households/:household_id/lists/:list_id/reminders/:id";
+        assert_eq!(
+            required_scope(list, &Method::PUT),
+            Some(Scope::RemindersWrite)
+        );
+
+        assert_eq!(required_scope("/admin/api-keys", &Method::GET), None);
+        assert_eq!(
+            required_scope("/households/:household_id", &Method::GET),
+            None
+        );
+    }
+
+    /// Test creating, authenticating with and revoking a key.
+    #[tokio::test]
+    async fn test_lifecycle() {
+        let docs = Memory::default();
+        let admin = AuthUser {
+            id: "shared".into(),
+            username: "shared".into(),
+        };
+        let new = NewApiKey {
+            name: " cron ".into(),
+            scopes: vec![Scope::RemindersRead, Scope::RemindersRead],
+            user_id: None,
+        };
+
+        let created = api_keys::create(&docs, &admin, new).await.unwrap();
+        assert!(created.key.starts_with(api_keys::PREFIX));
+        assert_eq!(created.api_key.name, "cron");
+        assert_eq!(created.api_key.scopes, vec![Scope::RemindersRead]);
+
+        let key = api_keys::authenticate(&docs, &created.key).await.unwrap();
+        assert_eq!(key.unwrap().user_id, "shared");
+        let forged = format!("{}x", created.key);
+        assert!(api_keys::authenticate(&docs, &forged)
+            .await
+            .unwrap()
+            .is_none());
+
+        assert_eq!(api_keys::list(&docs).await.unwrap().len(), 1);
+        api_keys::revoke(&docs, &created.api_key.id).await.unwrap();
+        assert!(api_keys::authenticate(&docs, &created.key)
+            .await
+            .unwrap()
+            .is_none());
+        let missing = api_keys::revoke(&docs, &created.api_key.id).await;
+        assert!(matches!(missing, Err(Error::NotFound)));
+    }
+
+    /// Test that keys need scopes and an existing user.
+    #[tokio::test]
+    async fn test_invalid() {
+        let docs = Memory::default();
+        let admin = AuthUser {
+            id: "shared".into(),
+            username: "shared".into(),
+        };
+
+        let new = NewApiKey {
+            name: "cron".into(),
+            scopes: vec![],
+            user_id: None,
+        };
+        let result = api_keys::create(&docs, &admin, new).await;
+        assert!(matches!(result, Err(Error::NoScopes)));
+
+        let new = NewApiKey {
+            name: "cron".into(),
+            scopes: vec![Scope::RemindersWrite],
+            user_id: Some("nobody".into()),
+        };
+        let result = api_keys::create(&docs, &admin, new).await;
+        assert!(matches!(result, Err(Error::UnknownUser)));
+    }
+}
@@ -0,0 +1,201 @@
+//! Calendar feeds.
+//!
+//! A calendar feed is a secret URL that calendar apps can poll for a user's
+//! reminders, since they cannot send a bearer token. The token in the URL
+//! only gives read access to the reminders, and each user has at most one
+//! feed. Feeds are kept in the `calendar_feeds` document collection under the
+//! SHA-256 hash of their token.
+use crate::accounts;
+use crate::households;
+use crate::middleware::auth::AuthUser;
+use crate::models::{
+    calendar::{CalendarFeed, CreatedFeed},
+    generic_response::ResponseMessage,
+    reminder::Reminder,
+};
+use crate::store::{self, from_document, to_document, DocumentStore, Storage};
+use axum::{http::StatusCode, response::IntoResponse};
+
+const FEEDS: &str = "calendar_feeds";
+
+/// Errors that can occur when using calendar feeds.
+#[derive(Debug)]
+pub enum Error {
+    NotFound,
+    Store(store::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl std::error::Error for Error {}
+
+/// Allow Error to be displayed.
+impl std::fmt::Display for Error {
+    /// Display Error.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::NotFound => write!(f, "Calendar feed not found"),
+            Error::Store(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::convert::From<store::Error> for Error {
+    fn from(value: store::Error) -> Self {
+        Error::Store(value)
+    }
+}
+
+impl std::convert::From<Error> for axum::response::Response {
+    fn from(value: Error) -> Self {
+        match value {
+            Error::NotFound => ResponseMessage::from(value.to_string())
+                .with_status(StatusCode::NOT_FOUND)
+                .into_response(),
+            Error::Store(e) => e.into(),
+        }
+    }
+}
+
+/// The path of the feed with the given token.
+pub fn url(token: &str) -> String {
+    format!("/calendar/{token}/reminders.ics")
+}
+
+/// Create a calendar feed for the user, replacing any feed they had.
+///
+/// # Returns
+///
+/// The feed's URL. This is the only time it is available.
+pub async fn create(docs: &dyn DocumentStore, user: &AuthUser) -> Result<CreatedFeed> {
+    remove(docs, &user.id).await?;
+
+    let token = crate::random::token();
+    let feed = CalendarFeed {
+        user_id: user.id.clone(),
+        username: user.username.clone(),
+        created: crate::time::now(),
+    };
+    docs.put_document(FEEDS, &accounts::token_key(&token), to_document(&feed)?)
+        .await?;
+
+    Ok(CreatedFeed {
+        url: url(&token),
+        created: feed.created,
+    })
+}
+
+/// Revoke the user's calendar feed.
+///
+/// # Errors
+///
+/// Returns `Error::NotFound` if the user has no feed.
+pub async fn revoke(docs: &dyn DocumentStore, user_id: &str) -> Result<()> {
+    match remove(docs, user_id).await? {
+        0 => Err(Error::NotFound),
+        _ => Ok(()),
+    }
+}
+
+/// Delete every feed of a user.
+///
+/// # Returns
+///
+/// The number of feeds deleted.
+async fn remove(docs: &dyn DocumentStore, user_id: &str) -> store::Result<usize> {
+    let mut removed = 0;
+    for (hash, feed) in docs.list_documents(FEEDS).await? {
+        if from_document::<CalendarFeed>(feed)?.user_id == user_id {
+            docs.delete_document(FEEDS, &hash).await?;
+            removed += 1;
+        }
+    }
+
+    Ok(removed)
+}
+
+/// Get the reminders shown by the feed with the given token: the user's own
+/// and those of every household list they belong to.
+///
+/// # Errors
+///
+/// Returns `Error::NotFound` if the token is not a feed's.
+pub async fn reminders(store: &dyn Storage, token: &str) -> Result<Vec<Reminder>> {
+    let feed = store
+        .get_document(FEEDS, &accounts::token_key(token))
+        .await?
+        .ok_or(Error::NotFound)?;
+    let feed: CalendarFeed = from_document(feed)?;
+    let user = AuthUser {
+        id: feed.user_id,
+        username: feed.username,
+    };
+
+    let mut scopes = vec![user.scope()];
+    scopes.extend(households::scopes_for_user(store, &user.id).await?);
+    let mut reminders = Vec::new();
+    for scope in scopes {
+        reminders.extend(store.reminders(&scope).list().await?.value);
+    }
+
+    Ok(reminders)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::calendar::{self, Error};
+    use crate::middleware::auth::AuthUser;
+    use crate::models::reminder::Reminder;
+    use crate::store::{memory::Memory, Storage};
+
+    /// Test that a feed shows the user's reminders until it is replaced or
+    /// revoked.
+    #[tokio::test]
+    async fn test_feed() {
+        let store = Memory::default();
+        let user = AuthUser {
+            id: "u1".into(),
+            username: "sam".into(),
+        };
+        let reminder = Reminder {
+            id: None,
+            title: "Bins".into(),
+            due: 1,
+            priority: 0,
+            assignee: None,
+            completed: false,
+            completed_at: None,
+            rrule: None,
+        };
+        store
+            .reminders("users/u1")
+            .create(reminder.clone())
+            .await
+            .unwrap();
+        store.reminders("users/u2").create(reminder).await.unwrap();
+
+        let first = calendar::create(&store, &user).await.unwrap();
+        let token = |url: &str| url.split('/').nth(2).unwrap().to_string();
+        let reminders = calendar::reminders(&store, &token(&first.url))
+            .await
+            .unwrap();
+        assert_eq!(reminders.len(), 1);
+
+        let second = calendar::create(&store, &user).await.unwrap();
+        assert_ne!(first.url, second.url);
+        assert!(matches!(
+            calendar::reminders(&store, &token(&first.url)).await,
+            Err(Error::NotFound)
+        ));
+
+        calendar::revoke(&store, "u1").await.unwrap();
+        assert!(matches!(
+            calendar::reminders(&store, &token(&second.url)).await,
+            Err(Error::NotFound)
+        ));
+        assert!(matches!(
+            calendar::revoke(&store, "u1").await,
+            Err(Error::NotFound)
+        ));
+    }
+}
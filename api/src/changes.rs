@@ -0,0 +1,741 @@
+//! Change log for offline-first clients.
+//!
+//! The [`Recorder`] keeps the latest change to each reminder in a change log
+//! per scope, in the `changes/<scope>` document collection under the
+//! reminder's id. Entries carry the sequence number of the event they were
+//! recorded from, and deleted reminders stay in the log as tombstones, so a
+//! client can ask for everything that changed after the last sequence number
+//! it saw, across restarts.
+//!
+//! Clients that were offline send their edits as a batch of operations, which
+//! are applied in the order the client made them. An update or delete of a
+//! reminder the server changed later than the client's edit is a conflict,
+//! and the server's version is kept. Updates of deleted reminders are always
+//! conflicts.
+//!
+//! If the recorder misses events, it moves the horizon of the log past them,
+//! and clients asking for changes from before it get every reminder instead.
+use crate::events::{Bus, Event, EventKind, Events};
+use crate::models::{
+    generic_response::ResponseMessage,
+    reminder::Reminder,
+    sync::{Change, Changes, Operation, OperationResult, OperationStatus},
+};
+use crate::store::{self, from_document, to_document, DocumentStore, ReminderStore};
+use axum::{http::StatusCode, response::IntoResponse};
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, broadcast::error::RecvError, watch};
+
+/// Document holding the sequence number changes are complete from.
+const HORIZON: (&str, &str) = ("change_horizon", "all");
+
+/// Most operations in one batch.
+const MAX_OPERATIONS: usize = 500;
+
+/// Longest to wait for the recorder to catch up before answering.
+const CATCH_UP: Duration = Duration::from_secs(2);
+
+/// Errors that can occur when syncing.
+#[derive(Debug)]
+pub enum Error {
+    TooMany,
+    Store(store::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl std::error::Error for Error {}
+
+/// Allow Error to be displayed.
+impl std::fmt::Display for Error {
+    /// Display Error.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::TooMany => write!(
+                f,
+                "At most {MAX_OPERATIONS} operations can be synced at once"
+            ),
+            Error::Store(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::convert::From<store::Error> for Error {
+    fn from(value: store::Error) -> Self {
+        Error::Store(value)
+    }
+}
+
+impl std::convert::From<Error> for axum::response::Response {
+    fn from(value: Error) -> Self {
+        match value {
+            Error::TooMany => ResponseMessage::from(value.to_string())
+                .with_status(StatusCode::BAD_REQUEST)
+                .into_response(),
+            Error::Store(e) => e.into(),
+        }
+    }
+}
+
+/// The collection of a scope's change log, keyed by reminder id.
+fn collection(scope: &str) -> String {
+    match scope {
+        "" => "changes/shared".into(),
+        scope => format!("changes/{scope}"),
+    }
+}
+
+/// The collection of the reminders a scope's clients created, keyed by the
+/// id the client gave them.
+fn claims_collection(scope: &str) -> String {
+    match scope {
+        "" => "sync_claims/shared".into(),
+        scope => format!("sync_claims/{scope}"),
+    }
+}
+
+/// How far the change log has been recorded.
+#[derive(Clone)]
+pub struct Log {
+    recorded: watch::Receiver<u64>,
+}
+
+impl Log {
+    /// Sequence number of the last event recorded.
+    pub fn recorded(&self) -> u64 {
+        *self.recorded.borrow()
+    }
+
+    /// Wait until every event up to `seq` has been recorded, or for a short
+    /// while if the recorder is behind. Changes are read from the log, so
+    /// this lets clients see their own edits straight away.
+    pub async fn caught_up(&self, seq: u64) {
+        let mut recorded = self.recorded.clone();
+        let _ = tokio::time::timeout(CATCH_UP, recorded.wait_for(|&r| r >= seq)).await;
+    }
+}
+
+/// Records the changes published on the bus in the change log.
+pub struct Recorder {
+    docs: Arc<dyn DocumentStore>,
+    events: broadcast::Receiver<Event>,
+    recorded: watch::Sender<u64>,
+}
+
+impl Recorder {
+    /// Create a recorder for the events published on `bus` from now on.
+    pub fn new(docs: Arc<dyn DocumentStore>, bus: &Bus) -> Self {
+        let (_, events, latest) = bus.resume(u64::MAX);
+
+        Recorder {
+            docs,
+            events,
+            recorded: watch::channel(latest).0,
+        }
+    }
+
+    /// How far the change log has been recorded.
+    pub fn log(&self) -> Log {
+        Log {
+            recorded: self.recorded.subscribe(),
+        }
+    }
+
+    /// Record events in the background.
+    pub fn spawn(mut self) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            if let Err(e) = self.init().await {
+                log::error!("failed to start the change log: {e}");
+            }
+
+            let mut missed = false;
+            loop {
+                let event = match self.events.recv().await {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(n)) => {
+                        log::warn!("change log fell behind and missed {n} events");
+                        missed = true;
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+
+                if let Err(e) = self.record(&event).await {
+                    log::error!("failed to record event {} in the change log: {e}", event.id);
+                    missed = true;
+                }
+                if std::mem::take(&mut missed) {
+                    if let Err(e) = set_horizon(self.docs.as_ref(), event.seq).await {
+                        log::error!("failed to move the change log horizon: {e}");
+                    }
+                }
+                self.recorded.send_replace(event.seq);
+            }
+        })
+    }
+
+    /// Start the log from now the first time it is used, as reminders
+    /// changed before then have no entries.
+    pub async fn init(&self) -> store::Result<()> {
+        let (collection, id) = HORIZON;
+        let start = *self.recorded.borrow();
+        match self.docs.get_document(collection, id).await? {
+            Some(_) => Ok(()),
+            None => set_horizon(self.docs.as_ref(), start).await,
+        }
+    }
+
+    /// Record an event, if it changed a reminder.
+    pub async fn record(&self, event: &Event) -> store::Result<()> {
+        let (Some(_), Some(id)) = (event.kind.change(), event.reminder.id.clone()) else {
+            return Ok(());
+        };
+        let deleted = event.kind == EventKind::Deleted;
+        let change = Change {
+            seq: event.seq,
+            id: id.clone(),
+            deleted,
+            time: event.time,
+            reminder: (!deleted).then(|| event.reminder.clone()),
+        };
+
+        self.docs
+            .put_document(&collection(&event.scope), &id, to_document(&change)?)
+            .await
+    }
+}
+
+/// Sequence number changes are complete from.
+async fn horizon(docs: &dyn DocumentStore) -> store::Result<u64> {
+    let (collection, id) = HORIZON;
+    let horizon = docs.get_document(collection, id).await?;
+
+    Ok(horizon.and_then(|h| h["seq"].as_u64()).unwrap_or_default())
+}
+
+/// Move the horizon to `seq`, so clients asking for changes from before it
+/// get every reminder.
+async fn set_horizon(docs: &dyn DocumentStore, seq: u64) -> store::Result<()> {
+    let (collection, id) = HORIZON;
+
+    docs.put_document(collection, id, json!({ "seq": seq }))
+        .await
+}
+
+/// Get the change log of a scope, keyed by reminder id.
+async fn entries(docs: &dyn DocumentStore, scope: &str) -> store::Result<HashMap<String, Change>> {
+    let mut entries = HashMap::new();
+    for (id, value) in docs.list_documents(&collection(scope)).await? {
+        match from_document::<Change>(value) {
+            Ok(change) => {
+                entries.insert(id, change);
+            }
+            Err(e) => log::warn!("skipping unreadable change log entry {id}: {e}"),
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Get the changes to a scope's reminders after `since`.
+///
+/// # Arguments
+///
+/// * `since` - Sequence number of the last change the client has, or `None`
+///   for every reminder.
+/// * `recorded` - Sequence number the log has been recorded up to, which
+///   becomes the client's next `since`.
+///
+/// # Returns
+///
+/// The changes in order, or every reminder if `since` is `None` or from
+/// before the horizon.
+pub async fn since(
+    docs: &dyn DocumentStore,
+    store: &dyn ReminderStore,
+    scope: &str,
+    since: Option<u64>,
+    recorded: u64,
+) -> store::Result<Changes> {
+    let horizon = horizon(docs).await?;
+    let since = since.filter(|&since| since >= horizon && since <= recorded);
+    let mut entries = entries(docs, scope).await?;
+
+    let mut changes: Vec<Change> = match since {
+        Some(since) => entries
+            .into_values()
+            .filter(|change| change.seq > since)
+            .collect(),
+        None => store
+            .list()
+            .await?
+            .value
+            .into_iter()
+            .map(|reminder| {
+                let id = reminder.id.clone().unwrap_or_default();
+                let logged = entries.remove(&id);
+                Change {
+                    seq: logged.as_ref().map_or(0, |c| c.seq),
+                    time: logged.as_ref().map_or(0, |c| c.time),
+                    id,
+                    deleted: false,
+                    reminder: Some(reminder),
+                }
+            })
+            .collect(),
+    };
+    changes.sort_by(|a, b| (a.seq, &a.id).cmp(&(b.seq, &b.id)));
+
+    Ok(Changes {
+        seq: recorded,
+        complete: since.is_none(),
+        changes,
+    })
+}
+
+/// Apply a batch of operations to a scope's reminders.
+///
+/// Operations are applied in the order of their timestamps, and in the order
+/// they were sent when those are equal. Creates are always applied, once per
+/// `client_id`. Updates and deletes are conflicts if the reminder changed
+/// after the operation's timestamp, unless that change was made earlier in
+/// the same batch.
+///
+/// # Arguments
+///
+/// * `store` - The scope's reminders.
+/// * `events` - Where to publish the changes made.
+/// * `operations` - The operations, in the order the client sent them.
+///
+/// # Returns
+///
+/// The outcome of each operation, in the order they were sent.
+///
+/// # Errors
+///
+/// Returns `Error::TooMany` if there are too many operations, or a store
+/// error if one could not be applied. Operations before it stay applied.
+pub async fn sync(
+    docs: &dyn DocumentStore,
+    store: &dyn ReminderStore,
+    events: &Events,
+    operations: Vec<Operation>,
+) -> Result<Vec<OperationResult>> {
+    if operations.len() > MAX_OPERATIONS {
+        return Err(Error::TooMany);
+    }
+
+    let mut batch = Batch {
+        docs,
+        store,
+        events,
+        touched: HashSet::new(),
+    };
+    let mut order: Vec<_> = operations.into_iter().enumerate().collect();
+    order.sort_by_key(|(index, operation)| (operation.timestamp(), *index));
+
+    let mut results = Vec::with_capacity(order.len());
+    for (index, operation) in order {
+        let (status, id, message) = batch.apply(operation).await?;
+        results.push(OperationResult {
+            index,
+            status,
+            id,
+            message: message.map(String::from),
+        });
+    }
+    results.sort_by_key(|result| result.index);
+
+    Ok(results)
+}
+
+/// The state of a batch being applied.
+struct Batch<'a> {
+    docs: &'a dyn DocumentStore,
+    store: &'a dyn ReminderStore,
+    events: &'a Events,
+    /// Reminders changed by the batch so far.
+    touched: HashSet<String>,
+}
+
+type Outcome = (OperationStatus, Option<String>, Option<&'static str>);
+
+impl Batch<'_> {
+    /// Apply one operation.
+    async fn apply(&mut self, operation: Operation) -> store::Result<Outcome> {
+        match operation {
+            Operation::Create {
+                client_id,
+                reminder,
+                ..
+            } => self.create(&client_id, reminder).await,
+            Operation::Update {
+                id,
+                reminder,
+                timestamp,
+            } => {
+                let id = self.resolve(&id).await?;
+                self.update(id, reminder, timestamp).await
+            }
+            Operation::Delete { id, timestamp } => {
+                let id = self.resolve(&id).await?;
+                self.delete(id, timestamp).await
+            }
+        }
+    }
+
+    /// Create a reminder, unless one was already created for `client_id`.
+    async fn create(&mut self, client_id: &str, reminder: Reminder) -> store::Result<Outcome> {
+        let claims = claims_collection(self.events.scope());
+        let claim = json!({ "id": null, "time": crate::time::now() });
+        match self.docs.insert_document(&claims, client_id, claim).await {
+            Ok(()) => {}
+            Err(store::Error::Conflict) => {
+                let id = self.docs.get_document(&claims, client_id).await?;
+                let id = id.and_then(|claim| claim["id"].as_str().map(String::from));
+                return Ok((OperationStatus::Applied, id, Some("Already created")));
+            }
+            Err(e) => return Err(e),
+        }
+
+        let created = match self.store.create(reminder).await {
+            Ok(created) => created,
+            Err(e) => {
+                self.docs.delete_document(&claims, client_id).await?;
+                return Err(e);
+            }
+        };
+        let id = created.id.clone().unwrap_or_default();
+        let claim = json!({ "id": id, "time": crate::time::now() });
+        self.docs.put_document(&claims, client_id, claim).await?;
+        self.touched.insert(id.clone());
+        self.events.emit(EventKind::Created, created);
+
+        Ok((OperationStatus::Applied, Some(id), None))
+    }
+
+    /// Replace a reminder, unless it changed after `timestamp`.
+    async fn update(
+        &mut self,
+        id: String,
+        reminder: Reminder,
+        timestamp: u64,
+    ) -> store::Result<Outcome> {
+        let current = match self.store.get(&id).await {
+            Ok(current) => current,
+            Err(store::Error::NotFound) => {
+                return Ok(match self.logged(&id).await? {
+                    Some(change) if change.deleted => (
+                        OperationStatus::Conflict,
+                        Some(id),
+                        Some("Reminder was deleted"),
+                    ),
+                    _ => (OperationStatus::NotFound, Some(id), None),
+                });
+            }
+            Err(e) => return Err(e),
+        };
+        if self.changed_after(&id, timestamp).await? {
+            return Ok((
+                OperationStatus::Conflict,
+                Some(id),
+                Some("Reminder was changed later"),
+            ));
+        }
+
+        let reminder = Reminder {
+            id: Some(id.clone()),
+            ..reminder
+        };
+        match self
+            .store
+            .replace(&id, reminder.clone(), Some(&current.etag))
+            .await
+        {
+            Ok(()) => {}
+            Err(store::Error::PreconditionFailed) => {
+                return Ok((
+                    OperationStatus::Conflict,
+                    Some(id),
+                    Some("Reminder was changed while syncing"),
+                ));
+            }
+            Err(e) => return Err(e),
+        }
+        let kind = match !current.value.completed && reminder.completed {
+            true => EventKind::Completed,
+            false => EventKind::Updated,
+        };
+        self.touched.insert(id.clone());
+        self.events.emit(kind, reminder);
+
+        Ok((OperationStatus::Applied, Some(id), None))
+    }
+
+    /// Delete a reminder, unless it changed after `timestamp`. Deleting a
+    /// reminder that is already gone is applied.
+    async fn delete(&mut self, id: String, timestamp: u64) -> store::Result<Outcome> {
+        let current = match self.store.get(&id).await {
+            Ok(current) => current,
+            Err(store::Error::NotFound) => return Ok((OperationStatus::Applied, Some(id), None)),
+            Err(e) => return Err(e),
+        };
+        if self.changed_after(&id, timestamp).await? {
+            return Ok((
+                OperationStatus::Conflict,
+                Some(id),
+                Some("Reminder was changed later"),
+            ));
+        }
+
+        match self.store.delete(&id, Some(&current.etag)).await {
+            Ok(()) | Err(store::Error::NotFound) => {}
+            Err(store::Error::PreconditionFailed) => {
+                return Ok((
+                    OperationStatus::Conflict,
+                    Some(id),
+                    Some("Reminder was changed while syncing"),
+                ));
+            }
+            Err(e) => return Err(e),
+        }
+        self.touched.insert(id.clone());
+        self.events.emit(EventKind::Deleted, current.value);
+
+        Ok((OperationStatus::Applied, Some(id), None))
+    }
+
+    /// The server's id of a reminder, given either it or the id the client
+    /// created it with.
+    async fn resolve(&self, id: &str) -> store::Result<String> {
+        let claims = claims_collection(self.events.scope());
+        let claim = self.docs.get_document(&claims, id).await?;
+
+        Ok(claim
+            .and_then(|claim| claim["id"].as_str().map(String::from))
+            .unwrap_or_else(|| id.into()))
+    }
+
+    /// The latest change to a reminder in the log.
+    async fn logged(&self, id: &str) -> store::Result<Option<Change>> {
+        let change = self
+            .docs
+            .get_document(&collection(self.events.scope()), id)
+            .await?;
+
+        change.map(from_document).transpose()
+    }
+
+    /// Whether the reminder last changed after `timestamp`, other than by
+    /// this batch.
+    async fn changed_after(&self, id: &str, timestamp: u64) -> store::Result<bool> {
+        if self.touched.contains(id) {
+            return Ok(false);
+        }
+
+        Ok(self
+            .logged(id)
+            .await?
+            .is_some_and(|change| change.time > timestamp))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::changes::{self, Recorder};
+    use crate::events::{Bus, EventKind, Events};
+    use crate::models::{
+        reminder::Reminder,
+        sync::{Operation, OperationStatus},
+    };
+    use crate::store::{memory::Memory, Storage};
+    use std::sync::Arc;
+
+    fn reminder(title: &str) -> Reminder {
+        Reminder {
+            id: None,
+            title: title.into(),
+            due: 1,
+            priority: 0,
+            assignee: None,
+            completed: false,
+            completed_at: None,
+            rrule: None,
+        }
+    }
+
+    /// Record every event published so far.
+    async fn record(recorder: &mut Recorder) {
+        while let Ok(event) = recorder.events.try_recv() {
+            recorder.record(&event).await.unwrap();
+            recorder.recorded.send_replace(event.seq);
+        }
+    }
+
+    /// Test that changes since a sequence number include tombstones, and that
+    /// asking from before the horizon gets every reminder.
+    #[tokio::test]
+    async fn test_since() {
+        let db = Arc::new(Memory::default());
+        let store = db.reminders("users/u1");
+        let bus = Bus::default();
+        let events = Events::new(bus.clone(), "users/u1");
+        let mut recorder = Recorder::new(db.clone(), &bus);
+        let log = recorder.log();
+        recorder.init().await.unwrap();
+
+        let kept = store.create(reminder("bins")).await.unwrap();
+        let gone = store.create(reminder("dishes")).await.unwrap();
+        events.emit(EventKind::Created, kept.clone());
+        events.emit(EventKind::Created, gone.clone());
+        record(&mut recorder).await;
+        let start = log.recorded();
+
+        store
+            .delete(gone.id.as_deref().unwrap(), None)
+            .await
+            .unwrap();
+        events.emit(EventKind::Deleted, gone.clone());
+        Events::new(bus.clone(), "").emit(EventKind::Created, kept.clone());
+        record(&mut recorder).await;
+
+        let delta = changes::since(
+            db.as_ref(),
+            store.as_ref(),
+            "users/u1",
+            Some(start),
+            log.recorded(),
+        )
+        .await
+        .unwrap();
+        assert!(!delta.complete);
+        assert_eq!(delta.seq, start + 2);
+        assert_eq!(delta.changes.len(), 1);
+        assert_eq!(delta.changes[0].id, gone.id.clone().unwrap());
+        assert!(delta.changes[0].deleted);
+        assert!(delta.changes[0].reminder.is_none());
+
+        let all = changes::since(
+            db.as_ref(),
+            store.as_ref(),
+            "users/u1",
+            Some(0),
+            log.recorded(),
+        )
+        .await
+        .unwrap();
+        assert!(all.complete);
+        assert_eq!(all.changes.len(), 1);
+        assert_eq!(all.changes[0].reminder.as_ref().unwrap().title, "Bins");
+
+        changes::set_horizon(db.as_ref(), start + 1).await.unwrap();
+        let stale = changes::since(
+            db.as_ref(),
+            store.as_ref(),
+            "users/u1",
+            Some(start),
+            log.recorded(),
+        )
+        .await
+        .unwrap();
+        assert!(stale.complete);
+    }
+
+    /// Test that offline edits are applied in timestamp order, that later
+    /// server changes win, and that retried creates are only applied once.
+    #[tokio::test]
+    async fn test_sync() {
+        let db = Arc::new(Memory::default());
+        let store = db.reminders("");
+        let bus = Bus::default();
+        let events = Events::new(bus.clone(), "");
+        let mut recorder = Recorder::new(db.clone(), &bus);
+
+        let existing = store.create(reminder("bins")).await.unwrap();
+        let id = existing.id.clone().unwrap();
+        events.emit(EventKind::Created, existing.clone());
+        record(&mut recorder).await;
+        let changed_at = crate::time::now();
+
+        let operations = vec![
+            Operation::Update {
+                id: "c1".into(),
+                reminder: Reminder {
+                    completed: true,
+                    ..reminder("dishes")
+                },
+                timestamp: changed_at + 20,
+            },
+            Operation::Create {
+                client_id: "c1".into(),
+                reminder: reminder("dishes"),
+                timestamp: changed_at + 10,
+            },
+            Operation::Update {
+                id: id.clone(),
+                reminder: reminder("stale"),
+                timestamp: changed_at - 60,
+            },
+            Operation::Delete {
+                id: "missing".into(),
+                timestamp: changed_at + 30,
+            },
+            Operation::Update {
+                id: "missing".into(),
+                reminder: reminder("missing"),
+                timestamp: changed_at + 40,
+            },
+        ];
+        let results = changes::sync(db.as_ref(), store.as_ref(), &events, operations.clone())
+            .await
+            .unwrap();
+        let statuses: Vec<_> = results.iter().map(|r| r.status).collect();
+        assert_eq!(
+            statuses,
+            [
+                OperationStatus::Applied,
+                OperationStatus::Applied,
+                OperationStatus::Conflict,
+                OperationStatus::Applied,
+                OperationStatus::NotFound,
+            ]
+        );
+        let created = results[1].id.clone().unwrap();
+        assert_eq!(results[0].id.as_deref(), Some(created.as_str()));
+        assert!(store.get(&created).await.unwrap().value.completed);
+        assert_eq!(store.get(&id).await.unwrap().value.title, "Bins");
+        record(&mut recorder).await;
+
+        let retried = changes::sync(
+            db.as_ref(),
+            store.as_ref(),
+            &events,
+            operations[1..2].to_vec(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(retried[0].id.as_deref(), Some(created.as_str()));
+        assert_eq!(store.list().await.unwrap().value.len(), 2);
+
+        let delete = Operation::Delete {
+            id: "c1".into(),
+            timestamp: changed_at + 50,
+        };
+        changes::sync(db.as_ref(), store.as_ref(), &events, vec![delete])
+            .await
+            .unwrap();
+        record(&mut recorder).await;
+        let update = Operation::Update {
+            id: created.clone(),
+            reminder: reminder("dishes"),
+            timestamp: changed_at + 60,
+        };
+        let results = changes::sync(db.as_ref(), store.as_ref(), &events, vec![update])
+            .await
+            .unwrap();
+        assert_eq!(results[0].status, OperationStatus::Conflict);
+    }
+}
@@ -0,0 +1,803 @@
+//! Server configuration.
+//!
+//! Configuration is loaded once at startup, from the lowest to the highest
+//! precedence:
+//!
+//! 1. Built-in defaults.
+//! 2. A TOML file, named by `--config` or `REMINDERS_CONFIG`, or
+//!    `reminders.toml` in the working directory if it exists.
+//! 3. Environment variables, such as `STORAGE_BACKEND` and `AUTH_TOKEN`.
+//! 4. Command-line flags, including `--set <key>=<value>` for any setting.
+//!
+//! Settings are named by their dotted path in the TOML file, such as
+//! `storage.backend`. The whole configuration is validated before the server
+//! starts listening.
+use clap::Parser;
+use serde::{de::IntoDeserializer, Deserialize};
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+
+/// File read when no configuration file is named, if it exists.
+const DEFAULT_FILE: &str = "reminders.toml";
+
+/// Environment variables and the settings they set.
+const ENV_VARS: &[(&str, &str)] = &[
+    ("BIND_ADDRESS", "server.bind"),
+    ("PORT", "server.port"),
+    ("CORS_ORIGINS", "server.cors_origins"),
+    ("STORAGE_BACKEND", "storage.backend"),
+    ("FIREBASE_URI", "storage.firebase_uri"),
+    ("SQLITE_PATH", "storage.sqlite_path"),
+    ("MEMORY_FIXTURE", "storage.memory_fixture"),
+    ("MEMORY_PERSIST", "storage.memory_persist"),
+    ("AUTH_MODE", "auth.mode"),
+    ("AUTH_TOKEN", "auth.shared_token"),
+    ("ADMIN_USERS", "auth.admin_users"),
+    ("JWT_ALGORITHM", "auth.jwt.algorithm"),
+    ("JWT_SECRET", "auth.jwt.secret"),
+    ("JWT_PRIVATE_KEY_FILE", "auth.jwt.private_key_file"),
+    ("JWT_PUBLIC_KEY_FILE", "auth.jwt.public_key_file"),
+    ("JWT_ACCESS_LIFETIME", "auth.jwt.access_lifetime"),
+    ("OIDC_ISSUER", "auth.oidc.issuer"),
+    ("OIDC_AUDIENCE", "auth.oidc.audience"),
+    ("OIDC_JWKS_URI", "auth.oidc.jwks_uri"),
+    ("OIDC_JWKS_FILE", "auth.oidc.jwks_file"),
+    ("LOG_FORMAT", "log.format"),
+    ("MAX_BODY_BYTES", "limits.max_body_bytes"),
+    ("REQUEST_TIMEOUT_SECS", "limits.request_timeout_secs"),
+    ("COMPLETED_RETENTION_DAYS", "retention.completed_days"),
+    ("NOTIFICATIONS_ENABLED", "notifications.enabled"),
+    ("NOTIFICATION_BACKENDS", "notifications.backends"),
+    ("NOTIFICATION_CATCH_UP_SECS", "notifications.catch_up_secs"),
+    ("VAPID_SUBJECT", "notifications.webpush.subject"),
+    ("VAPID_PRIVATE_KEY", "notifications.webpush.private_key"),
+    ("WEBPUSH_TTL_SECS", "notifications.webpush.ttl_secs"),
+    (
+        "WEBPUSH_ALLOW_PRIVATE",
+        "notifications.webpush.allow_private",
+    ),
+    ("DIGEST_HOUR", "notifications.digest_hour"),
+    ("SMTP_HOST", "notifications.email.host"),
+    ("SMTP_PORT", "notifications.email.port"),
+    ("SMTP_SECURITY", "notifications.email.security"),
+    ("SMTP_USERNAME", "notifications.email.username"),
+    ("SMTP_PASSWORD", "notifications.email.password"),
+    ("SMTP_FROM", "notifications.email.from"),
+    ("WEBHOOKS_ENABLED", "webhooks.enabled"),
+    ("WEBHOOK_MAX_ATTEMPTS", "webhooks.max_attempts"),
+    ("WEBHOOK_BACKOFF_SECS", "webhooks.initial_backoff_secs"),
+    ("WEBHOOK_TIMEOUT_SECS", "webhooks.timeout_secs"),
+    ("WEBHOOK_ALLOW_PRIVATE", "webhooks.allow_private"),
+];
+
+/// Minimum length of an HS256 secret, in bytes.
+const MIN_SECRET_LENGTH: usize = 32;
+
+/// Command-line flags.
+#[derive(Debug, Default, Parser)]
+#[command(version, about = "Reminders API server")]
+pub struct Args {
+    /// TOML configuration file.
+    #[arg(long, value_name = "PATH")]
+    pub config: Option<PathBuf>,
+    /// Address to listen on.
+    #[arg(long)]
+    pub bind: Option<String>,
+    /// Port to listen on.
+    #[arg(long)]
+    pub port: Option<String>,
+    /// Storage backend: firebase, sqlite or memory.
+    #[arg(long)]
+    pub storage: Option<String>,
+    /// Authentication mode: shared, accounts or oidc.
+    #[arg(long)]
+    pub auth_mode: Option<String>,
+    /// Log format: text or json.
+    #[arg(long)]
+    pub log_format: Option<String>,
+    /// Set any setting by its dotted path, such as `limits.max_body_bytes=65536`.
+    #[arg(long = "set", value_name = "KEY=VALUE")]
+    pub settings: Vec<String>,
+}
+
+/// Server configuration.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub storage: StorageConfig,
+    pub auth: AuthConfig,
+    pub log: LogConfig,
+    pub limits: Limits,
+    pub retention: RetentionConfig,
+    pub notifications: NotificationsConfig,
+    pub webhooks: WebhooksConfig,
+}
+
+/// Where and how to listen.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub bind: IpAddr,
+    pub port: u16,
+    /// Origins browsers may call the API from, or `*` for any. CORS is
+    /// disabled when empty.
+    pub cors_origins: Vec<String>,
+}
+
+/// Storage backends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    Firebase,
+    Sqlite,
+    Memory,
+}
+
+/// Which storage backend to use, and its settings.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageConfig {
+    pub backend: Backend,
+    /// Root URI of the Firebase Realtime Database.
+    pub firebase_uri: Option<String>,
+    pub sqlite_path: PathBuf,
+    /// JSON file to load the in-memory store from.
+    pub memory_fixture: Option<PathBuf>,
+    /// JSON file to save the in-memory store to.
+    pub memory_persist: Option<PathBuf>,
+}
+
+/// How requests are authenticated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthMode {
+    /// Only the shared token.
+    Shared,
+    /// Built-in accounts, with sessions, JWT access tokens and API keys.
+    Accounts,
+    /// ID tokens from an OpenID Connect provider, and API keys.
+    Oidc,
+}
+
+/// Authentication settings.
+///
+/// The shared token is accepted in every mode when it is set.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    pub mode: AuthMode,
+    /// Token acting as the built-in user owning the shared reminders.
+    pub shared_token: Option<String>,
+    /// Ids or usernames of the users who may manage the API. In OIDC mode,
+    /// name admins by id, as no user signing in with OIDC gets a username
+    /// listed here.
+    pub admin_users: Vec<String>,
+    pub jwt: JwtConfig,
+    pub oidc: OidcConfig,
+}
+
+/// Algorithms for signing access tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum JwtAlgorithm {
+    HS256,
+    EdDSA,
+}
+
+/// How access tokens are signed.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct JwtConfig {
+    pub algorithm: JwtAlgorithm,
+    /// HS256 secret. A random one is used if unset.
+    pub secret: Option<String>,
+    /// EdDSA PKCS#8 private key, PEM encoded.
+    pub private_key_file: Option<PathBuf>,
+    /// EdDSA SPKI public key, PEM encoded.
+    pub public_key_file: Option<PathBuf>,
+    /// Lifetime of an access token, in seconds.
+    pub access_lifetime: u64,
+}
+
+/// The OpenID Connect provider to accept ID tokens from.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OidcConfig {
+    pub issuer: Option<String>,
+    /// Client id tokens must be issued to. Required in OIDC mode, so tokens
+    /// the provider issued to other clients are not accepted.
+    pub audience: Option<String>,
+    /// Where to fetch the key set from. Found through discovery if unset.
+    pub jwks_uri: Option<String>,
+    /// File to read the key set from instead of fetching it.
+    pub jwks_file: Option<PathBuf>,
+}
+
+/// Log output formats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+/// Logging settings.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub format: LogFormat,
+}
+
+/// Limits on requests.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    pub max_body_bytes: usize,
+    pub request_timeout_secs: u64,
+}
+
+/// How long completed reminders are kept.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetentionConfig {
+    /// Days to keep completed reminders for, counted from when they were
+    /// completed, after which they are deleted for good. `0`, the default,
+    /// keeps them forever. Set with `COMPLETED_RETENTION_DAYS`.
+    pub completed_days: u64,
+}
+
+/// Notification backends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NotifierBackend {
+    /// Write notifications to the server log.
+    Log,
+    /// Send notifications to subscribed browsers.
+    WebPush,
+    /// Email daily digests.
+    Email,
+}
+
+/// When and how to notify people about due reminders.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotificationsConfig {
+    /// Whether to run the scheduler at all.
+    pub enabled: bool,
+    pub backends: Vec<NotifierBackend>,
+    /// How long after falling due a reminder is still notified about, in
+    /// seconds, such as when the server was down at the time.
+    pub catch_up_secs: u64,
+    /// Hour of the day, in each user's time zone, to send daily digests at.
+    pub digest_hour: u64,
+    pub webpush: WebPushConfig,
+    pub email: EmailConfig,
+}
+
+/// How to send Web Push messages.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebPushConfig {
+    /// A `mailto:` or `https:` URL push services can contact the operator at.
+    pub subject: Option<String>,
+    /// VAPID P-256 private key, as URL-safe base64.
+    pub private_key: Option<String>,
+    /// How long push services keep a message for a browser that is offline, in seconds.
+    pub ttl_secs: u64,
+    /// Whether subscriptions may use plain HTTP and loopback, private and
+    /// link-local addresses, such as a push service on the same machine. Off
+    /// by default, so users cannot make the server reach its own network.
+    pub allow_private: bool,
+}
+
+/// How to secure the connection to the SMTP server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    /// Upgrade a plain connection with STARTTLS.
+    StartTls,
+    /// Connect with TLS from the start.
+    Tls,
+    /// No encryption, for local mail servers only.
+    None,
+}
+
+/// How to send email.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EmailConfig {
+    /// SMTP server host name.
+    pub host: Option<String>,
+    pub port: u16,
+    pub security: SmtpSecurity,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Sender, such as `Reminders <reminders@example.com>`.
+    pub from: Option<String>,
+}
+
+/// How to deliver webhooks.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhooksConfig {
+    /// Whether to deliver webhooks at all.
+    pub enabled: bool,
+    /// How many times to try delivering an event before giving up.
+    pub max_attempts: u32,
+    /// Seconds to wait before the first retry, doubling after each failure.
+    pub initial_backoff_secs: u64,
+    /// How long to wait for a receiver to respond, in seconds.
+    pub timeout_secs: u64,
+    /// Whether webhooks may post to loopback, private, link-local and
+    /// unspecified addresses, such as a receiver on the same machine. Off by
+    /// default, so users cannot make the server reach its own network.
+    pub allow_private: bool,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            bind: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port: 9999,
+            cors_origins: Vec::new(),
+        }
+    }
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            backend: Backend::Firebase,
+            firebase_uri: None,
+            sqlite_path: "reminders.db".into(),
+            memory_fixture: None,
+            memory_persist: None,
+        }
+    }
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig {
+            mode: AuthMode::Accounts,
+            shared_token: None,
+            admin_users: Vec::new(),
+            jwt: JwtConfig::default(),
+            oidc: OidcConfig::default(),
+        }
+    }
+}
+
+impl Default for JwtConfig {
+    fn default() -> Self {
+        JwtConfig {
+            algorithm: JwtAlgorithm::HS256,
+            secret: None,
+            private_key_file: None,
+            public_key_file: None,
+            access_lifetime: 15 * 60,
+        }
+    }
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            format: LogFormat::Text,
+        }
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_body_bytes: 2 * 1024 * 1024,
+            request_timeout_secs: 30,
+        }
+    }
+}
+
+impl Default for NotificationsConfig {
+    fn default() -> Self {
+        NotificationsConfig {
+            enabled: true,
+            backends: vec![NotifierBackend::Log],
+            catch_up_secs: 60 * 60,
+            digest_hour: 7,
+            webpush: WebPushConfig::default(),
+            email: EmailConfig::default(),
+        }
+    }
+}
+
+impl Default for WebPushConfig {
+    fn default() -> Self {
+        WebPushConfig {
+            subject: None,
+            private_key: None,
+            ttl_secs: 24 * 60 * 60,
+            allow_private: false,
+        }
+    }
+}
+
+impl Default for EmailConfig {
+    fn default() -> Self {
+        EmailConfig {
+            host: None,
+            port: 587,
+            security: SmtpSecurity::StartTls,
+            username: None,
+            password: None,
+            from: None,
+        }
+    }
+}
+
+impl Default for WebhooksConfig {
+    fn default() -> Self {
+        WebhooksConfig {
+            enabled: true,
+            max_attempts: 6,
+            initial_backoff_secs: 30,
+            timeout_secs: 10,
+            allow_private: false,
+        }
+    }
+}
+
+impl Config {
+    /// Load and validate the configuration from the file, environment and
+    /// command-line flags.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error describing the first setting that cannot be read or is
+    /// not valid.
+    pub fn load(args: Args) -> Result<Self, String> {
+        let file = args
+            .config
+            .clone()
+            .or_else(|| std::env::var_os("REMINDERS_CONFIG").map(PathBuf::from));
+        let mut config = match file {
+            Some(path) => Config::from_file(&path)?,
+            None if Path::new(DEFAULT_FILE).exists() => Config::from_file(Path::new(DEFAULT_FILE))?,
+            None => Config::default(),
+        };
+
+        config.apply_env(|name| std::env::var(name).ok())?;
+        config.apply_args(args)?;
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    /// Read the configuration from a TOML file, with defaults for anything
+    /// the file leaves out.
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let toml = std::fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {}: {e}", path.display()))?;
+        toml::from_str(&toml).map_err(|e| format!("invalid config in {}: {e}", path.display()))
+    }
+
+    /// Override settings with the environment variables that are set and not empty.
+    ///
+    /// # Arguments
+    ///
+    /// * `var` - Gets an environment variable.
+    pub fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) -> Result<(), String> {
+        for (name, key) in ENV_VARS {
+            if let Some(value) = var(name).filter(|value| !value.is_empty()) {
+                self.set(key, &value).map_err(|e| format!("{name}: {e}"))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Override settings with command-line flags.
+    pub fn apply_args(&mut self, args: Args) -> Result<(), String> {
+        let flags = [
+            ("server.bind", args.bind),
+            ("server.port", args.port),
+            ("storage.backend", args.storage),
+            ("auth.mode", args.auth_mode),
+            ("log.format", args.log_format),
+        ];
+        for (key, value) in flags {
+            if let Some(value) = value {
+                self.set(key, &value)?;
+            }
+        }
+
+        for setting in args.settings {
+            let (key, value) = setting
+                .split_once('=')
+                .ok_or_else(|| format!("--set {setting}: expected KEY=VALUE"))?;
+            self.set(key.trim(), value.trim())?;
+        }
+
+        Ok(())
+    }
+
+    /// Set a setting by its dotted path.
+    ///
+    /// Lists are given as comma-separated values.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), String> {
+        let string = || Some(value.to_string());
+        let path = || Some(PathBuf::from(value));
+
+        match key {
+            "server.bind" => self.server.bind = parse(key, value)?,
+            "server.port" => self.server.port = parse(key, value)?,
+            "server.cors_origins" => self.server.cors_origins = list(value),
+            "storage.backend" => self.storage.backend = parse_enum(key, value)?,
+            "storage.firebase_uri" => self.storage.firebase_uri = string(),
+            "storage.sqlite_path" => self.storage.sqlite_path = value.into(),
+            "storage.memory_fixture" => self.storage.memory_fixture = path(),
+            "storage.memory_persist" => self.storage.memory_persist = path(),
+            "auth.mode" => self.auth.mode = parse_enum(key, value)?,
+            "auth.shared_token" => self.auth.shared_token = string(),
+            "auth.admin_users" => self.auth.admin_users = list(value),
+            "auth.jwt.algorithm" => self.auth.jwt.algorithm = parse_enum(key, value)?,
+            "auth.jwt.secret" => self.auth.jwt.secret = string(),
+            "auth.jwt.private_key_file" => self.auth.jwt.private_key_file = path(),
+            "auth.jwt.public_key_file" => self.auth.jwt.public_key_file = path(),
+            "auth.jwt.access_lifetime" => self.auth.jwt.access_lifetime = parse(key, value)?,
+            "auth.oidc.issuer" => self.auth.oidc.issuer = string(),
+            "auth.oidc.audience" => self.auth.oidc.audience = string(),
+            "auth.oidc.jwks_uri" => self.auth.oidc.jwks_uri = string(),
+            "auth.oidc.jwks_file" => self.auth.oidc.jwks_file = path(),
+            "log.format" => self.log.format = parse_enum(key, value)?,
+            "limits.max_body_bytes" => self.limits.max_body_bytes = parse(key, value)?,
+            "limits.request_timeout_secs" => self.limits.request_timeout_secs = parse(key, value)?,
+            "retention.completed_days" => self.retention.completed_days = parse(key, value)?,
+            "notifications.enabled" => self.notifications.enabled = parse(key, value)?,
+            "notifications.backends" => {
+                self.notifications.backends = list(value)
+                    .iter()
+                    .map(|backend| parse_enum(key, backend))
+                    .collect::<Result<_, _>>()?
+            }
+            "notifications.catch_up_secs" => self.notifications.catch_up_secs = parse(key, value)?,
+            "notifications.webpush.subject" => self.notifications.webpush.subject = string(),
+            "notifications.webpush.private_key" => {
+                self.notifications.webpush.private_key = string()
+            }
+            "notifications.webpush.ttl_secs" => {
+                self.notifications.webpush.ttl_secs = parse(key, value)?
+            }
+            "notifications.webpush.allow_private" => {
+                self.notifications.webpush.allow_private = parse(key, value)?
+            }
+            "notifications.digest_hour" => self.notifications.digest_hour = parse(key, value)?,
+            "notifications.email.host" => self.notifications.email.host = string(),
+            "notifications.email.port" => self.notifications.email.port = parse(key, value)?,
+            "notifications.email.security" => {
+                self.notifications.email.security = parse_enum(key, value)?
+            }
+            "notifications.email.username" => self.notifications.email.username = string(),
+            "notifications.email.password" => self.notifications.email.password = string(),
+            "notifications.email.from" => self.notifications.email.from = string(),
+            "webhooks.enabled" => self.webhooks.enabled = parse(key, value)?,
+            "webhooks.max_attempts" => self.webhooks.max_attempts = parse(key, value)?,
+            "webhooks.initial_backoff_secs" => {
+                self.webhooks.initial_backoff_secs = parse(key, value)?
+            }
+            "webhooks.timeout_secs" => self.webhooks.timeout_secs = parse(key, value)?,
+            "webhooks.allow_private" => self.webhooks.allow_private = parse(key, value)?,
+            _ => return Err(format!("unknown setting {key}")),
+        }
+
+        Ok(())
+    }
+
+    /// Check the settings are consistent and complete.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.storage.backend == Backend::Firebase && self.storage.firebase_uri.is_none() {
+            return Err("storage.firebase_uri must be set to use Firebase".into());
+        }
+
+        match self.auth.mode {
+            AuthMode::Shared if self.auth.shared_token.is_none() => {
+                return Err("auth.shared_token must be set in shared mode".into())
+            }
+            AuthMode::Oidc if self.auth.oidc.issuer.is_none() => {
+                return Err("auth.oidc.issuer must be set in oidc mode".into())
+            }
+            AuthMode::Oidc if self.auth.oidc.audience.is_none() => {
+                return Err("auth.oidc.audience must be set in oidc mode".into())
+            }
+            _ => {}
+        }
+
+        let jwt = &self.auth.jwt;
+        match jwt.algorithm {
+            JwtAlgorithm::HS256 => {
+                if jwt
+                    .secret
+                    .as_ref()
+                    .is_some_and(|s| s.len() < MIN_SECRET_LENGTH)
+                {
+                    return Err(format!(
+                        "auth.jwt.secret must be at least {MIN_SECRET_LENGTH} bytes"
+                    ));
+                }
+            }
+            JwtAlgorithm::EdDSA => {
+                if jwt.private_key_file.is_none() || jwt.public_key_file.is_none() {
+                    return Err("auth.jwt.private_key_file and auth.jwt.public_key_file must be set for EdDSA".into());
+                }
+            }
+        }
+        if jwt.access_lifetime == 0 {
+            return Err("auth.jwt.access_lifetime must be more than 0".into());
+        }
+
+        for origin in &self.server.cors_origins {
+            let valid = origin == "*"
+                || ((origin.starts_with("https://") || origin.starts_with("http://"))
+                    && !origin.ends_with('/')
+                    && origin.parse::<axum::http::HeaderValue>().is_ok());
+            if !valid {
+                return Err(format!("server.cors_origins: invalid origin {origin}"));
+            }
+        }
+
+        if self
+            .notifications
+            .backends
+            .contains(&NotifierBackend::WebPush)
+        {
+            crate::notifiers::webpush::Vapid::from_config(&self.notifications.webpush)?;
+        }
+
+        if self
+            .notifications
+            .backends
+            .contains(&NotifierBackend::Email)
+        {
+            crate::notifiers::email::Email::from_config(&self.notifications.email)?;
+        }
+        if self.notifications.digest_hour > 23 {
+            return Err("notifications.digest_hour must be from 0 to 23".into());
+        }
+
+        if self.webhooks.max_attempts == 0 || self.webhooks.timeout_secs == 0 {
+            return Err(
+                "webhooks.max_attempts and webhooks.timeout_secs must be more than 0".into(),
+            );
+        }
+
+        if self.limits.max_body_bytes == 0 || self.limits.request_timeout_secs == 0 {
+            return Err("limits must be more than 0".into());
+        }
+
+        Ok(())
+    }
+}
+
+/// Parse a setting with `FromStr`.
+fn parse<T: std::str::FromStr>(key: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value for {key}: {value}"))
+}
+
+/// Parse a setting by the name it has in the TOML file.
+fn parse_enum<T: serde::de::DeserializeOwned>(key: &str, value: &str) -> Result<T, String> {
+    T::deserialize(value.into_deserializer())
+        .map_err(|_: serde::de::value::Error| format!("invalid value for {key}: {value}"))
+}
+
+/// Split a comma-separated list, dropping empty entries.
+fn list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{Args, AuthMode, Backend, Config, LogFormat};
+    use std::collections::HashMap;
+
+    /// Test that the environment overrides the file and flags override both.
+    #[test]
+    fn test_precedence() {
+        let mut config: Config = toml::from_str(
+            r#"
+            [server]
+            port = 8080
+            cors_origins = ["https://app.example.com"]
+
+            [storage]
+            backend = "sqlite"
+            sqlite_path = "file.db"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.server.port, 8080);
+        assert_eq!(config.auth.mode, AuthMode::Accounts);
+        assert_eq!(config.retention.completed_days, 0);
+
+        let env = HashMap::from([
+            ("PORT", "9000"),
+            ("SQLITE_PATH", "env.db"),
+            ("ADMIN_USERS", "sam, alex"),
+            ("AUTH_TOKEN", ""),
+        ]);
+        config
+            .apply_env(|name| env.get(name).map(|value| value.to_string()))
+            .unwrap();
+        assert_eq!(config.server.port, 9000);
+        assert_eq!(config.storage.sqlite_path.to_str(), Some("env.db"));
+        assert_eq!(config.auth.admin_users, vec!["sam", "alex"]);
+        assert!(config.auth.shared_token.is_none());
+
+        let args = Args {
+            port: Some("7000".into()),
+            log_format: Some("json".into()),
+            settings: vec!["limits.max_body_bytes=1024".into()],
+            ..Default::default()
+        };
+        config.apply_args(args).unwrap();
+        assert_eq!(config.server.port, 7000);
+        assert_eq!(config.log.format, LogFormat::Json);
+        assert_eq!(config.limits.max_body_bytes, 1024);
+        assert_eq!(config.storage.backend, Backend::Sqlite);
+        config.validate().unwrap();
+    }
+
+    /// Test that bad values and inconsistent settings are rejected.
+    #[test]
+    fn test_invalid() {
+        let mut config = Config::default();
+        assert!(config.set("server.port", "99999").is_err());
+        assert!(config.set("storage.backend", "postgres").is_err());
+        assert!(config.set("no.such.setting", "1").is_err());
+        assert!(toml::from_str::<Config>("[server]\nprot = 1").is_err());
+
+        let firebase = Config::default();
+        assert!(firebase.validate().is_err());
+
+        let mut config = Config::default();
+        config.set("storage.backend", "memory").unwrap();
+        config.validate().unwrap();
+
+        config.set("auth.mode", "oidc").unwrap();
+        assert!(config.validate().is_err());
+        config
+            .set("auth.oidc.issuer", "https://id.example.com")
+            .unwrap();
+        assert!(config.validate().is_err());
+        config.set("auth.oidc.audience", "reminders-app").unwrap();
+        config.validate().unwrap();
+
+        config.set("auth.jwt.secret", "short").unwrap();
+        assert!(config.validate().is_err());
+        config.set("auth.jwt.secret", &"s".repeat(32)).unwrap();
+        config.set("server.cors_origins", "example.com").unwrap();
+        assert!(config.validate().is_err());
+        config.set("server.cors_origins", "").unwrap();
+
+        config
+            .set("notifications.backends", "log, webpush")
+            .unwrap();
+        assert!(config.validate().is_err());
+        config
+            .set("notifications.webpush.subject", "mailto:ops@example.com")
+            .unwrap();
+        config
+            .set(
+                "notifications.webpush.private_key",
+                "q1dXpw3UpT5VOmu_cf_v6ih07Aems3njxI-JWgLcM94",
+            )
+            .unwrap();
+        config.validate().unwrap();
+        assert!(config.set("notifications.backends", "pager").is_err());
+    }
+}
@@ -0,0 +1,500 @@
+//! Daily digests of overdue reminders and reminders due today.
+//!
+//! Users opt in by giving an email address and their time zone. Once a day,
+//! after the configured hour in their time zone, the scheduler builds a digest
+//! of the open reminders they can see that are overdue or due that day, and
+//! hands it to the notifiers. Each day's digest is claimed in the `digests`
+//! document collection first, so it is sent at most once.
+use crate::households;
+use crate::middleware::auth::SHARED_USER;
+use crate::models::{
+    digest::DigestSettings, generic_response::ResponseMessage, recurrence::Date, reminder::Reminder,
+};
+use crate::notifiers::{Digest, Notifier};
+use crate::store::{self, from_document, to_document, DocumentStore, Storage};
+use axum::{http::StatusCode, response::IntoResponse};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+const SETTINGS: &str = "digest_settings";
+const DIGESTS: &str = "digests";
+
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// How long to keep a digest's claim. It only matters on the day it was made.
+const CLAIM_LIFETIME: u64 = 2 * 24 * 60 * 60;
+
+/// The range of time zones, in minutes ahead of UTC.
+const OFFSETS: std::ops::RangeInclusive<i32> = -12 * 60..=14 * 60;
+
+/// Errors that can occur when managing digest settings.
+#[derive(Debug)]
+pub enum Error {
+    NotFound,
+    InvalidEmail,
+    InvalidOffset,
+    Store(store::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl std::error::Error for Error {}
+
+/// Allow Error to be displayed.
+impl std::fmt::Display for Error {
+    /// Display Error.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::NotFound => write!(f, "Daily digest is not set up"),
+            Error::InvalidEmail => write!(f, "Invalid email address"),
+            Error::InvalidOffset => write!(f, "UTC offset must be between -720 and 840 minutes"),
+            Error::Store(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::convert::From<store::Error> for Error {
+    fn from(value: store::Error) -> Self {
+        Error::Store(value)
+    }
+}
+
+impl std::convert::From<Error> for axum::response::Response {
+    fn from(value: Error) -> Self {
+        let status = match value {
+            Error::NotFound => StatusCode::NOT_FOUND,
+            Error::InvalidEmail | Error::InvalidOffset => StatusCode::BAD_REQUEST,
+            Error::Store(e) => return e.into(),
+        };
+
+        ResponseMessage::from(value.to_string())
+            .with_status(status)
+            .into_response()
+    }
+}
+
+/// A claimed digest.
+#[derive(Debug, Deserialize, Serialize)]
+struct Claim {
+    user_id: String,
+    /// The user's local day, as days since 1970-01-01.
+    day: i64,
+    sent: u64,
+}
+
+/// Get a user's digest settings.
+///
+/// # Errors
+///
+/// Returns `Error::NotFound` if the user has not opted in.
+pub async fn get_settings(docs: &dyn DocumentStore, user_id: &str) -> Result<DigestSettings> {
+    match docs.get_document(SETTINGS, user_id).await? {
+        Some(settings) => Ok(from_document(settings)?),
+        None => Err(Error::NotFound),
+    }
+}
+
+/// Opt a user in to the daily digest, or change where it is sent.
+///
+/// # Errors
+///
+/// Returns `Error::InvalidEmail` or `Error::InvalidOffset` if the settings are
+/// not acceptable.
+pub async fn set_settings(
+    docs: &dyn DocumentStore,
+    user_id: &str,
+    settings: DigestSettings,
+) -> Result<DigestSettings> {
+    let settings = DigestSettings {
+        email: settings.email.trim().into(),
+        ..settings
+    };
+    if settings.email.parse::<lettre::Address>().is_err() {
+        return Err(Error::InvalidEmail);
+    }
+    if !OFFSETS.contains(&settings.utc_offset_minutes) {
+        return Err(Error::InvalidOffset);
+    }
+
+    docs.put_document(SETTINGS, user_id, to_document(&settings)?)
+        .await?;
+
+    Ok(settings)
+}
+
+/// Opt a user out of the daily digest.
+///
+/// # Errors
+///
+/// Returns `Error::NotFound` if the user had not opted in.
+pub async fn clear_settings(docs: &dyn DocumentStore, user_id: &str) -> Result<()> {
+    get_settings(docs, user_id).await?;
+
+    Ok(docs.delete_document(SETTINGS, user_id).await?)
+}
+
+/// Build a user's digest for the day it is at `now` in their time zone.
+///
+/// Each group is ordered the way the app lists reminders: by priority, then
+/// due date, then title.
+pub async fn build(
+    store: &dyn Storage,
+    user_id: &str,
+    settings: &DigestSettings,
+    now: u64,
+) -> store::Result<Digest> {
+    let offset = i64::from(settings.utc_offset_minutes) * 60;
+    let day = (now as i64 + offset).div_euclid(SECONDS_PER_DAY);
+    let start = day * SECONDS_PER_DAY - offset;
+    let end = start + SECONDS_PER_DAY;
+
+    let mut scopes = vec![match user_id {
+        SHARED_USER => String::new(),
+        id => crate::accounts::scope(id),
+    }];
+    scopes.extend(households::scopes_for_user(store, user_id).await?);
+
+    let (mut overdue, mut today) = (Vec::new(), Vec::new());
+    for scope in scopes {
+        for reminder in store.reminders(&scope).list().await?.value {
+            match reminder.due as i64 {
+                _ if reminder.completed => {}
+                due if due < start => overdue.push(reminder),
+                due if due < end => today.push(reminder),
+                _ => {}
+            }
+        }
+    }
+    sort(&mut overdue);
+    sort(&mut today);
+
+    Ok(Digest {
+        user_id: user_id.into(),
+        email: settings.email.clone(),
+        date: Date::from_days(day),
+        utc_offset_minutes: settings.utc_offset_minutes,
+        overdue,
+        today,
+    })
+}
+
+/// Send the digest of every user whose day has reached `hour` and who has
+/// not had one yet today.
+///
+/// Digests with nothing in them are not sent. A user whose digest cannot be
+/// built is logged and skipped, and their claim is removed so the next run
+/// tries again.
+///
+/// # Returns
+///
+/// The number of digests sent.
+///
+/// # Errors
+///
+/// Returns an error if the digest settings cannot be listed.
+pub async fn run(
+    store: &dyn Storage,
+    notifiers: &[Arc<dyn Notifier>],
+    hour: u64,
+    now: u64,
+) -> store::Result<usize> {
+    let mut sent = 0;
+    for (user_id, settings) in store.list_documents(SETTINGS).await? {
+        let settings: DigestSettings = match from_document(settings) {
+            Ok(settings) => settings,
+            Err(e) => {
+                log::error!("failed to read the digest settings of {user_id}: {e}");
+                continue;
+            }
+        };
+        let local = now as i64 + i64::from(settings.utc_offset_minutes) * 60;
+        if local.rem_euclid(SECONDS_PER_DAY) < hour as i64 * 3600 {
+            continue;
+        }
+
+        let day = local.div_euclid(SECONDS_PER_DAY);
+        let key = crate::accounts::token_key(&format!("{user_id}\n{day}"));
+        match claim(store, &key, &user_id, day, now).await {
+            Ok(true) => {}
+            Ok(false) => continue,
+            Err(e) => {
+                log::error!("failed to claim the digest of {user_id}: {e}");
+                continue;
+            }
+        }
+
+        let digest = match build(store, &user_id, &settings, now).await {
+            Ok(digest) => digest,
+            Err(e) => {
+                log::error!("failed to build the digest of {user_id}: {e}");
+                if let Err(e) = store.delete_document(DIGESTS, &key).await {
+                    log::error!("failed to release the digest claim of {user_id}: {e}");
+                }
+                continue;
+            }
+        };
+        if digest.overdue.is_empty() && digest.today.is_empty() {
+            continue;
+        }
+        for notifier in notifiers {
+            if let Err(e) = notifier.digest(&digest).await {
+                log::error!(
+                    "{} failed to send the digest of {user_id}: {e}",
+                    notifier.name()
+                );
+            }
+        }
+        sent += 1;
+    }
+
+    Ok(sent)
+}
+
+/// Claim a user's digest for a day, so only one server sends it.
+///
+/// # Returns
+///
+/// Whether the claim was made, or `false` if it was already taken.
+async fn claim(
+    docs: &dyn DocumentStore,
+    key: &str,
+    user_id: &str,
+    day: i64,
+    now: u64,
+) -> store::Result<bool> {
+    let claim = Claim {
+        user_id: user_id.into(),
+        day,
+        sent: now,
+    };
+    match docs
+        .insert_document(DIGESTS, key, to_document(&claim)?)
+        .await
+    {
+        Ok(()) => Ok(true),
+        Err(store::Error::Conflict) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Delete digest claims that are no longer needed.
+///
+/// # Returns
+///
+/// The number of claims deleted.
+pub async fn prune(docs: &dyn DocumentStore, now: u64) -> store::Result<usize> {
+    let mut pruned = 0;
+    for (id, claim) in docs.list_documents(DIGESTS).await? {
+        if from_document::<Claim>(claim)?.sent + CLAIM_LIFETIME < now {
+            docs.delete_document(DIGESTS, &id).await?;
+            pruned += 1;
+        }
+    }
+
+    Ok(pruned)
+}
+
+/// Order reminders by priority, then due date, then title.
+fn sort(reminders: &mut [Reminder]) {
+    reminders.sort_by(|a, b| (a.priority, a.due, &a.title).cmp(&(b.priority, b.due, &b.title)));
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::digest::{self, Error};
+    use crate::middleware::auth::AuthUser;
+    use crate::models::{digest::DigestSettings, reminder::Reminder};
+    use crate::notifiers::{Digest, Notification, Notifier};
+    use crate::store::{memory::Memory, DocumentStore, Storage};
+    use crate::{accounts, households};
+    use async_trait::async_trait;
+    use std::sync::{Arc, Mutex};
+
+    /// 2024-03-08 00:00 UTC.
+    const MIDNIGHT: u64 = 1_709_856_000;
+    const HOUR: u64 = 60 * 60;
+
+    /// Remembers the digests it is given.
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<Digest>>);
+
+    #[async_trait]
+    impl Notifier for Recorder {
+        fn name(&self) -> &'static str {
+            "recorder"
+        }
+
+        async fn notify(&self, _: &Notification) -> Result<(), String> {
+            Ok(())
+        }
+
+        async fn digest(&self, digest: &Digest) -> Result<(), String> {
+            self.0.lock().unwrap().push(digest.clone());
+            Ok(())
+        }
+    }
+
+    fn reminder(title: &str, due: u64, priority: u64) -> Reminder {
+        Reminder {
+            id: None,
+            title: title.into(),
+            due,
+            priority,
+            assignee: None,
+            completed: false,
+            completed_at: None,
+            rrule: None,
+        }
+    }
+
+    fn titles(reminders: &[Reminder]) -> Vec<&str> {
+        reminders.iter().map(|r| r.title.as_str()).collect()
+    }
+
+    /// Test which reminders go in a digest, in the user's time zone.
+    #[tokio::test]
+    async fn test_build() {
+        let store = Memory::default();
+        let own = store.reminders(&accounts::scope("u1"));
+        own.create(reminder("Bins", MIDNIGHT - 3 * HOUR, 1))
+            .await
+            .unwrap();
+        own.create(reminder("Dentist", MIDNIGHT + 20 * HOUR, 0))
+            .await
+            .unwrap();
+        own.create(reminder("Taxes", MIDNIGHT + 30 * HOUR, 0))
+            .await
+            .unwrap();
+        let mut done = reminder("Done", MIDNIGHT, 0);
+        done.complete(MIDNIGHT);
+        own.create(done).await.unwrap();
+
+        let user = AuthUser {
+            id: "u1".into(),
+            username: "sam".into(),
+        };
+        let household = households::create(&store, &user, "Home").await.unwrap();
+        let list = households::add_list(&store, "u1", &household.id, "Chores")
+            .await
+            .unwrap();
+        store
+            .reminders(&households::list_scope(&household.id, &list.id))
+            .create(reminder("Vacuum", MIDNIGHT + 9 * HOUR, 0))
+            .await
+            .unwrap();
+
+        // At 10:00 UTC, "Bins" was due yesterday and "Taxes" is tomorrow.
+        let utc = DigestSettings {
+            email: "sam@example.com".into(),
+            utc_offset_minutes: 0,
+        };
+        let digest = digest::build(&store, "u1", &utc, MIDNIGHT + 10 * HOUR)
+            .await
+            .unwrap();
+        assert_eq!(digest.date.to_string(), "08 Mar 2024");
+        assert_eq!(titles(&digest.overdue), vec!["Bins"]);
+        assert_eq!(titles(&digest.today), vec!["Vacuum", "Dentist"]);
+
+        // Five hours behind UTC, it is still the 7th at 02:00 UTC.
+        let behind = DigestSettings {
+            utc_offset_minutes: -5 * 60,
+            ..utc
+        };
+        let digest = digest::build(&store, "u1", &behind, MIDNIGHT + 2 * HOUR)
+            .await
+            .unwrap();
+        assert_eq!(digest.date.to_string(), "07 Mar 2024");
+        assert!(digest.overdue.is_empty());
+        assert_eq!(titles(&digest.today), vec!["Bins"]);
+    }
+
+    /// Test that a digest is sent once a day, after the digest hour.
+    #[tokio::test]
+    async fn test_run() {
+        let store: Arc<dyn Storage> = Arc::new(Memory::default());
+        store
+            .reminders(&accounts::scope("u1"))
+            .create(reminder("Bins", MIDNIGHT, 0))
+            .await
+            .unwrap();
+        let settings = DigestSettings {
+            email: " sam@example.com ".into(),
+            utc_offset_minutes: 60,
+        };
+        let saved = digest::set_settings(store.as_ref(), "u1", settings)
+            .await
+            .unwrap();
+        assert_eq!(saved.email, "sam@example.com");
+
+        let recorder = Arc::new(Recorder::default());
+        let notifiers: Vec<Arc<dyn Notifier>> = vec![recorder.clone()];
+        let run = |now| {
+            let (store, notifiers) = (store.clone(), notifiers.clone());
+            async move {
+                digest::run(store.as_ref(), &notifiers, 7, now)
+                    .await
+                    .unwrap()
+            }
+        };
+
+        // 07:00 local is 06:00 UTC.
+        assert_eq!(run(MIDNIGHT + 5 * HOUR).await, 0);
+        assert_eq!(run(MIDNIGHT + 6 * HOUR).await, 1);
+        assert_eq!(run(MIDNIGHT + 12 * HOUR).await, 0);
+        assert_eq!(run(MIDNIGHT + 30 * HOUR).await, 1);
+        assert_eq!(recorder.0.lock().unwrap().len(), 2);
+
+        let pruned = digest::prune(store.as_ref(), MIDNIGHT + 60 * HOUR).await;
+        assert_eq!(pruned.unwrap(), 1);
+    }
+
+    /// Test that a user whose digest cannot be read does not stop the others.
+    #[tokio::test]
+    async fn test_run_skips_failures() {
+        let store = Memory::default();
+        store
+            .put_document("digest_settings", "broken", serde_json::json!({"email": 1}))
+            .await
+            .unwrap();
+        let settings = DigestSettings {
+            email: "sam@example.com".into(),
+            utc_offset_minutes: 0,
+        };
+        digest::set_settings(&store, "u1", settings).await.unwrap();
+        store
+            .reminders(&accounts::scope("u1"))
+            .create(reminder("Bins", MIDNIGHT, 0))
+            .await
+            .unwrap();
+
+        let recorder = Arc::new(Recorder::default());
+        let notifiers: Vec<Arc<dyn Notifier>> = vec![recorder.clone()];
+        let sent = digest::run(&store, &notifiers, 7, MIDNIGHT + 7 * HOUR).await;
+
+        assert_eq!(sent.unwrap(), 1);
+        assert_eq!(recorder.0.lock().unwrap()[0].user_id, "u1");
+    }
+
+    /// Test that bad settings are rejected and users can opt out.
+    #[tokio::test]
+    async fn test_settings() {
+        let docs = Memory::default();
+        let bad_email = DigestSettings {
+            email: "not an address".into(),
+            utc_offset_minutes: 0,
+        };
+        let result = digest::set_settings(&docs, "u1", bad_email).await;
+        assert!(matches!(result, Err(Error::InvalidEmail)));
+        let bad_offset = DigestSettings {
+            email: "sam@example.com".into(),
+            utc_offset_minutes: 15 * 60,
+        };
+        let result = digest::set_settings(&docs, "u1", bad_offset).await;
+        assert!(matches!(result, Err(Error::InvalidOffset)));
+
+        assert!(matches!(
+            digest::clear_settings(&docs, "u1").await,
+            Err(Error::NotFound)
+        ));
+    }
+}
@@ -0,0 +1,250 @@
+//! Reminder lifecycle events.
+//!
+//! The v2 handlers publish an event on the [`Bus`] whenever they change a
+//! reminder, and the scheduler publishes one when a reminder falls due.
+//! Anything that reacts to changes, such as webhooks, subscribes to the bus.
+//!
+//! Each event has a sequence number, and the most recent events are kept so a
+//! client that briefly lost its connection can resume after the last one it
+//! saw. Sequence numbers start from the time the server started, in
+//! microseconds, so they keep increasing across restarts and a client
+//! resuming from before a restart is told to fetch everything again.
+use crate::models::{record::ReminderRecord, reminder::Reminder};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+
+/// How many events a slow subscriber can fall behind by before it misses
+/// some, and how many recent events are kept for resuming.
+const CAPACITY: usize = 1024;
+
+/// What happened to a reminder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+pub enum EventKind {
+    #[serde(rename = "reminder.created")]
+    Created,
+    #[serde(rename = "reminder.updated")]
+    Updated,
+    #[serde(rename = "reminder.deleted")]
+    Deleted,
+    #[serde(rename = "reminder.completed")]
+    Completed,
+    #[serde(rename = "reminder.due")]
+    Due,
+}
+
+impl EventKind {
+    /// The change clients keeping their reminders in sync are sent:
+    /// `created`, `updated` or `deleted`. Completing a reminder is an update,
+    /// and a reminder falling due is not a change.
+    pub fn change(self) -> Option<&'static str> {
+        match self {
+            EventKind::Created => Some("created"),
+            EventKind::Updated | EventKind::Completed => Some("updated"),
+            EventKind::Deleted => Some("deleted"),
+            EventKind::Due => None,
+        }
+    }
+}
+
+/// Something that happened to a reminder.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Event {
+    pub id: String,
+    /// Position of the event among every event published.
+    pub seq: u64,
+    #[serde(rename = "type")]
+    pub kind: EventKind,
+    /// When it happened, in seconds since the epoch.
+    pub time: u64,
+    /// The reminders scope the reminder is in.
+    pub scope: String,
+    /// The reminder after the change, or as it was before it was deleted.
+    pub reminder: Reminder,
+}
+
+/// The most recent events.
+struct Recent {
+    /// Sequence number of the next event.
+    next: u64,
+    events: VecDeque<Event>,
+}
+
+/// Carries events from where they happen to everything subscribed.
+#[derive(Clone)]
+pub struct Bus {
+    sender: broadcast::Sender<Event>,
+    recent: Arc<Mutex<Recent>>,
+}
+
+impl Default for Bus {
+    fn default() -> Self {
+        Bus {
+            sender: broadcast::channel(CAPACITY).0,
+            recent: Arc::new(Mutex::new(Recent {
+                next: crate::time::now() * 1_000_000 + 1,
+                events: VecDeque::with_capacity(CAPACITY),
+            })),
+        }
+    }
+}
+
+impl Bus {
+    /// Publish an event about a reminder.
+    ///
+    /// # Arguments
+    ///
+    /// * `kind` - What happened.
+    /// * `scope` - The reminders scope the reminder is in.
+    /// * `reminder` - The reminder, with its id.
+    pub fn publish(&self, kind: EventKind, scope: &str, reminder: Reminder) {
+        // Send the reminder as it reads back from storage.
+        let reminder = match reminder.id.clone() {
+            Some(id) => ReminderRecord::from(reminder).into_reminder(&id),
+            None => reminder,
+        };
+
+        // Sequence numbers are handed out and sent under the lock, so
+        // subscribers receive events in order.
+        let mut recent = self.recent.lock().unwrap();
+        let event = Event {
+            id: crate::random::id(),
+            seq: recent.next,
+            kind,
+            time: crate::time::now(),
+            scope: scope.into(),
+            reminder,
+        };
+        recent.next += 1;
+        if recent.events.len() == CAPACITY {
+            recent.events.pop_front();
+        }
+        recent.events.push_back(event.clone());
+
+        // Nobody may be listening, which is fine.
+        let _ = self.sender.send(event);
+    }
+
+    /// Receive every event published from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.sender.subscribe()
+    }
+
+    /// Sequence number of the latest event.
+    pub fn latest(&self) -> u64 {
+        self.recent.lock().unwrap().next - 1
+    }
+
+    /// Receive every event published after the one with sequence number `since`.
+    ///
+    /// # Returns
+    ///
+    /// The events already published after `since`, or `None` if they are no
+    /// longer kept, then a receiver for the events still to come, and the
+    /// sequence number of the latest event.
+    pub fn resume(&self, since: u64) -> (Option<Vec<Event>>, broadcast::Receiver<Event>, u64) {
+        let recent = self.recent.lock().unwrap();
+        let latest = recent.next - 1;
+        let oldest = recent.events.front().map_or(recent.next, |event| event.seq);
+
+        let missed = (since <= latest && since + 1 >= oldest).then(|| {
+            recent
+                .events
+                .iter()
+                .filter(|event| event.seq > since)
+                .cloned()
+                .collect()
+        });
+
+        (missed, self.sender.subscribe(), latest)
+    }
+}
+
+/// Publishes events about the reminders in one scope.
+#[derive(Clone)]
+pub struct Events {
+    bus: Bus,
+    scope: String,
+}
+
+impl Events {
+    /// Publish events about the reminders in `scope`.
+    pub fn new(bus: Bus, scope: &str) -> Self {
+        Events {
+            bus,
+            scope: scope.into(),
+        }
+    }
+
+    /// Publish an event about a reminder in the scope.
+    pub fn emit(&self, kind: EventKind, reminder: Reminder) {
+        self.bus.publish(kind, &self.scope, reminder);
+    }
+
+    /// The reminders scope events are published for.
+    pub fn scope(&self) -> &str {
+        &self.scope
+    }
+
+    /// Receive every event published from now on, in any scope.
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.bus.subscribe()
+    }
+
+    /// Sequence number of the latest event, in any scope.
+    pub fn latest(&self) -> u64 {
+        self.bus.latest()
+    }
+
+    /// Receive every event published after `since`, in any scope.
+    ///
+    /// See [`Bus::resume`].
+    pub fn resume(&self, since: u64) -> (Option<Vec<Event>>, broadcast::Receiver<Event>, u64) {
+        self.bus.resume(since)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::events::{Bus, EventKind, CAPACITY};
+    use crate::models::reminder::Reminder;
+
+    fn reminder(id: &str) -> Reminder {
+        Reminder {
+            id: Some(id.into()),
+            title: "Bins".into(),
+            due: 1,
+            priority: 0,
+            assignee: None,
+            completed: false,
+            completed_at: None,
+            rrule: None,
+        }
+    }
+
+    /// Test resuming from recent, current, forgotten and unknown sequence numbers.
+    #[test]
+    fn test_resume() {
+        let bus = Bus::default();
+        let (_, _, start) = bus.resume(0);
+
+        bus.publish(EventKind::Created, "", reminder("a"));
+        bus.publish(EventKind::Updated, "", reminder("a"));
+        let (missed, _, latest) = bus.resume(start + 1);
+        let missed = missed.unwrap();
+        assert_eq!(latest, start + 2);
+        assert_eq!(missed.len(), 1);
+        assert_eq!(missed[0].seq, start + 2);
+        assert_eq!(missed[0].kind, EventKind::Updated);
+
+        assert!(bus.resume(latest).0.unwrap().is_empty());
+        assert!(bus.resume(latest + 1).0.is_none());
+
+        for _ in 0..CAPACITY {
+            bus.publish(EventKind::Updated, "", reminder("a"));
+        }
+        assert!(bus.resume(start + 1).0.is_none());
+        assert_eq!(bus.resume(latest + 1).0.unwrap().len(), CAPACITY - 1);
+    }
+}
@@ -1,14 +1,15 @@
 //! Interface with Firebase.
-use axum::{response::IntoResponse, http::StatusCode};
-use crate::models::generic_response::ResponseMessage;
 use gcp_auth::{AuthenticationManager, Token};
+use std::sync::Arc;
+use tokio::sync::RwLock;
 
 /// Errors that can occur when interfacing with Firebase.
 #[derive(Debug)]
 pub enum Error {
-    URINotSet,
     Authentication,
     NotFound,
+    Query,
+    PreconditionFailed,
     PostData,
     DeleteData,
 }
@@ -22,37 +23,29 @@ impl std::fmt::Display for Error {
     /// Display FirebaseError.
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Error::URINotSet => write!(f, "FIREBASE_URI not set"),
             Error::Authentication => write!(f, "Authentication error"),
             Error::NotFound => write!(f, "Not found"),
+            Error::Query => write!(f, "Query rejected"),
+            Error::PreconditionFailed => write!(f, "ETag does not match"),
             Error::PostData => write!(f, "Error posting data"),
             Error::DeleteData => write!(f, "Error deleting data"),
         }
     }
 }
 
-impl std::convert::From<Error> for axum::response::Response {
-    fn from(value: Error) -> Self {
-        log::error!("{value}");
-        match value {
-            Error::NotFound => {
-                ResponseMessage::from(value)
-                    .with_status(StatusCode::NOT_FOUND)
-                    .into_response()
-            }
-            _ => {
-                ResponseMessage::from(value)
-                    .with_status(StatusCode::INTERNAL_SERVER_ERROR)
-                    .into_response()
-            }
-        }
-    }
+/// Request header asking Firebase to return the ETag of the data.
+const ETAG_REQUEST: &str = "X-Firebase-ETag";
+
+/// Body returned by Firebase when a record is pushed to a list.
+#[derive(serde::Deserialize)]
+struct PushResponse {
+    name: String,
 }
 
 /// Firebase interface.
 #[derive(Clone)]
 pub struct Firebase {
-    token: Token,
+    token: Arc<RwLock<Token>>,
     client: reqwest::Client,
     pub uri: String,
 }
@@ -60,15 +53,18 @@ pub struct Firebase {
 impl Firebase {
     /// Create a new Firebase instance.
     ///
+    /// # Arguments
+    ///
+    /// * `uri` - The root URI of the Realtime Database.
+    ///
     /// # Errors
     ///
-    /// Returns an error if the FIREBASE_URI environment variable is not set or if authentication fails.
-    pub async fn new() -> Result<Self> {
-        let uri = std::env::var("FIREBASE_URI").map_err(|_| Error::URINotSet)?;
+    /// Returns an error if authentication fails.
+    pub async fn new(uri: String) -> Result<Self> {
         let token = Firebase::get_token().await?;
 
         Ok(Self {
-            token,
+            token: Arc::new(RwLock::new(token)),
             client: reqwest::Client::new(),
             uri,
         })
@@ -82,7 +78,7 @@ impl Firebase {
     async fn get_token() -> Result<Token> {
         AuthenticationManager::new()
             .await
-            .unwrap()
+            .map_err(|_| Error::Authentication)?
             .get_token(&[
                 "https://www.googleapis.com/auth/firebase.database",
                 "https://www.googleapis.com/auth/userinfo.email",
@@ -91,49 +87,97 @@ impl Firebase {
             .map_err(|_| Error::Authentication)
     }
 
-    /// Refresh the Firebase token.
+    /// Refresh the Firebase token if it has expired, returning the current one.
     ///
     /// # Errors
     ///
     /// Returns an error if authentication fails.
-    async fn refresh(&mut self) -> Result<()> {
-        if self.token.has_expired() {
-            self.token = Firebase::get_token().await?;
+    async fn refresh(&self) -> Result<String> {
+        let mut token = self.token.write().await;
+
+        if token.has_expired() {
+            *token = Firebase::get_token().await?;
         }
 
-        Ok(())
+        Ok(token.as_str().to_string())
     }
 
-    /// Get data from Firebase.
+    /// Get data from Firebase, filtered with query parameters.
+    ///
+    /// Parameter values must already be JSON encoded, e.g. `("orderBy", "\"due\"")`.
     ///
     /// # Arguments
     ///
     /// * `path` - The path to the data.
+    /// * `params` - Query parameters such as `orderBy`, `startAt` and `limitToFirst`.
     ///
     /// # Errors
     ///
-    /// Returns an error if authentication fails or if the data is not found.
-    pub async fn get<T: serde::de::DeserializeOwned>(&mut self, path: &str) -> Result<T> {
-        self.refresh().await?;
+    /// Returns an error if authentication fails, if the data is not found or
+    /// if Firebase rejects the query (for example when an index is missing).
+    pub async fn query<T: serde::de::DeserializeOwned>(
+        &self,
+        path: &str,
+        params: &[(&str, String)],
+    ) -> Result<T> {
+        let token = self.refresh().await?;
 
         let url = format!("{}{}.json", &self.uri, path);
 
         let response = self
             .client
             .get(url)
-            .bearer_auth(self.token.as_str())
+            .query(params)
+            .bearer_auth(token)
             .send()
             .await
             .map_err(|_| Error::Authentication)?;
 
-        let res = match response.status().is_success() {
-            true => response,
-            false => return Err(Error::NotFound),
+        let res = match response.status() {
+            status if status.is_success() => response,
+            reqwest::StatusCode::BAD_REQUEST => return Err(Error::Query),
+            _ => return Err(Error::NotFound),
         };
 
         res.json().await.map_err(|_| Error::NotFound)
     }
 
+    /// Get data from Firebase along with its ETag.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to the data.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if authentication fails or if the data is not found.
+    pub async fn get_with_etag<T: serde::de::DeserializeOwned>(
+        &self,
+        path: &str,
+    ) -> Result<(T, String)> {
+        let token = self.refresh().await?;
+
+        let url = format!("{}{}.json", &self.uri, path);
+
+        let response = self
+            .client
+            .get(url)
+            .header(ETAG_REQUEST, "true")
+            .bearer_auth(token)
+            .send()
+            .await
+            .map_err(|_| Error::Authentication)?;
+
+        if !response.status().is_success() {
+            return Err(Error::NotFound);
+        }
+
+        let etag = etag(&response)?;
+        let data = response.json().await.map_err(|_| Error::NotFound)?;
+
+        Ok((data, etag))
+    }
+
     /// Post data to Firebase.
     ///
     /// # Arguments
@@ -141,30 +185,37 @@ impl Firebase {
     /// * `path` - The path to the data.
     /// * `data` - The data to post.
     ///
+    /// # Returns
+    ///
+    /// The push key Firebase generated for the new record.
+    ///
     /// # Errors
     ///
     /// Returns an error if authentication fails or if the data is not found.
-    pub async fn post<T>(&mut self, path: &str, data: T) -> Result<()>
+    pub async fn post<T>(&self, path: &str, data: T) -> Result<String>
     where
         T: serde::Serialize + std::fmt::Debug,
     {
-        self.refresh().await?;
+        let token = self.refresh().await?;
 
         let url = format!("{}{}.json", &self.uri, path);
 
         let response = self
             .client
             .post(url)
-            .bearer_auth(self.token.as_str())
+            .bearer_auth(token)
             .json(&data)
             .send()
             .await
             .map_err(|_| Error::Authentication)?;
 
-        match response.status().is_success() {
-            true => Ok(()),
-            false => Err(Error::PostData),
+        if !response.status().is_success() {
+            return Err(Error::PostData);
         }
+
+        let created: PushResponse = response.json().await.map_err(|_| Error::PostData)?;
+
+        Ok(created.name)
     }
 
     /// Update a record in Firebase.
@@ -177,18 +228,18 @@ impl Firebase {
     /// # Errors
     ///
     /// Returns an error if authentication fails or if the data is not found.
-    pub async fn put<T>(&mut self, path: &str, data: T) -> Result<()>
+    pub async fn put<T>(&self, path: &str, data: T) -> Result<()>
     where
         T: serde::Serialize + std::fmt::Debug,
     {
-        self.refresh().await?;
+        let token = self.refresh().await?;
 
         let url = format!("{}{}.json", &self.uri, path);
 
         let response = self
             .client
             .put(url)
-            .bearer_auth(self.token.as_str())
+            .bearer_auth(token)
             .json(&data)
             .send()
             .await
@@ -200,6 +251,42 @@ impl Firebase {
         }
     }
 
+    /// Update a record in Firebase only if it has not changed since it was read.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to the data.
+    /// * `data` - The data to update.
+    /// * `etag` - The ETag the record had when it was read.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::PreconditionFailed` if the record's ETag no longer matches.
+    pub async fn put_if_match<T>(&self, path: &str, data: T, etag: &str) -> Result<()>
+    where
+        T: serde::Serialize + std::fmt::Debug,
+    {
+        let token = self.refresh().await?;
+
+        let url = format!("{}{}.json", &self.uri, path);
+
+        let response = self
+            .client
+            .put(url)
+            .header(reqwest::header::IF_MATCH, etag)
+            .bearer_auth(token)
+            .json(&data)
+            .send()
+            .await
+            .map_err(|_| Error::Authentication)?;
+
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            reqwest::StatusCode::PRECONDITION_FAILED => Err(Error::PreconditionFailed),
+            _ => Err(Error::PostData),
+        }
+    }
+
     /// Delete a record in Firebase.
     ///
     /// # Arguments
@@ -209,15 +296,15 @@ impl Firebase {
     /// # Errors
     ///
     /// Returns an error if authentication fails or if the data is not found.
-    pub async fn delete(&mut self, path: &str) -> Result<()> {
-        self.refresh().await?;
+    pub async fn delete(&self, path: &str) -> Result<()> {
+        let token = self.refresh().await?;
 
         let url = format!("{}{}.json", &self.uri, path);
 
         let response = self
             .client
             .delete(url)
-            .bearer_auth(self.token.as_str())
+            .bearer_auth(token)
             .send()
             .await
             .map_err(|_| Error::Authentication)?;
@@ -227,4 +314,77 @@ impl Firebase {
             false => Err(Error::DeleteData),
         }
     }
+
+    /// Delete a record in Firebase only if it has not changed since it was read.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to the data.
+    /// * `etag` - The ETag the record had when it was read.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::PreconditionFailed` if the record's ETag no longer matches.
+    pub async fn delete_if_match(&self, path: &str, etag: &str) -> Result<()> {
+        let token = self.refresh().await?;
+
+        let url = format!("{}{}.json", &self.uri, path);
+
+        let response = self
+            .client
+            .delete(url)
+            .header(reqwest::header::IF_MATCH, etag)
+            .bearer_auth(token)
+            .send()
+            .await
+            .map_err(|_| Error::Authentication)?;
+
+        match response.status() {
+            status if status.is_success() => Ok(()),
+            reqwest::StatusCode::PRECONDITION_FAILED => Err(Error::PreconditionFailed),
+            _ => Err(Error::DeleteData),
+        }
+    }
+
+    /// Open a stream of the changes under a path, as Server-Sent Events.
+    ///
+    /// The token is sent as a query parameter rather than a header, so it
+    /// survives the redirect Firebase may answer with.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to watch.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if authentication fails or Firebase refuses the stream.
+    pub async fn listen(&self, path: &str) -> Result<reqwest::Response> {
+        let token = self.refresh().await?;
+
+        let url = format!("{}{}.json", &self.uri, path);
+
+        let response = self
+            .client
+            .get(url)
+            .header(reqwest::header::ACCEPT, "text/event-stream")
+            .query(&[("access_token", token)])
+            .send()
+            .await
+            .map_err(|_| Error::Authentication)?;
+
+        match response.status().is_success() {
+            true => Ok(response),
+            false => Err(Error::NotFound),
+        }
+    }
+}
+
+/// Read the ETag Firebase returned for a request sent with `X-Firebase-ETag`.
+fn etag(response: &reqwest::Response) -> Result<String> {
+    response
+        .headers()
+        .get(reqwest::header::ETAG)
+        .and_then(|v| v.to_str().ok())
+        .map(String::from)
+        .ok_or(Error::NotFound)
 }
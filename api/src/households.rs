@@ -0,0 +1,629 @@
+//! Households, their shared lists and memberships.
+//!
+//! A household is a group of users sharing lists of reminders. Each member has
+//! a [`Role`]: viewers can read the household's reminders, editors can also
+//! change them and add lists, and the owner manages lists, members and
+//! invitations. Users join a household with a one-time invite code.
+//!
+//! Households are kept in the `households` document collection. Members of a
+//! household are kept in `members/<household id>`, and every user's households
+//! are indexed in `memberships/<user id>`. Each list's reminders live in their
+//! own reminders scope.
+use crate::middleware::auth::AuthUser;
+use crate::models::{
+    generic_response::ResponseMessage,
+    household::{
+        Household, HouseholdDetails, HouseholdSummary, Invite, InviteResponse, List, ListResponse,
+        Member, MemberResponse, Role,
+    },
+};
+use crate::store::{self, from_document, to_document, DocumentStore, Storage};
+use axum::{http::StatusCode, response::IntoResponse};
+
+const HOUSEHOLDS: &str = "households";
+const INVITES: &str = "invites";
+const INVITE_CLAIMS: &str = "invite_claims";
+
+/// How long an invite code can be used for, in seconds.
+const INVITE_LIFETIME: u64 = 7 * 24 * 60 * 60;
+
+/// Length of an invite code.
+const INVITE_CODE_LENGTH: usize = 10;
+
+const MAX_NAME_LENGTH: usize = 100;
+
+/// Errors that can occur when managing households.
+#[derive(Debug)]
+pub enum Error {
+    NotFound,
+    Forbidden,
+    InvalidName,
+    InvalidRole,
+    InvalidInvite,
+    Store(store::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl std::error::Error for Error {}
+
+/// Allow Error to be displayed.
+impl std::fmt::Display for Error {
+    /// Display Error.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::NotFound => write!(f, "Not found"),
+            Error::Forbidden => write!(f, "Your role does not allow this"),
+            Error::InvalidName => write!(f, "Name must be 1 to {MAX_NAME_LENGTH} characters"),
+            Error::InvalidRole => write!(f, "Role must be viewer or editor"),
+            Error::InvalidInvite => write!(f, "Invite code is invalid, used or expired"),
+            Error::Store(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::convert::From<store::Error> for Error {
+    fn from(value: store::Error) -> Self {
+        Error::Store(value)
+    }
+}
+
+impl std::convert::From<Error> for axum::response::Response {
+    fn from(value: Error) -> Self {
+        let status = match value {
+            Error::NotFound => StatusCode::NOT_FOUND,
+            Error::Forbidden => StatusCode::FORBIDDEN,
+            Error::InvalidName | Error::InvalidRole | Error::InvalidInvite => {
+                StatusCode::BAD_REQUEST
+            }
+            Error::Store(e) => return e.into(),
+        };
+
+        ResponseMessage::from(value.to_string())
+            .with_status(status)
+            .into_response()
+    }
+}
+
+/// Create a household, owned by the user creating it.
+///
+/// # Errors
+///
+/// Returns `Error::InvalidName` if the name is empty or too long.
+pub async fn create(docs: &dyn DocumentStore, user: &AuthUser, name: &str) -> Result<Household> {
+    let household = Household {
+        id: crate::random::id(),
+        name: valid_name(name)?,
+        owner: user.id.clone(),
+        created: crate::time::now(),
+    };
+
+    docs.put_document(HOUSEHOLDS, &household.id, to_document(&household)?)
+        .await?;
+    add_member(docs, &household.id, user, Role::Owner).await?;
+
+    Ok(household)
+}
+
+/// Get the households a user belongs to, with the user's role in each.
+pub async fn list_for_user(
+    docs: &dyn DocumentStore,
+    user_id: &str,
+) -> Result<Vec<HouseholdSummary>> {
+    let mut households = Vec::new();
+    for (id, _) in docs
+        .list_documents(&memberships_collection(user_id))
+        .await?
+    {
+        let (Some(household), Some(role)) =
+            (get(docs, &id).await?, role_of(docs, user_id, &id).await?)
+        else {
+            continue;
+        };
+        households.push(HouseholdSummary {
+            id,
+            name: household.name,
+            role,
+        });
+    }
+
+    Ok(households)
+}
+
+/// Get a household with its members and lists.
+///
+/// # Errors
+///
+/// Returns `Error::Forbidden` if the user is not a member.
+pub async fn details(
+    docs: &dyn DocumentStore,
+    user_id: &str,
+    household_id: &str,
+) -> Result<HouseholdDetails> {
+    let role = require(docs, user_id, household_id, Role::Viewer).await?;
+    let household = get(docs, household_id).await?.ok_or(Error::NotFound)?;
+
+    let mut members = Vec::new();
+    for (user_id, member) in docs
+        .list_documents(&members_collection(household_id))
+        .await?
+    {
+        let member: Member = from_document(member)?;
+        members.push(MemberResponse {
+            user_id,
+            username: member.username,
+            role: member.role,
+        });
+    }
+
+    let mut lists = Vec::new();
+    for (id, list) in docs.list_documents(&lists_collection(household_id)).await? {
+        let list: List = from_document(list)?;
+        lists.push(ListResponse {
+            id,
+            name: list.name,
+        });
+    }
+
+    Ok(HouseholdDetails {
+        id: household.id,
+        name: household.name,
+        owner: household.owner,
+        role,
+        members,
+        lists,
+    })
+}
+
+/// Get the role of a user in a household.
+///
+/// # Returns
+///
+/// The role, or `None` if the user is not a member.
+pub async fn role_of(
+    docs: &dyn DocumentStore,
+    user_id: &str,
+    household_id: &str,
+) -> store::Result<Option<Role>> {
+    docs.get_document(&members_collection(household_id), user_id)
+        .await?
+        .map(|member| from_document::<Member>(member).map(|member| member.role))
+        .transpose()
+}
+
+/// Check a user may use a list with the given role.
+///
+/// # Returns
+///
+/// The reminders scope of the list.
+///
+/// # Errors
+///
+/// Returns `Error::Forbidden` if the user is not a member or their role is too
+/// low, or `Error::NotFound` if the household has no such list.
+pub async fn list_access(
+    docs: &dyn DocumentStore,
+    user_id: &str,
+    household_id: &str,
+    list_id: &str,
+    needed: Role,
+) -> Result<String> {
+    require(docs, user_id, household_id, needed).await?;
+    if docs
+        .get_document(&lists_collection(household_id), list_id)
+        .await?
+        .is_none()
+    {
+        return Err(Error::NotFound);
+    }
+
+    Ok(list_scope(household_id, list_id))
+}
+
+/// Add a list to a household. Editors and owners may add lists.
+pub async fn add_list(
+    docs: &dyn DocumentStore,
+    user_id: &str,
+    household_id: &str,
+    name: &str,
+) -> Result<ListResponse> {
+    require(docs, user_id, household_id, Role::Editor).await?;
+
+    let id = crate::random::id();
+    let list = List {
+        name: valid_name(name)?,
+        created: crate::time::now(),
+    };
+    docs.put_document(&lists_collection(household_id), &id, to_document(&list)?)
+        .await?;
+
+    Ok(ListResponse {
+        id,
+        name: list.name,
+    })
+}
+
+/// Delete a list and its reminders. Only the owner may delete lists.
+pub async fn delete_list(
+    store: &dyn Storage,
+    user_id: &str,
+    household_id: &str,
+    list_id: &str,
+) -> Result<()> {
+    let scope = list_access(store, user_id, household_id, list_id, Role::Owner).await?;
+
+    let reminders = store.reminders(&scope);
+    for reminder in reminders.list().await?.value {
+        if let Some(id) = reminder.id {
+            reminders.delete(&id, None).await?;
+        }
+    }
+
+    Ok(store
+        .delete_document(&lists_collection(household_id), list_id)
+        .await?)
+}
+
+/// Change the role of a member. Only the owner may change roles, and the
+/// owner's own role cannot be changed.
+pub async fn set_role(
+    docs: &dyn DocumentStore,
+    user_id: &str,
+    household_id: &str,
+    member_id: &str,
+    role: Role,
+) -> Result<()> {
+    require(docs, user_id, household_id, Role::Owner).await?;
+    if role == Role::Owner {
+        return Err(Error::InvalidRole);
+    }
+    if member_id == user_id {
+        return Err(Error::Forbidden);
+    }
+
+    let collection = members_collection(household_id);
+    let mut member: Member = docs
+        .get_document(&collection, member_id)
+        .await?
+        .map(from_document)
+        .transpose()?
+        .ok_or(Error::NotFound)?;
+    member.role = role;
+
+    Ok(docs
+        .put_document(&collection, member_id, to_document(&member)?)
+        .await?)
+}
+
+/// Remove a member from a household.
+///
+/// The owner may remove other members, and members may remove themselves. The
+/// owner cannot leave their own household.
+pub async fn remove_member(
+    docs: &dyn DocumentStore,
+    user_id: &str,
+    household_id: &str,
+    member_id: &str,
+) -> Result<()> {
+    let role = require(docs, user_id, household_id, Role::Viewer).await?;
+    match (role, member_id == user_id) {
+        (Role::Owner, true) => return Err(Error::Forbidden),
+        (Role::Owner, false) | (_, true) => {}
+        (_, false) => return Err(Error::Forbidden),
+    }
+    if role_of(docs, member_id, household_id).await?.is_none() {
+        return Err(Error::NotFound);
+    }
+
+    docs.delete_document(&members_collection(household_id), member_id)
+        .await?;
+    Ok(docs
+        .delete_document(&memberships_collection(member_id), household_id)
+        .await?)
+}
+
+/// Create a one-time invite code to join a household as a viewer or editor.
+/// Only the owner may invite.
+pub async fn create_invite(
+    docs: &dyn DocumentStore,
+    user_id: &str,
+    household_id: &str,
+    role: Role,
+) -> Result<InviteResponse> {
+    require(docs, user_id, household_id, Role::Owner).await?;
+    if role == Role::Owner {
+        return Err(Error::InvalidRole);
+    }
+
+    let code = crate::random::code(INVITE_CODE_LENGTH);
+    let invite = Invite {
+        household_id: household_id.into(),
+        role,
+        expires: crate::time::now() + INVITE_LIFETIME,
+    };
+    docs.insert_document(INVITES, &code, to_document(&invite)?)
+        .await?;
+
+    Ok(InviteResponse {
+        code,
+        role,
+        expires: invite.expires,
+    })
+}
+
+/// Join a household with an invite code.
+///
+/// Codes are case insensitive. A code can only be used once, even by
+/// concurrent requests.
+///
+/// # Errors
+///
+/// Returns `Error::InvalidInvite` if the code is unknown, used or expired.
+pub async fn accept_invite(
+    docs: &dyn DocumentStore,
+    user: &AuthUser,
+    code: &str,
+) -> Result<HouseholdSummary> {
+    let code = code.trim().to_uppercase();
+    let invite: Invite = docs
+        .get_document(INVITES, &code)
+        .await?
+        .map(from_document)
+        .transpose()?
+        .ok_or(Error::InvalidInvite)?;
+
+    // Claiming the code is what makes it one-time: only one claim can be inserted.
+    match docs
+        .insert_document(INVITE_CLAIMS, &code, to_document(&user.id)?)
+        .await
+    {
+        Err(store::Error::Conflict) => return Err(Error::InvalidInvite),
+        result => result?,
+    }
+    docs.delete_document(INVITES, &code).await?;
+
+    if invite.expires <= crate::time::now() {
+        return Err(Error::InvalidInvite);
+    }
+    let household = get(docs, &invite.household_id)
+        .await?
+        .ok_or(Error::InvalidInvite)?;
+
+    // Joining never lowers the role of an existing member.
+    let role = match role_of(docs, &user.id, &household.id).await? {
+        Some(current) if current >= invite.role => current,
+        _ => {
+            add_member(docs, &household.id, user, invite.role).await?;
+            invite.role
+        }
+    };
+
+    Ok(HouseholdSummary {
+        id: household.id,
+        name: household.name,
+        role,
+    })
+}
+
+/// Get the reminders scopes of every household list.
+pub async fn scopes(docs: &dyn DocumentStore) -> store::Result<Vec<String>> {
+    let mut scopes = Vec::new();
+    for (household_id, _) in docs.list_documents(HOUSEHOLDS).await? {
+        for (list_id, _) in docs
+            .list_documents(&lists_collection(&household_id))
+            .await?
+        {
+            scopes.push(list_scope(&household_id, &list_id));
+        }
+    }
+
+    Ok(scopes)
+}
+
+/// Get the reminders scopes of every list in the households a user belongs to.
+pub async fn scopes_for_user(
+    docs: &dyn DocumentStore,
+    user_id: &str,
+) -> store::Result<Vec<String>> {
+    let mut scopes = Vec::new();
+    for (household_id, _) in docs
+        .list_documents(&memberships_collection(user_id))
+        .await?
+    {
+        for (list_id, _) in docs
+            .list_documents(&lists_collection(&household_id))
+            .await?
+        {
+            scopes.push(list_scope(&household_id, &list_id));
+        }
+    }
+
+    Ok(scopes)
+}
+
+/// Get the ids of every member of a household.
+pub async fn member_ids(
+    docs: &dyn DocumentStore,
+    household_id: &str,
+) -> store::Result<Vec<String>> {
+    Ok(docs
+        .list_documents(&members_collection(household_id))
+        .await?
+        .into_iter()
+        .map(|(id, _)| id)
+        .collect())
+}
+
+/// The reminders scope of a household list.
+pub fn list_scope(household_id: &str, list_id: &str) -> String {
+    format!("households/{household_id}/lists/{list_id}")
+}
+
+/// Get a household by id.
+async fn get(docs: &dyn DocumentStore, id: &str) -> Result<Option<Household>> {
+    Ok(docs
+        .get_document(HOUSEHOLDS, id)
+        .await?
+        .map(from_document)
+        .transpose()?)
+}
+
+/// Check a user has at least the given role in a household.
+///
+/// # Returns
+///
+/// The user's role.
+async fn require(
+    docs: &dyn DocumentStore,
+    user_id: &str,
+    household_id: &str,
+    needed: Role,
+) -> Result<Role> {
+    match role_of(docs, user_id, household_id).await? {
+        Some(role) if role >= needed => Ok(role),
+        _ => Err(Error::Forbidden),
+    }
+}
+
+/// Add a user to a household, or change their role if they are already a member.
+async fn add_member(
+    docs: &dyn DocumentStore,
+    household_id: &str,
+    user: &AuthUser,
+    role: Role,
+) -> Result<()> {
+    let member = Member {
+        username: user.username.clone(),
+        role,
+    };
+    docs.put_document(
+        &members_collection(household_id),
+        &user.id,
+        to_document(&member)?,
+    )
+    .await?;
+    Ok(docs
+        .put_document(
+            &memberships_collection(&user.id),
+            household_id,
+            to_document(&true)?,
+        )
+        .await?)
+}
+
+/// Trim a household or list name and check its length.
+fn valid_name(name: &str) -> Result<String> {
+    let name = name.trim();
+    match (1..=MAX_NAME_LENGTH).contains(&name.chars().count()) {
+        true => Ok(name.into()),
+        false => Err(Error::InvalidName),
+    }
+}
+
+/// The collection of a household's members, keyed by user id.
+fn members_collection(household_id: &str) -> String {
+    format!("members/{household_id}")
+}
+
+/// The collection of a user's households, keyed by household id.
+fn memberships_collection(user_id: &str) -> String {
+    format!("memberships/{user_id}")
+}
+
+/// The collection of a household's lists, keyed by list id.
+fn lists_collection(household_id: &str) -> String {
+    format!("lists/{household_id}")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::households::{self, Error};
+    use crate::middleware::auth::AuthUser;
+    use crate::models::household::Role;
+    use crate::store::memory::Memory;
+
+    fn user(id: &str) -> AuthUser {
+        AuthUser {
+            id: id.into(),
+            username: id.into(),
+        }
+    }
+
+    /// Test creating a household, inviting members and enforcing roles.
+    #[tokio::test]
+    async fn test_roles() {
+        let docs = Memory::default();
+        let (owner, alex, kim) = (user("owner"), user("alex"), user("kim"));
+
+        let household = households::create(&docs, &owner, " Home ").await.unwrap();
+        assert_eq!(household.name, "Home");
+        let list = households::add_list(&docs, "owner", &household.id, "Chores")
+            .await
+            .unwrap();
+
+        let invite = households::create_invite(&docs, "owner", &household.id, Role::Viewer)
+            .await
+            .unwrap();
+        let joined = households::accept_invite(&docs, &alex, &invite.code.to_lowercase())
+            .await
+            .unwrap();
+        assert_eq!(joined.role, Role::Viewer);
+
+        let reused = households::accept_invite(&docs, &kim, &invite.code).await;
+        assert!(matches!(reused, Err(Error::InvalidInvite)));
+
+        let scope =
+            households::list_access(&docs, "alex", &household.id, &list.id, Role::Viewer).await;
+        assert_eq!(
+            scope.unwrap(),
+            households::list_scope(&household.id, &list.id)
+        );
+        let write =
+            households::list_access(&docs, "alex", &household.id, &list.id, Role::Editor).await;
+        assert!(matches!(write, Err(Error::Forbidden)));
+        let outsider =
+            households::list_access(&docs, "kim", &household.id, &list.id, Role::Viewer).await;
+        assert!(matches!(outsider, Err(Error::Forbidden)));
+
+        let promote =
+            households::set_role(&docs, "alex", &household.id, "alex", Role::Editor).await;
+        assert!(matches!(promote, Err(Error::Forbidden)));
+        households::set_role(&docs, "owner", &household.id, "alex", Role::Editor)
+            .await
+            .unwrap();
+        let write =
+            households::list_access(&docs, "alex", &household.id, &list.id, Role::Editor).await;
+        assert!(write.is_ok());
+
+        let mine = households::list_for_user(&docs, "alex").await.unwrap();
+        assert_eq!(mine.len(), 1);
+        assert_eq!(mine[0].role, Role::Editor);
+
+        let leave_owner = households::remove_member(&docs, "owner", &household.id, "owner").await;
+        assert!(matches!(leave_owner, Err(Error::Forbidden)));
+        households::remove_member(&docs, "alex", &household.id, "alex")
+            .await
+            .unwrap();
+        assert!(households::list_for_user(&docs, "alex")
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    /// Test that invites cannot make owners and names must not be empty.
+    #[tokio::test]
+    async fn test_invalid() {
+        let docs = Memory::default();
+        let owner = user("owner");
+
+        let empty = households::create(&docs, &owner, "  ").await;
+        assert!(matches!(empty, Err(Error::InvalidName)));
+
+        let household = households::create(&docs, &owner, "Home").await.unwrap();
+        let invite = households::create_invite(&docs, "owner", &household.id, Role::Owner).await;
+        assert!(matches!(invite, Err(Error::InvalidRole)));
+
+        let unknown = households::accept_invite(&docs, &owner, "NOPE").await;
+        assert!(matches!(unknown, Err(Error::InvalidInvite)));
+    }
+}
@@ -0,0 +1,639 @@
+//! iCalendar (RFC 5545) export and import of reminders.
+//!
+//! Each reminder is exported as a `VTODO`, or a `VEVENT` for calendar apps that
+//! only show events. Times are written in UTC.
+//!
+//! Imports only read `VTODO`s. Date-times with a time zone other than UTC are
+//! taken as UTC, as no time zone database is available.
+//!
+//! The app's `priority` is the reminder's position in a list the user sorts
+//! by hand, lowest first, so it maps to iCalendar's `PRIORITY` of 1 (highest)
+//! to 9 (lowest) as `priority + 1`, with everything from 8 on being 9.
+//! Imported reminders without a priority go to the end of the list.
+use crate::models::{
+    calendar::SkippedComponent,
+    generic_response::ResponseMessage,
+    recurrence::{date_time, parse_date_time, Recurrence},
+    reminder::{fix_case, Reminder},
+};
+use axum::{http::StatusCode, response::IntoResponse};
+use serde::Deserialize;
+
+/// Longest line in octets, not counting the line break.
+const MAX_LINE: usize = 75;
+
+/// Priority the app gives new reminders, which puts them at the end of the list.
+const DEFAULT_PRIORITY: u64 = 1000;
+
+/// Most components in one import.
+const MAX_COMPONENTS: usize = 1000;
+
+/// Errors that can occur when importing a calendar.
+#[derive(Debug)]
+pub enum Error {
+    NotCalendar,
+    TooMany,
+}
+
+impl std::error::Error for Error {}
+
+/// Allow Error to be displayed.
+impl std::fmt::Display for Error {
+    /// Display Error.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::NotCalendar => write!(f, "Not an iCalendar file"),
+            Error::TooMany => write!(
+                f,
+                "At most {MAX_COMPONENTS} components can be imported at once"
+            ),
+        }
+    }
+}
+
+impl std::convert::From<Error> for axum::response::Response {
+    fn from(value: Error) -> Self {
+        ResponseMessage::from(value.to_string())
+            .with_status(StatusCode::BAD_REQUEST)
+            .into_response()
+    }
+}
+
+/// Which component each reminder becomes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Component {
+    /// A to-do, due at the reminder's due date.
+    #[default]
+    Todo,
+    /// An event, starting at the reminder's due date.
+    Event,
+}
+
+/// Write reminders as an iCalendar object.
+///
+/// # Arguments
+///
+/// * `reminders` - The reminders, with their ids.
+/// * `component` - Which component each reminder becomes.
+/// * `now` - When the object was written, in seconds since the epoch.
+///
+/// # Returns
+///
+/// The calendar, with CRLF line breaks.
+pub fn export(reminders: &[Reminder], component: Component, now: u64) -> String {
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".into(),
+        concat!(
+            "PRODID:-//reminders//reminders ",
+            env!("CARGO_PKG_VERSION"),
+            "//EN"
+        )
+        .into(),
+        "CALSCALE:GREGORIAN".into(),
+        "X-WR-CALNAME:Reminders".into(),
+    ];
+    for reminder in reminders {
+        lines.extend(self::component(reminder, component, now));
+    }
+    lines.push("END:VCALENDAR".into());
+
+    lines.iter().map(|line| fold(line)).collect()
+}
+
+/// The lines of the component for one reminder.
+fn component(reminder: &Reminder, component: Component, now: u64) -> Vec<String> {
+    let name = match component {
+        Component::Todo => "VTODO",
+        Component::Event => "VEVENT",
+    };
+    let id = reminder.id.as_deref().unwrap_or_default();
+    let mut lines = vec![
+        format!("BEGIN:{name}"),
+        format!("UID:{}@reminders", escape(id)),
+        format!("DTSTAMP:{}", date_time(now)),
+        format!("SUMMARY:{}", escape(&reminder.title)),
+    ];
+
+    // A recurring to-do needs a start for its rule to count from, and a due
+    // date must come after the start, so it only gets the start.
+    match (component, &reminder.rrule) {
+        (Component::Todo, None) => lines.push(format!("DUE:{}", date_time(reminder.due))),
+        _ => lines.push(format!("DTSTART:{}", date_time(reminder.due))),
+    }
+    if let Some(rule) = &reminder.rrule {
+        lines.push(format!("RRULE:{rule}"));
+    }
+    lines.push(format!("PRIORITY:{}", priority(reminder.priority)));
+    if let Some(assignee) = reminder
+        .assignee
+        .as_deref()
+        .filter(|a| !a.trim().is_empty())
+    {
+        lines.push(attendee(assignee.trim()));
+    }
+
+    match component {
+        Component::Todo if reminder.completed => {
+            lines.push("STATUS:COMPLETED".into());
+            if let Some(completed_at) = reminder.completed_at {
+                lines.push(format!("COMPLETED:{}", date_time(completed_at)));
+            }
+        }
+        Component::Todo => lines.push("STATUS:NEEDS-ACTION".into()),
+        // Reminders should not show as busy time.
+        Component::Event => lines.push("TRANSP:TRANSPARENT".into()),
+    }
+    lines.push(format!("END:{name}"));
+
+    lines
+}
+
+/// The iCalendar `PRIORITY` of a reminder's priority.
+pub fn priority(priority: u64) -> u64 {
+    priority.saturating_add(1).min(9)
+}
+
+/// The `ATTENDEE` line for an assignee.
+///
+/// Assignees are usually names, which are given as the common name of a URN
+/// since an attendee must be a URI. Email addresses are given as `mailto:`.
+/// Control characters, which could start a new line, are left out of the name.
+fn attendee(assignee: &str) -> String {
+    let name: String = assignee
+        .chars()
+        .filter(|c| !c.is_control())
+        .map(|c| if c == '"' { '\'' } else { c })
+        .collect();
+    let plain = !assignee.contains(|c: char| c.is_whitespace() || c.is_control());
+    let address = match assignee.contains('@') && plain {
+        true => format!("mailto:{assignee}"),
+        false => format!("urn:x-reminders:assignee:{}", percent_encode(assignee)),
+    };
+
+    format!("ATTENDEE;CN=\"{name}\":{address}")
+}
+
+/// Read the reminders in the `VTODO`s of an iCalendar object.
+///
+/// # Arguments
+///
+/// * `text` - The calendar.
+/// * `now` - When completed to-dos without a completion time were completed,
+///   in seconds since the epoch.
+///
+/// # Returns
+///
+/// The reminders, without ids, and the components that were not read: other
+/// types of component, cancelled to-dos and to-dos that are missing a summary
+/// or due date or have a value that cannot be read. Time zone definitions
+/// are not reported.
+///
+/// # Errors
+///
+/// Returns `Error::NotCalendar` if there is no `VCALENDAR`, or
+/// `Error::TooMany` if it has too many components.
+pub fn import(text: &str, now: u64) -> Result<(Vec<Reminder>, Vec<SkippedComponent>), Error> {
+    let (mut reminders, mut skipped) = (Vec::new(), Vec::new());
+    let mut stack: Vec<String> = Vec::new();
+    let mut calendar = false;
+    let mut components = 0;
+    let mut current: Option<(usize, String, Vec<Property>)> = None;
+
+    for (line, text) in unfold(text) {
+        let Some(property) = Property::parse(&text) else {
+            continue;
+        };
+        match property.name.as_str() {
+            "BEGIN" => {
+                let name = property.value.trim().to_uppercase();
+                calendar |= stack.is_empty() && name == "VCALENDAR";
+                if stack.len() == 1 && name != "VTIMEZONE" {
+                    components += 1;
+                    if components > MAX_COMPONENTS {
+                        return Err(Error::TooMany);
+                    }
+                    current = Some((line, name.clone(), Vec::new()));
+                }
+                stack.push(name);
+            }
+            "END" => {
+                stack.pop();
+                if stack.len() != 1 {
+                    continue;
+                }
+                let Some((line, component, properties)) = current.take() else {
+                    continue;
+                };
+                let read = match component.as_str() {
+                    "VTODO" => reminder(&properties, now),
+                    _ => Err("Only VTODO components are imported".into()),
+                };
+                match read {
+                    Ok(reminder) => reminders.push(reminder),
+                    Err(reason) => skipped.push(SkippedComponent {
+                        line,
+                        component,
+                        uid: find(&properties, "UID").map(|p| unescape(&p.value)),
+                        summary: find(&properties, "SUMMARY").map(|p| unescape(&p.value)),
+                        reason,
+                    }),
+                }
+            }
+            // Properties of nested components, such as alarms, are ignored.
+            _ if stack.len() == 2 => {
+                if let Some((_, _, properties)) = current.as_mut() {
+                    properties.push(property);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    match calendar {
+        true => Ok((reminders, skipped)),
+        false => Err(Error::NotCalendar),
+    }
+}
+
+/// Read a reminder from the properties of a `VTODO`.
+///
+/// # Errors
+///
+/// Returns why the to-do cannot be imported.
+fn reminder(properties: &[Property], now: u64) -> Result<Reminder, String> {
+    let title = find(properties, "SUMMARY")
+        .map(|p| unescape(&p.value).trim().to_string())
+        .filter(|title| !title.is_empty())
+        .ok_or("Missing SUMMARY")?;
+
+    let status = find(properties, "STATUS").map(|p| p.value.trim().to_uppercase());
+    if status.as_deref() == Some("CANCELLED") {
+        return Err("Cancelled".into());
+    }
+
+    let due = find(properties, "DUE")
+        .or_else(|| find(properties, "DTSTART"))
+        .ok_or("Missing DUE")?;
+    let due = parse_date_time(due.value.trim())
+        .ok_or_else(|| format!("Invalid {} {}", due.name, due.value))?;
+
+    let priority = match find(properties, "PRIORITY").map(|p| p.value.trim()) {
+        None => DEFAULT_PRIORITY,
+        Some(value) => match value.parse::<u64>() {
+            Ok(0) => DEFAULT_PRIORITY,
+            Ok(priority @ 1..=9) => priority - 1,
+            _ => return Err(format!("Invalid PRIORITY {value}")),
+        },
+    };
+
+    let rrule = find(properties, "RRULE")
+        .map(|p| p.value.trim().parse::<Recurrence>())
+        .transpose()
+        .map_err(|e| format!("Unsupported RRULE: {e}"))?;
+
+    let assignee = find(properties, "ATTENDEE").and_then(|attendee| {
+        let name = attendee.param("CN").map(String::from).or_else(|| {
+            let address = attendee.value.trim();
+            address
+                .get(..7)
+                .filter(|scheme| scheme.eq_ignore_ascii_case("mailto:"))
+                .map(|_| address[7..].to_string())
+        });
+        name.filter(|name| !name.trim().is_empty())
+    });
+
+    let completed = status.as_deref() == Some("COMPLETED");
+    let completed_at = find(properties, "COMPLETED")
+        .and_then(|p| parse_date_time(p.value.trim()))
+        .or(Some(now))
+        .filter(|_| completed);
+
+    Ok(Reminder {
+        id: None,
+        title: fix_case(&title),
+        due,
+        priority,
+        assignee,
+        completed,
+        completed_at,
+        rrule,
+    })
+}
+
+/// A content line, such as `DUE;VALUE=DATE:20240131`.
+#[derive(Debug)]
+struct Property {
+    /// Name, in upper case.
+    name: String,
+    params: Vec<(String, String)>,
+    value: String,
+}
+
+impl Property {
+    /// Parse a content line.
+    ///
+    /// # Returns
+    ///
+    /// The property, or `None` if the line has no value.
+    fn parse(line: &str) -> Option<Self> {
+        let mut quoted = false;
+        let mut parts = vec![String::new()];
+        let mut chars = line.chars();
+        for c in chars.by_ref() {
+            match c {
+                '"' => quoted = !quoted,
+                ':' if !quoted => break,
+                ';' if !quoted => parts.push(String::new()),
+                c => parts.last_mut().unwrap().push(c),
+            }
+        }
+        let value: String = chars.collect();
+        if quoted || !line.contains(':') {
+            return None;
+        }
+
+        let name = parts.remove(0).trim().to_uppercase();
+        let params = parts
+            .into_iter()
+            .filter_map(|param| {
+                let (key, value) = param.split_once('=')?;
+                Some((key.trim().to_uppercase(), value.to_string()))
+            })
+            .collect();
+
+        Some(Property {
+            name,
+            params,
+            value,
+        })
+    }
+
+    /// The value of a parameter.
+    fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// The first property with the given name.
+fn find<'a>(properties: &'a [Property], name: &str) -> Option<&'a Property> {
+    properties.iter().find(|p| p.name == name)
+}
+
+/// Join folded lines back together.
+///
+/// # Returns
+///
+/// Each line, with the number of the line of the file it starts on.
+fn unfold(text: &str) -> Vec<(usize, String)> {
+    let mut lines: Vec<(usize, String)> = Vec::new();
+    for (number, line) in text.split('\n').enumerate() {
+        let line = line.strip_suffix('\r').unwrap_or(line);
+        match lines.last_mut() {
+            Some((_, last)) if line.starts_with([' ', '\t']) => last.push_str(&line[1..]),
+            _ if !line.trim().is_empty() => lines.push((number + 1, line.to_string())),
+            _ => {}
+        }
+    }
+
+    lines
+}
+
+/// Undo the escaping of a text value.
+fn unescape(text: &str) -> String {
+    let mut unescaped = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => match chars.next() {
+                Some('n' | 'N') => unescaped.push('\n'),
+                Some(c) => unescaped.push(c),
+                None => unescaped.push('\\'),
+            },
+            c => unescaped.push(c),
+        }
+    }
+
+    unescaped
+}
+
+/// Escape a text value.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace(['\n', '\r'], "\\n")
+}
+
+/// Percent-encode everything but unreserved URI characters.
+fn percent_encode(text: &str) -> String {
+    text.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (b as char).to_string()
+            }
+            b => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+/// Fold a line into lines of at most 75 octets, each continuation starting
+/// with a space, and end it with CRLF.
+fn fold(line: &str) -> String {
+    let mut folded = String::with_capacity(line.len() + 2);
+    let mut length = 0;
+    for c in line.chars() {
+        if length + c.len_utf8() > MAX_LINE {
+            folded.push_str("\r\n ");
+            length = 1;
+        }
+        folded.push(c);
+        length += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+
+    folded
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ical::{export, fold, import, Component, Error};
+    use crate::models::reminder::Reminder;
+
+    fn reminder() -> Reminder {
+        Reminder {
+            id: Some("a".into()),
+            title: "Bins, recycling; garden".into(),
+            due: 1_706_745_599,
+            priority: 0,
+            assignee: Some("Sam Kenney".into()),
+            completed: false,
+            completed_at: None,
+            rrule: None,
+        }
+    }
+
+    /// Test exporting a reminder as a to-do and as an event.
+    #[test]
+    fn test_export() {
+        let calendar = export(&[reminder()], Component::Todo, 1_700_000_000);
+        assert!(calendar.starts_with("BEGIN:VCALENDAR\r\nVERSION:2.0\r\n"));
+        assert!(calendar.ends_with("END:VTODO\r\nEND:VCALENDAR\r\n"));
+        for line in [
+            "UID:a@reminders",
+            "DTSTAMP:20231114T221320Z",
+            "SUMMARY:Bins\\, recycling\\; garden",
+            "DUE:20240131T235959Z",
+            "PRIORITY:1",
+            "ATTENDEE;CN=\"Sam Kenney\":urn:x-reminders:assignee:Sam%20Kenney",
+            "STATUS:NEEDS-ACTION",
+        ] {
+            assert!(calendar.contains(&format!("\r\n{line}\r\n")), "{line}");
+        }
+
+        let mut recurring = reminder();
+        recurring.rrule = Some("FREQ=WEEKLY;BYDAY=WE".parse().unwrap());
+        recurring.priority = 1000;
+        recurring.assignee = Some("sam@example.com".into());
+        let calendar = export(&[recurring], Component::Event, 1_700_000_000);
+        for line in [
+            "BEGIN:VEVENT",
+            "DTSTART:20240131T235959Z",
+            "RRULE:FREQ=WEEKLY;BYDAY=WE",
+            "PRIORITY:9",
+            "ATTENDEE;CN=\"sam@example.com\":mailto:sam@example.com",
+            "TRANSP:TRANSPARENT",
+        ] {
+            assert!(calendar.contains(&format!("\r\n{line}\r\n")), "{line}");
+        }
+        assert!(!calendar.contains("DUE:"));
+
+        let mut injected = reminder();
+        injected.assignee = Some("Sam\r\nX-EVIL:1\u{7f}".into());
+        let calendar = export(&[injected], Component::Todo, 1_700_000_000);
+        let line = "ATTENDEE;CN=\"SamX-EVIL:1\":urn:x-reminders:assignee:Sam%0D%0AX-EVIL%3A1%7F";
+        assert!(calendar.contains(&format!("\r\n{line}\r\n")), "{calendar}");
+        assert!(!calendar.contains("\nX-EVIL"));
+    }
+
+    /// Test that long lines are folded without splitting characters.
+    #[test]
+    fn test_fold() {
+        let line = format!("SUMMARY:{}", "é".repeat(40));
+        let folded = fold(&line);
+        let lines: Vec<&str> = folded.trim_end().split("\r\n").collect();
+
+        assert_eq!(lines.len(), 2);
+        assert!(lines.iter().all(|line| line.len() <= 75));
+        assert_eq!(lines.concat().replacen(' ', "", 1), line);
+    }
+
+    /// Test importing to-dos, and reporting the components that are skipped.
+    #[test]
+    fn test_import() {
+        let calendar = [
+            "BEGIN:VCALENDAR",
+            "VERSION:2.0",
+            "BEGIN:VTIMEZONE",
+            "TZID:Europe/London",
+            "END:VTIMEZONE",
+            "BEGIN:VTODO",
+            "UID:1",
+            "SUMMARY:bins\\, recycling and the",
+            "  garden waste",
+            "DUE;VALUE=DATE:20240131",
+            "PRIORITY:2",
+            "RRULE:FREQ=WEEKLY;BYDAY=WE",
+            "ATTENDEE;CN=\"Sam: Kenney\":mailto:sam@example.com",
+            "BEGIN:VALARM",
+            "TRIGGER:-PT15M",
+            "SUMMARY:Alarm",
+            "END:VALARM",
+            "END:VTODO",
+            "BEGIN:VTODO",
+            "SUMMARY:Rent",
+            "DUE;TZID=Europe/London:20240131T090000",
+            "STATUS:COMPLETED",
+            "END:VTODO",
+            "BEGIN:VEVENT",
+            "UID:2",
+            "SUMMARY:Party",
+            "END:VEVENT",
+            "BEGIN:VTODO",
+            "SUMMARY:Dishes",
+            "END:VTODO",
+            "BEGIN:VTODO",
+            "SUMMARY:Laundry",
+            "DUE:20240131T090000Z",
+            "RRULE:FREQ=HOURLY",
+            "END:VTODO",
+            "BEGIN:VTODO",
+            "SUMMARY:Gutters",
+            "DUE:20240131T090000Z",
+            "STATUS:CANCELLED",
+            "END:VTODO",
+            "END:VCALENDAR",
+        ]
+        .join("\r\n");
+
+        let (reminders, skipped) = import(&calendar, 1_700_000_000).unwrap();
+        assert_eq!(reminders.len(), 2);
+        assert_eq!(reminders[0].title, "Bins, recycling and the garden waste");
+        assert_eq!(reminders[0].due, 1_706_659_200);
+        assert_eq!(reminders[0].priority, 1);
+        assert_eq!(reminders[0].assignee.as_deref(), Some("Sam: Kenney"));
+        assert_eq!(
+            reminders[0].rrule.as_ref().unwrap().to_string(),
+            "FREQ=WEEKLY;BYDAY=WE"
+        );
+        assert!(!reminders[0].completed);
+        assert_eq!(reminders[1].due, 1_706_691_600);
+        assert_eq!(reminders[1].priority, 1000);
+        assert!(reminders[1].completed);
+        assert_eq!(reminders[1].completed_at, Some(1_700_000_000));
+
+        let skipped: Vec<_> = skipped
+            .iter()
+            .map(|s| (s.line, s.component.as_str(), s.summary.as_deref().unwrap()))
+            .collect();
+        assert_eq!(
+            skipped,
+            [
+                (24, "VEVENT", "Party"),
+                (28, "VTODO", "Dishes"),
+                (31, "VTODO", "Laundry"),
+                (36, "VTODO", "Gutters"),
+            ]
+        );
+
+        assert!(matches!(import("SUMMARY:Bins", 0), Err(Error::NotCalendar)));
+    }
+
+    /// Test that exported reminders import as they were.
+    #[test]
+    fn test_round_trip() {
+        let mut recurring = reminder();
+        recurring.rrule = Some("FREQ=MONTHLY;BYMONTHDAY=1".parse().unwrap());
+        let mut completed = reminder();
+        completed.complete(1_700_000_000);
+
+        let calendar = export(&[reminder(), recurring, completed], Component::Todo, 0);
+        let (reminders, skipped) = import(&calendar, 0).unwrap();
+
+        assert!(skipped.is_empty());
+        assert_eq!(reminders.len(), 3);
+        for reminder in &reminders {
+            assert_eq!(reminder.title, "Bins, recycling; garden");
+            assert_eq!(reminder.due, 1_706_745_599);
+            assert_eq!(reminder.priority, 0);
+            assert_eq!(reminder.assignee.as_deref(), Some("Sam Kenney"));
+        }
+        assert!(reminders[1].rrule.is_some());
+        assert_eq!(reminders[2].completed_at, Some(1_700_000_000));
+    }
+}
@@ -1,16 +1,24 @@
 //! Utility functions for the server.
+use crate::config::LogFormat;
 use tracing::Level;
 use tracing_subscriber::{filter, prelude::*};
 
 /// Initialise logging on inbound requests and outgoing responses.
-pub fn init() {
+///
+/// # Arguments
+///
+/// * `format` - Whether to log human readable text or one JSON object per line.
+pub fn init(format: LogFormat) {
     let filter = filter::Targets::new()
         .with_target("tower_http::trace::on_response", Level::TRACE)
         .with_target("tower_http::trace::on_request", Level::TRACE)
         .with_target("tower_http::trace::make_span", Level::DEBUG)
         .with_default(Level::INFO);
 
-    let tracing_layer = tracing_subscriber::fmt::layer();
+    let tracing_layer = match format {
+        LogFormat::Text => tracing_subscriber::fmt::layer().boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer().json().boxed(),
+    };
 
     tracing_subscriber::registry()
         .with(tracing_layer)
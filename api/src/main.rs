@@ -1,47 +1,1165 @@
 //! Main entry point for the API.
+mod accounts;
+mod api_keys;
+mod calendar;
+mod changes;
+mod config;
+mod digest;
+mod events;
 mod firebase;
+mod households;
+mod ical;
 mod logger;
 mod middleware;
 mod models;
+mod notifiers;
+mod oidc;
+mod push;
+mod random;
+mod retention;
 mod routes;
-use axum::Router;
-use firebase::Firebase;
-use std::sync::Arc;
+mod scheduler;
+mod store;
+mod time;
+mod tokens;
+mod webhooks;
+use axum::{
+    extract::DefaultBodyLimit,
+    http::{header, HeaderName, HeaderValue, Method},
+    routing::{get, post},
+    Router,
+};
+use clap::Parser;
+use config::{AuthMode, Config};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use store::Storage;
 use tokio::sync::RwLock;
+use tower_http::cors::{AllowOrigin, CorsLayer};
 
 type SharedState = Arc<RwLock<AppState>>;
 
 /// Application state.
 #[derive(Clone)]
 pub struct AppState {
-    db: Firebase,
+    config: Arc<Config>,
+    store: Arc<dyn Storage>,
+    tokens: Arc<tokens::Keys>,
+    oidc: Option<Arc<oidc::Verifier>>,
+    events: events::Bus,
+    changes: changes::Log,
 }
 
-async fn serve() -> Result<(), String> {
-    let db = Firebase::new().await.map_err(|e| e.to_string())?;
-    let state = Arc::new(RwLock::new(AppState { db }));
+/// Build the application router.
+fn app(state: AppState) -> Router {
+    let config = state.config.clone();
+    let state = Arc::new(RwLock::new(state));
 
-    let app = Router::new()
+    let mut router = Router::new()
         .fallback(routes::err_404::handle_404)
-        .route("/reminders/v2/", routes::reminders::v2::router())
-        .route_layer(axum::middleware::from_fn(middleware::auth::auth))
+        .merge(routes::reminders::v2::routes("/reminders/v2"))
+        .merge(routes::reminders::v2::routes(
+            "/households/:household_id/lists/:list_id/reminders",
+        ))
+        .merge(routes::households::router())
+        .merge(routes::admin::router())
+        .merge(routes::push::router())
+        .merge(routes::digest::router())
+        .merge(routes::webhooks::router())
+        .merge(routes::calendar::router())
+        .route("/auth/logout", post(routes::auth::logout))
+        .route("/auth/me", get(routes::auth::me))
+        .route_layer(axum::middleware::from_fn_with_state(
+            state.clone(),
+            middleware::auth::auth,
+        ));
+
+    // Calendar apps authenticate with the token in the feed's URL.
+    router = router.merge(routes::calendar::feed_router());
+
+    // Only built-in accounts can sign in with a password.
+    if config.auth.mode == AuthMode::Accounts {
+        router = router
+            .route("/auth/register", post(routes::auth::register))
+            .route("/auth/login", post(routes::auth::login))
+            .route("/auth/token", post(routes::auth::token))
+            .route("/auth/refresh", post(routes::auth::refresh))
+            .route("/auth/revoke", post(routes::auth::revoke));
+    }
+
+    let mut router = router
         .with_state(state)
-        .layer(tower_http::trace::TraceLayer::new_for_http());
+        .layer(DefaultBodyLimit::max(config.limits.max_body_bytes))
+        .layer(tower_http::timeout::TimeoutLayer::new(Duration::from_secs(
+            config.limits.request_timeout_secs,
+        )));
+    if let Some(cors) = cors(&config.server.cors_origins) {
+        router = router.layer(cors);
+    }
+
+    router.layer(tower_http::trace::TraceLayer::new_for_http())
+}
+
+/// Build the CORS layer for the allowed origins.
+///
+/// # Returns
+///
+/// The layer, or `None` if no origins are allowed.
+fn cors(origins: &[String]) -> Option<CorsLayer> {
+    let allow_origin = match origins {
+        [] => return None,
+        origins if origins.iter().any(|origin| origin == "*") => AllowOrigin::any(),
+        origins => AllowOrigin::list(
+            origins
+                .iter()
+                .filter_map(|origin| HeaderValue::from_str(origin).ok()),
+        ),
+    };
+
+    Some(
+        CorsLayer::new()
+            .allow_origin(allow_origin)
+            .allow_methods([
+                Method::GET,
+                Method::POST,
+                Method::PUT,
+                Method::PATCH,
+                Method::DELETE,
+            ])
+            .allow_headers([
+                header::AUTHORIZATION,
+                header::CONTENT_TYPE,
+                header::IF_MATCH,
+            ])
+            .expose_headers([header::ETAG, HeaderName::from_static("x-next-cursor")]),
+    )
+}
+
+/// Resolve once the process is asked to stop.
+async fn shutdown_signal() {
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        log::error!("failed to listen for shutdown signal: {e}");
+    }
+}
+
+async fn serve(config: Config) -> Result<(), String> {
+    let mut store = store::open(&config.storage).await?;
+    let events = events::Bus::default();
+    store.watch(&events);
+    let recorder = changes::Recorder::new(store.clone(), &events);
+    let changes = recorder.log();
+    recorder.spawn();
+    if config.webhooks.enabled {
+        webhooks::Dispatcher::new(store.clone(), &config.webhooks).spawn(&events);
+    }
+    // The scheduler also publishes `reminder.due` events for webhooks.
+    if config.notifications.enabled || config.webhooks.enabled {
+        let (wake, woken) = tokio::sync::mpsc::unbounded_channel();
+        let notifiers = match config.notifications.enabled {
+            true => notifiers::from_config(store.clone(), &config.notifications)?,
+            false => Vec::new(),
+        };
+        let mut scheduler =
+            scheduler::Scheduler::new(store.clone(), notifiers, config.notifications.catch_up_secs)
+                .with_events(events.clone());
+        if config.notifications.enabled {
+            scheduler = scheduler.with_digests(config.notifications.digest_hour);
+        }
+        scheduler.spawn(woken);
+        store = Arc::new(scheduler::Watched::new(store, wake));
+    }
+    if let Some(retention) = retention::window(&config.retention) {
+        retention::spawn(store.clone(), retention);
+    }
+    let state = AppState {
+        store: store.clone(),
+        tokens: Arc::new(tokens::Keys::from_config(&config.auth.jwt)?),
+        oidc: match config.auth.mode {
+            AuthMode::Oidc => oidc::Verifier::from_config(&config.auth.oidc).map(Arc::new),
+            _ => None,
+        },
+        events,
+        changes,
+        config: Arc::new(config),
+    };
 
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:9999").await.map_err(|e| e.to_string())?;
+    let address = SocketAddr::new(state.config.server.bind, state.config.server.port);
+    let listener = tokio::net::TcpListener::bind(address)
+        .await
+        .map_err(|e| format!("failed to listen on {address}: {e}"))?;
 
-    log::info!("listening on http://{}", listener.local_addr().map_err(|e| e.to_string())?);
+    log::info!(
+        "listening on http://{}",
+        listener.local_addr().map_err(|e| e.to_string())?
+    );
 
-    axum::serve(listener, app).await.map_err(|e| e.to_string())
+    axum::serve(listener, app(state))
+        .with_graceful_shutdown(shutdown_signal())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    store.shutdown().await.map_err(|e| e.to_string())
 }
 
 /// Entry point.
 #[tokio::main]
 async fn main() {
-    logger::init();
+    let config = match Config::load(config::Args::parse()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("invalid configuration: {e}");
+            std::process::exit(2);
+        }
+    };
+    logger::init(config.log.format);
 
-    if let Err(e) = serve().await {
+    if let Err(e) = serve(config).await {
         log::error!("{e}");
         std::process::exit(1);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        app, changes::Recorder, config::Config, events::Bus, store::memory::Memory, tokens::Keys,
+        AppState,
+    };
+    use axum::{
+        body::Body,
+        http::{header, Method, Request, StatusCode},
+    };
+    use futures_util::{SinkExt, StreamExt};
+    use std::sync::Arc;
+    use tower::ServiceExt;
+
+    const TOKEN: &str = "test-token";
+
+    /// Build a router backed by an empty in-memory store.
+    fn test_app() -> axum::Router {
+        test_app_with(Config::default())
+    }
+
+    /// Build a router with the given configuration, backed by an empty in-memory store.
+    fn test_app_with(mut config: Config) -> axum::Router {
+        config.auth.shared_token = Some(TOKEN.into());
+        let store = Arc::new(Memory::default());
+        let events = Bus::default();
+        let recorder = Recorder::new(store.clone(), &events);
+        let changes = recorder.log();
+        recorder.spawn();
+
+        app(AppState {
+            config: Arc::new(config),
+            store,
+            tokens: Arc::new(Keys::hs256(b"an hs256 secret that is long enough", 60)),
+            oidc: None,
+            events,
+            changes,
+        })
+    }
+
+    /// Send a request with the shared secret and return the status and JSON body.
+    async fn send(
+        app: &axum::Router,
+        method: Method,
+        uri: &str,
+        body: Option<serde_json::Value>,
+    ) -> (StatusCode, serde_json::Value) {
+        send_as(app, TOKEN, method, uri, body).await
+    }
+
+    /// Send a request with the given bearer token and return the status and JSON body.
+    async fn send_as(
+        app: &axum::Router,
+        token: &str,
+        method: Method,
+        uri: &str,
+        body: Option<serde_json::Value>,
+    ) -> (StatusCode, serde_json::Value) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(header::AUTHORIZATION, format!("Bearer {token}"))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(body.map_or_else(Body::empty, |b| Body::from(b.to_string())))
+            .unwrap();
+
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+
+        (status, serde_json::from_slice(&bytes).unwrap_or_default())
+    }
+
+    /// Register and log in a user, returning their bearer token.
+    async fn login(app: &axum::Router, username: &str) -> String {
+        let credentials = serde_json::json!({
+            "username": username, "password": "correct horse"
+        });
+        send_as(
+            app,
+            "",
+            Method::POST,
+            "/auth/register",
+            Some(credentials.clone()),
+        )
+        .await;
+        let (_, body) = send_as(app, "", Method::POST, "/auth/login", Some(credentials)).await;
+
+        body["token"].as_str().unwrap().to_string()
+    }
+
+    /// Test the create, list, update and delete flow used by the Flutter client.
+    #[tokio::test]
+    async fn test_reminder_lifecycle() {
+        let app = test_app();
+        let reminder = serde_json::json!({
+            "id": null, "title": "bins", "due": 1234, "priority": 0, "assignee": null
+        });
+
+        let (status, _) = send(&app, Method::POST, "/reminders/v2/", Some(reminder)).await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        let (status, body) = send(&app, Method::GET, "/reminders/v2/", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body[0]["title"], "Bins");
+
+        let mut updated = body[0].clone();
+        updated["due"] = 5678.into();
+        let (status, _) = send(&app, Method::PUT, "/reminders/v2/", Some(updated.clone())).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (_, body) = send(&app, Method::GET, "/reminders/v2/", None).await;
+        assert_eq!(body[0]["due"], 5678);
+
+        let (status, _) = send(&app, Method::DELETE, "/reminders/v2/", Some(updated)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (_, body) = send(&app, Method::GET, "/reminders/v2/", None).await;
+        assert_eq!(body, serde_json::json!([]));
+    }
+
+    /// Test that a change is pushed to a stream of the same collection.
+    #[tokio::test]
+    async fn test_stream() {
+        let app = test_app();
+        let request = Request::builder()
+            .uri("/reminders/v2/stream")
+            .header(header::AUTHORIZATION, format!("Bearer {TOKEN}"))
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/event-stream"
+        );
+        let mut body = response.into_body().into_data_stream();
+
+        let reminder = serde_json::json!({
+            "title": "bins", "due": 1, "priority": 0, "assignee": null
+        });
+        send(&app, Method::POST, "/reminders/v2/", Some(reminder)).await;
+
+        let chunk = body.next().await.unwrap().unwrap();
+        let event = String::from_utf8_lossy(&chunk);
+        assert!(event.contains("event: created\n"));
+        assert!(event.contains(r#""title":"Bins""#));
+    }
+
+    /// Test that a stream ends once the API key it was opened with is revoked.
+    #[tokio::test(start_paused = true)]
+    async fn test_stream_revoked() {
+        let app = test_app();
+        let new = serde_json::json!({ "name": "dashboard", "scopes": ["reminders:read"] });
+        let (_, body) = send(&app, Method::POST, "/admin/api-keys", Some(new)).await;
+        let key = body["key"].as_str().unwrap();
+        let uri = format!("/admin/api-keys/{}", body["id"].as_str().unwrap());
+        let request = Request::builder()
+            .uri("/reminders/v2/stream")
+            .header(header::AUTHORIZATION, format!("Bearer {key}"))
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let mut body = response.into_body().into_data_stream();
+
+        let (status, _) = send(&app, Method::DELETE, &uri, None).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // Keep-alive comments may come first, but the stream ends at the next check.
+        let mut chunks = 0;
+        while body.next().await.is_some() {
+            chunks += 1;
+            assert!(chunks < 100, "stream did not end");
+        }
+    }
+
+    /// Get a calendar, with or without the shared secret.
+    async fn get_calendar(app: &axum::Router, uri: &str, bearer: bool) -> (StatusCode, String) {
+        let mut request = Request::builder().uri(uri);
+        if bearer {
+            request = request.header(header::AUTHORIZATION, format!("Bearer {TOKEN}"));
+        }
+        let response = app
+            .clone()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+
+        (status, String::from_utf8_lossy(&bytes).into())
+    }
+
+    /// Test exporting the reminders as iCalendar, and reading them from a
+    /// calendar feed without a bearer token.
+    #[tokio::test]
+    async fn test_calendar() {
+        let app = test_app();
+        let reminder = serde_json::json!({
+            "title": "bins", "due": 1_706_745_599, "priority": 0, "assignee": null
+        });
+        send(&app, Method::POST, "/reminders/v2/", Some(reminder)).await;
+
+        let (status, calendar) = get_calendar(&app, "/reminders/v2/export.ics", true).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(calendar.contains("BEGIN:VTODO\r\n"));
+        assert!(calendar.contains("SUMMARY:Bins\r\nDUE:20240131T235959Z\r\n"));
+        let (status, _) = get_calendar(&app, "/reminders/v2/export.ics", false).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let (status, feed) = send(&app, Method::POST, "/calendar/feed", None).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let url = format!("{}?component=event", feed["url"].as_str().unwrap());
+        let (status, calendar) = get_calendar(&app, &url, false).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(calendar.contains("BEGIN:VEVENT\r\n"));
+
+        let (status, _) = send(&app, Method::DELETE, "/calendar/feed", None).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = get_calendar(&app, &url, false).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    /// Test previewing and then importing an iCalendar file.
+    #[tokio::test]
+    async fn test_import() {
+        let app = test_app();
+        let calendar = [
+            "BEGIN:VCALENDAR",
+            "BEGIN:VTODO",
+            "SUMMARY:bins",
+            "DUE:20240131T235959Z",
+            "END:VTODO",
+            "BEGIN:VEVENT",
+            "SUMMARY:Party",
+            "END:VEVENT",
+            "END:VCALENDAR",
+        ]
+        .join("\r\n");
+        let import = |uri: &str| {
+            let request = Request::builder()
+                .method(Method::POST)
+                .uri(uri)
+                .header(header::AUTHORIZATION, format!("Bearer {TOKEN}"))
+                .header(header::CONTENT_TYPE, "text/calendar")
+                .body(Body::from(calendar.clone()))
+                .unwrap();
+            app.clone().oneshot(request)
+        };
+        let json = |response: axum::response::Response| async move {
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            serde_json::from_slice::<serde_json::Value>(&bytes).unwrap()
+        };
+
+        let response = import("/reminders/v2/import?dry_run=true").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = json(response).await;
+        assert_eq!(body["dry_run"], true);
+        assert_eq!(body["reminders"][0]["title"], "Bins");
+        assert_eq!(body["skipped"][0]["component"], "VEVENT");
+        let (_, listed) = send(&app, Method::GET, "/reminders/v2/", None).await;
+        assert_eq!(listed.as_array().unwrap().len(), 0);
+
+        let response = import("/reminders/v2/import").await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let body = json(response).await;
+        assert!(body["reminders"][0]["id"].is_string());
+        let (_, listed) = send(&app, Method::GET, "/reminders/v2/", None).await;
+        assert_eq!(listed[0]["due"], 1_706_745_599);
+    }
+
+    type Socket = tokio_tungstenite::WebSocketStream<
+        tokio_tungstenite::MaybeTlsStream<tokio::net::TcpStream>,
+    >;
+
+    /// Open a WebSocket to the reminders of the server at `address`.
+    async fn connect(address: std::net::SocketAddr, query: &str) -> Socket {
+        connect_as(address, TOKEN, query).await
+    }
+
+    /// Open a WebSocket to the reminders with the given bearer token.
+    async fn connect_as(address: std::net::SocketAddr, token: &str, query: &str) -> Socket {
+        use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+
+        let mut request = format!("ws://{address}/reminders/v2/ws{query}")
+            .into_client_request()
+            .unwrap();
+        let bearer = format!("Bearer {token}").parse().unwrap();
+        request.headers_mut().insert(header::AUTHORIZATION, bearer);
+
+        tokio_tungstenite::connect_async(request).await.unwrap().0
+    }
+
+    /// Send a JSON message over a WebSocket.
+    async fn send_json(socket: &mut Socket, message: serde_json::Value) {
+        use tokio_tungstenite::tungstenite::Message;
+
+        socket
+            .send(Message::Text(message.to_string()))
+            .await
+            .unwrap();
+    }
+
+    /// Receive the next JSON message over a WebSocket.
+    async fn next_json(socket: &mut Socket) -> serde_json::Value {
+        use tokio_tungstenite::tungstenite::Message;
+
+        match socket.next().await.unwrap().unwrap() {
+            Message::Text(text) => serde_json::from_str(&text).unwrap(),
+            message => panic!("unexpected message {message:?}"),
+        }
+    }
+
+    /// Test editing over a WebSocket, then resuming from the last change seen.
+    #[tokio::test]
+    async fn test_ws() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, test_app()).await.unwrap() });
+
+        let mut socket = connect(address, "").await;
+        let ready = next_json(&mut socket).await;
+        assert_eq!(ready["type"], "ready");
+
+        let reminder =
+            serde_json::json!({"title": "bins", "due": 1, "priority": 0, "assignee": null});
+        send_json(
+            &mut socket,
+            serde_json::json!({"type": "create", "request_id": "1", "reminder": reminder}),
+        )
+        .await;
+        let ack = next_json(&mut socket).await;
+        assert_eq!(ack["type"], "ack");
+        assert_eq!(ack["request_id"], "1");
+        assert_eq!(ack["status"], 201);
+        let change = next_json(&mut socket).await;
+        assert_eq!(change["type"], "change");
+        assert_eq!(change["change"], "created");
+        assert_eq!(change["reminder"]["title"], "Bins");
+        assert_eq!(change["reminder"]["id"], ack["id"]);
+
+        send_json(
+            &mut socket,
+            serde_json::json!({"type": "delete", "request_id": "2", "id": ack["id"], "if_match": "stale"}),
+        )
+        .await;
+        let ack = next_json(&mut socket).await;
+        assert_eq!(ack["request_id"], "2");
+        assert_eq!(ack["status"], 412);
+
+        send_json(
+            &mut socket,
+            serde_json::json!({"type": "rename", "request_id": "3"}),
+        )
+        .await;
+        let ack = next_json(&mut socket).await;
+        assert_eq!(ack["request_id"], "3");
+        assert_eq!(ack["status"], 400);
+        socket.close(None).await.unwrap();
+
+        let mut socket = connect(address, &format!("?since={}", ready["seq"])).await;
+        assert_eq!(next_json(&mut socket).await, change);
+        let ready = next_json(&mut socket).await;
+        assert_eq!(ready["type"], "ready");
+        assert_eq!(ready["seq"], change["seq"]);
+
+        let mut socket = connect(address, "?since=1").await;
+        assert_eq!(next_json(&mut socket).await["type"], "resync");
+        assert_eq!(next_json(&mut socket).await["type"], "ready");
+    }
+
+    /// Test that an API key needs the write scope to send commands over a
+    /// WebSocket, even though reading is enough to open it.
+    #[tokio::test]
+    async fn test_ws_read_only_key() {
+        let app = test_app();
+        let new = serde_json::json!({ "name": "cron", "scopes": ["reminders:read"] });
+        let (_, body) = send(&app, Method::POST, "/admin/api-keys", Some(new)).await;
+        let key = body["key"].as_str().unwrap().to_string();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let mut socket = connect_as(address, &key, "").await;
+        assert_eq!(next_json(&mut socket).await["type"], "ready");
+        let reminder =
+            serde_json::json!({"title": "bins", "due": 1, "priority": 0, "assignee": null});
+        send_json(
+            &mut socket,
+            serde_json::json!({"type": "create", "request_id": "1", "reminder": reminder}),
+        )
+        .await;
+        let ack = next_json(&mut socket).await;
+        assert_eq!(ack["request_id"], "1");
+        assert_eq!(ack["status"], 403);
+
+        let mut socket = connect(address, "").await;
+        next_json(&mut socket).await;
+        send_json(
+            &mut socket,
+            serde_json::json!({"type": "delete", "request_id": "2", "id": "x"}),
+        )
+        .await;
+        assert_eq!(next_json(&mut socket).await["status"], 404);
+    }
+
+    /// Test that a WebSocket is closed once the session it was opened with ends.
+    #[tokio::test(start_paused = true)]
+    async fn test_ws_logged_out() {
+        use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+        use tokio_tungstenite::tungstenite::Message;
+
+        let app = test_app();
+        let sam = login(&app, "sam").await;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = app.clone();
+        tokio::spawn(async move { axum::serve(listener, server).await.unwrap() });
+
+        let mut socket = connect_as(address, &sam, "").await;
+        assert_eq!(next_json(&mut socket).await["type"], "ready");
+        let (status, _) = send_as(&app, &sam, Method::POST, "/auth/logout", None).await;
+        assert_eq!(status, StatusCode::OK);
+
+        match socket.next().await.unwrap().unwrap() {
+            Message::Close(Some(frame)) => assert_eq!(frame.code, CloseCode::Policy),
+            message => panic!("unexpected message {message:?}"),
+        }
+    }
+
+    /// Test the resource routes that take the id in the path.
+    #[tokio::test]
+    async fn test_reminder_by_id() {
+        let app = test_app();
+        let reminder = serde_json::json!({
+            "title": "Rent", "due": 1, "priority": 0, "assignee": "Sam"
+        });
+        send(&app, Method::POST, "/reminders/v2/", Some(reminder.clone())).await;
+        let (_, body) = send(&app, Method::GET, "/reminders/v2/", None).await;
+        let uri = format!("/reminders/v2/{}", body[0]["id"].as_str().unwrap());
+
+        let (status, body) = send(&app, Method::GET, &uri, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["assignee"], "Sam");
+
+        let (status, _) = send(&app, Method::PUT, &uri, Some(reminder)).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, _) = send(&app, Method::DELETE, &uri, None).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, _) = send(&app, Method::GET, &uri, None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, _) = send(&app, Method::DELETE, &uri, None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+    }
+
+    /// Test that ids that could reach outside a collection are rejected, in the
+    /// path and in bodies.
+    #[tokio::test]
+    async fn test_invalid_ids() {
+        let app = test_app();
+        let reminder = |id: &str| {
+            serde_json::json!({
+                "id": id, "title": "Rent", "due": 1, "priority": 0, "assignee": null
+            })
+        };
+
+        for id in ["%2E%2E", "a%2Fb", "..%2F..%2Fdocs"] {
+            let uri = format!("/reminders/v2/{id}");
+            let (status, _) = send(&app, Method::GET, &uri, None).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            let (status, _) = send(&app, Method::DELETE, &uri, None).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+        }
+
+        for id in ["..", "a/b", "../../docs/accounts"] {
+            let (status, _) =
+                send(&app, Method::DELETE, "/reminders/v2/", Some(reminder(id))).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            let (status, _) = send(&app, Method::PUT, "/reminders/v2/", Some(reminder(id))).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+            let body = serde_json::json!([reminder(id)]);
+            let (status, _) = send(&app, Method::PATCH, "/reminders/v2/", Some(body)).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+
+            let batch = serde_json::json!({"operations": [
+                {"op": "create", "client_id": id, "reminder": reminder("x"), "timestamp": 1},
+            ]});
+            let (status, _) = send(&app, Method::POST, "/reminders/v2/sync", Some(batch)).await;
+            assert_eq!(status, StatusCode::BAD_REQUEST);
+        }
+    }
+
+    /// Test getting the changes since a sequence number, and syncing edits
+    /// made offline.
+    #[tokio::test]
+    async fn test_changes() {
+        let app = test_app();
+        let reminder = serde_json::json!({
+            "title": "rent", "due": 1, "priority": 0, "assignee": null
+        });
+        send(&app, Method::POST, "/reminders/v2/", Some(reminder.clone())).await;
+
+        let (status, body) = send(&app, Method::GET, "/reminders/v2/changes", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["complete"], true);
+        assert_eq!(body["changes"][0]["reminder"]["title"], "Rent");
+        let id = body["changes"][0]["id"].as_str().unwrap().to_string();
+        let seq = body["seq"].clone();
+
+        send(&app, Method::DELETE, &format!("/reminders/v2/{id}"), None).await;
+        let uri = format!("/reminders/v2/changes?since={seq}");
+        let (_, body) = send(&app, Method::GET, &uri, None).await;
+        assert_eq!(body["complete"], false);
+        assert_eq!(body["changes"][0]["id"], id.as_str());
+        assert_eq!(body["changes"][0]["deleted"], true);
+        let seq = body["seq"].clone();
+
+        let batch = serde_json::json!({"since": seq, "operations": [
+            {"op": "create", "client_id": "c1", "reminder": reminder, "timestamp": 1},
+            {"op": "update", "id": id, "reminder": reminder, "timestamp": 2},
+        ]});
+        let (status, body) = send(&app, Method::POST, "/reminders/v2/sync", Some(batch)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["results"][0]["status"], "applied");
+        assert_eq!(body["results"][1]["status"], "conflict");
+        assert_eq!(body["complete"], false);
+        assert_eq!(body["changes"][0]["id"], body["results"][0]["id"]);
+    }
+
+    /// Test that a bulk update only touches the listed reminders, and writes
+    /// none of those whose id is listed twice.
+    #[tokio::test]
+    async fn test_patch_is_non_destructive() {
+        let app = test_app();
+        for title in ["Bins", "Rent"] {
+            let reminder = serde_json::json!({
+                "title": title, "due": 1, "priority": 0, "assignee": null
+            });
+            send(&app, Method::POST, "/reminders/v2/", Some(reminder)).await;
+        }
+        let (_, body) = send(&app, Method::GET, "/reminders/v2/", None).await;
+        let mut first = body[0].clone();
+        first["priority"] = 3.into();
+
+        let missing_id = serde_json::json!([first, {
+            "title": "Dog", "due": 1, "priority": 0, "assignee": null
+        }]);
+        let (status, _) = send(&app, Method::PATCH, "/reminders/v2/", Some(missing_id)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, body) = send(
+            &app,
+            Method::PATCH,
+            "/reminders/v2/",
+            Some(serde_json::json!([first])),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["results"][0]["status"], "updated");
+
+        let (_, body) = send(&app, Method::GET, "/reminders/v2/", None).await;
+        assert_eq!(body.as_array().unwrap().len(), 2);
+        assert_eq!(body[0]["priority"], 3);
+
+        let mut again = first.clone();
+        again["priority"] = 5.into();
+        let second = body[1].clone();
+        let duplicated = serde_json::json!([again, second, first]);
+        let (status, body) = send(&app, Method::PATCH, "/reminders/v2/", Some(duplicated)).await;
+        assert_eq!(status, StatusCode::OK);
+        let statuses: Vec<_> = body["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|result| result["status"].as_str().unwrap())
+            .collect();
+        assert_eq!(statuses, ["duplicate", "updated", "duplicate"]);
+        assert_eq!(body["results"][0]["id"], first["id"]);
+
+        let (_, body) = send(&app, Method::GET, "/reminders/v2/", None).await;
+        assert_eq!(body[0]["priority"], 3);
+    }
+
+    /// Test that a stale `If-Match` header is rejected with a 412.
+    #[tokio::test]
+    async fn test_if_match() {
+        let app = test_app();
+        let reminder = serde_json::json!({
+            "title": "Rent", "due": 1, "priority": 0, "assignee": null
+        });
+        send(&app, Method::POST, "/reminders/v2/", Some(reminder.clone())).await;
+        let (_, body) = send(&app, Method::GET, "/reminders/v2/", None).await;
+        let uri = format!("/reminders/v2/{}", body[0]["id"].as_str().unwrap());
+
+        let request = Request::builder()
+            .uri(&uri)
+            .header(header::AUTHORIZATION, format!("Bearer {TOKEN}"))
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        let etag = response.headers()[header::ETAG].clone();
+
+        let conditional_put = |due: u64| {
+            let mut body = reminder.clone();
+            body["due"] = due.into();
+            Request::builder()
+                .method(Method::PUT)
+                .uri(&uri)
+                .header(header::AUTHORIZATION, format!("Bearer {TOKEN}"))
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(header::IF_MATCH, etag.clone())
+                .body(Body::from(body.to_string()))
+                .unwrap()
+        };
+
+        let response = app.clone().oneshot(conditional_put(2)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = app.clone().oneshot(conditional_put(3)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PRECONDITION_FAILED);
+    }
+
+    /// Test that completed reminders are hidden from the default list.
+    #[tokio::test]
+    async fn test_complete() {
+        let app = test_app();
+        let reminder = serde_json::json!({
+            "title": "Rent", "due": 1, "priority": 0, "assignee": null
+        });
+        send(&app, Method::POST, "/reminders/v2/", Some(reminder)).await;
+        let (_, body) = send(&app, Method::GET, "/reminders/v2/", None).await;
+        let uri = format!("/reminders/v2/{}", body[0]["id"].as_str().unwrap());
+
+        let (status, _) = send(&app, Method::POST, &format!("{uri}/complete"), None).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (_, body) = send(&app, Method::GET, "/reminders/v2/", None).await;
+        assert_eq!(body, serde_json::json!([]));
+
+        let (_, body) = send(&app, Method::GET, "/reminders/v2/?status=completed", None).await;
+        assert_eq!(body[0]["completed"], true);
+        assert!(body[0]["completed_at"].is_u64());
+
+        let (status, _) = send(&app, Method::POST, &format!("{uri}/uncomplete"), None).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (_, body) = send(&app, Method::GET, "/reminders/v2/", None).await;
+        assert_eq!(body.as_array().unwrap().len(), 1);
+    }
+
+    /// Test that each account only sees its own reminders.
+    #[tokio::test]
+    async fn test_accounts() {
+        let app = test_app();
+        let mut tokens = vec![];
+        for username in ["sam", "alex"] {
+            let credentials = serde_json::json!({
+                "username": username, "password": "correct horse"
+            });
+            let (status, body) = send_as(
+                &app,
+                "",
+                Method::POST,
+                "/auth/register",
+                Some(credentials.clone()),
+            )
+            .await;
+            assert_eq!(status, StatusCode::CREATED);
+            assert_eq!(body["username"], username);
+
+            let (status, body) =
+                send_as(&app, "", Method::POST, "/auth/login", Some(credentials)).await;
+            assert_eq!(status, StatusCode::OK);
+            tokens.push(body["token"].as_str().unwrap().to_string());
+        }
+        let (sam, alex) = (&tokens[0], &tokens[1]);
+
+        let (_, body) = send_as(&app, sam, Method::GET, "/auth/me", None).await;
+        assert_eq!(body["username"], "sam");
+
+        let reminder = serde_json::json!({
+            "title": "Bins", "due": 1, "priority": 0, "assignee": null
+        });
+        let (status, _) = send_as(&app, sam, Method::POST, "/reminders/v2/", Some(reminder)).await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        let (_, body) = send_as(&app, sam, Method::GET, "/reminders/v2/", None).await;
+        assert_eq!(body.as_array().unwrap().len(), 1);
+        let uri = format!("/reminders/v2/{}", body[0]["id"].as_str().unwrap());
+
+        let (_, body) = send_as(&app, alex, Method::GET, "/reminders/v2/", None).await;
+        assert_eq!(body, serde_json::json!([]));
+        let (status, _) = send_as(&app, alex, Method::DELETE, &uri, None).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (_, body) = send(&app, Method::GET, "/reminders/v2/", None).await;
+        assert_eq!(body, serde_json::json!([]));
+
+        let (status, _) = send_as(&app, sam, Method::POST, "/auth/logout", None).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send_as(&app, sam, Method::GET, "/reminders/v2/", None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    /// Test that requests without the shared secret are rejected.
+    #[tokio::test]
+    async fn test_unauthorized() {
+        let request = Request::builder()
+            .uri("/reminders/v2/")
+            .body(Body::empty())
+            .unwrap();
+
+        let response = test_app().oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    /// Test sharing a household list between members with different roles.
+    #[tokio::test]
+    async fn test_households() {
+        let app = test_app();
+        let (sam, alex, kim) = (
+            login(&app, "sam").await,
+            login(&app, "alex").await,
+            login(&app, "kim").await,
+        );
+
+        let name = serde_json::json!({ "name": "Home" });
+        let (status, body) = send_as(&app, &sam, Method::POST, "/households", Some(name)).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let household = format!("/households/{}", body["id"].as_str().unwrap());
+
+        let name = serde_json::json!({ "name": "Chores" });
+        let uri = format!("{household}/lists");
+        let (status, body) = send_as(&app, &sam, Method::POST, &uri, Some(name)).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let list = format!(
+            "{household}/lists/{}/reminders/",
+            body["id"].as_str().unwrap()
+        );
+
+        let role = serde_json::json!({ "role": "viewer" });
+        let uri = format!("{household}/invites");
+        let (status, body) = send_as(&app, &sam, Method::POST, &uri, Some(role)).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let accept = format!("/invites/{}/accept", body["code"].as_str().unwrap());
+        let (status, body) = send_as(&app, &alex, Method::POST, &accept, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["role"], "viewer");
+        let (status, _) = send_as(&app, &kim, Method::POST, &accept, None).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let reminder = serde_json::json!({
+            "title": "Bins", "due": 1, "priority": 0, "assignee": null
+        });
+        let (status, _) = send_as(&app, &sam, Method::POST, &list, Some(reminder.clone())).await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        let (status, body) = send_as(&app, &alex, Method::GET, &list, None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body[0]["title"], "Bins");
+        let (status, _) = send_as(&app, &alex, Method::POST, &list, Some(reminder.clone())).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = send_as(&app, &kim, Method::GET, &list, None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (_, body) = send_as(&app, &alex, Method::GET, "/households", None).await;
+        assert_eq!(body[0]["name"], "Home");
+        let (_, body) = send_as(&app, &alex, Method::GET, "/auth/me", None).await;
+        let uri = format!("{household}/members/{}", body["id"].as_str().unwrap());
+        let role = serde_json::json!({ "role": "editor" });
+        let (status, _) = send_as(&app, &sam, Method::PUT, &uri, Some(role)).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send_as(&app, &alex, Method::POST, &list, Some(reminder)).await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        let (_, body) = send_as(&app, &sam, Method::GET, &list, None).await;
+        assert_eq!(body.as_array().unwrap().len(), 2);
+        let (_, body) = send_as(&app, &sam, Method::GET, "/reminders/v2/", None).await;
+        assert_eq!(body, serde_json::json!([]));
+
+        let (_, body) = send_as(&app, &sam, Method::GET, &household, None).await;
+        assert_eq!(body["members"].as_array().unwrap().len(), 2);
+        assert_eq!(body["role"], "owner");
+    }
+
+    /// Test logging in for JWT access tokens and rotating refresh tokens.
+    #[tokio::test]
+    async fn test_tokens() {
+        let app = test_app();
+        login(&app, "sam").await;
+
+        let credentials = serde_json::json!({
+            "username": "sam", "password": "correct horse"
+        });
+        let (status, body) =
+            send_as(&app, "", Method::POST, "/auth/token", Some(credentials)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["token_type"], "Bearer");
+        let access = body["access_token"].as_str().unwrap().to_string();
+        let refresh = serde_json::json!({ "refresh_token": body["refresh_token"] });
+
+        let (status, body) = send_as(&app, &access, Method::GET, "/auth/me", None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["username"], "sam");
+        let (status, _) = send_as(&app, &format!("{access}x"), Method::GET, "/auth/me", None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let (status, body) = send_as(
+            &app,
+            "",
+            Method::POST,
+            "/auth/refresh",
+            Some(refresh.clone()),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        let rotated = serde_json::json!({ "refresh_token": body["refresh_token"] });
+        let access = body["access_token"].as_str().unwrap().to_string();
+        let (status, _) = send_as(&app, &access, Method::GET, "/reminders/v2/", None).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let (status, _) = send_as(&app, "", Method::POST, "/auth/refresh", Some(refresh)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        let (status, _) = send_as(&app, "", Method::POST, "/auth/refresh", Some(rotated)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+        let wrong = serde_json::json!({ "username": "sam", "password": "wrong password" });
+        let (status, _) = send_as(&app, "", Method::POST, "/auth/token", Some(wrong)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    /// Test that API keys can only call the routes their scopes allow.
+    #[tokio::test]
+    async fn test_api_keys() {
+        let app = test_app();
+        let sam = login(&app, "sam").await;
+
+        let new = serde_json::json!({ "name": "cron", "scopes": ["reminders:read"] });
+        let (status, _) = send_as(
+            &app,
+            &sam,
+            Method::POST,
+            "/admin/api-keys",
+            Some(new.clone()),
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, body) = send(&app, Method::POST, "/admin/api-keys", Some(new)).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let read = body["key"].as_str().unwrap().to_string();
+        let read_id = body["id"].as_str().unwrap().to_string();
+
+        let new = serde_json::json!({ "name": "automation", "scopes": ["reminders:write"] });
+        let (_, body) = send(&app, Method::POST, "/admin/api-keys", Some(new)).await;
+        let write = body["key"].as_str().unwrap().to_string();
+
+        let reminder = serde_json::json!({
+            "title": "Bins", "due": 1, "priority": 0, "assignee": null
+        });
+        let (status, _) = send_as(&app, &read, Method::GET, "/reminders/v2/", None).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send_as(
+            &app,
+            &read,
+            Method::POST,
+            "/reminders/v2/",
+            Some(reminder.clone()),
+        )
+        .await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) =
+            send_as(&app, &write, Method::POST, "/reminders/v2/", Some(reminder)).await;
+        assert_eq!(status, StatusCode::CREATED);
+        let (status, _) = send_as(&app, &write, Method::GET, "/reminders/v2/", None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        let (status, _) = send_as(&app, &read, Method::GET, "/auth/me", None).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+
+        let (_, body) = send(&app, Method::GET, "/reminders/v2/", None).await;
+        assert_eq!(body[0]["title"], "Bins");
+
+        let (_, body) = send(&app, Method::GET, "/admin/api-keys", None).await;
+        assert_eq!(body.as_array().unwrap().len(), 2);
+        assert!(body[0].get("key").is_none());
+        assert!(body[0].get("hash").is_none());
+
+        let uri = format!("/admin/api-keys/{read_id}");
+        let (status, _) = send(&app, Method::DELETE, &uri, None).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send_as(&app, &read, Method::GET, "/reminders/v2/", None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+
+    /// Test that the configuration decides which routes, tokens and bodies are accepted.
+    #[tokio::test]
+    async fn test_config() {
+        let mut config = Config::default();
+        config.set("auth.mode", "shared").unwrap();
+        config.set("limits.max_body_bytes", "64").unwrap();
+        config
+            .set("server.cors_origins", "https://app.example.com")
+            .unwrap();
+        let app = test_app_with(config);
+
+        let credentials = serde_json::json!({
+            "username": "sam", "password": "correct horse"
+        });
+        let (status, _) =
+            send_as(&app, "", Method::POST, "/auth/register", Some(credentials)).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, _) = send(&app, Method::GET, "/reminders/v2/", None).await;
+        assert_eq!(status, StatusCode::OK);
+
+        let reminder = serde_json::json!({
+            "title": "x".repeat(64), "due": 1, "priority": 0, "assignee": null
+        });
+        let (status, _) = send(&app, Method::POST, "/reminders/v2/", Some(reminder)).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+
+        let request = Request::builder()
+            .method(Method::OPTIONS)
+            .uri("/reminders/v2/")
+            .header(header::ORIGIN, "https://app.example.com")
+            .header(header::ACCESS_CONTROL_REQUEST_METHOD, "PUT")
+            .body(Body::empty())
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        assert_eq!(
+            response.headers()[header::ACCESS_CONTROL_ALLOW_ORIGIN],
+            "https://app.example.com"
+        );
+    }
+}
@@ -1,32 +1,264 @@
-//! Basic authentication middleware using a shared secret.
-use axum::{extract::Request, http, http::StatusCode, middleware::Next, response::Response};
+//! Authentication middleware and the authenticated user extractor.
+//!
+//! Requests are authenticated with a bearer token. Which tokens are accepted
+//! depends on the configured [`AuthMode`]:
+//!
+//! * In every mode, the shared token acts as a built-in user owning the shared
+//!   reminders collection.
+//! * In accounts mode, so do JWT access tokens issued by `POST /auth/token` or
+//!   `POST /auth/refresh`, session tokens issued by `POST /auth/login`, and API keys.
+//! * In OIDC mode, so do ID tokens from the OpenID Connect provider, and API keys.
+//!
+//! API keys may only call the routes their scopes allow.
+use crate::{
+    accounts, api_keys,
+    config::AuthMode,
+    models::{api_key::Scope, user::User},
+    oidc, SharedState,
+};
+use async_trait::async_trait;
+use axum::{
+    extract::{FromRequestParts, MatchedPath, Request, State},
+    http::{self, request::Parts, HeaderMap, StatusCode},
+    middleware::Next,
+    response::Response,
+};
+use sha2::{Digest, Sha256};
 
-/// Ensure a request contains the shared secret.
+/// Id of the built-in user authenticated by `AUTH_TOKEN`.
+pub const SHARED_USER: &str = "shared";
+
+/// The user a request is authenticated as.
 ///
-/// # Returns
+/// Added to the request by [`auth`], and extracted by handlers that need it.
+#[derive(Debug, Clone)]
+pub struct AuthUser {
+    pub id: String,
+    pub username: String,
+}
+
+impl AuthUser {
+    /// The built-in user authenticated by `AUTH_TOKEN`.
+    fn shared() -> Self {
+        AuthUser {
+            id: SHARED_USER.into(),
+            username: SHARED_USER.into(),
+        }
+    }
+
+    /// The reminders scope of the user.
+    pub fn scope(&self) -> String {
+        match self.id.as_str() {
+            SHARED_USER => String::new(),
+            id => accounts::scope(id),
+        }
+    }
+}
+
+impl AuthUser {
+    /// Whether the user may manage the API.
+    ///
+    /// The built-in shared user is always an admin, as are the users named in
+    /// `admin_users` by user id or username. Users created for OIDC identities
+    /// never get a listed username, so only their ids can make them admins.
+    pub fn is_admin(&self, admin_users: &[String]) -> bool {
+        self.id == SHARED_USER
+            || admin_users
+                .iter()
+                .any(|admin| *admin == self.id || admin.eq_ignore_ascii_case(&self.username))
+    }
+}
+
+impl From<User> for AuthUser {
+    fn from(user: User) -> Self {
+        AuthUser {
+            id: user.id,
+            username: user.username,
+        }
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for AuthUser {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, _: &S) -> Result<Self, Self::Rejection> {
+        parts
+            .extensions
+            .get::<AuthUser>()
+            .cloned()
+            .ok_or(StatusCode::UNAUTHORIZED)
+    }
+}
+
+/// An authenticated admin, extracted by handlers that manage the API.
 ///
-/// A 401 response if the request does not contain the shared secret.
-/// Otherwise, the next middleware is called.
-pub async fn auth(req: Request, next: Next) -> Result<Response, StatusCode> {
-    let auth_header = req
-        .headers()
-        .get(http::header::AUTHORIZATION)
-        .and_then(|header| header.to_str().ok());
+/// Rejects the request with a 403 if the user is not an admin.
+pub struct Admin(pub AuthUser);
 
-    if auth_header.is_none() {
-        return Err(StatusCode::UNAUTHORIZED);
+#[async_trait]
+impl FromRequestParts<SharedState> for Admin {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &SharedState,
+    ) -> Result<Self, Self::Rejection> {
+        let user = AuthUser::from_request_parts(parts, state).await?;
+        match user.is_admin(&state.read().await.config.auth.admin_users) {
+            true => Ok(Admin(user)),
+            false => Err(StatusCode::FORBIDDEN),
+        }
     }
+}
 
-    if !authorize_current_user(auth_header.unwrap()) {
-        return Err(StatusCode::UNAUTHORIZED);
+/// The scopes of the API key a request is authenticated with.
+///
+/// Added to the request by [`auth`] alongside the [`AuthUser`], only when the
+/// request uses an API key. Routes that change reminders other than through
+/// their request method, such as WebSocket commands, check it themselves.
+#[derive(Debug, Clone)]
+pub struct KeyScopes(pub Vec<Scope>);
+
+impl KeyScopes {
+    /// Whether the key has a scope.
+    pub fn allows(&self, scope: Scope) -> bool {
+        self.0.contains(&scope)
     }
+}
+
+/// Ensure a request carries a valid bearer token.
+///
+/// # Returns
+///
+/// A 401 response if the request does not carry a valid token, or a 403 if it
+/// carries an API key without the scope the route needs. Otherwise, the
+/// authenticated [`AuthUser`] is added to the request and the next middleware
+/// is called.
+pub async fn auth(
+    State(state): State<SharedState>,
+    mut req: Request,
+    next: Next,
+) -> Result<Response, StatusCode> {
+    let token = bearer_token(req.headers()).ok_or(StatusCode::UNAUTHORIZED)?;
+    let (user, scopes) = identify(&state, token).await?;
+
+    if let Some(scopes) = scopes {
+        let path = req
+            .extensions()
+            .get::<MatchedPath>()
+            .map_or("", MatchedPath::as_str);
+        match api_keys::required_scope(path, req.method()) {
+            Some(scope) if scopes.contains(&scope) => {}
+            _ => return Err(StatusCode::FORBIDDEN),
+        }
+        req.extensions_mut().insert(KeyScopes(scopes));
+    }
+    req.extensions_mut().insert(user);
 
     Ok(next.run(req).await)
 }
 
-/// Validate that the shared secret matches the one in the environment.
-fn authorize_current_user(auth_token: &str) -> bool {
-    let token = std::env::var("AUTH_TOKEN").expect("AUTH_TOKEN not set");
+/// Whether a token still authenticates a user.
+///
+/// Connections that stay open, such as streams, call this now and then, so
+/// they end once their session or API key is revoked or their token expires.
+pub async fn still_authenticated(state: &SharedState, token: &str, user: &AuthUser) -> bool {
+    matches!(identify(state, token).await, Ok((current, _)) if current.id == user.id)
+}
+
+/// Find the user a bearer token authenticates.
+///
+/// # Returns
+///
+/// The user, and the scopes of the API key if the token is one.
+///
+/// # Errors
+///
+/// Returns a 401 if the token is not valid, or a 500 if it cannot be checked.
+async fn identify(
+    state: &SharedState,
+    token: &str,
+) -> Result<(AuthUser, Option<Vec<Scope>>), StatusCode> {
+    let (store, config, keys, verifier) = {
+        let state = state.read().await;
+        (
+            state.store.clone(),
+            state.config.clone(),
+            state.tokens.clone(),
+            state.oidc.clone(),
+        )
+    };
+    let mode = config.auth.mode;
+
+    let user = if is_shared_secret(config.auth.shared_token.as_deref(), token) {
+        AuthUser::shared()
+    } else if mode != AuthMode::Shared && token.starts_with(api_keys::PREFIX) {
+        let key = api_keys::authenticate(store.as_ref(), token)
+            .await
+            .map_err(|e| {
+                log::error!("failed to authenticate request: {e}");
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?
+            .ok_or(StatusCode::UNAUTHORIZED)?;
+
+        let user = AuthUser {
+            id: key.user_id,
+            username: key.username,
+        };
+        return Ok((user, Some(key.scopes)));
+    } else if mode == AuthMode::Accounts && is_jwt(token) {
+        let claims = keys.verify(token).ok_or(StatusCode::UNAUTHORIZED)?;
+        AuthUser {
+            id: claims.sub,
+            username: claims.username,
+        }
+    } else if mode == AuthMode::Accounts {
+        accounts::authenticate(store.as_ref(), token)
+            .await
+            .map_err(|e| {
+                log::error!("failed to authenticate request: {e}");
+                StatusCode::INTERNAL_SERVER_ERROR
+            })?
+            .ok_or(StatusCode::UNAUTHORIZED)?
+            .into()
+    } else if let (AuthMode::Oidc, Some(verifier)) = (mode, verifier) {
+        oidc::authenticate(store.as_ref(), &verifier, token, &config.auth.admin_users)
+            .await
+            .map_err(|e| match e {
+                oidc::Error::InvalidToken(_) => StatusCode::UNAUTHORIZED,
+                e => {
+                    log::error!("failed to authenticate request: {e}");
+                    StatusCode::INTERNAL_SERVER_ERROR
+                }
+            })?
+            .into()
+    } else {
+        return Err(StatusCode::UNAUTHORIZED);
+    };
+
+    Ok((user, None))
+}
+
+/// Get the bearer token from the `Authorization` header.
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(http::header::AUTHORIZATION)
+        .and_then(|header| header.to_str().ok())
+        .and_then(|header| header.strip_prefix("Bearer "))
+        .filter(|token| !token.is_empty())
+}
+
+/// Whether a token has the three dot-separated parts of a JWT.
+///
+/// Session tokens are unpadded URL-safe base64 and never contain a dot.
+fn is_jwt(token: &str) -> bool {
+    token.split('.').count() == 3
+}
 
-    format!("Bearer {}", token).as_str() == auth_token
+/// Validate a token against the shared secret, if one is set.
+fn is_shared_secret(secret: Option<&str>, token: &str) -> bool {
+    // Compare digests so the comparison time does not depend on the secret.
+    secret
+        .is_some_and(|secret| Sha256::digest(secret.as_bytes()) == Sha256::digest(token.as_bytes()))
 }
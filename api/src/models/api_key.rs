@@ -0,0 +1,68 @@
+//! API key models.
+use serde::{Deserialize, Serialize};
+
+/// What an API key may do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Scope {
+    /// Read reminders.
+    #[serde(rename = "reminders:read")]
+    RemindersRead,
+    /// Create, change, complete and delete reminders.
+    #[serde(rename = "reminders:write")]
+    RemindersWrite,
+}
+
+/// An API key, as stored in the `api_keys` document collection under its id.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    /// Id of the user the key acts as.
+    pub user_id: String,
+    pub username: String,
+    pub scopes: Vec<Scope>,
+    /// Hex SHA-256 hash of the key.
+    pub hash: String,
+    pub created: u64,
+}
+
+/// Name, scopes and optionally the user sent to create an API key.
+#[derive(Deserialize)]
+pub struct NewApiKey {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    /// Id of the user the key acts as. Defaults to the user creating it.
+    pub user_id: Option<String>,
+}
+
+/// The public view of an API key.
+#[derive(Debug, Serialize)]
+pub struct ApiKeyResponse {
+    pub id: String,
+    pub name: String,
+    pub user_id: String,
+    pub username: String,
+    pub scopes: Vec<Scope>,
+    pub created: u64,
+}
+
+/// A newly created API key. The key itself is only ever shown here.
+#[derive(Debug, Serialize)]
+pub struct CreatedApiKey {
+    pub key: String,
+    #[serde(flatten)]
+    pub api_key: ApiKeyResponse,
+}
+
+impl From<ApiKey> for ApiKeyResponse {
+    fn from(key: ApiKey) -> Self {
+        ApiKeyResponse {
+            id: key.id,
+            name: key.name,
+            user_id: key.user_id,
+            username: key.username,
+            scopes: key.scopes,
+            created: key.created,
+        }
+    }
+}
@@ -0,0 +1,36 @@
+//! Per-item results for bulk operations.
+use serde::Serialize;
+
+/// Outcome of a bulk operation for a single reminder.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ItemStatus {
+    Updated,
+    NotFound,
+    /// The id was listed more than once, so none of its reminders were written.
+    Duplicate,
+}
+
+/// Result of a bulk operation for a single reminder.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ItemResult {
+    pub id: String,
+    pub status: ItemStatus,
+}
+
+impl ItemResult {
+    /// Create a result for the reminder with the given id.
+    pub fn new(id: &str, status: ItemStatus) -> Self {
+        Self {
+            id: id.to_string(),
+            status,
+        }
+    }
+}
+
+/// Response body for a bulk operation.
+#[derive(Debug, Serialize)]
+pub struct BulkResponse {
+    pub message: String,
+    pub results: Vec<ItemResult>,
+}
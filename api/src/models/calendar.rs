@@ -0,0 +1,45 @@
+//! Calendar feed models.
+use serde::{Deserialize, Serialize};
+
+/// A user's calendar feed, as stored in the `calendar_feeds` document
+/// collection under the SHA-256 hash of its token.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CalendarFeed {
+    /// Id of the user whose reminders the feed shows.
+    pub user_id: String,
+    pub username: String,
+    pub created: u64,
+}
+
+/// A newly created calendar feed. The URL holds the feed's token, so it is
+/// only ever shown here.
+#[derive(Debug, Serialize)]
+pub struct CreatedFeed {
+    /// Path of the feed, relative to the API.
+    pub url: String,
+    pub created: u64,
+}
+
+/// A component of an imported calendar that was not imported.
+#[derive(Debug, Serialize)]
+pub struct SkippedComponent {
+    /// Line of the file the component starts on.
+    pub line: usize,
+    /// Type of the component, such as `VTODO`.
+    pub component: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub uid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary: Option<String>,
+    /// Why it was not imported.
+    pub reason: String,
+}
+
+/// The outcome of importing a calendar.
+#[derive(Debug, Serialize)]
+pub struct ImportResponse {
+    pub dry_run: bool,
+    /// The reminders created, or that would be created in a dry run.
+    pub reminders: Vec<crate::models::reminder::Reminder>,
+    pub skipped: Vec<SkippedComponent>,
+}
@@ -0,0 +1,14 @@
+//! Daily digest models.
+use serde::{Deserialize, Serialize};
+
+/// Where and when to send a user's daily digest, as stored in the
+/// `digest_settings` document collection under the user's id.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct DigestSettings {
+    /// Address to email the digest to.
+    pub email: String,
+    /// The user's time zone, as minutes ahead of UTC. Decides when their day
+    /// starts and so which reminders are due today.
+    #[serde(default)]
+    pub utc_offset_minutes: i32,
+}
@@ -41,8 +41,8 @@ impl std::convert::From<&str> for ResponseMessage {
     }
 }
 
-impl std::convert::From<crate::firebase::Error> for ResponseMessage {
-    fn from(value: crate::firebase::Error) -> Self {
+impl std::convert::From<crate::store::Error> for ResponseMessage {
+    fn from(value: crate::store::Error) -> Self {
         Self {
             message: value.to_string(),
             status: StatusCode::OK,
@@ -0,0 +1,105 @@
+//! Household, shared list and membership models.
+use serde::{Deserialize, Serialize};
+
+/// What a member may do in a household.
+///
+/// Roles are ordered, so a role grants everything the roles before it do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Can read the household's reminders.
+    Viewer,
+    /// Can also create, edit and complete reminders, and add lists.
+    Editor,
+    /// Can also manage lists, members and invitations.
+    Owner,
+}
+
+/// A household, as stored in the `households` document collection.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Household {
+    pub id: String,
+    pub name: String,
+    /// Id of the user who created the household.
+    pub owner: String,
+    pub created: u64,
+}
+
+/// A household member, as stored in the `members/<household id>` collection
+/// under the user's id.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Member {
+    pub username: String,
+    pub role: Role,
+}
+
+/// A shared list of reminders, as stored in the `lists/<household id>`
+/// collection under its id.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct List {
+    pub name: String,
+    pub created: u64,
+}
+
+/// An invitation to join a household, as stored in the `invites` collection
+/// under its code.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Invite {
+    pub household_id: String,
+    pub role: Role,
+    pub expires: u64,
+}
+
+/// Name sent to create a household or list.
+#[derive(Deserialize)]
+pub struct NewName {
+    pub name: String,
+}
+
+/// Role sent to invite a member or change a member's role.
+#[derive(Deserialize)]
+pub struct RoleRequest {
+    pub role: Role,
+}
+
+/// A household the user belongs to, with the user's role in it.
+#[derive(Debug, Serialize)]
+pub struct HouseholdSummary {
+    pub id: String,
+    pub name: String,
+    pub role: Role,
+}
+
+/// A member of a household.
+#[derive(Debug, Serialize)]
+pub struct MemberResponse {
+    pub user_id: String,
+    pub username: String,
+    pub role: Role,
+}
+
+/// A list in a household.
+#[derive(Debug, Serialize)]
+pub struct ListResponse {
+    pub id: String,
+    pub name: String,
+}
+
+/// A household with its members and lists.
+#[derive(Debug, Serialize)]
+pub struct HouseholdDetails {
+    pub id: String,
+    pub name: String,
+    pub owner: String,
+    pub role: Role,
+    pub members: Vec<MemberResponse>,
+    pub lists: Vec<ListResponse>,
+}
+
+/// A newly created invitation.
+#[derive(Debug, Serialize)]
+pub struct InviteResponse {
+    pub code: String,
+    pub role: Role,
+    pub expires: u64,
+}
@@ -1,4 +1,16 @@
 //! Models for the API.
+pub mod api_key;
+pub mod bulk;
+pub mod calendar;
+pub mod digest;
 pub mod generic_response;
+pub mod household;
+pub mod push;
+pub mod query;
+pub mod record;
+pub mod recurrence;
 pub mod reminder;
 pub mod result;
+pub mod sync;
+pub mod user;
+pub mod webhook;
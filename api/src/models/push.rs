@@ -0,0 +1,59 @@
+//! Web Push subscription models.
+use serde::{Deserialize, Serialize};
+
+/// The keys a browser gives with a push subscription, as unpadded URL-safe base64.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PushKeys {
+    /// The browser's P-256 public key, uncompressed.
+    pub p256dh: String,
+    /// The 16-byte authentication secret.
+    pub auth: String,
+}
+
+/// A push subscription, as stored in the `push_subscriptions/<user id>`
+/// document collection under its id.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct PushSubscription {
+    pub id: String,
+    /// URL of the push service to send messages to.
+    pub endpoint: String,
+    pub keys: PushKeys,
+    /// Name of the device, for the user to tell their subscriptions apart.
+    pub device: Option<String>,
+    pub created: u64,
+}
+
+/// A subscription sent by a browser, in the shape of its `PushSubscription.toJSON()`.
+#[derive(Deserialize)]
+pub struct NewPushSubscription {
+    pub endpoint: String,
+    pub keys: PushKeys,
+    #[serde(default)]
+    pub device: Option<String>,
+}
+
+/// The public view of a push subscription.
+#[derive(Debug, Serialize)]
+pub struct PushSubscriptionResponse {
+    pub id: String,
+    pub endpoint: String,
+    pub device: Option<String>,
+    pub created: u64,
+}
+
+/// The VAPID public key browsers need to subscribe, as unpadded URL-safe base64.
+#[derive(Serialize)]
+pub struct VapidPublicKey {
+    pub public_key: String,
+}
+
+impl From<PushSubscription> for PushSubscriptionResponse {
+    fn from(subscription: PushSubscription) -> Self {
+        PushSubscriptionResponse {
+            id: subscription.id,
+            endpoint: subscription.endpoint,
+            device: subscription.device,
+            created: subscription.created,
+        }
+    }
+}
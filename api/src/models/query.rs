@@ -0,0 +1,350 @@
+//! Filtering, sorting and pagination for the reminders list endpoint.
+use crate::models::reminder::Reminder;
+use crate::store::Error;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+
+/// Query parameters accepted by `GET /reminders/v2/`.
+///
+/// Every parameter is optional. Without a `sort`, reminders are returned in id
+/// (creation) order.
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct ListQuery {
+    /// Only include reminders due strictly before this timestamp.
+    pub due_before: Option<u64>,
+    /// Only include reminders due strictly after this timestamp.
+    pub due_after: Option<u64>,
+    /// Only include reminders assigned to this person.
+    pub assignee: Option<String>,
+    /// Only include reminders with this priority.
+    pub priority: Option<u64>,
+    /// Which reminders to include by completion state.
+    #[serde(default)]
+    pub status: Status,
+    /// Comma separated fields to sort by, each optionally prefixed with `-` to
+    /// sort descending, e.g. `priority,due,title`.
+    pub sort: Option<String>,
+    /// Maximum number of reminders to return.
+    pub limit: Option<usize>,
+    /// Opaque cursor returned with the previous page.
+    pub cursor: Option<String>,
+}
+
+/// Completion states a list query can select.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    /// Reminders that have not been completed.
+    #[default]
+    Open,
+    /// Reminders that have been completed.
+    Completed,
+    /// Every reminder.
+    All,
+}
+
+/// A field reminders can be sorted by.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortField {
+    Due,
+    Priority,
+    Title,
+    Assignee,
+}
+
+/// A field to sort by and its direction.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SortKey {
+    pub field: SortField,
+    pub descending: bool,
+}
+
+/// The value of a sort field for one reminder, used for ordering and cursors.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(untagged)]
+enum SortValue {
+    Number(u64),
+    Text(Option<String>),
+}
+
+/// One page of results.
+#[derive(Debug)]
+pub struct Page {
+    pub reminders: Vec<Reminder>,
+    /// Cursor for the next page, if there is one.
+    pub next_cursor: Option<String>,
+    /// Entity tag of the whole collection, if the backend could compute it cheaply.
+    pub etag: Option<String>,
+}
+
+impl ListQuery {
+    /// Parse the `sort` parameter.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidQuery` if a field is not sortable.
+    pub fn sort_keys(&self) -> Result<Vec<SortKey>, Error> {
+        let Some(sort) = &self.sort else {
+            return Ok(vec![]);
+        };
+
+        sort.split(',')
+            .filter(|s| !s.is_empty())
+            .map(|s| {
+                let (descending, name) = match s.strip_prefix('-') {
+                    Some(name) => (true, name),
+                    None => (false, s),
+                };
+                let field = match name {
+                    "due" => SortField::Due,
+                    "priority" => SortField::Priority,
+                    "title" => SortField::Title,
+                    "assignee" => SortField::Assignee,
+                    _ => return Err(Error::InvalidQuery(format!("cannot sort by {name}"))),
+                };
+
+                Ok(SortKey { field, descending })
+            })
+            .collect()
+    }
+
+    /// Whether a reminder passes every filter in the query.
+    pub fn matches(&self, reminder: &Reminder) -> bool {
+        self.due_before.is_none_or(|t| reminder.due < t)
+            && self.due_after.is_none_or(|t| reminder.due > t)
+            && self.priority.is_none_or(|p| reminder.priority == p)
+            && match self.status {
+                Status::Open => !reminder.completed,
+                Status::Completed => reminder.completed,
+                Status::All => true,
+            }
+            && self
+                .assignee
+                .as_ref()
+                .is_none_or(|a| reminder.assignee.as_ref() == Some(a))
+    }
+
+    /// Filter, sort and paginate a set of reminders.
+    ///
+    /// Backends that push some of the query down to the database can still call
+    /// this on what they fetched, as re-applying a filter has no effect.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidQuery` if the sort or cursor cannot be parsed.
+    pub fn apply(&self, reminders: Vec<Reminder>) -> Result<Page, Error> {
+        let keys = self.sort_keys()?;
+        let after = self.cursor.as_deref().map(decode_cursor).transpose()?;
+
+        let mut reminders: Vec<Reminder> =
+            reminders.into_iter().filter(|r| self.matches(r)).collect();
+        reminders.sort_by(|a, b| compare(&keys, &sort_values(&keys, a), &sort_values(&keys, b)));
+
+        if let Some(after) = after {
+            reminders
+                .retain(|r| compare(&keys, &sort_values(&keys, r), &after) == Ordering::Greater);
+        }
+
+        let next_cursor = match self.limit {
+            Some(limit) if reminders.len() > limit => {
+                reminders.truncate(limit);
+                reminders
+                    .last()
+                    .map(|last| encode_cursor(&sort_values(&keys, last)))
+            }
+            _ => None,
+        };
+
+        Ok(Page {
+            reminders,
+            next_cursor,
+            etag: None,
+        })
+    }
+}
+
+/// The sort values of a reminder, followed by its id as a tie-breaker.
+fn sort_values(keys: &[SortKey], reminder: &Reminder) -> Vec<SortValue> {
+    keys.iter()
+        .map(|key| match key.field {
+            SortField::Due => SortValue::Number(reminder.due),
+            SortField::Priority => SortValue::Number(reminder.priority),
+            SortField::Title => SortValue::Text(Some(reminder.title.clone())),
+            SortField::Assignee => SortValue::Text(reminder.assignee.clone()),
+        })
+        .chain(std::iter::once(SortValue::Text(reminder.id.clone())))
+        .collect()
+}
+
+/// Compare two sets of sort values, honouring the direction of each key.
+fn compare(keys: &[SortKey], a: &[SortValue], b: &[SortValue]) -> Ordering {
+    a.iter()
+        .zip(b)
+        .enumerate()
+        .map(|(i, (a, b))| match keys.get(i) {
+            Some(key) if key.descending => b.cmp(a),
+            _ => a.cmp(b),
+        })
+        .find(|o| o.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+fn encode_cursor(values: &[SortValue]) -> String {
+    let json = serde_json::to_vec(values).expect("sort values serialize to JSON");
+    URL_SAFE_NO_PAD.encode(json)
+}
+
+fn decode_cursor(cursor: &str) -> Result<Vec<SortValue>, Error> {
+    URL_SAFE_NO_PAD
+        .decode(cursor)
+        .ok()
+        .and_then(|json| serde_json::from_slice(&json).ok())
+        .ok_or_else(|| Error::InvalidQuery("invalid cursor".into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::query::{ListQuery, Status};
+    use crate::models::reminder::Reminder;
+
+    fn reminders() -> Vec<Reminder> {
+        [
+            ("a", "Rent", 300, 1, None),
+            ("b", "Bins", 100, 0, Some("Sam")),
+            ("c", "Dog", 200, 0, None),
+            ("d", "Apples", 200, 0, Some("Sam")),
+        ]
+        .into_iter()
+        .map(|(id, title, due, priority, assignee)| Reminder {
+            id: Some(id.into()),
+            title: title.into(),
+            due,
+            priority,
+            assignee: assignee.map(Into::into),
+            completed: false,
+            completed_at: None,
+            rrule: None,
+        })
+        .collect()
+    }
+
+    fn ids(reminders: &[Reminder]) -> Vec<&str> {
+        reminders.iter().map(|r| r.id.as_deref().unwrap()).collect()
+    }
+
+    /// Test the same ordering the Flutter home page applies.
+    #[test]
+    fn test_sort() {
+        let query = ListQuery {
+            sort: Some("priority,due,title".into()),
+            ..Default::default()
+        };
+
+        let page = query.apply(reminders()).unwrap();
+
+        assert_eq!(ids(&page.reminders), vec!["b", "d", "c", "a"]);
+        assert!(page.next_cursor.is_none());
+    }
+
+    /// Test sorting in descending order.
+    #[test]
+    fn test_sort_descending() {
+        let query = ListQuery {
+            sort: Some("-due".into()),
+            ..Default::default()
+        };
+
+        let page = query.apply(reminders()).unwrap();
+
+        assert_eq!(ids(&page.reminders), vec!["a", "c", "d", "b"]);
+    }
+
+    /// Test the due date, assignee and priority filters.
+    #[test]
+    fn test_filters() {
+        let query = ListQuery {
+            due_before: Some(300),
+            due_after: Some(100),
+            ..Default::default()
+        };
+        assert_eq!(
+            ids(&query.apply(reminders()).unwrap().reminders),
+            vec!["c", "d"]
+        );
+
+        let query = ListQuery {
+            assignee: Some("Sam".into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            ids(&query.apply(reminders()).unwrap().reminders),
+            vec!["b", "d"]
+        );
+
+        let query = ListQuery {
+            priority: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(ids(&query.apply(reminders()).unwrap().reminders), vec!["a"]);
+    }
+
+    /// Test that completed reminders are hidden unless asked for.
+    #[test]
+    fn test_status() {
+        let mut all = reminders();
+        all[0].complete(1);
+
+        let query = ListQuery::default();
+        assert_eq!(
+            ids(&query.apply(all.clone()).unwrap().reminders),
+            vec!["b", "c", "d"]
+        );
+
+        let query = ListQuery {
+            status: Status::Completed,
+            ..Default::default()
+        };
+        assert_eq!(ids(&query.apply(all.clone()).unwrap().reminders), vec!["a"]);
+
+        let query = ListQuery {
+            status: Status::All,
+            ..Default::default()
+        };
+        assert_eq!(query.apply(all).unwrap().reminders.len(), 4);
+    }
+
+    /// Test walking through every page with the returned cursors.
+    #[test]
+    fn test_pagination() {
+        let mut query = ListQuery {
+            sort: Some("due".into()),
+            limit: Some(3),
+            ..Default::default()
+        };
+
+        let first = query.apply(reminders()).unwrap();
+        assert_eq!(ids(&first.reminders), vec!["b", "c", "d"]);
+
+        query.cursor = first.next_cursor;
+        let second = query.apply(reminders()).unwrap();
+        assert_eq!(ids(&second.reminders), vec!["a"]);
+        assert!(second.next_cursor.is_none());
+    }
+
+    /// Test that bad sort fields and cursors are rejected.
+    #[test]
+    fn test_invalid() {
+        let query = ListQuery {
+            sort: Some("colour".into()),
+            ..Default::default()
+        };
+        assert!(query.apply(reminders()).is_err());
+
+        let query = ListQuery {
+            cursor: Some("not a cursor".into()),
+            ..Default::default()
+        };
+        assert!(query.apply(reminders()).is_err());
+    }
+}
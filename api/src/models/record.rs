@@ -0,0 +1,348 @@
+//! Stored reminder records.
+//!
+//! This is the shape of a reminder as it is kept under `reminders/v2/<id>` in
+//! Firebase and in memory store fixtures. Entries without a title and due
+//! time are reported with their key and skipped, rather than read as blank
+//! reminders. A bad value in any other field is reported and left unset, so
+//! one bad field does not lose the whole reminder.
+use crate::models::{
+    recurrence::Recurrence,
+    reminder::{fix_case, Reminder},
+};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+
+/// A reminder as stored, keyed by its id.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ReminderRecord {
+    pub title: String,
+    pub due: u64,
+    #[serde(default)]
+    pub priority: u64,
+    #[serde(default)]
+    pub assignee: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub completed: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completed_at: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rrule: Option<Recurrence>,
+    /// Fields this version does not know about. They are reported when read
+    /// and not written back.
+    #[serde(flatten, skip_serializing)]
+    pub unknown: Map<String, Value>,
+}
+
+/// A problem found while reading stored records.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    /// Key of the offending entry.
+    pub id: String,
+    pub kind: WarningKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WarningKind {
+    /// The entry could not be read and was skipped.
+    Malformed(String),
+    /// The entry was read, but had fields that were ignored.
+    UnknownFields(Vec<String>),
+    /// The entry was read, but an optional field could not be and was left
+    /// unset.
+    InvalidField { field: String, error: String },
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            WarningKind::Malformed(e) => write!(f, "skipped malformed reminder {}: {e}", self.id),
+            WarningKind::UnknownFields(fields) => write!(
+                f,
+                "ignored unknown fields on reminder {}: {}",
+                self.id,
+                fields.join(", ")
+            ),
+            WarningKind::InvalidField { field, error } => write!(
+                f,
+                "ignored invalid {field} on reminder {}: {error}",
+                self.id
+            ),
+        }
+    }
+}
+
+/// The result of reading a collection of stored records.
+#[derive(Debug, Default)]
+pub struct Decoded {
+    pub reminders: Vec<Reminder>,
+    pub warnings: Vec<Warning>,
+}
+
+impl Decoded {
+    /// Log every warning and return the reminders that were read.
+    pub fn logged(self) -> Vec<Reminder> {
+        for warning in &self.warnings {
+            log::warn!("{warning}");
+        }
+
+        self.reminders
+    }
+}
+
+impl ReminderRecord {
+    /// Read a single stored entry.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - Key of the entry.
+    /// * `value` - The raw entry.
+    ///
+    /// # Returns
+    ///
+    /// The reminder, if the entry could be read, and any warnings about it.
+    pub fn decode(id: &str, mut value: Value) -> (Option<Reminder>, Vec<Warning>) {
+        let warning = |kind| Warning {
+            id: id.to_string(),
+            kind,
+        };
+
+        let mut warnings: Vec<Warning> = match value.as_object_mut() {
+            Some(object) => Self::drop_invalid(object)
+                .into_iter()
+                .map(|(field, error)| warning(WarningKind::InvalidField { field, error }))
+                .collect(),
+            None => Vec::new(),
+        };
+
+        match serde_json::from_value::<ReminderRecord>(value) {
+            Err(e) => {
+                warnings.push(warning(WarningKind::Malformed(e.to_string())));
+                (None, warnings)
+            }
+            Ok(record) => {
+                let unknown: Vec<String> = record.unknown.keys().cloned().collect();
+                if !unknown.is_empty() {
+                    warnings.push(warning(WarningKind::UnknownFields(unknown)));
+                }
+                (Some(record.into_reminder(id)), warnings)
+            }
+        }
+    }
+
+    /// Remove the optional fields of an entry that cannot be read, so they take
+    /// their defaults.
+    ///
+    /// # Returns
+    ///
+    /// The name of each field that could not be read, and why.
+    fn drop_invalid(object: &mut Map<String, Value>) -> Vec<(String, String)> {
+        fn check<T: DeserializeOwned>(value: &Value) -> Option<String> {
+            serde_json::from_value::<T>(value.clone())
+                .err()
+                .map(|e| e.to_string())
+        }
+
+        let mut invalid = Vec::new();
+        object.retain(|field, value| {
+            let error = match field.as_str() {
+                "priority" => check::<u64>(value),
+                "assignee" => check::<Option<String>>(value),
+                "completed" => check::<bool>(value),
+                "completed_at" => check::<Option<u64>>(value),
+                "rrule" => check::<Option<Recurrence>>(value),
+                _ => None,
+            };
+            match error {
+                Some(error) => {
+                    invalid.push((field.clone(), error));
+                    false
+                }
+                None => true,
+            }
+        });
+
+        invalid
+    }
+
+    /// Read every entry of a stored collection.
+    ///
+    /// # Arguments
+    ///
+    /// * `raw` - The collection, keyed by id.
+    pub fn decode_all(raw: HashMap<String, Value>) -> Decoded {
+        let mut decoded = Decoded::default();
+
+        for (id, value) in raw {
+            let (reminder, warnings) = Self::decode(&id, value);
+            decoded.reminders.extend(reminder);
+            decoded.warnings.extend(warnings);
+        }
+
+        decoded
+    }
+
+    /// Build the stored records for a set of reminders, keyed by id.
+    pub fn encode_all(reminders: Vec<Reminder>) -> BTreeMap<String, ReminderRecord> {
+        reminders
+            .into_iter()
+            .map(|r| (r.id.clone().unwrap_or_default(), r.into()))
+            .collect()
+    }
+
+    /// Convert the record into a reminder with the given id.
+    pub fn into_reminder(self, id: &str) -> Reminder {
+        Reminder {
+            id: Some(id.to_string()),
+            title: fix_case(&self.title),
+            due: self.due,
+            priority: self.priority,
+            assignee: self.assignee,
+            completed: self.completed,
+            completed_at: self.completed_at,
+            rrule: self.rrule,
+        }
+    }
+}
+
+impl From<Reminder> for ReminderRecord {
+    fn from(reminder: Reminder) -> Self {
+        ReminderRecord {
+            title: reminder.title,
+            due: reminder.due,
+            priority: reminder.priority,
+            assignee: reminder.assignee,
+            completed: reminder.completed,
+            completed_at: reminder.completed_at,
+            rrule: reminder.rrule,
+            unknown: Map::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::record::{ReminderRecord, WarningKind};
+    use crate::models::reminder::Reminder;
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    /// Test reading a well formed Firebase entry.
+    #[test]
+    fn test_decode() {
+        let raw = HashMap::from([(
+            "abc".to_string(),
+            json!({"title": "hello, world", "due": 1234, "priority": 0}),
+        )]);
+
+        let decoded = ReminderRecord::decode_all(raw);
+
+        assert!(decoded.warnings.is_empty());
+        let r = &decoded.reminders[0];
+        assert_eq!(r.id.as_deref(), Some("abc"));
+        assert_eq!(r.title, "Hello, world");
+        assert_eq!(r.due, 1234);
+        assert_eq!(r.assignee, None);
+        assert!(!r.completed);
+    }
+
+    /// Test that malformed entries are skipped and reported with their key.
+    #[test]
+    fn test_decode_malformed() {
+        let raw = HashMap::from([
+            ("good".to_string(), json!({"title": "Bins", "due": 1})),
+            ("no_due".to_string(), json!({"title": "Bins"})),
+            ("not_an_object".to_string(), json!("Bins")),
+        ]);
+
+        let mut decoded = ReminderRecord::decode_all(raw);
+        decoded.warnings.sort_by(|a, b| a.id.cmp(&b.id));
+
+        assert_eq!(decoded.reminders.len(), 1);
+        assert_eq!(decoded.reminders[0].id.as_deref(), Some("good"));
+
+        let ids: Vec<&str> = decoded.warnings.iter().map(|w| w.id.as_str()).collect();
+        assert_eq!(ids, vec!["no_due", "not_an_object"]);
+        assert!(decoded
+            .warnings
+            .iter()
+            .all(|w| matches!(w.kind, WarningKind::Malformed(_))));
+        assert!(decoded.warnings[0]
+            .to_string()
+            .contains("missing field `due`"));
+    }
+
+    /// Test that bad optional fields are reported and left unset, and the
+    /// rest of the entry is still read.
+    #[test]
+    fn test_decode_invalid_fields() {
+        let (reminder, warnings) = ReminderRecord::decode(
+            "abc",
+            json!({
+                "title": "Bins",
+                "due": 1,
+                "priority": "high",
+                "assignee": 123,
+                "rrule": "FREQ=HOURLY",
+            }),
+        );
+
+        let reminder = reminder.unwrap();
+        assert_eq!(reminder.title, "Bins");
+        assert_eq!(reminder.priority, 0);
+        assert_eq!(reminder.assignee, None);
+        assert_eq!(reminder.rrule, None);
+
+        let mut fields: Vec<&str> = warnings
+            .iter()
+            .map(|w| match &w.kind {
+                WarningKind::InvalidField { field, .. } => field.as_str(),
+                kind => panic!("unexpected warning {kind:?}"),
+            })
+            .collect();
+        fields.sort();
+        assert_eq!(fields, vec!["assignee", "priority", "rrule"]);
+        assert!(warnings[0].to_string().starts_with("ignored invalid "));
+    }
+
+    /// Test that unknown fields are reported but the entry is still read.
+    #[test]
+    fn test_decode_unknown_fields() {
+        let (reminder, warnings) =
+            ReminderRecord::decode("abc", json!({"title": "Bins", "due": 1, "colour": "red"}));
+
+        assert!(reminder.is_some());
+        assert_eq!(
+            warnings[0].kind,
+            WarningKind::UnknownFields(vec!["colour".into()])
+        );
+    }
+
+    /// Test that reminders round-trip through their stored records.
+    #[test]
+    fn test_round_trip() {
+        let mut reminder = Reminder {
+            id: Some("abc".into()),
+            title: "Rent".into(),
+            due: 1234,
+            priority: 1,
+            assignee: Some("Sam".into()),
+            completed: false,
+            completed_at: None,
+            rrule: Some("FREQ=MONTHLY;BYMONTHDAY=1".parse().unwrap()),
+        };
+        reminder.complete(5678);
+
+        let records = ReminderRecord::encode_all(vec![reminder.clone()]);
+        let raw = serde_json::from_value(serde_json::to_value(records).unwrap()).unwrap();
+        let decoded = ReminderRecord::decode_all(raw);
+
+        assert!(decoded.warnings.is_empty());
+        let back = &decoded.reminders[0];
+        assert_eq!(back, &reminder);
+        assert_eq!(back.due, reminder.due);
+        assert_eq!(back.rrule, reminder.rrule);
+        assert_eq!(back.assignee, reminder.assignee);
+    }
+}
@@ -0,0 +1,792 @@
+//! Recurrence rules for repeating reminders.
+//!
+//! Supports the subset of RFC 5545 `RRULE` that household reminders need:
+//! `FREQ` (`DAILY`, `WEEKLY`, `MONTHLY` or `YEARLY`), `INTERVAL`, `BYDAY`,
+//! `BYMONTHDAY`, `COUNT` and `UNTIL`, e.g. `FREQ=WEEKLY;BYDAY=TU` or
+//! `FREQ=MONTHLY;BYMONTHDAY=1`.
+//!
+//! The current `due` date of a reminder acts as the rule's `DTSTART`. Dates are
+//! evaluated in UTC and the time of day of `due` is kept for every occurrence.
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::str::FromStr;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Give up looking for the next occurrence after this many periods, so a rule
+/// that can never match (e.g. `FREQ=DAILY;INTERVAL=7;BYDAY=TU` starting on a
+/// Monday) cannot loop forever.
+const MAX_PERIODS: u32 = 10_000;
+
+const WEEKDAYS: [&str; 7] = ["MO", "TU", "WE", "TH", "FR", "SA", "SU"];
+
+/// How often a rule repeats.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Frequency {
+    Daily,
+    Weekly,
+    Monthly,
+    Yearly,
+}
+
+/// A `BYDAY` entry, e.g. `TU`, `1MO` (first Monday) or `-1FR` (last Friday).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ByDay {
+    /// Which occurrence of the weekday within the month or year, counting from
+    /// the end if negative. `None` matches every occurrence.
+    pub ordinal: Option<i32>,
+    /// Day of the week, Monday being 0.
+    pub weekday: u32,
+}
+
+/// A parsed recurrence rule.
+///
+/// Serialises to and from its `RRULE` string form.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Recurrence {
+    pub frequency: Frequency,
+    pub interval: u32,
+    pub by_day: Vec<ByDay>,
+    pub by_month_day: Vec<i32>,
+    /// Number of occurrences left, including the current one.
+    pub count: Option<u32>,
+    /// Last time an occurrence may fall on, in seconds since the epoch.
+    pub until: Option<u64>,
+}
+
+impl Recurrence {
+    /// Find the first occurrence after `due`.
+    ///
+    /// # Arguments
+    ///
+    /// * `due` - The current occurrence, in seconds since the epoch.
+    ///
+    /// # Returns
+    ///
+    /// The next occurrence, or `None` if the rule has run out.
+    pub fn next_after(&self, due: u64) -> Option<u64> {
+        if self.count.is_some_and(|c| c <= 1) {
+            return None;
+        }
+
+        let day = (due / SECONDS_PER_DAY) as i64;
+        let time = due % SECONDS_PER_DAY;
+        let start = Date::from_days(day);
+        let interval = i64::from(self.interval);
+
+        (0..i64::from(MAX_PERIODS))
+            .map(|n| self.period(start, n * interval))
+            .find_map(|days| {
+                days.into_iter()
+                    .filter(|d| self.matches(start, *d))
+                    .map(|d| d as u64 * SECONDS_PER_DAY + time)
+                    .find(|t| *t > due)
+            })
+            .filter(|t| self.until.is_none_or(|until| *t <= until))
+    }
+
+    /// Advance the rule past the current occurrence.
+    ///
+    /// # Returns
+    ///
+    /// The next occurrence and the rule to keep using from it, or `None` if
+    /// the rule has run out.
+    pub fn advance(&self, due: u64) -> Option<(u64, Recurrence)> {
+        let next = self.next_after(due)?;
+        let rule = Recurrence {
+            count: self.count.map(|c| c - 1),
+            ..self.clone()
+        };
+
+        Some((next, rule))
+    }
+
+    /// The days, in order, of the period `offset` units after the one holding
+    /// `start`.
+    fn period(&self, start: Date, offset: i64) -> Vec<i64> {
+        match self.frequency {
+            Frequency::Daily => vec![start.days() + offset],
+            Frequency::Weekly => {
+                let monday = start.days() - i64::from(start.weekday()) + offset * 7;
+                (monday..monday + 7).collect()
+            }
+            Frequency::Monthly => {
+                let month = i64::from(start.month) - 1 + offset;
+                let year = start.year + month.div_euclid(12);
+                let first = Date::new(year, month.rem_euclid(12) as u32 + 1, 1).days();
+                (first..first + i64::from(days_in_month(year, month.rem_euclid(12) as u32 + 1)))
+                    .collect()
+            }
+            Frequency::Yearly => {
+                let year = start.year + offset;
+                let first = Date::new(year, 1, 1).days();
+                (first..Date::new(year + 1, 1, 1).days()).collect()
+            }
+        }
+    }
+
+    /// Whether a day within a period is an occurrence.
+    fn matches(&self, start: Date, day: i64) -> bool {
+        let date = Date::from_days(day);
+
+        if self.by_day.is_empty() && self.by_month_day.is_empty() {
+            return match self.frequency {
+                Frequency::Daily => true,
+                Frequency::Weekly => date.weekday() == start.weekday(),
+                Frequency::Monthly => date.day == start.day,
+                Frequency::Yearly => date.month == start.month && date.day == start.day,
+            };
+        }
+
+        let by_month_day = self.by_month_day.is_empty()
+            || self.by_month_day.iter().any(|&d| {
+                let len = days_in_month(date.year, date.month) as i32;
+                match d > 0 {
+                    true => d == date.day as i32,
+                    false => len + d + 1 == date.day as i32,
+                }
+            });
+
+        let by_day = self.by_day.is_empty()
+            || self.by_day.iter().any(|b| {
+                b.weekday == date.weekday()
+                    && b.ordinal
+                        .is_none_or(|n| self.weekday_ordinals(date).contains(&n))
+            });
+
+        by_month_day && by_day
+    }
+
+    /// Which occurrences of its weekday a date is within its month or year,
+    /// counting from the start and from the end.
+    fn weekday_ordinals(&self, date: Date) -> [i32; 2] {
+        let (first, last) = match self.frequency {
+            Frequency::Yearly => (
+                Date::new(date.year, 1, 1).days(),
+                Date::new(date.year + 1, 1, 1).days() - 1,
+            ),
+            _ => (
+                Date::new(date.year, date.month, 1).days(),
+                Date::new(date.year, date.month, days_in_month(date.year, date.month)).days(),
+            ),
+        };
+        let day = date.days();
+
+        [
+            ((day - first) / 7 + 1) as i32,
+            -(((last - day) / 7 + 1) as i32),
+        ]
+    }
+}
+
+impl FromStr for Recurrence {
+    type Err = String;
+
+    /// Parse an `RRULE` value, with or without the `RRULE:` prefix.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let s = s.strip_prefix("RRULE:").unwrap_or(s);
+
+        let mut frequency = None;
+        let mut interval = 1;
+        let mut by_day = vec![];
+        let mut by_month_day = vec![];
+        let mut count = None;
+        let mut until = None;
+
+        for part in s.split(';').filter(|p| !p.is_empty()) {
+            let (key, value) = part
+                .split_once('=')
+                .ok_or_else(|| format!("invalid rule part {part}"))?;
+            let value = value.to_ascii_uppercase();
+
+            match key.to_ascii_uppercase().as_str() {
+                "FREQ" => {
+                    frequency = Some(match value.as_str() {
+                        "DAILY" => Frequency::Daily,
+                        "WEEKLY" => Frequency::Weekly,
+                        "MONTHLY" => Frequency::Monthly,
+                        "YEARLY" => Frequency::Yearly,
+                        _ => return Err(format!("unsupported FREQ {value}")),
+                    })
+                }
+                "INTERVAL" => {
+                    interval = value
+                        .parse()
+                        .ok()
+                        .filter(|i| *i > 0)
+                        .ok_or_else(|| format!("invalid INTERVAL {value}"))?
+                }
+                "BYDAY" => {
+                    by_day = value
+                        .split(',')
+                        .map(parse_by_day)
+                        .collect::<Result<_, _>>()?
+                }
+                "BYMONTHDAY" => {
+                    by_month_day = value
+                        .split(',')
+                        .map(|d| {
+                            d.parse::<i32>()
+                                .ok()
+                                .filter(|d| (1..=31).contains(&d.abs()))
+                                .ok_or_else(|| format!("invalid BYMONTHDAY {d}"))
+                        })
+                        .collect::<Result<_, _>>()?
+                }
+                "COUNT" => {
+                    count = Some(
+                        value
+                            .parse()
+                            .ok()
+                            .filter(|c| *c > 0)
+                            .ok_or_else(|| format!("invalid COUNT {value}"))?,
+                    )
+                }
+                "UNTIL" => until = Some(parse_until(&value)?),
+                _ => return Err(format!("unsupported rule part {key}")),
+            }
+        }
+
+        let frequency = frequency.ok_or("FREQ is required")?;
+        if count.is_some() && until.is_some() {
+            return Err("COUNT and UNTIL cannot both be set".into());
+        }
+        if matches!(frequency, Frequency::Daily | Frequency::Weekly)
+            && by_day.iter().any(|b| b.ordinal.is_some())
+        {
+            return Err("BYDAY ordinals are only allowed with MONTHLY or YEARLY".into());
+        }
+        if frequency == Frequency::Weekly && !by_month_day.is_empty() {
+            return Err("BYMONTHDAY is not allowed with WEEKLY".into());
+        }
+
+        Ok(Recurrence {
+            frequency,
+            interval,
+            by_day,
+            by_month_day,
+            count,
+            until,
+        })
+    }
+}
+
+impl TryFrom<String> for Recurrence {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Recurrence> for String {
+    fn from(rule: Recurrence) -> Self {
+        rule.to_string()
+    }
+}
+
+impl fmt::Display for Recurrence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let frequency = match self.frequency {
+            Frequency::Daily => "DAILY",
+            Frequency::Weekly => "WEEKLY",
+            Frequency::Monthly => "MONTHLY",
+            Frequency::Yearly => "YEARLY",
+        };
+        write!(f, "FREQ={frequency}")?;
+
+        if self.interval != 1 {
+            write!(f, ";INTERVAL={}", self.interval)?;
+        }
+        if !self.by_day.is_empty() {
+            let days: Vec<String> = self
+                .by_day
+                .iter()
+                .map(|b| {
+                    let ordinal = b.ordinal.map(|n| n.to_string()).unwrap_or_default();
+                    format!("{ordinal}{}", WEEKDAYS[b.weekday as usize])
+                })
+                .collect();
+            write!(f, ";BYDAY={}", days.join(","))?;
+        }
+        if !self.by_month_day.is_empty() {
+            let days: Vec<String> = self.by_month_day.iter().map(i32::to_string).collect();
+            write!(f, ";BYMONTHDAY={}", days.join(","))?;
+        }
+        if let Some(count) = self.count {
+            write!(f, ";COUNT={count}")?;
+        }
+        if let Some(until) = self.until {
+            write!(f, ";UNTIL={}", date_time(until))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Format a time as an iCalendar UTC date-time, such as `20240131T235959Z`.
+///
+/// # Arguments
+///
+/// * `time` - Seconds since the epoch.
+pub fn date_time(time: u64) -> String {
+    let date = Date::from_days((time / SECONDS_PER_DAY) as i64);
+    let time = time % SECONDS_PER_DAY;
+
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        date.year,
+        date.month,
+        date.day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+/// Parse a `BYDAY` entry such as `TU`, `2MO` or `-1FR`.
+fn parse_by_day(s: &str) -> Result<ByDay, String> {
+    let split = s.len().saturating_sub(2);
+    let (ordinal, day) = s.split_at(split);
+    let weekday = WEEKDAYS
+        .iter()
+        .position(|d| *d == day)
+        .ok_or_else(|| format!("invalid BYDAY {s}"))? as u32;
+    let ordinal = match ordinal {
+        "" => None,
+        n => Some(
+            n.trim_start_matches('+')
+                .parse::<i32>()
+                .ok()
+                .filter(|n| *n != 0 && n.abs() <= 53)
+                .ok_or_else(|| format!("invalid BYDAY {s}"))?,
+        ),
+    };
+
+    Ok(ByDay { ordinal, weekday })
+}
+
+/// Parse an `UNTIL` value, either a UTC date-time (`20240131T235959Z`) or a
+/// date (`20240131`), which includes the whole day.
+fn parse_until(s: &str) -> Result<u64, String> {
+    let invalid = || format!("invalid UNTIL {s}");
+    let time = parse_date_time(s).ok_or_else(invalid)?;
+
+    match s.len() {
+        8 => Ok(time + SECONDS_PER_DAY - 1),
+        16 => Ok(time),
+        _ => Err(invalid()),
+    }
+}
+
+/// Parse an iCalendar date (`20240131`), which is taken as its midnight, or
+/// date-time (`20240131T235959Z`).
+///
+/// Date-times without the `Z` are in a time zone this does not know, and are
+/// taken as UTC.
+///
+/// # Returns
+///
+/// Seconds since the epoch, or `None` if `s` is not a valid date or
+/// date-time from 1970 on.
+pub fn parse_date_time(s: &str) -> Option<u64> {
+    let number = |range: std::ops::Range<usize>| -> Option<u32> {
+        s.get(range)
+            .filter(|n| n.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|n| n.parse().ok())
+    };
+
+    let (year, month, day) = (number(0..4)?, number(4..6)?, number(6..8)?);
+    if !(1..=12).contains(&month) || day == 0 || day > days_in_month(year as i64, month) {
+        return None;
+    }
+    let days = Date::new(year as i64, month, day).days();
+    if days < 0 {
+        return None;
+    }
+
+    let time = match s.len() {
+        8 => 0,
+        15 | 16 if &s[8..9] == "T" && (s.len() == 15 || s.ends_with('Z')) => {
+            let (h, m, sec) = (number(9..11)?, number(11..13)?, number(13..15)?);
+            if h > 23 || m > 59 || sec > 59 {
+                return None;
+            }
+            u64::from(h * 3600 + m * 60 + sec)
+        }
+        _ => return None,
+    };
+
+    Some(days as u64 * SECONDS_PER_DAY + time)
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// A date in the proleptic Gregorian calendar.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Date {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+}
+
+impl Date {
+    fn new(year: i64, month: u32, day: u32) -> Self {
+        Date { year, month, day }
+    }
+
+    /// Convert days since 1970-01-01 to a date.
+    pub fn from_days(days: i64) -> Self {
+        // Howard Hinnant's civil_from_days.
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = yoe + era * 400 + i64::from(month <= 2);
+
+        Date { year, month, day }
+    }
+
+    /// Convert the date to days since 1970-01-01.
+    fn days(&self) -> i64 {
+        // Howard Hinnant's days_from_civil.
+        let year = self.year - i64::from(self.month <= 2);
+        let era = year.div_euclid(400);
+        let yoe = year.rem_euclid(400);
+        let month = i64::from(self.month);
+        let mp = if month > 2 { month - 3 } else { month + 9 };
+        let doy = (153 * mp + 2) / 5 + i64::from(self.day) - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+
+        era * 146_097 + doe - 719_468
+    }
+
+    /// Day of the week, Monday being 0.
+    fn weekday(&self) -> u32 {
+        // 1970-01-01 was a Thursday.
+        (self.days() + 3).rem_euclid(7) as u32
+    }
+}
+
+/// Display a date the way the app does, such as `08 Mar 2024`.
+impl fmt::Display for Date {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const MONTHS: [&str; 12] = [
+            "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+        ];
+        let month = MONTHS[(self.month as usize - 1) % 12];
+
+        write!(f, "{:02} {month} {}", self.day, self.year)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::recurrence::{Date, Recurrence, SECONDS_PER_DAY};
+
+    /// Seconds since the epoch for a UTC date and time.
+    fn at(year: i64, month: u32, day: u32, hour: u64) -> u64 {
+        Date::new(year, month, day).days() as u64 * SECONDS_PER_DAY + hour * 3600
+    }
+
+    /// Every occurrence of a rule starting from `due`.
+    fn occurrences(rule: &str, due: u64, n: usize) -> Vec<u64> {
+        let mut rule: Recurrence = rule.parse().unwrap();
+        let mut due = due;
+        let mut all = vec![due];
+        while all.len() < n {
+            let Some((next, advanced)) = rule.advance(due) else {
+                break;
+            };
+            all.push(next);
+            (due, rule) = (next, advanced);
+        }
+        all
+    }
+
+    /// Test converting between dates and days since the epoch.
+    #[test]
+    fn test_date_round_trip() {
+        assert_eq!(Date::new(1970, 1, 1).days(), 0);
+        assert_eq!(Date::new(2000, 3, 1).days(), 11_017);
+        assert_eq!(Date::new(1969, 12, 31).days(), -1);
+        for days in -1000..100_000 {
+            assert_eq!(Date::from_days(days).days(), days);
+        }
+        assert_eq!(Date::new(1970, 1, 1).weekday(), 3);
+        assert_eq!(Date::new(2024, 1, 1).weekday(), 0);
+    }
+
+    /// Test that a daily rule keeps the time of day.
+    #[test]
+    fn test_daily() {
+        let due = at(2024, 2, 27, 9);
+        assert_eq!(
+            occurrences("FREQ=DAILY", due, 4),
+            vec![
+                due,
+                at(2024, 2, 28, 9),
+                at(2024, 2, 29, 9),
+                at(2024, 3, 1, 9)
+            ]
+        );
+        assert_eq!(
+            occurrences("FREQ=DAILY;INTERVAL=3", due, 3),
+            vec![due, at(2024, 3, 1, 9), at(2024, 3, 4, 9)]
+        );
+    }
+
+    /// Test a daily rule limited to weekdays.
+    #[test]
+    fn test_daily_by_day() {
+        // Friday 5th January 2024.
+        let due = at(2024, 1, 5, 8);
+        assert_eq!(
+            occurrences("FREQ=DAILY;BYDAY=MO,TU,WE,TH,FR", due, 3),
+            vec![due, at(2024, 1, 8, 8), at(2024, 1, 9, 8)]
+        );
+    }
+
+    /// Test "bins every Tuesday".
+    #[test]
+    fn test_weekly() {
+        // Tuesday 2nd January 2024.
+        let due = at(2024, 1, 2, 19);
+        assert_eq!(
+            occurrences("FREQ=WEEKLY;BYDAY=TU", due, 3),
+            vec![due, at(2024, 1, 9, 19), at(2024, 1, 16, 19)]
+        );
+        assert_eq!(
+            occurrences("FREQ=WEEKLY", due, 2),
+            vec![due, at(2024, 1, 9, 19)]
+        );
+    }
+
+    /// Test a fortnightly rule on several days stays on the fortnightly grid.
+    #[test]
+    fn test_weekly_interval_by_day() {
+        // Tuesday 2nd January 2024.
+        let due = at(2024, 1, 2, 0);
+        assert_eq!(
+            occurrences("FREQ=WEEKLY;INTERVAL=2;BYDAY=TU,TH", due, 5),
+            vec![
+                due,
+                at(2024, 1, 4, 0),
+                at(2024, 1, 16, 0),
+                at(2024, 1, 18, 0),
+                at(2024, 1, 30, 0)
+            ]
+        );
+    }
+
+    /// Test a weekly rule crossing the end of a year.
+    #[test]
+    fn test_weekly_across_year() {
+        // Sunday 29th December 2024.
+        let due = at(2024, 12, 29, 12);
+        assert_eq!(
+            occurrences("FREQ=WEEKLY;BYDAY=SU,WE", due, 3),
+            vec![due, at(2025, 1, 1, 12), at(2025, 1, 5, 12)]
+        );
+    }
+
+    /// Test "pay rent on the 1st".
+    #[test]
+    fn test_monthly_by_month_day() {
+        let due = at(2024, 11, 1, 0);
+        assert_eq!(
+            occurrences("FREQ=MONTHLY;BYMONTHDAY=1", due, 4),
+            vec![
+                due,
+                at(2024, 12, 1, 0),
+                at(2025, 1, 1, 0),
+                at(2025, 2, 1, 0)
+            ]
+        );
+    }
+
+    /// Test that the last day of the month follows the length of each month.
+    #[test]
+    fn test_monthly_last_day() {
+        let due = at(2024, 1, 31, 0);
+        assert_eq!(
+            occurrences("FREQ=MONTHLY;BYMONTHDAY=-1", due, 4),
+            vec![
+                due,
+                at(2024, 2, 29, 0),
+                at(2024, 3, 31, 0),
+                at(2024, 4, 30, 0)
+            ]
+        );
+    }
+
+    /// Test that months without the day are skipped, as RFC 5545 requires.
+    #[test]
+    fn test_monthly_skips_short_months() {
+        let due = at(2024, 1, 31, 0);
+        assert_eq!(
+            occurrences("FREQ=MONTHLY", due, 3),
+            vec![due, at(2024, 3, 31, 0), at(2024, 5, 31, 0)]
+        );
+    }
+
+    /// Test the first Monday and last Friday of each month.
+    #[test]
+    fn test_monthly_by_day_ordinal() {
+        // Monday 1st January 2024.
+        let due = at(2024, 1, 1, 0);
+        assert_eq!(
+            occurrences("FREQ=MONTHLY;BYDAY=1MO", due, 3),
+            vec![due, at(2024, 2, 5, 0), at(2024, 3, 4, 0)]
+        );
+
+        // Friday 26th January 2024.
+        let due = at(2024, 1, 26, 0);
+        assert_eq!(
+            occurrences("FREQ=MONTHLY;BYDAY=-1FR", due, 3),
+            vec![due, at(2024, 2, 23, 0), at(2024, 3, 29, 0)]
+        );
+    }
+
+    /// Test that BYDAY limits BYMONTHDAY, e.g. every Friday the 13th.
+    #[test]
+    fn test_monthly_by_day_and_month_day() {
+        // Friday 13th October 2023.
+        let due = at(2023, 10, 13, 0);
+        assert_eq!(
+            occurrences("FREQ=MONTHLY;BYDAY=FR;BYMONTHDAY=13", due, 3),
+            vec![due, at(2024, 9, 13, 0), at(2024, 12, 13, 0)]
+        );
+    }
+
+    /// Test a quarterly rule.
+    #[test]
+    fn test_monthly_interval() {
+        let due = at(2024, 11, 15, 0);
+        assert_eq!(
+            occurrences("FREQ=MONTHLY;INTERVAL=3", due, 3),
+            vec![due, at(2025, 2, 15, 0), at(2025, 5, 15, 0)]
+        );
+    }
+
+    /// Test a yearly rule, including one on a leap day.
+    #[test]
+    fn test_yearly() {
+        let due = at(2024, 6, 1, 0);
+        assert_eq!(
+            occurrences("FREQ=YEARLY", due, 3),
+            vec![due, at(2025, 6, 1, 0), at(2026, 6, 1, 0)]
+        );
+
+        let due = at(2024, 2, 29, 0);
+        assert_eq!(
+            occurrences("FREQ=YEARLY", due, 3),
+            vec![due, at(2028, 2, 29, 0), at(2032, 2, 29, 0)]
+        );
+    }
+
+    /// Test that a rule which can never match again gives up.
+    #[test]
+    fn test_never_matches() {
+        // Monday 1st January 2024, so every seventh day is also a Monday.
+        let rule: Recurrence = "FREQ=DAILY;INTERVAL=7;BYDAY=TU".parse().unwrap();
+        assert_eq!(rule.next_after(at(2024, 1, 1, 0)), None);
+    }
+
+    /// Test that COUNT is the number of occurrences left.
+    #[test]
+    fn test_count() {
+        let due = at(2024, 1, 1, 0);
+        assert_eq!(occurrences("FREQ=DAILY;COUNT=3", due, 10).len(), 3);
+
+        let rule: Recurrence = "FREQ=DAILY;COUNT=3".parse().unwrap();
+        let (_, rule) = rule.advance(due).unwrap();
+        assert_eq!(rule.to_string(), "FREQ=DAILY;COUNT=2");
+    }
+
+    /// Test that UNTIL is inclusive and accepts dates and date-times.
+    #[test]
+    fn test_until() {
+        let due = at(2024, 1, 1, 9);
+        assert_eq!(
+            occurrences("FREQ=DAILY;UNTIL=20240103", due, 10),
+            vec![due, at(2024, 1, 2, 9), at(2024, 1, 3, 9)]
+        );
+        assert_eq!(
+            occurrences("FREQ=DAILY;UNTIL=20240103T090000Z", due, 10).len(),
+            3
+        );
+        assert_eq!(
+            occurrences("FREQ=DAILY;UNTIL=20240103T085959Z", due, 10).len(),
+            2
+        );
+    }
+
+    /// Test parsing and formatting rules.
+    #[test]
+    fn test_parse() {
+        for rule in [
+            "FREQ=DAILY",
+            "FREQ=WEEKLY;INTERVAL=2;BYDAY=TU,TH",
+            "FREQ=MONTHLY;BYDAY=1MO,-1FR",
+            "FREQ=MONTHLY;BYMONTHDAY=1,-1;COUNT=12",
+            "FREQ=YEARLY;UNTIL=20301231T235959Z",
+        ] {
+            assert_eq!(rule.parse::<Recurrence>().unwrap().to_string(), rule);
+        }
+
+        assert_eq!(
+            "RRULE:freq=weekly;byday=tu"
+                .parse::<Recurrence>()
+                .unwrap()
+                .to_string(),
+            "FREQ=WEEKLY;BYDAY=TU"
+        );
+        assert_eq!(
+            "FREQ=DAILY;UNTIL=20240103"
+                .parse::<Recurrence>()
+                .unwrap()
+                .to_string(),
+            "FREQ=DAILY;UNTIL=20240103T235959Z"
+        );
+    }
+
+    /// Test that unsupported or malformed rules are rejected.
+    #[test]
+    fn test_parse_invalid() {
+        for rule in [
+            "",
+            "INTERVAL=2",
+            "FREQ=HOURLY",
+            "FREQ=DAILY;INTERVAL=0",
+            "FREQ=DAILY;BYDAY=XX",
+            "FREQ=WEEKLY;BYDAY=1MO",
+            "FREQ=WEEKLY;BYMONTHDAY=1",
+            "FREQ=MONTHLY;BYMONTHDAY=32",
+            "FREQ=DAILY;COUNT=0",
+            "FREQ=DAILY;COUNT=2;UNTIL=20240101",
+            "FREQ=DAILY;UNTIL=20240230",
+            "FREQ=DAILY;BYSETPOS=1",
+            "FREQ",
+        ] {
+            assert!(rule.parse::<Recurrence>().is_err(), "{rule}");
+        }
+    }
+}
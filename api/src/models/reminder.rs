@@ -1,10 +1,9 @@
 //! Reminder model.
+use crate::models::recurrence::Recurrence;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use std::collections::HashMap;
 
 /// Return a string with the first letter capitalised.
-fn fix_case(s: &str) -> String {
+pub fn fix_case(s: &str) -> String {
     s.chars()
         .enumerate()
         .map(|(i, c)| {
@@ -16,30 +15,10 @@ fn fix_case(s: &str) -> String {
         .collect()
 }
 
-pub fn reminders_to_firebase(reminders: Vec<Reminder>) -> HashMap<String, HashMap<String, Value>> {
-    reminders
-        .into_iter()
-        .map(|r| {
-            let mut inner: HashMap<String, Value> = HashMap::new();
-
-            let assignee = match r.assignee {
-                Some(a) => Value::String(a.into()),
-                None => Value::Null,
-            };
-
-            inner.insert("title".into(), Value::String(r.title));
-            inner.insert("due".into(), Value::Number(r.due.into()));
-            inner.insert("priority".into(), Value::Number(r.priority.into()));
-            inner.insert("assignee".into(), assignee);
-
-            (r.id.unwrap_or_default(), inner)
-        })
-        .collect()
-}
-
 /// Reminder model
 ///
-/// When serializing, the id field is skipped if it is None.
+/// When serializing, the id field is skipped if it is None, and the completion
+/// and recurrence fields are skipped while unset so older clients see no change.
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Reminder {
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -48,34 +27,40 @@ pub struct Reminder {
     pub due: u64,
     pub priority: u64,
     pub assignee: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub completed: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completed_at: Option<u64>,
+    /// Recurrence rule; completing a recurring reminder moves `due` to the next
+    /// occurrence instead of completing it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rrule: Option<Recurrence>,
 }
 
 impl Reminder {
-    /// Create a new Reminder.
-    ///
-    /// # Arguments
+    /// Complete the current occurrence of the reminder.
     ///
-    /// * `json` - A HashMap of HashMaps of Values.
+    /// A recurring reminder stays open and moves on to its next occurrence. It
+    /// is only marked as completed once its rule has run out.
     ///
-    /// # Returns
+    /// # Arguments
     ///
-    /// A Vec of Reminders.
-    pub fn from_json(json: HashMap<String, HashMap<String, Value>>) -> Vec<Reminder> {
-        json.into_iter()
-            .map(|(id, content)| Reminder {
-                id: Some(id),
-                title: fix_case(content["title"].as_str().unwrap_or("")),
-                due: content["due"].as_u64().unwrap_or(0),
-                priority: content["priority"].as_u64().unwrap_or(0),
-                assignee: match content.get("assignee") {
-                    None => None,
-                    Some(value) => match value {
-                        Value::String(_) => Some(value.as_str().expect("Not a string").to_string()),
-                        _ => None,
-                    },
-                },
-            })
-            .collect()
+    /// * `now` - The completion time, in seconds since the epoch.
+    pub fn complete(&mut self, now: u64) {
+        if let Some((due, rule)) = self.rrule.as_ref().and_then(|r| r.advance(self.due)) {
+            self.due = due;
+            self.rrule = Some(rule);
+            return;
+        }
+
+        self.completed = true;
+        self.completed_at = Some(now);
+    }
+
+    /// Mark the reminder as not completed.
+    pub fn uncomplete(&mut self) {
+        self.completed = false;
+        self.completed_at = None;
     }
 }
 
@@ -90,30 +75,27 @@ impl std::cmp::PartialEq for Reminder {
     ///
     /// A boolean.
     fn eq(&self, other: &Self) -> bool {
-        if self.id == other.id {
-            true
-        } else {
-            false
-        }
+        self.id == other.id
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::models::record::{ReminderRecord, WarningKind};
     use crate::models::reminder::{fix_case, Reminder};
     use std::collections::HashMap;
 
-    /// Test whether the `Reminder::from_json` correctly interprets a Firebase response.
+    /// Test whether a stored Firebase entry is read correctly.
     #[test]
     fn test_from_json() {
-        let mut outer: HashMap<String, HashMap<String, serde_json::Value>> = HashMap::new();
-        let mut inner: HashMap<String, serde_json::Value> = HashMap::new();
+        let mut outer: HashMap<String, serde_json::Value> = HashMap::new();
+        let mut inner = serde_json::Map::new();
 
         inner.insert("title".into(), "Hello, world".into());
         inner.insert("due".into(), 1234.into());
         inner.insert("priority".into(), 0.into());
         inner.insert("assignee".into(), serde_json::Value::Null);
-        outer.insert("abc".into(), inner);
+        outer.insert("abc".into(), inner.into());
 
         let r = vec![Reminder {
             title: "Hello, world".into(),
@@ -121,21 +103,28 @@ mod tests {
             id: Some("abc".into()),
             priority: 0,
             assignee: None,
+            completed: false,
+            completed_at: None,
+            rrule: None,
         }];
 
-        assert_eq!(r, Reminder::from_json(outer))
+        let decoded = ReminderRecord::decode_all(outer);
+        assert!(decoded.warnings.is_empty());
+        assert_eq!(r, decoded.reminders)
     }
 
+    /// Test that an assignee that is not a string is left unset with a warning,
+    /// rather than losing the reminder.
     #[test]
     fn test_from_json_invalid_assignee() {
-        let mut outer: HashMap<String, HashMap<String, serde_json::Value>> = HashMap::new();
-        let mut inner: HashMap<String, serde_json::Value> = HashMap::new();
+        let mut outer: HashMap<String, serde_json::Value> = HashMap::new();
+        let mut inner = serde_json::Map::new();
 
         inner.insert("title".into(), "Hello, world".into());
         inner.insert("due".into(), 1234.into());
         inner.insert("priority".into(), 0.into());
         inner.insert("assignee".into(), 123.into());
-        outer.insert("abc".into(), inner);
+        outer.insert("abc".into(), inner.into());
 
         let r = vec![Reminder {
             title: "Hello, world".into(),
@@ -143,9 +132,20 @@ mod tests {
             id: Some("abc".into()),
             priority: 0,
             assignee: None,
+            completed: false,
+            completed_at: None,
+            rrule: None,
         }];
 
-        assert_eq!(r, Reminder::from_json(outer))
+        let decoded = ReminderRecord::decode_all(outer);
+        assert_eq!(r, decoded.reminders);
+        assert_eq!(decoded.reminders[0].assignee, None);
+        assert_eq!(decoded.warnings.len(), 1);
+        assert_eq!(decoded.warnings[0].id, "abc");
+        assert!(matches!(
+            &decoded.warnings[0].kind,
+            WarningKind::InvalidField { field, .. } if field == "assignee"
+        ));
     }
 
     /// Test that a Reminder serialises properly when no ID is set.
@@ -157,6 +157,9 @@ mod tests {
             id: None,
             priority: 0,
             assignee: Some("Sam".into()),
+            completed: false,
+            completed_at: None,
+            rrule: None,
         };
 
         let json = serde_json::to_string(&r).unwrap();
@@ -176,6 +179,9 @@ mod tests {
             id: Some("asdf".into()),
             priority: 0,
             assignee: None,
+            completed: false,
+            completed_at: None,
+            rrule: None,
         };
 
         let json = serde_json::to_string(&r).unwrap();
@@ -186,6 +192,75 @@ mod tests {
         )
     }
 
+    /// Test that completion fields are serialised once a reminder is completed.
+    #[test]
+    fn test_serialising_completed() {
+        let mut r = Reminder {
+            title: "Hello, world".into(),
+            due: 1234,
+            id: None,
+            priority: 0,
+            assignee: None,
+            completed: false,
+            completed_at: None,
+            rrule: None,
+        };
+        r.complete(5678);
+
+        let json = serde_json::to_string(&r).unwrap();
+
+        assert_eq!(
+            json,
+            r#"{"title":"Hello, world","due":1234,"priority":0,"assignee":null,"completed":true,"completed_at":5678}"#
+        );
+
+        r.uncomplete();
+        let parsed: Reminder = serde_json::from_str(&serde_json::to_string(&r).unwrap()).unwrap();
+        assert!(!parsed.completed);
+        assert_eq!(parsed.completed_at, None);
+    }
+
+    /// Test that completing a recurring reminder advances it until the rule runs out.
+    #[test]
+    fn test_complete_recurring() {
+        let mut r = Reminder {
+            title: "Bins".into(),
+            due: 1704222000,
+            id: None,
+            priority: 0,
+            assignee: None,
+            completed: false,
+            completed_at: None,
+            rrule: Some("FREQ=WEEKLY;COUNT=2".parse().unwrap()),
+        };
+
+        r.complete(1704225600);
+        assert!(!r.completed);
+        assert_eq!(r.due, 1704222000 + 7 * 24 * 60 * 60);
+        assert_eq!(r.rrule.as_ref().unwrap().count, Some(1));
+
+        r.complete(1704830400);
+        assert!(r.completed);
+        assert_eq!(r.completed_at, Some(1704830400));
+    }
+
+    /// Test that rules round-trip through JSON and invalid rules are rejected.
+    #[test]
+    fn test_rrule_json() {
+        let r: Reminder = serde_json::from_str(
+            r#"{"title":"Rent","due":0,"priority":0,"assignee":null,"rrule":"FREQ=MONTHLY;BYMONTHDAY=1"}"#,
+        )
+        .unwrap();
+        assert!(serde_json::to_string(&r)
+            .unwrap()
+            .ends_with(r#""rrule":"FREQ=MONTHLY;BYMONTHDAY=1"}"#));
+
+        let invalid = serde_json::from_str::<Reminder>(
+            r#"{"title":"Rent","due":0,"priority":0,"assignee":null,"rrule":"FREQ=HOURLY"}"#,
+        );
+        assert!(invalid.is_err());
+    }
+
     /// Test the fix_case function.
     #[test]
     fn test_fix_case() {
@@ -0,0 +1,117 @@
+//! Delta sync models.
+use crate::models::reminder::Reminder;
+use serde::{Deserialize, Serialize};
+
+/// The latest change to a reminder, as kept in the change log of its scope
+/// under the reminder's id.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Change {
+    /// Sequence number of the change.
+    pub seq: u64,
+    pub id: String,
+    /// Whether the reminder was deleted. Deleted reminders stay in the log as
+    /// tombstones, without the reminder.
+    #[serde(default)]
+    pub deleted: bool,
+    /// When the change was made, in seconds since the epoch.
+    pub time: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reminder: Option<Reminder>,
+}
+
+/// The changes since a sequence number.
+#[derive(Debug, Serialize)]
+pub struct Changes {
+    /// Sequence number to ask for the next changes after.
+    pub seq: u64,
+    /// Whether `changes` holds every reminder rather than only those changed,
+    /// in which case the client should replace what it has.
+    pub complete: bool,
+    pub changes: Vec<Change>,
+}
+
+/// An edit a client made, possibly while offline.
+///
+/// `timestamp` is when the client made the edit, in seconds since the epoch.
+/// `id` may be the `client_id` of a reminder the client created, whether or
+/// not the server has seen the create yet.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Operation {
+    Create {
+        /// Id the client gave the reminder, so retried creates are only
+        /// applied once.
+        client_id: String,
+        reminder: Reminder,
+        timestamp: u64,
+    },
+    Update {
+        id: String,
+        reminder: Reminder,
+        timestamp: u64,
+    },
+    Delete {
+        id: String,
+        timestamp: u64,
+    },
+}
+
+impl Operation {
+    /// When the client made the edit.
+    pub fn timestamp(&self) -> u64 {
+        match self {
+            Operation::Create { timestamp, .. }
+            | Operation::Update { timestamp, .. }
+            | Operation::Delete { timestamp, .. } => *timestamp,
+        }
+    }
+
+    /// The id the edit is for, which is the `client_id` of a create.
+    pub fn id(&self) -> &str {
+        match self {
+            Operation::Create { client_id: id, .. }
+            | Operation::Update { id, .. }
+            | Operation::Delete { id, .. } => id,
+        }
+    }
+}
+
+/// A batch of edits to apply.
+#[derive(Debug, Deserialize)]
+pub struct SyncRequest {
+    /// Sequence number of the last changes the client has, or `None` to get
+    /// every reminder back.
+    #[serde(default)]
+    pub since: Option<u64>,
+    pub operations: Vec<Operation>,
+}
+
+/// What happened to an operation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OperationStatus {
+    Applied,
+    /// The server's version changed later, so it was kept.
+    Conflict,
+    NotFound,
+}
+
+/// The outcome of one operation, in the order they were sent.
+#[derive(Debug, Serialize)]
+pub struct OperationResult {
+    pub index: usize,
+    pub status: OperationStatus,
+    /// The server's id of the reminder.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+/// The outcome of a batch, and the state after it was applied.
+#[derive(Debug, Serialize)]
+pub struct SyncResponse {
+    pub results: Vec<OperationResult>,
+    #[serde(flatten)]
+    pub changes: Changes,
+}
@@ -0,0 +1,84 @@
+//! User account models.
+use serde::{Deserialize, Serialize};
+
+/// A user account, as stored in the `accounts` document collection.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct User {
+    pub id: String,
+    pub username: String,
+    /// Argon2 hash of the password, in PHC string format.
+    pub password_hash: String,
+    /// Creation time, in seconds since the epoch.
+    pub created: u64,
+}
+
+/// A login session, as stored in the `sessions` document collection under the
+/// SHA-256 hash of its token.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Session {
+    pub user_id: String,
+    pub created: u64,
+    pub expires: u64,
+}
+
+/// Username and password sent to register or log in.
+#[derive(Deserialize)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+/// The public view of a user account.
+#[derive(Debug, Serialize)]
+pub struct Profile {
+    pub id: String,
+    pub username: String,
+}
+
+/// A newly issued bearer token.
+#[derive(Debug, Serialize)]
+pub struct TokenResponse {
+    pub token: String,
+    /// Expiry time, in seconds since the epoch.
+    pub expires: u64,
+}
+
+impl From<&User> for Profile {
+    fn from(user: &User) -> Self {
+        Profile {
+            id: user.id.clone(),
+            username: user.username.clone(),
+        }
+    }
+}
+
+/// A refresh token, as stored in the `refresh_tokens/<family>` collection under
+/// the SHA-256 hash of the token.
+///
+/// Each refresh issues a new token in the same family and marks the old one as
+/// rotated. Presenting a rotated token again revokes the whole family.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RefreshToken {
+    pub user_id: String,
+    pub created: u64,
+    pub expires: u64,
+    #[serde(default)]
+    pub rotated: bool,
+}
+
+/// Refresh token sent to refresh or revoke it.
+#[derive(Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+/// A newly issued access token and refresh token.
+#[derive(Debug, Serialize)]
+pub struct TokenPair {
+    /// Signed JWT access token.
+    pub access_token: String,
+    pub token_type: &'static str,
+    /// Lifetime of the access token, in seconds.
+    pub expires_in: u64,
+    pub refresh_token: String,
+}
@@ -0,0 +1,114 @@
+//! Webhook models.
+use crate::events::{Event, EventKind};
+use serde::{Deserialize, Serialize};
+
+/// A webhook, as stored in the `webhooks` document collection under its id.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Webhook {
+    pub id: String,
+    /// The user who added the webhook.
+    pub user_id: String,
+    /// URL to post events to.
+    pub url: String,
+    /// Secret the events are signed with.
+    pub secret: String,
+    /// Events to send, or every event if empty.
+    pub events: Vec<EventKind>,
+    /// The household list whose reminders to send events about, or `None`
+    /// for the user's own reminders.
+    pub household_id: Option<String>,
+    pub list_id: Option<String>,
+    /// The reminders scope the webhook is for.
+    pub scope: String,
+    pub created: u64,
+}
+
+impl Webhook {
+    /// Whether the webhook wants an event.
+    pub fn wants(&self, event: &Event) -> bool {
+        event.scope == self.scope && (self.events.is_empty() || self.events.contains(&event.kind))
+    }
+}
+
+/// A webhook to add.
+#[derive(Deserialize)]
+pub struct NewWebhook {
+    pub url: String,
+    /// Secret to sign events with. One is generated if it is not given.
+    #[serde(default)]
+    pub secret: Option<String>,
+    #[serde(default)]
+    pub events: Vec<EventKind>,
+    /// Send events about a household list's reminders instead of the user's own.
+    #[serde(default)]
+    pub household_id: Option<String>,
+    #[serde(default)]
+    pub list_id: Option<String>,
+}
+
+/// The public view of a webhook, without its secret.
+#[derive(Debug, Serialize)]
+pub struct WebhookResponse {
+    pub id: String,
+    pub url: String,
+    pub events: Vec<EventKind>,
+    pub household_id: Option<String>,
+    pub list_id: Option<String>,
+    pub created: u64,
+}
+
+/// A newly added webhook, with its secret. The secret is only shown once.
+#[derive(Debug, Serialize)]
+pub struct CreatedWebhook {
+    #[serde(flatten)]
+    pub webhook: WebhookResponse,
+    pub secret: String,
+}
+
+/// Where a delivery has got to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    /// Not delivered yet, but will be tried again.
+    Pending,
+    Succeeded,
+    /// Every attempt failed.
+    Failed,
+}
+
+/// One attempt at delivering an event.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Attempt {
+    pub time: u64,
+    /// The receiver's response status, if it responded.
+    pub status_code: Option<u16>,
+    /// Why the attempt failed, if it did.
+    pub error: Option<String>,
+}
+
+/// An event being delivered to a webhook, as stored in the
+/// `webhook_deliveries/<webhook id>` document collection under its id.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Delivery {
+    pub id: String,
+    pub webhook_id: String,
+    pub event: Event,
+    pub status: DeliveryStatus,
+    pub attempts: Vec<Attempt>,
+    /// When to try again, if the delivery is pending.
+    pub next_attempt: Option<u64>,
+    pub created: u64,
+}
+
+impl From<Webhook> for WebhookResponse {
+    fn from(webhook: Webhook) -> Self {
+        WebhookResponse {
+            id: webhook.id,
+            url: webhook.url,
+            events: webhook.events,
+            household_id: webhook.household_id,
+            list_id: webhook.list_id,
+            created: webhook.created,
+        }
+    }
+}
@@ -0,0 +1,276 @@
+//! Email daily digests over SMTP.
+//!
+//! Each digest is sent as a plain-text and an HTML alternative, listing the
+//! overdue reminders and then those due today, like the app's home page.
+use crate::config::{EmailConfig, SmtpSecurity};
+use crate::models::{recurrence::Date, reminder::Reminder};
+use crate::notifiers::{Digest, Notification, Notifier};
+use async_trait::async_trait;
+use lettre::{
+    message::{Mailbox, MultiPart},
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+use std::fmt::Write;
+use std::time::Duration;
+
+/// How long to wait for the SMTP server.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Colour the app shows overdue reminders in.
+const OVERDUE_COLOUR: &str = "#d32f2f";
+
+/// Sends digests through an SMTP server.
+pub struct Email {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl Email {
+    /// Create the notifier from the configuration. Nothing is sent until a
+    /// digest is.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the server or sender are missing or not valid.
+    pub fn from_config(config: &EmailConfig) -> Result<Self, String> {
+        let host = config
+            .host
+            .as_deref()
+            .ok_or("notifications.email.host must be set to send email")?;
+        let from = config
+            .from
+            .as_deref()
+            .ok_or("notifications.email.from must be set to send email")?
+            .parse::<Mailbox>()
+            .map_err(|e| format!("notifications.email.from: {e}"))?;
+
+        let builder = match config.security {
+            SmtpSecurity::StartTls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host),
+            SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(host),
+            SmtpSecurity::None => Ok(AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(
+                host,
+            )),
+        }
+        .map_err(|e| format!("notifications.email.host: {e}"))?;
+        let mut builder = builder.port(config.port).timeout(Some(TIMEOUT));
+        if let (Some(username), Some(password)) = (&config.username, &config.password) {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+
+        Ok(Email {
+            transport: builder.build(),
+            from,
+        })
+    }
+
+    /// Build the email for a digest.
+    pub fn message(&self, digest: &Digest) -> Result<Message, String> {
+        let to = digest.email.parse::<Mailbox>().map_err(|e| e.to_string())?;
+
+        Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(subject(digest))
+            .multipart(MultiPart::alternative_plain_html(
+                render_text(digest),
+                render_html(digest),
+            ))
+            .map_err(|e| e.to_string())
+    }
+}
+
+#[async_trait]
+impl Notifier for Email {
+    fn name(&self) -> &'static str {
+        "email"
+    }
+
+    /// Email only sends digests, not a message per reminder.
+    async fn notify(&self, _notification: &Notification) -> Result<(), String> {
+        Ok(())
+    }
+
+    async fn digest(&self, digest: &Digest) -> Result<(), String> {
+        self.transport
+            .send(self.message(digest)?)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        Ok(())
+    }
+}
+
+/// The subject of a digest, such as `Reminders for 08 Mar 2024: 1 overdue, 2 today`.
+pub fn subject(digest: &Digest) -> String {
+    format!(
+        "Reminders for {}: {} overdue, {} today",
+        digest.date,
+        digest.overdue.len(),
+        digest.today.len()
+    )
+}
+
+/// Render a digest as plain text.
+pub fn render_text(digest: &Digest) -> String {
+    let mut text = format!("Your reminders for {}\n", digest.date);
+    for (heading, reminders) in groups(digest) {
+        let _ = write!(text, "\n{heading}\n");
+        for reminder in reminders {
+            let _ = writeln!(text, "- {}", describe(digest, reminder));
+        }
+    }
+
+    text
+}
+
+/// Render a digest as HTML.
+pub fn render_html(digest: &Digest) -> String {
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<body>\n<h1>Your reminders for {}</h1>\n",
+        digest.date
+    );
+    for (heading, reminders) in groups(digest) {
+        let style = match heading {
+            "Overdue" => format!(" style=\"color: {OVERDUE_COLOUR}\""),
+            _ => String::new(),
+        };
+        let _ = writeln!(html, "<h2>{heading}</h2>\n<ul>");
+        for reminder in reminders {
+            let _ = writeln!(
+                html,
+                "<li{style}>{}</li>",
+                escape(&describe(digest, reminder))
+            );
+        }
+        html.push_str("</ul>\n");
+    }
+    html.push_str("</body>\n</html>\n");
+
+    html
+}
+
+/// The groups of a digest that have reminders in them, with their headings.
+fn groups(digest: &Digest) -> impl Iterator<Item = (&'static str, &Vec<Reminder>)> {
+    [("Overdue", &digest.overdue), ("Today", &digest.today)]
+        .into_iter()
+        .filter(|(_, reminders)| !reminders.is_empty())
+}
+
+/// Describe a reminder the way the app does: its title, then its due date if
+/// that is not today, then who it is assigned to.
+fn describe(digest: &Digest, reminder: &Reminder) -> String {
+    let offset = i64::from(digest.utc_offset_minutes) * 60;
+    let due = Date::from_days((reminder.due as i64 + offset).div_euclid(24 * 60 * 60));
+
+    let mut description = reminder.title.clone();
+    if due != digest.date {
+        let _ = write!(description, " ({due})");
+    }
+    if let Some(assignee) = &reminder.assignee {
+        let _ = write!(description, " - {assignee}");
+    }
+
+    description
+}
+
+/// Escape text for HTML.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{EmailConfig, SmtpSecurity};
+    use crate::models::{recurrence::Date, reminder::Reminder};
+    use crate::notifiers::{email::Email, Digest, Notifier};
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    fn reminder(title: &str, due: u64, assignee: Option<&str>) -> Reminder {
+        Reminder {
+            id: None,
+            title: title.into(),
+            due,
+            priority: 0,
+            assignee: assignee.map(String::from),
+            completed: false,
+            completed_at: None,
+            rrule: None,
+        }
+    }
+
+    /// Accept one email over SMTP and return its data.
+    async fn smtp_sink(listener: tokio::net::TcpListener) -> String {
+        let (socket, _) = listener.accept().await.unwrap();
+        let (read, mut write) = socket.into_split();
+        let mut lines = BufReader::new(read).lines();
+        write.write_all(b"220 localhost ESMTP\r\n").await.unwrap();
+
+        let mut data = String::new();
+        while let Some(line) = lines.next_line().await.unwrap() {
+            let command = line.to_ascii_uppercase();
+            let reply: &[u8] = if command.starts_with("EHLO") {
+                b"250 localhost\r\n"
+            } else if command.starts_with("DATA") {
+                write.write_all(b"354 go ahead\r\n").await.unwrap();
+                while let Some(line) = lines.next_line().await.unwrap() {
+                    if line == "." {
+                        break;
+                    }
+                    data.push_str(&line);
+                    data.push('\n');
+                }
+                b"250 queued\r\n"
+            } else if command.starts_with("QUIT") {
+                write.write_all(b"221 bye\r\n").await.unwrap();
+                break;
+            } else {
+                b"250 ok\r\n"
+            };
+            write.write_all(reply).await.unwrap();
+        }
+
+        data
+    }
+
+    /// Test sending a digest to a local SMTP sink.
+    #[tokio::test]
+    async fn test_send_digest() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let sink = tokio::spawn(smtp_sink(listener));
+
+        let config = EmailConfig {
+            host: Some("127.0.0.1".into()),
+            port,
+            security: SmtpSecurity::None,
+            username: None,
+            password: None,
+            from: Some("Reminders <reminders@example.com>".into()),
+        };
+        let email = Email::from_config(&config).unwrap();
+        // 2024-03-08, a day after "Bins" was due.
+        let digest = Digest {
+            user_id: "u1".into(),
+            email: "sam@example.com".into(),
+            date: Date::from_days(19_790),
+            utc_offset_minutes: 0,
+            overdue: vec![reminder("Bins", 19_789 * 86_400, None)],
+            today: vec![reminder("Fish & chips", 19_790 * 86_400, Some("Alex"))],
+        };
+        email.digest(&digest).await.unwrap();
+
+        let data = sink.await.unwrap();
+        assert!(data.contains("To: sam@example.com"));
+        assert!(data.contains("Subject: Reminders for 08 Mar 2024: 1 overdue, 1 today"));
+        assert!(data.contains("Content-Type: text/plain"));
+        assert!(data.contains("Content-Type: text/html"));
+        assert!(data.contains("- Bins (07 Mar 2024)"));
+        assert!(data.contains("- Fish & chips - Alex"));
+        assert!(data.contains("<li>Fish &amp; chips - Alex</li>"));
+    }
+}
@@ -0,0 +1,37 @@
+//! Write notifications to the server log.
+use crate::notifiers::{Digest, Notification, Notifier};
+use async_trait::async_trait;
+
+/// Logs each notification, which is useful for trying the scheduler out.
+pub struct Log;
+
+#[async_trait]
+impl Notifier for Log {
+    fn name(&self) -> &'static str {
+        "log"
+    }
+
+    async fn notify(&self, notification: &Notification) -> Result<(), String> {
+        ::log::info!(
+            "reminder {} \"{}\" in scope \"{}\" is due for {}",
+            notification.reminder.id.as_deref().unwrap_or_default(),
+            notification.reminder.title,
+            notification.scope,
+            notification.recipients.join(", ")
+        );
+
+        Ok(())
+    }
+
+    async fn digest(&self, digest: &Digest) -> Result<(), String> {
+        ::log::info!(
+            "digest for {} on {}: {} overdue, {} due today",
+            digest.user_id,
+            digest.date,
+            digest.overdue.len(),
+            digest.today.len()
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,142 @@
+//! Backends that tell people a reminder is due.
+//!
+//! The scheduler hands each due reminder, and each user's daily digest, to
+//! every configured [`Notifier`]. A backend that fails is logged and does not
+//! stop the others.
+pub mod email;
+mod log;
+pub mod webpush;
+use crate::config::{NotificationsConfig, NotifierBackend};
+use crate::households;
+use crate::middleware::auth::SHARED_USER;
+use crate::models::{recurrence::Date, reminder::Reminder};
+use crate::store::{self, DocumentStore, Storage};
+use async_trait::async_trait;
+use std::sync::Arc;
+
+/// A reminder that has fallen due.
+#[derive(Debug, Clone)]
+pub struct Notification {
+    /// The reminders scope the reminder is in.
+    pub scope: String,
+    /// Ids of the users who can see the reminder.
+    pub recipients: Vec<String>,
+    pub reminder: Reminder,
+}
+
+/// A user's daily digest of overdue reminders and reminders due today.
+#[derive(Debug, Clone)]
+pub struct Digest {
+    pub user_id: String,
+    /// Address to send the digest to.
+    pub email: String,
+    /// The user's local date.
+    pub date: Date,
+    /// The user's time zone, as minutes ahead of UTC.
+    pub utc_offset_minutes: i32,
+    /// Reminders due before today, in the order the app lists them.
+    pub overdue: Vec<Reminder>,
+    /// Reminders due today, in the order the app lists them.
+    pub today: Vec<Reminder>,
+}
+
+/// A way of delivering notifications.
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    /// Name of the backend, used in logs.
+    fn name(&self) -> &'static str;
+
+    /// Deliver a notification to its recipients.
+    ///
+    /// # Errors
+    ///
+    /// Returns a description of the failure if it could not be delivered.
+    async fn notify(&self, notification: &Notification) -> Result<(), String>;
+
+    /// Deliver a user's daily digest. Backends that do not send digests ignore it.
+    ///
+    /// # Errors
+    ///
+    /// Returns a description of the failure if it could not be delivered.
+    async fn digest(&self, _digest: &Digest) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Build the configured notifier backends.
+///
+/// # Arguments
+///
+/// * `store` - The storage backends keep their own state in, such as subscriptions.
+///
+/// # Errors
+///
+/// Returns an error if a backend's settings are not valid.
+pub fn from_config(
+    store: Arc<dyn Storage>,
+    config: &NotificationsConfig,
+) -> Result<Vec<Arc<dyn Notifier>>, String> {
+    config
+        .backends
+        .iter()
+        .map(|backend| -> Result<Arc<dyn Notifier>, String> {
+            Ok(match backend {
+                NotifierBackend::Log => Arc::new(log::Log),
+                NotifierBackend::Email => Arc::new(email::Email::from_config(&config.email)?),
+                NotifierBackend::WebPush => Arc::new(webpush::WebPush::from_config(
+                    store.clone(),
+                    &config.webpush,
+                )?),
+            })
+        })
+        .collect()
+}
+
+/// Get the ids of the users who can see the reminders in a scope.
+///
+/// # Arguments
+///
+/// * `scope` - The shared scope, a user's scope or a household list's scope.
+///
+/// # Returns
+///
+/// The user ids. A household list's reminders go to every member.
+pub async fn recipients(docs: &dyn DocumentStore, scope: &str) -> store::Result<Vec<String>> {
+    if scope.is_empty() {
+        return Ok(vec![SHARED_USER.into()]);
+    }
+
+    let parts: Vec<&str> = scope.split('/').collect();
+    match parts.as_slice() {
+        ["users", user_id] => Ok(vec![user_id.to_string()]),
+        ["households", household_id, "lists", _] => {
+            households::member_ids(docs, household_id).await
+        }
+        _ => Ok(Vec::new()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::households;
+    use crate::middleware::auth::AuthUser;
+    use crate::notifiers::recipients;
+    use crate::store::memory::Memory;
+
+    /// Test who is notified about the reminders in each kind of scope.
+    #[tokio::test]
+    async fn test_recipients() {
+        let docs = Memory::default();
+        let owner = AuthUser {
+            id: "u1".into(),
+            username: "sam".into(),
+        };
+        let household = households::create(&docs, &owner, "Home").await.unwrap();
+        let scope = households::list_scope(&household.id, "l1");
+
+        assert_eq!(recipients(&docs, "").await.unwrap(), vec!["shared"]);
+        assert_eq!(recipients(&docs, "users/u2").await.unwrap(), vec!["u2"]);
+        assert_eq!(recipients(&docs, &scope).await.unwrap(), vec!["u1"]);
+        assert!(recipients(&docs, "elsewhere").await.unwrap().is_empty());
+    }
+}
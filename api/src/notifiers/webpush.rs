@@ -0,0 +1,491 @@
+//! Send notifications to browsers with Web Push.
+//!
+//! Messages are encrypted for each subscription with the `aes128gcm` content
+//! coding of RFC 8291, and the server identifies itself to push services with
+//! a VAPID token (RFC 8292) signed by its P-256 key.
+use crate::config::WebPushConfig;
+use crate::models::push::PushSubscription;
+use crate::notifiers::{Notification, Notifier};
+use crate::push;
+use crate::store::Storage;
+use aes_gcm::{aead::Aead, Aes128Gcm, KeyInit};
+use async_trait::async_trait;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hkdf::Hkdf;
+use p256::ecdsa::{signature::Signer, Signature, SigningKey};
+use p256::{elliptic_curve::sec1::ToEncodedPoint, PublicKey, SecretKey};
+use rand::RngCore;
+use sha2::Sha256;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Size of the single record a message is sent as.
+const RECORD_SIZE: u32 = 4096;
+
+/// Largest payload that fits in one record, after the padding delimiter and
+/// authentication tag.
+const MAX_PAYLOAD: usize = RECORD_SIZE as usize - 17;
+
+/// How long a VAPID token is valid for. Push services reject more than 24 hours.
+const TOKEN_LIFETIME: u64 = 12 * 60 * 60;
+
+/// How long to wait for a push service to respond.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The server's VAPID identity.
+pub struct Vapid {
+    key: SigningKey,
+    /// A `mailto:` or `https:` URL push services can contact the operator at.
+    subject: String,
+}
+
+impl Vapid {
+    /// Read the VAPID key and subject from the configuration.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if either is missing or not valid.
+    pub fn from_config(config: &WebPushConfig) -> Result<Self, String> {
+        let subject = config
+            .subject
+            .clone()
+            .ok_or("notifications.webpush.subject must be set to use Web Push")?;
+        if !(subject.starts_with("mailto:") || subject.starts_with("https://")) {
+            return Err("notifications.webpush.subject must be a mailto: or https: URL".into());
+        }
+
+        let key = config
+            .private_key
+            .as_deref()
+            .ok_or("notifications.webpush.private_key must be set to use Web Push")?;
+        let key = push::decode_key(key)
+            .and_then(|key| SigningKey::from_slice(&key).ok())
+            .ok_or("notifications.webpush.private_key must be a base64 P-256 private key")?;
+
+        Ok(Vapid { key, subject })
+    }
+
+    /// The public key browsers subscribe with, as unpadded URL-safe base64.
+    pub fn public_key(&self) -> String {
+        let point = self.key.verifying_key().to_encoded_point(false);
+        URL_SAFE_NO_PAD.encode(point.as_bytes())
+    }
+
+    /// Build the `Authorization` header for a request to a push service.
+    ///
+    /// # Arguments
+    ///
+    /// * `endpoint` - The subscription's endpoint; the token is for its origin.
+    /// * `now` - The current time, in seconds since the epoch.
+    pub fn authorization(&self, endpoint: &str, now: u64) -> Result<String, String> {
+        let origin = reqwest::Url::parse(endpoint)
+            .map_err(|e| e.to_string())?
+            .origin()
+            .ascii_serialization();
+        let header = URL_SAFE_NO_PAD.encode(r#"{"typ":"JWT","alg":"ES256"}"#);
+        let claims = serde_json::json!({
+            "aud": origin,
+            "exp": now + TOKEN_LIFETIME,
+            "sub": self.subject,
+        });
+        let claims = URL_SAFE_NO_PAD.encode(claims.to_string());
+
+        let message = format!("{header}.{claims}");
+        let signature: Signature = self.key.sign(message.as_bytes());
+        let token = format!("{message}.{}", URL_SAFE_NO_PAD.encode(signature.to_bytes()));
+
+        Ok(format!("vapid t={token}, k={}", self.public_key()))
+    }
+}
+
+/// Encrypt a message for a subscription with the `aes128gcm` content coding.
+///
+/// # Arguments
+///
+/// * `p256dh` - The browser's public key, uncompressed.
+/// * `auth` - The browser's authentication secret.
+/// * `payload` - The message.
+///
+/// # Returns
+///
+/// The encrypted body, including the header with the salt and the server's
+/// one-off public key.
+pub fn encrypt(p256dh: &[u8], auth: &[u8], payload: &[u8]) -> Result<Vec<u8>, String> {
+    let mut salt = [0; 16];
+    rand::thread_rng().fill_bytes(&mut salt);
+
+    encrypt_with(
+        &SecretKey::random(&mut rand::thread_rng()),
+        salt,
+        p256dh,
+        auth,
+        payload,
+    )
+}
+
+/// Encrypt a message with the given one-off key and salt.
+fn encrypt_with(
+    secret: &SecretKey,
+    salt: [u8; 16],
+    p256dh: &[u8],
+    auth: &[u8],
+    payload: &[u8],
+) -> Result<Vec<u8>, String> {
+    if payload.len() > MAX_PAYLOAD {
+        return Err(format!("payload is over {MAX_PAYLOAD} bytes"));
+    }
+    let ua_public = PublicKey::from_sec1_bytes(p256dh).map_err(|_| "bad p256dh")?;
+    let as_public = secret.public_key().to_encoded_point(false);
+    let shared = p256::ecdh::diffie_hellman(secret.to_nonzero_scalar(), ua_public.as_affine());
+
+    // Mix the browser's authentication secret into the shared secret.
+    let mut info = b"WebPush: info\0".to_vec();
+    info.extend_from_slice(p256dh);
+    info.extend_from_slice(as_public.as_bytes());
+    let mut ikm = [0; 32];
+    Hkdf::<Sha256>::new(Some(auth), shared.raw_secret_bytes())
+        .expand(&info, &mut ikm)
+        .map_err(|e| e.to_string())?;
+
+    let hkdf = Hkdf::<Sha256>::new(Some(&salt), &ikm);
+    let mut key = [0; 16];
+    let mut nonce = [0; 12];
+    hkdf.expand(b"Content-Encoding: aes128gcm\0", &mut key)
+        .and_then(|_| hkdf.expand(b"Content-Encoding: nonce\0", &mut nonce))
+        .map_err(|e| e.to_string())?;
+
+    // A single, final record: the payload then the last record delimiter.
+    let mut record = payload.to_vec();
+    record.push(2);
+    let ciphertext = Aes128Gcm::new(&key.into())
+        .encrypt(&nonce.into(), record.as_slice())
+        .map_err(|e| e.to_string())?;
+
+    let mut body = salt.to_vec();
+    body.extend_from_slice(&RECORD_SIZE.to_be_bytes());
+    body.push(as_public.len() as u8);
+    body.extend_from_slice(as_public.as_bytes());
+    body.extend_from_slice(&ciphertext);
+
+    Ok(body)
+}
+
+/// What happened to a push message.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Delivery {
+    /// The push service accepted it.
+    Sent,
+    /// The subscription has expired or been withdrawn.
+    Gone,
+}
+
+/// Sends due reminders to each recipient's subscribed browsers.
+pub struct WebPush {
+    store: Arc<dyn Storage>,
+    vapid: Vapid,
+    /// How long push services keep a message for a browser that is offline, in seconds.
+    ttl: u64,
+    /// Whether subscriptions may reach loopback, private and link-local addresses.
+    allow_private: bool,
+}
+
+impl WebPush {
+    /// Create the notifier from the configuration.
+    ///
+    /// # Arguments
+    ///
+    /// * `store` - Where subscriptions are kept.
+    pub fn from_config(store: Arc<dyn Storage>, config: &WebPushConfig) -> Result<Self, String> {
+        Ok(WebPush {
+            store,
+            vapid: Vapid::from_config(config)?,
+            ttl: config.ttl_secs,
+            allow_private: config.allow_private,
+        })
+    }
+
+    /// Send a message to a subscription.
+    ///
+    /// The endpoint's host is resolved and checked for each message, so a
+    /// subscription cannot be pointed at the server's own network later.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the endpoint resolves to a private address, or the
+    /// push service cannot be reached or refuses the message.
+    pub async fn send(
+        &self,
+        subscription: &PushSubscription,
+        payload: &[u8],
+    ) -> Result<Delivery, String> {
+        let p256dh = push::decode_key(&subscription.keys.p256dh).ok_or("bad p256dh")?;
+        let auth = push::decode_key(&subscription.keys.auth).ok_or("bad auth")?;
+        let body = encrypt(&p256dh, &auth, payload)?;
+
+        let client = crate::webhooks::pinned(&subscription.endpoint, self.allow_private)
+            .await?
+            .timeout(TIMEOUT)
+            .build()
+            .map_err(|e| e.to_string())?;
+        let response = client
+            .post(&subscription.endpoint)
+            .header("TTL", self.ttl)
+            .header("Content-Encoding", "aes128gcm")
+            .header(reqwest::header::CONTENT_TYPE, "application/octet-stream")
+            .header(
+                reqwest::header::AUTHORIZATION,
+                self.vapid
+                    .authorization(&subscription.endpoint, crate::time::now())?,
+            )
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+
+        match response.status() {
+            status if status.is_success() => Ok(Delivery::Sent),
+            reqwest::StatusCode::NOT_FOUND | reqwest::StatusCode::GONE => Ok(Delivery::Gone),
+            status => Err(format!("push service responded with {status}")),
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for WebPush {
+    fn name(&self) -> &'static str {
+        "webpush"
+    }
+
+    async fn notify(&self, notification: &Notification) -> Result<(), String> {
+        let reminder = &notification.reminder;
+        let payload = serde_json::json!({
+            "title": reminder.title,
+            "reminder_id": reminder.id,
+            "scope": notification.scope,
+            "due": reminder.due,
+        })
+        .to_string();
+
+        let mut failures = Vec::new();
+        for user_id in &notification.recipients {
+            let subscriptions = push::subscriptions(self.store.as_ref(), user_id)
+                .await
+                .map_err(|e| e.to_string())?;
+            for subscription in subscriptions {
+                match self.send(&subscription, payload.as_bytes()).await {
+                    Ok(Delivery::Sent) => {}
+                    Ok(Delivery::Gone) => {
+                        push::expire(self.store.as_ref(), user_id, &subscription.id)
+                            .await
+                            .map_err(|e| e.to_string())?;
+                    }
+                    Err(e) => failures.push(format!("{}: {e}", subscription.endpoint)),
+                }
+            }
+        }
+
+        match failures.is_empty() {
+            true => Ok(()),
+            false => Err(failures.join("; ")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::WebPushConfig;
+    use crate::models::push::{NewPushSubscription, PushKeys};
+    use crate::models::reminder::Reminder;
+    use crate::notifiers::webpush::{encrypt_with, Vapid, WebPush};
+    use crate::notifiers::{Notification, Notifier};
+    use crate::push;
+    use crate::store::{memory::Memory, Storage};
+    use aes_gcm::{aead::Aead, Aes128Gcm, KeyInit};
+    use axum::{
+        body::Bytes,
+        extract::{Path, State},
+        http::{HeaderMap, StatusCode},
+        routing::post,
+        Router,
+    };
+    use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+    use hkdf::Hkdf;
+    use p256::ecdsa::{signature::Verifier, Signature, VerifyingKey};
+    use p256::{elliptic_curve::sec1::ToEncodedPoint, PublicKey, SecretKey};
+    use sha2::Sha256;
+    use std::sync::{Arc, Mutex};
+
+    fn decode(value: &str) -> Vec<u8> {
+        URL_SAFE_NO_PAD.decode(value).unwrap()
+    }
+
+    /// Decrypt a message the way a browser does.
+    fn decrypt(ua_secret: &SecretKey, auth: &[u8], body: &[u8]) -> Vec<u8> {
+        let (salt, rest) = body.split_at(16);
+        let id_length = rest[4] as usize;
+        let (as_public, ciphertext) = rest[5..].split_at(id_length);
+
+        let as_public = PublicKey::from_sec1_bytes(as_public).unwrap();
+        let shared =
+            p256::ecdh::diffie_hellman(ua_secret.to_nonzero_scalar(), as_public.as_affine());
+        let mut info = b"WebPush: info\0".to_vec();
+        info.extend_from_slice(ua_secret.public_key().to_encoded_point(false).as_bytes());
+        info.extend_from_slice(as_public.to_encoded_point(false).as_bytes());
+        let mut ikm = [0; 32];
+        Hkdf::<Sha256>::new(Some(auth), shared.raw_secret_bytes())
+            .expand(&info, &mut ikm)
+            .unwrap();
+
+        let hkdf = Hkdf::<Sha256>::new(Some(salt), &ikm);
+        let mut key = [0; 16];
+        let mut nonce = [0; 12];
+        hkdf.expand(b"Content-Encoding: aes128gcm\0", &mut key)
+            .unwrap();
+        hkdf.expand(b"Content-Encoding: nonce\0", &mut nonce)
+            .unwrap();
+
+        let mut record = Aes128Gcm::new(&key.into())
+            .decrypt(&nonce.into(), ciphertext)
+            .unwrap();
+        assert_eq!(record.pop(), Some(2));
+        record
+    }
+
+    /// Test encryption against the example in RFC 8291, appendix A.
+    #[test]
+    fn test_rfc8291_example() {
+        let secret =
+            SecretKey::from_slice(&decode("yfWPiYE-n46HLnH0KqZOF1fJJU3MYrct3AELtAQ-oRw")).unwrap();
+        let salt = decode("DGv6ra1nlYgDCS1FRnbzlw").try_into().unwrap();
+        let p256dh = decode("BCVxsr7N_eNgVRqvHtD0zTZsEc6-VV-JvLexhqUzORcxaOzi6-AYWXvTBHm4bjyPjs7Vd8pZGH6SRpkNtoIAiw4");
+        let auth = decode("BTBZMqHH6r4Tts7J_aSIgg");
+
+        let body = encrypt_with(
+            &secret,
+            salt,
+            &p256dh,
+            &auth,
+            b"When I grow up, I want to be a watermelon",
+        )
+        .unwrap();
+
+        assert_eq!(
+            URL_SAFE_NO_PAD.encode(body),
+            "DGv6ra1nlYgDCS1FRnbzlwAAEABBBP4z9KsN6nGRTbVYI_c7VJSPQTBtkgcy27mlmlMoZIIgDll6e3vCYLocInmYWAmS6TlzAC8wEqKK6PBru3jl7A_yl95bQpu6cVPTpK4Mqgkf1CXztLVBSt2Ks3oZwbuwXPXLWyouBWLVWGNWQexSgSxsj_Qulcy4a-fN"
+        );
+    }
+
+    /// Requests received by the mock push service.
+    type Received = Arc<Mutex<Vec<(HeaderMap, Bytes)>>>;
+
+    /// Accept messages, except for the subscription called `gone`.
+    async fn receive(
+        State(received): State<Received>,
+        Path(id): Path<String>,
+        headers: HeaderMap,
+        body: Bytes,
+    ) -> StatusCode {
+        if id == "gone" {
+            return StatusCode::GONE;
+        }
+        received.lock().unwrap().push((headers, body));
+        StatusCode::CREATED
+    }
+
+    /// Test sending a due reminder through a local mock push service.
+    #[tokio::test]
+    async fn test_mock_push_service() {
+        let received = Received::default();
+        let service = Router::new()
+            .route("/push/:id", post(receive))
+            .with_state(received.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, service).await });
+
+        let store: Arc<dyn Storage> = Arc::new(Memory::default());
+        let ua_secret = SecretKey::random(&mut rand::thread_rng());
+        let auth = [7; 16];
+        for id in ["laptop", "gone"] {
+            let subscription = NewPushSubscription {
+                endpoint: format!("http://{address}/push/{id}"),
+                keys: PushKeys {
+                    p256dh: URL_SAFE_NO_PAD
+                        .encode(ua_secret.public_key().to_encoded_point(false).as_bytes()),
+                    auth: URL_SAFE_NO_PAD.encode(auth),
+                },
+                device: None,
+            };
+            push::subscribe(store.as_ref(), "u1", subscription, true)
+                .await
+                .unwrap();
+        }
+
+        let vapid_secret = SecretKey::random(&mut rand::thread_rng());
+        let config = WebPushConfig {
+            subject: Some("mailto:ops@example.com".into()),
+            private_key: Some(URL_SAFE_NO_PAD.encode(vapid_secret.to_bytes())),
+            ttl_secs: 60,
+            allow_private: true,
+        };
+        let notifier = WebPush::from_config(store.clone(), &config).unwrap();
+        let notification = Notification {
+            scope: "users/u1".into(),
+            recipients: vec!["u1".into()],
+            reminder: Reminder {
+                id: Some("r1".into()),
+                title: "Bins".into(),
+                due: 1000,
+                priority: 0,
+                assignee: None,
+                completed: false,
+                completed_at: None,
+                rrule: None,
+            },
+        };
+        notifier.notify(&notification).await.unwrap();
+
+        let received = received.lock().unwrap().clone();
+        assert_eq!(received.len(), 1);
+        let (headers, body) = &received[0];
+        assert_eq!(headers["content-encoding"], "aes128gcm");
+        assert_eq!(headers["ttl"], "60");
+        let payload: serde_json::Value =
+            serde_json::from_slice(&decrypt(&ua_secret, &auth, body)).unwrap();
+        assert_eq!(payload["title"], "Bins");
+        assert_eq!(payload["reminder_id"], "r1");
+
+        // The VAPID token is signed by the key browsers subscribed with.
+        let authorization = headers["authorization"].to_str().unwrap();
+        let (token, public_key) = authorization
+            .strip_prefix("vapid t=")
+            .and_then(|rest| rest.split_once(", k="))
+            .unwrap();
+        assert_eq!(
+            public_key,
+            Vapid::from_config(&config).unwrap().public_key()
+        );
+        let (message, signature) = token.rsplit_once('.').unwrap();
+        let key = VerifyingKey::from_sec1_bytes(&decode(public_key)).unwrap();
+        let signature = Signature::from_slice(&decode(signature)).unwrap();
+        key.verify(message.as_bytes(), &signature).unwrap();
+        let claims: serde_json::Value =
+            serde_json::from_slice(&decode(message.split('.').nth(1).unwrap())).unwrap();
+        assert_eq!(claims["aud"], format!("http://{address}"));
+        assert_eq!(claims["sub"], "mailto:ops@example.com");
+
+        // The subscription the push service no longer knows about is forgotten.
+        let remaining = push::list(store.as_ref(), "u1").await.unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert!(remaining[0].endpoint.ends_with("/laptop"));
+
+        // Without private addresses allowed, nothing is sent to the local service.
+        let config = WebPushConfig {
+            allow_private: false,
+            ..config
+        };
+        let notifier = WebPush::from_config(store.clone(), &config).unwrap();
+        let error = notifier.notify(&notification).await.unwrap_err();
+        assert!(error.contains("private address"), "{error}");
+    }
+}
@@ -0,0 +1,312 @@
+//! Web Push subscriptions.
+//!
+//! A browser subscribes with its vendor's push service, then registers the
+//! subscription here so due reminders can be sent to it. Each user can have
+//! a subscription per browser or device. They are kept in the
+//! `push_subscriptions/<user id>` document collections, under an id derived
+//! from the endpoint, so subscribing the same browser again replaces its
+//! subscription.
+use crate::models::{
+    generic_response::ResponseMessage,
+    push::{NewPushSubscription, PushSubscription, PushSubscriptionResponse},
+};
+use crate::store::{self, from_document, to_document, DocumentStore};
+use axum::{http::StatusCode, response::IntoResponse};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+
+const MAX_ENDPOINT_LENGTH: usize = 2048;
+const MAX_DEVICE_LENGTH: usize = 100;
+
+/// Errors that can occur when managing push subscriptions.
+#[derive(Debug)]
+pub enum Error {
+    NotFound,
+    NotConfigured,
+    InvalidSubscription(&'static str),
+    Store(store::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl std::error::Error for Error {}
+
+/// Allow Error to be displayed.
+impl std::fmt::Display for Error {
+    /// Display Error.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::NotFound => write!(f, "Push subscription not found"),
+            Error::NotConfigured => write!(f, "Web Push is not configured"),
+            Error::InvalidSubscription(e) => write!(f, "Invalid push subscription: {e}"),
+            Error::Store(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::convert::From<store::Error> for Error {
+    fn from(value: store::Error) -> Self {
+        Error::Store(value)
+    }
+}
+
+impl std::convert::From<Error> for axum::response::Response {
+    fn from(value: Error) -> Self {
+        let status = match value {
+            Error::NotFound | Error::NotConfigured => StatusCode::NOT_FOUND,
+            Error::InvalidSubscription(_) => StatusCode::BAD_REQUEST,
+            Error::Store(e) => return e.into(),
+        };
+
+        ResponseMessage::from(value.to_string())
+            .with_status(status)
+            .into_response()
+    }
+}
+
+/// Register a browser's push subscription for a user.
+///
+/// # Arguments
+///
+/// * `user_id` - The user to send the reminders they can see to the browser.
+/// * `new` - The subscription, as the browser gave it.
+/// * `allow_private` - Whether the endpoint may use plain HTTP or name a
+///   loopback, private, link-local or `localhost` host.
+///
+/// # Errors
+///
+/// Returns `Error::InvalidSubscription` if the endpoint is not a public HTTPS
+/// URL or the keys are not a P-256 public key and a 16-byte secret.
+pub async fn subscribe(
+    docs: &dyn DocumentStore,
+    user_id: &str,
+    new: NewPushSubscription,
+    allow_private: bool,
+) -> Result<PushSubscriptionResponse> {
+    validate_endpoint(&new.endpoint, allow_private)?;
+    let p256dh = decode_key(&new.keys.p256dh).ok_or(Error::InvalidSubscription("bad p256dh"))?;
+    if p256::PublicKey::from_sec1_bytes(&p256dh).is_err() {
+        return Err(Error::InvalidSubscription("bad p256dh"));
+    }
+    if decode_key(&new.keys.auth).map(|auth| auth.len()) != Some(16) {
+        return Err(Error::InvalidSubscription("bad auth"));
+    }
+
+    let device = new
+        .device
+        .map(|device| device.trim().to_string())
+        .filter(|device| !device.is_empty());
+    if device
+        .as_ref()
+        .is_some_and(|device| device.chars().count() > MAX_DEVICE_LENGTH)
+    {
+        return Err(Error::InvalidSubscription("device name is too long"));
+    }
+
+    let subscription = PushSubscription {
+        id: crate::accounts::token_key(&new.endpoint)[..32].into(),
+        endpoint: new.endpoint,
+        keys: new.keys,
+        device,
+        created: crate::time::now(),
+    };
+    docs.put_document(
+        &collection(user_id),
+        &subscription.id,
+        to_document(&subscription)?,
+    )
+    .await?;
+
+    Ok(subscription.into())
+}
+
+/// Get a user's push subscriptions.
+pub async fn list(
+    docs: &dyn DocumentStore,
+    user_id: &str,
+) -> Result<Vec<PushSubscriptionResponse>> {
+    let mut subscriptions: Vec<PushSubscriptionResponse> = subscriptions(docs, user_id)
+        .await?
+        .into_iter()
+        .map(Into::into)
+        .collect();
+    subscriptions.sort_by_key(|subscription| subscription.created);
+
+    Ok(subscriptions)
+}
+
+/// Remove one of a user's push subscriptions.
+///
+/// # Errors
+///
+/// Returns `Error::NotFound` if the user has no subscription with the id.
+pub async fn unsubscribe(docs: &dyn DocumentStore, user_id: &str, id: &str) -> Result<()> {
+    if docs.get_document(&collection(user_id), id).await?.is_none() {
+        return Err(Error::NotFound);
+    }
+
+    Ok(expire(docs, user_id, id).await?)
+}
+
+/// Get a user's push subscriptions, including their keys.
+pub async fn subscriptions(
+    docs: &dyn DocumentStore,
+    user_id: &str,
+) -> store::Result<Vec<PushSubscription>> {
+    docs.list_documents(&collection(user_id))
+        .await?
+        .into_iter()
+        .map(|(_, subscription)| from_document(subscription))
+        .collect()
+}
+
+/// Forget a subscription the push service says no longer exists.
+pub async fn expire(docs: &dyn DocumentStore, user_id: &str, id: &str) -> store::Result<()> {
+    docs.delete_document(&collection(user_id), id).await
+}
+
+/// Decode a key given as URL-safe base64, with or without padding.
+pub fn decode_key(key: &str) -> Option<Vec<u8>> {
+    URL_SAFE_NO_PAD.decode(key.trim_end_matches('=')).ok()
+}
+
+/// Check an endpoint is somewhere push messages may be sent.
+///
+/// Plain HTTP and private addresses are only allowed with `allow_private`,
+/// for trying things out against a local push service. Host names are
+/// checked again when sending; see [`crate::webhooks::pinned`].
+fn validate_endpoint(endpoint: &str, allow_private: bool) -> Result<()> {
+    if endpoint.len() > MAX_ENDPOINT_LENGTH {
+        return Err(Error::InvalidSubscription("endpoint is too long"));
+    }
+
+    let url = reqwest::Url::parse(endpoint)
+        .map_err(|_| Error::InvalidSubscription("endpoint is not a URL"))?;
+    match url.scheme() {
+        "https" => {}
+        "http" if allow_private => {}
+        _ => return Err(Error::InvalidSubscription("endpoint must use HTTPS")),
+    }
+    let local =
+        crate::webhooks::private(&url).ok_or(Error::InvalidSubscription("endpoint has no host"))?;
+    match local && !allow_private {
+        true => Err(Error::InvalidSubscription(
+            "endpoint must not be a private address",
+        )),
+        false => Ok(()),
+    }
+}
+
+/// The collection of a user's push subscriptions, keyed by subscription id.
+fn collection(user_id: &str) -> String {
+    format!("push_subscriptions/{user_id}")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::push::{NewPushSubscription, PushKeys};
+    use crate::push::{self, Error};
+    use crate::store::memory::Memory;
+
+    fn subscription(endpoint: &str, auth: &str) -> NewPushSubscription {
+        NewPushSubscription {
+            endpoint: endpoint.into(),
+            keys: PushKeys {
+                p256dh: "BCVxsr7N_eNgVRqvHtD0zTZsEc6-VV-JvLexhqUzORcxaOzi6-AYWXvTBHm4bjyPjs7Vd8pZGH6SRpkNtoIAiw4".into(),
+                auth: auth.into(),
+            },
+            device: Some(" Laptop ".into()),
+        }
+    }
+
+    /// Test subscribing, resubscribing the same browser and unsubscribing.
+    #[tokio::test]
+    async fn test_subscriptions() {
+        let docs = Memory::default();
+        let endpoint = "https://push.example.com/send/abc";
+
+        let first = push::subscribe(
+            &docs,
+            "u1",
+            subscription(endpoint, "BTBZMqHH6r4Tts7J_aSIgg"),
+            false,
+        )
+        .await
+        .unwrap();
+        assert_eq!(first.device.as_deref(), Some("Laptop"));
+        let again = push::subscribe(
+            &docs,
+            "u1",
+            subscription(endpoint, "BTBZMqHH6r4Tts7J_aSIgg=="),
+            false,
+        )
+        .await
+        .unwrap();
+        assert_eq!(again.id, first.id);
+        assert_eq!(push::list(&docs, "u1").await.unwrap().len(), 1);
+        assert!(push::list(&docs, "u2").await.unwrap().is_empty());
+
+        let result = push::unsubscribe(&docs, "u2", &first.id).await;
+        assert!(matches!(result, Err(Error::NotFound)));
+        push::unsubscribe(&docs, "u1", &first.id).await.unwrap();
+        assert!(push::list(&docs, "u1").await.unwrap().is_empty());
+    }
+
+    /// Test that subscriptions with bad endpoints or keys are rejected.
+    #[tokio::test]
+    async fn test_invalid() {
+        let docs = Memory::default();
+        let auth = "BTBZMqHH6r4Tts7J_aSIgg";
+
+        for endpoint in [
+            "http://push.example.com/abc",
+            "not a url",
+            "ftp://localhost/",
+        ] {
+            let result = push::subscribe(&docs, "u1", subscription(endpoint, auth), false).await;
+            assert!(matches!(result, Err(Error::InvalidSubscription(_))));
+        }
+
+        let short = subscription("https://push.example.com/abc", "BTBZ");
+        let result = push::subscribe(&docs, "u1", short, false).await;
+        assert!(matches!(
+            result,
+            Err(Error::InvalidSubscription("bad auth"))
+        ));
+
+        let mut bad_key = subscription("https://push.example.com/abc", auth);
+        bad_key.keys.p256dh = "AAAA".into();
+        let result = push::subscribe(&docs, "u1", bad_key, false).await;
+        assert!(matches!(
+            result,
+            Err(Error::InvalidSubscription("bad p256dh"))
+        ));
+    }
+
+    /// Test that endpoints on the server's own network are only allowed when
+    /// private addresses are.
+    #[tokio::test]
+    async fn test_private_endpoints() {
+        let docs = Memory::default();
+        let auth = "BTBZMqHH6r4Tts7J_aSIgg";
+
+        for endpoint in [
+            "http://localhost:8080/push",
+            "https://localhost/push",
+            "https://push.localhost/push",
+            "https://127.0.0.1/push",
+            "https://10.0.0.1/push",
+            "https://169.254.169.254/latest",
+            "https://[::1]/push",
+            "https://[::ffff:192.168.0.1]/push",
+        ] {
+            let result = push::subscribe(&docs, "u1", subscription(endpoint, auth), false).await;
+            assert!(
+                matches!(result, Err(Error::InvalidSubscription(_))),
+                "{endpoint} was allowed"
+            );
+        }
+
+        let local = subscription("http://localhost:8080/push", auth);
+        assert!(push::subscribe(&docs, "u1", local, true).await.is_ok());
+    }
+}
@@ -0,0 +1,28 @@
+//! Random identifiers, tokens and codes.
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use rand::{Rng, RngCore};
+
+/// Characters used in codes people type in, without look-alikes such as `0` and `O`.
+const CODE_CHARS: &[u8] = b"23456789ABCDEFGHJKLMNPQRSTUVWXYZ";
+
+/// Generate a new 128-bit id, as hex.
+pub fn id() -> String {
+    let mut bytes = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Generate a new 256-bit bearer token, as unpadded URL-safe base64.
+pub fn token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Generate a code of the given length for people to type in.
+pub fn code(len: usize) -> String {
+    let mut rng = rand::thread_rng();
+    (0..len)
+        .map(|_| CODE_CHARS[rng.gen_range(0..CODE_CHARS.len())] as char)
+        .collect()
+}
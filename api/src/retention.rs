@@ -0,0 +1,113 @@
+//! Purge completed reminders once they are older than the retention window.
+//!
+//! Nothing is purged unless `retention.completed_days` is set, since purged
+//! reminders cannot be brought back.
+use crate::config::RetentionConfig;
+use crate::models::query::{ListQuery, Status};
+use crate::store::{self, ReminderStore, Storage};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How often to look for reminders to purge.
+const INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Get the retention window from the configuration.
+///
+/// # Returns
+///
+/// The window in seconds, or `None` if it is set to `0` (keep forever).
+pub fn window(config: &RetentionConfig) -> Option<u64> {
+    (config.completed_days > 0).then_some(config.completed_days * 24 * 60 * 60)
+}
+
+/// Periodically purge completed reminders in the background.
+///
+/// # Arguments
+///
+/// * `store` - The storage to purge, covering every reminders scope.
+/// * `retention` - How long to keep completed reminders, in seconds.
+pub fn spawn(store: Arc<dyn Storage>, retention: u64) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(INTERVAL);
+        loop {
+            interval.tick().await;
+            match purge_all(store.as_ref(), retention, crate::time::now()).await {
+                Ok(0) => {}
+                Ok(n) => log::info!("purged {n} completed reminders"),
+                Err(e) => log::error!("failed to purge completed reminders: {e}"),
+            }
+        }
+    })
+}
+
+/// Purge the shared reminders, those of every user and every household list.
+///
+/// # Returns
+///
+/// The number of reminders deleted.
+pub async fn purge_all(store: &dyn Storage, retention: u64, now: u64) -> store::Result<usize> {
+    let mut purged = 0;
+    for scope in store::scopes(store).await? {
+        purged += purge(store.reminders(&scope).as_ref(), retention, now).await?;
+    }
+
+    Ok(purged)
+}
+
+/// Delete every reminder completed more than `retention` seconds before `now`.
+///
+/// # Returns
+///
+/// The number of reminders deleted.
+pub async fn purge(store: &dyn ReminderStore, retention: u64, now: u64) -> store::Result<usize> {
+    let query = ListQuery {
+        status: Status::Completed,
+        ..Default::default()
+    };
+    let cutoff = now.saturating_sub(retention);
+
+    let mut purged = 0;
+    for reminder in store.query(&query).await?.reminders {
+        if reminder.completed_at.is_some_and(|t| t < cutoff) {
+            store.delete(&reminder.id.unwrap_or_default(), None).await?;
+            purged += 1;
+        }
+    }
+
+    Ok(purged)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::reminder::Reminder;
+    use crate::retention::purge;
+    use crate::store::{memory::Memory, Storage};
+
+    /// Test that only reminders completed before the window are purged.
+    #[tokio::test]
+    async fn test_purge() {
+        let store = Memory::default().reminders("");
+        for completed_at in [None, Some(100), Some(900)] {
+            let mut reminder = Reminder {
+                id: None,
+                title: "Bins".into(),
+                due: 0,
+                priority: 0,
+                assignee: None,
+                completed: false,
+                completed_at: None,
+                rrule: None,
+            };
+            if let Some(t) = completed_at {
+                reminder.complete(t);
+            }
+            store.create(reminder).await.unwrap();
+        }
+
+        assert_eq!(purge(store.as_ref(), 500, 1000).await.unwrap(), 1);
+
+        let remaining = store.list().await.unwrap().value;
+        assert_eq!(remaining.len(), 2);
+        assert!(remaining.iter().all(|r| r.completed_at != Some(100)));
+    }
+}
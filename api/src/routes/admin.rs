@@ -0,0 +1,68 @@
+//! # Admin routes.
+//!
+//! Every admin route needs a bearer token belonging to an admin.
+use crate::{
+    api_keys,
+    middleware::auth::Admin,
+    models::{api_key::NewApiKey, generic_response::ResponseMessage, result::Result},
+    SharedState,
+};
+use axum::{
+    extract::{Json, Path, State},
+    http::StatusCode,
+    response::{self, IntoResponse, Response},
+    routing::{delete, get},
+    Router,
+};
+
+/// Returns the admin routes.
+pub fn router() -> Router<SharedState> {
+    Router::new()
+        .route("/admin/api-keys", get(list_api_keys).post(create_api_key))
+        .route("/admin/api-keys/:id", delete(revoke_api_key))
+}
+
+/// Get every API key.
+///
+/// # Returns
+///
+/// A JSON response with the keys, without the keys themselves.
+pub async fn list_api_keys(State(state): State<SharedState>, _: Admin) -> Result<Response> {
+    let store = state.read().await.store.clone();
+    let keys = api_keys::list(store.as_ref()).await?;
+
+    Ok(response::Json(keys).into_response())
+}
+
+/// Create an API key.
+///
+/// # Returns
+///
+/// A JSON response with the new key and a 201 status code, or a 400 if the
+/// name, scopes or user are not acceptable.
+pub async fn create_api_key(
+    State(state): State<SharedState>,
+    Admin(admin): Admin,
+    Json(body): Json<NewApiKey>,
+) -> Result<Response> {
+    let store = state.read().await.store.clone();
+    let key = api_keys::create(store.as_ref(), &admin, body).await?;
+
+    Ok((StatusCode::CREATED, response::Json(key)).into_response())
+}
+
+/// Revoke an API key.
+///
+/// # Returns
+///
+/// A JSON response with a 200 status code, or a 404 if there is no such key.
+pub async fn revoke_api_key(
+    State(state): State<SharedState>,
+    _: Admin,
+    Path(id): Path<String>,
+) -> Result<Response> {
+    let store = state.read().await.store.clone();
+    api_keys::revoke(store.as_ref(), &id).await?;
+
+    Ok(ResponseMessage::from("Revoked API key").into_response())
+}
@@ -0,0 +1,131 @@
+//! # Account routes.
+//!
+//! Registration, login and the token endpoints are open; logging out and
+//! fetching the current user need a bearer token.
+use crate::{
+    accounts,
+    middleware::auth::{bearer_token, AuthUser},
+    models::{
+        generic_response::ResponseMessage,
+        result::Result,
+        user::{Credentials, Profile, RefreshRequest},
+    },
+    tokens, SharedState,
+};
+use axum::{
+    extract::{Json, State},
+    http::{HeaderMap, StatusCode},
+    response::{self, IntoResponse, Response},
+};
+
+/// Create an account.
+///
+/// # Returns
+///
+/// A JSON response with the new account and a 201 status code, a 400 if the
+/// username or password is not acceptable, or a 409 if the username is taken.
+pub async fn register(
+    State(state): State<SharedState>,
+    Json(credentials): Json<Credentials>,
+) -> Result<Response> {
+    let store = state.read().await.store.clone();
+    let user = accounts::register(store.as_ref(), credentials).await?;
+
+    Ok((StatusCode::CREATED, response::Json(Profile::from(&user))).into_response())
+}
+
+/// Log in with a username and password.
+///
+/// # Returns
+///
+/// A JSON response with a bearer token and its expiry, or a 401 if the
+/// credentials are wrong.
+pub async fn login(
+    State(state): State<SharedState>,
+    Json(credentials): Json<Credentials>,
+) -> Result<Response> {
+    let store = state.read().await.store.clone();
+    let token = accounts::login(store.as_ref(), credentials).await?;
+
+    Ok(response::Json(token).into_response())
+}
+
+/// Exchange a username and password for a JWT access token and a refresh token.
+///
+/// # Returns
+///
+/// A JSON response with both tokens, or a 401 if the credentials are wrong.
+pub async fn token(
+    State(state): State<SharedState>,
+    Json(credentials): Json<Credentials>,
+) -> Result<Response> {
+    let (store, keys) = {
+        let state = state.read().await;
+        (state.store.clone(), state.tokens.clone())
+    };
+    let user = accounts::check_credentials(store.as_ref(), credentials).await?;
+    let tokens = tokens::issue(store.as_ref(), &keys, &user).await?;
+
+    Ok(response::Json(tokens).into_response())
+}
+
+/// Exchange a refresh token for a new access token and refresh token.
+///
+/// # Returns
+///
+/// A JSON response with both tokens, or a 401 if the refresh token is invalid,
+/// expired, revoked or has already been used.
+pub async fn refresh(
+    State(state): State<SharedState>,
+    Json(body): Json<RefreshRequest>,
+) -> Result<Response> {
+    let (store, keys) = {
+        let state = state.read().await;
+        (state.store.clone(), state.tokens.clone())
+    };
+    let tokens = tokens::refresh(store.as_ref(), &keys, &body.refresh_token).await?;
+
+    Ok(response::Json(tokens).into_response())
+}
+
+/// Revoke a refresh token and every token refreshed from the same login.
+///
+/// # Returns
+///
+/// A JSON response with a 200 status code.
+pub async fn revoke(
+    State(state): State<SharedState>,
+    Json(body): Json<RefreshRequest>,
+) -> Result<Response> {
+    let store = state.read().await.store.clone();
+    tokens::revoke(store.as_ref(), &body.refresh_token).await?;
+
+    Ok(ResponseMessage::from("Revoked token").into_response())
+}
+
+/// End the session of the token the request was made with.
+///
+/// # Returns
+///
+/// A JSON response with a 200 status code.
+pub async fn logout(State(state): State<SharedState>, headers: HeaderMap) -> Result<Response> {
+    let store = state.read().await.store.clone();
+    if let Some(token) = bearer_token(&headers) {
+        accounts::logout(store.as_ref(), token).await?;
+    }
+
+    Ok(ResponseMessage::from("Logged out").into_response())
+}
+
+/// Get the user the request is authenticated as.
+///
+/// # Returns
+///
+/// A JSON response with the user's id and username.
+pub async fn me(user: AuthUser) -> Response {
+    response::Json(Profile {
+        id: user.id,
+        username: user.username,
+    })
+    .into_response()
+}
@@ -0,0 +1,98 @@
+//! # Calendar feed routes.
+//!
+//! Creating and revoking a feed needs a bearer token. The feed itself is
+//! read with the secret token in its URL instead, as calendar apps cannot
+//! send headers.
+use crate::{
+    calendar,
+    ical::{self, Component},
+    middleware::auth::AuthUser,
+    models::{generic_response::ResponseMessage, result::Result},
+    SharedState,
+};
+use axum::{
+    extract::{Path, Query, State},
+    http::{header, StatusCode},
+    response::{self, IntoResponse, Response},
+    routing::{get, post},
+    Router,
+};
+use serde::Deserialize;
+
+/// Query parameters of the calendar routes.
+#[derive(Deserialize)]
+pub struct CalendarQuery {
+    /// Whether reminders are written as `todo`s or `event`s.
+    #[serde(default)]
+    pub component: Component,
+}
+
+/// Returns the routes that manage the user's calendar feed.
+pub fn router() -> Router<SharedState> {
+    Router::new().route("/calendar/feed", post(create).delete(revoke))
+}
+
+/// Returns the route that serves calendar feeds, which needs no bearer token.
+pub fn feed_router() -> Router<SharedState> {
+    Router::new().route("/calendar/:token/reminders.ics", get(feed))
+}
+
+/// Respond with an iCalendar object.
+pub fn ics(calendar: String) -> Response {
+    (
+        [
+            (header::CONTENT_TYPE, "text/calendar; charset=utf-8"),
+            (
+                header::CONTENT_DISPOSITION,
+                "inline; filename=\"reminders.ics\"",
+            ),
+        ],
+        calendar,
+    )
+        .into_response()
+}
+
+/// Create a calendar feed for the user, replacing the URL of any feed they
+/// already had.
+///
+/// # Returns
+///
+/// A JSON response with the feed's URL and a 201 status code.
+pub async fn create(State(state): State<SharedState>, user: AuthUser) -> Result<Response> {
+    let store = state.read().await.store.clone();
+    let feed = calendar::create(store.as_ref(), &user).await?;
+
+    Ok((StatusCode::CREATED, response::Json(feed)).into_response())
+}
+
+/// Revoke the user's calendar feed.
+///
+/// # Returns
+///
+/// A JSON response with a 200 status code, or a 404 if the user has no feed.
+pub async fn revoke(State(state): State<SharedState>, user: AuthUser) -> Result<Response> {
+    let store = state.read().await.store.clone();
+    calendar::revoke(store.as_ref(), &user.id).await?;
+
+    Ok(ResponseMessage::from("Revoked calendar feed").into_response())
+}
+
+/// Get the reminders of the feed with the token in the path.
+///
+/// # Returns
+///
+/// A `text/calendar` response, or a 404 if there is no such feed.
+pub async fn feed(
+    State(state): State<SharedState>,
+    Path(token): Path<String>,
+    Query(query): Query<CalendarQuery>,
+) -> Result<Response> {
+    let store = state.read().await.store.clone();
+    let reminders = calendar::reminders(store.as_ref(), &token).await?;
+
+    Ok(ics(ical::export(
+        &reminders,
+        query.component,
+        crate::time::now(),
+    )))
+}
@@ -0,0 +1,64 @@
+//! # Daily digest routes.
+//!
+//! Every digest route needs a bearer token, and changes the settings of the
+//! authenticated user.
+use crate::{
+    digest,
+    middleware::auth::AuthUser,
+    models::{digest::DigestSettings, generic_response::ResponseMessage, result::Result},
+    SharedState,
+};
+use axum::{
+    extract::{Json, State},
+    response::{self, IntoResponse, Response},
+    routing::get,
+    Router,
+};
+
+/// Returns the daily digest routes.
+pub fn router() -> Router<SharedState> {
+    Router::new().route(
+        "/notifications/digest",
+        get(settings).put(set_settings).delete(clear_settings),
+    )
+}
+
+/// Get where the user's daily digest is sent.
+///
+/// # Returns
+///
+/// A JSON response with the settings, or a 404 if the user has not opted in.
+pub async fn settings(State(state): State<SharedState>, user: AuthUser) -> Result<Response> {
+    let store = state.read().await.store.clone();
+    let settings = digest::get_settings(store.as_ref(), &user.id).await?;
+
+    Ok(response::Json(settings).into_response())
+}
+
+/// Opt the user in to the daily digest, or change where it is sent.
+///
+/// # Returns
+///
+/// A JSON response with the settings, or a 400 if they are not acceptable.
+pub async fn set_settings(
+    State(state): State<SharedState>,
+    user: AuthUser,
+    Json(body): Json<DigestSettings>,
+) -> Result<Response> {
+    let store = state.read().await.store.clone();
+    let settings = digest::set_settings(store.as_ref(), &user.id, body).await?;
+
+    Ok(response::Json(settings).into_response())
+}
+
+/// Opt the user out of the daily digest.
+///
+/// # Returns
+///
+/// A JSON response with a 200 status code, or a 404 if the user had not opted in.
+pub async fn clear_settings(State(state): State<SharedState>, user: AuthUser) -> Result<Response> {
+    let store = state.read().await.store.clone();
+    digest::clear_settings(store.as_ref(), &user.id).await?;
+
+    Ok(ResponseMessage::from("Stopped daily digest").into_response())
+}
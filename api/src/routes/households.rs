@@ -0,0 +1,195 @@
+//! # Household routes.
+//!
+//! Every household route needs a bearer token. What a user may do in a
+//! household depends on their role in it.
+use crate::{
+    households,
+    middleware::auth::AuthUser,
+    models::{
+        generic_response::ResponseMessage,
+        household::{NewName, RoleRequest},
+        result::Result,
+    },
+    SharedState,
+};
+use axum::{
+    extract::{Json, Path, State},
+    http::StatusCode,
+    response::{self, IntoResponse, Response},
+    routing::{get, post, put},
+    Router,
+};
+
+/// Returns the household routes.
+pub fn router() -> Router<SharedState> {
+    Router::new()
+        .route("/households", get(list).post(create))
+        .route("/households/:household_id", get(details))
+        .route("/households/:household_id/lists", post(add_list))
+        .route(
+            "/households/:household_id/lists/:list_id",
+            axum::routing::delete(delete_list),
+        )
+        .route(
+            "/households/:household_id/members/:user_id",
+            put(set_role).delete(remove_member),
+        )
+        .route("/households/:household_id/invites", post(create_invite))
+        .route("/invites/:code/accept", post(accept_invite))
+}
+
+/// Get the households the user belongs to.
+///
+/// # Returns
+///
+/// A JSON response with each household and the user's role in it.
+pub async fn list(State(state): State<SharedState>, user: AuthUser) -> Result<Response> {
+    let store = state.read().await.store.clone();
+    let households = households::list_for_user(store.as_ref(), &user.id).await?;
+
+    Ok(response::Json(households).into_response())
+}
+
+/// Create a household owned by the user.
+///
+/// # Returns
+///
+/// A JSON response with the new household and a 201 status code, or a 400 if
+/// the name is not acceptable.
+pub async fn create(
+    State(state): State<SharedState>,
+    user: AuthUser,
+    Json(body): Json<NewName>,
+) -> Result<Response> {
+    let store = state.read().await.store.clone();
+    let household = households::create(store.as_ref(), &user, &body.name).await?;
+
+    Ok((StatusCode::CREATED, response::Json(household)).into_response())
+}
+
+/// Get a household with its members and lists.
+///
+/// # Returns
+///
+/// A JSON response with the household, or a 403 if the user is not a member.
+pub async fn details(
+    State(state): State<SharedState>,
+    user: AuthUser,
+    Path(household_id): Path<String>,
+) -> Result<Response> {
+    let store = state.read().await.store.clone();
+    let details = households::details(store.as_ref(), &user.id, &household_id).await?;
+
+    Ok(response::Json(details).into_response())
+}
+
+/// Add a list to a household.
+///
+/// # Returns
+///
+/// A JSON response with the new list and a 201 status code, or a 403 if the
+/// user is not an editor or owner.
+pub async fn add_list(
+    State(state): State<SharedState>,
+    user: AuthUser,
+    Path(household_id): Path<String>,
+    Json(body): Json<NewName>,
+) -> Result<Response> {
+    let store = state.read().await.store.clone();
+    let list = households::add_list(store.as_ref(), &user.id, &household_id, &body.name).await?;
+
+    Ok((StatusCode::CREATED, response::Json(list)).into_response())
+}
+
+/// Delete a list and its reminders.
+///
+/// # Returns
+///
+/// A JSON response with a 200 status code, or a 403 if the user is not the owner.
+pub async fn delete_list(
+    State(state): State<SharedState>,
+    user: AuthUser,
+    Path((household_id, list_id)): Path<(String, String)>,
+) -> Result<Response> {
+    let store = state.read().await.store.clone();
+    households::delete_list(store.as_ref(), &user.id, &household_id, &list_id).await?;
+
+    Ok(ResponseMessage::from("Deleted list").into_response())
+}
+
+/// Change the role of a member.
+///
+/// # Returns
+///
+/// A JSON response with a 200 status code, or a 403 if the user is not the owner.
+pub async fn set_role(
+    State(state): State<SharedState>,
+    user: AuthUser,
+    Path((household_id, member_id)): Path<(String, String)>,
+    Json(body): Json<RoleRequest>,
+) -> Result<Response> {
+    let store = state.read().await.store.clone();
+    households::set_role(
+        store.as_ref(),
+        &user.id,
+        &household_id,
+        &member_id,
+        body.role,
+    )
+    .await?;
+
+    Ok(ResponseMessage::from("Updated member").into_response())
+}
+
+/// Remove a member from a household, or leave it.
+///
+/// # Returns
+///
+/// A JSON response with a 200 status code, or a 403 if the user may not remove
+/// the member.
+pub async fn remove_member(
+    State(state): State<SharedState>,
+    user: AuthUser,
+    Path((household_id, member_id)): Path<(String, String)>,
+) -> Result<Response> {
+    let store = state.read().await.store.clone();
+    households::remove_member(store.as_ref(), &user.id, &household_id, &member_id).await?;
+
+    Ok(ResponseMessage::from("Removed member").into_response())
+}
+
+/// Create a one-time invite code for a household.
+///
+/// # Returns
+///
+/// A JSON response with the code and a 201 status code, or a 403 if the user is
+/// not the owner.
+pub async fn create_invite(
+    State(state): State<SharedState>,
+    user: AuthUser,
+    Path(household_id): Path<String>,
+    Json(body): Json<RoleRequest>,
+) -> Result<Response> {
+    let store = state.read().await.store.clone();
+    let invite =
+        households::create_invite(store.as_ref(), &user.id, &household_id, body.role).await?;
+
+    Ok((StatusCode::CREATED, response::Json(invite)).into_response())
+}
+
+/// Join a household with an invite code.
+///
+/// # Returns
+///
+/// A JSON response with the household and the user's role in it, or a 400 if
+/// the code is invalid, used or expired.
+pub async fn accept_invite(
+    State(state): State<SharedState>,
+    user: AuthUser,
+    Path(code): Path<String>,
+) -> Result<Response> {
+    let store = state.read().await.store.clone();
+    let household = households::accept_invite(store.as_ref(), &user, &code).await?;
+
+    Ok(response::Json(household).into_response())
+}
@@ -1,4 +1,11 @@
 //! # Routes.
 
+pub mod admin;
+pub mod auth;
+pub mod calendar;
+pub mod digest;
 pub mod err_404;
+pub mod households;
+pub mod push;
 pub mod reminders;
+pub mod webhooks;
@@ -0,0 +1,98 @@
+//! # Web Push routes.
+//!
+//! Every push route needs a bearer token. Subscriptions belong to the user
+//! who registered them.
+use crate::{
+    middleware::auth::AuthUser,
+    models::{
+        generic_response::ResponseMessage,
+        push::{NewPushSubscription, VapidPublicKey},
+        result::Result,
+    },
+    notifiers::webpush::Vapid,
+    push, SharedState,
+};
+use axum::{
+    extract::{Json, Path, State},
+    http::StatusCode,
+    response::{self, IntoResponse, Response},
+    routing::{delete, get},
+    Router,
+};
+
+/// Returns the Web Push routes.
+pub fn router() -> Router<SharedState> {
+    Router::new()
+        .route("/push/vapid-public-key", get(public_key))
+        .route("/push/subscriptions", get(list).post(subscribe))
+        .route("/push/subscriptions/:id", delete(unsubscribe))
+}
+
+/// Get the key browsers need to subscribe with.
+///
+/// # Returns
+///
+/// A JSON response with the VAPID public key, or a 404 if Web Push is not
+/// configured.
+pub async fn public_key(State(state): State<SharedState>, _: AuthUser) -> Result<Response> {
+    let config = state.read().await.config.clone();
+    let vapid = Vapid::from_config(&config.notifications.webpush)
+        .map_err(|_| push::Error::NotConfigured)?;
+
+    Ok(response::Json(VapidPublicKey {
+        public_key: vapid.public_key(),
+    })
+    .into_response())
+}
+
+/// Get the user's push subscriptions.
+///
+/// # Returns
+///
+/// A JSON response with each subscription, without its keys.
+pub async fn list(State(state): State<SharedState>, user: AuthUser) -> Result<Response> {
+    let store = state.read().await.store.clone();
+    let subscriptions = push::list(store.as_ref(), &user.id).await?;
+
+    Ok(response::Json(subscriptions).into_response())
+}
+
+/// Register a browser's push subscription for the user.
+///
+/// # Returns
+///
+/// A JSON response with the subscription and a 201 status code, or a 400 if
+/// the subscription is not valid.
+pub async fn subscribe(
+    State(state): State<SharedState>,
+    user: AuthUser,
+    Json(body): Json<NewPushSubscription>,
+) -> Result<Response> {
+    let (store, allow_private) = {
+        let state = state.read().await;
+        (
+            state.store.clone(),
+            state.config.notifications.webpush.allow_private,
+        )
+    };
+    let subscription = push::subscribe(store.as_ref(), &user.id, body, allow_private).await?;
+
+    Ok((StatusCode::CREATED, response::Json(subscription)).into_response())
+}
+
+/// Remove one of the user's push subscriptions.
+///
+/// # Returns
+///
+/// A JSON response with a 200 status code, or a 404 if the user has no such
+/// subscription.
+pub async fn unsubscribe(
+    State(state): State<SharedState>,
+    user: AuthUser,
+    Path(id): Path<String>,
+) -> Result<Response> {
+    let store = state.read().await.store.clone();
+    push::unsubscribe(store.as_ref(), &user.id, &id).await?;
+
+    Ok(ResponseMessage::from("Removed push subscription").into_response())
+}
@@ -0,0 +1,176 @@
+//! Reminders collection extractor
+//!
+//! This module works out which reminders collection a request is for, and
+//! whether the authenticated user may use it.
+use crate::{
+    events::Events,
+    households,
+    middleware::auth::{self, AuthUser},
+    models::household::Role,
+    store::{self, DocumentStore, ReminderStore},
+    SharedState,
+};
+use async_trait::async_trait;
+use axum::{
+    extract::{FromRequestParts, Path},
+    http::{request::Parts, Method},
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Deserializer};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+/// How often connections that stay open check they may still read the
+/// reminders they were opened for.
+pub const RECHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The id of a single reminder in the path.
+///
+/// Paths with an id that is not a [`store::valid_id`] are rejected with a 400.
+#[derive(Deserialize)]
+pub struct ItemPath {
+    #[serde(deserialize_with = "id")]
+    pub id: String,
+}
+
+/// Deserialize an id, failing if it is not a [`store::valid_id`].
+pub fn id<'de, D: Deserializer<'de>>(deserializer: D) -> Result<String, D::Error> {
+    let id = String::deserialize(deserializer)?;
+    match store::valid_id(&id) {
+        true => Ok(id),
+        false => Err(serde::de::Error::custom(format!("invalid id {id:?}"))),
+    }
+}
+
+/// Check the ids a request gives in its body.
+///
+/// # Errors
+///
+/// Returns `store::Error::InvalidId`, a 400, if an id is not a
+/// [`store::valid_id`].
+pub fn check_ids<'a>(ids: impl IntoIterator<Item = &'a str>) -> store::Result<()> {
+    ids.into_iter().try_for_each(store::check_id)
+}
+
+/// The reminders collection a request is for.
+///
+/// Routes nested under `/households/:household_id/lists/:list_id` use that
+/// list's reminders, which members can read and editors can change. Other
+/// routes use the authenticated user's own reminders. Changes are published
+/// through the [`Events`] for the same collection.
+pub struct Reminders(pub Arc<dyn ReminderStore>, pub Events);
+
+#[async_trait]
+impl FromRequestParts<SharedState> for Reminders {
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &SharedState,
+    ) -> Result<Self, Self::Rejection> {
+        let user = AuthUser::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        let params = Path::<HashMap<String, String>>::from_request_parts(parts, state)
+            .await
+            .map(|Path(params)| params)
+            .unwrap_or_default();
+        let (store, bus) = {
+            let state = state.read().await;
+            (state.store.clone(), state.events.clone())
+        };
+
+        let needed = match parts.method {
+            Method::GET | Method::HEAD => Role::Viewer,
+            _ => Role::Editor,
+        };
+        let scope = scope(store.as_ref(), &user, &params, needed).await?;
+
+        Ok(Reminders(store.reminders(&scope), Events::new(bus, &scope)))
+    }
+}
+
+/// The credentials and path of a request, kept so a connection that stays
+/// open can check its access again later.
+///
+/// Membership of a household and sessions and API keys can all be taken away
+/// while a stream is open, so streams call [`Grant::allows`] every
+/// [`RECHECK_INTERVAL`] and end when it fails.
+pub struct Grant {
+    state: SharedState,
+    token: String,
+    user: AuthUser,
+    params: HashMap<String, String>,
+}
+
+#[async_trait]
+impl FromRequestParts<SharedState> for Grant {
+    type Rejection = Response;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &SharedState,
+    ) -> Result<Self, Self::Rejection> {
+        let user = AuthUser::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        let token = auth::bearer_token(&parts.headers)
+            .unwrap_or_default()
+            .to_string();
+        let params = Path::<HashMap<String, String>>::from_request_parts(parts, state)
+            .await
+            .map(|Path(params)| params)
+            .unwrap_or_default();
+
+        Ok(Grant {
+            state: state.clone(),
+            token,
+            user,
+            params,
+        })
+    }
+}
+
+impl Grant {
+    /// Work out which reminders scope the request is for, as [`scope`] does.
+    pub async fn scope(&self, needed: Role) -> Result<String, Response> {
+        let docs = self.state.read().await.store.clone();
+        scope(docs.as_ref(), &self.user, &self.params, needed).await
+    }
+
+    /// Whether the request's token still authenticates its user, and the user
+    /// can still read the reminders in `scope`.
+    pub async fn allows(&self, scope: &str) -> bool {
+        auth::still_authenticated(&self.state, &self.token, &self.user).await
+            && self
+                .scope(Role::Viewer)
+                .await
+                .is_ok_and(|current| current == scope)
+    }
+}
+
+/// Work out which reminders scope a request is for.
+///
+/// # Arguments
+///
+/// * `user` - The authenticated user.
+/// * `params` - The path parameters, which name a household list if the
+///   request is for one.
+/// * `needed` - The role needed in the household to use the list.
+///
+/// # Errors
+///
+/// Returns a 403 if the user's role in the household is too low, or a 404 if
+/// the household has no such list.
+pub async fn scope(
+    docs: &dyn DocumentStore,
+    user: &AuthUser,
+    params: &HashMap<String, String>,
+    needed: Role,
+) -> Result<String, Response> {
+    match (params.get("household_id"), params.get("list_id")) {
+        (Some(household_id), Some(list_id)) => {
+            Ok(households::list_access(docs, &user.id, household_id, list_id, needed).await?)
+        }
+        _ => Ok(user.scope()),
+    }
+}
@@ -0,0 +1,75 @@
+//! Complete and uncomplete methods
+//!
+//! This module contains the endpoints that mark a reminder as done or not done.
+use super::access::{ItemPath, Reminders};
+use super::etag::if_match;
+use crate::events::EventKind;
+use crate::models::{generic_response::ResponseMessage, reminder::Reminder, result::Result};
+use crate::store::ReminderStore;
+use axum::{
+    extract::Path,
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+};
+
+/// Mark a reminder as completed.
+///
+/// # Returns
+///
+/// A JSON response with a 200 status code, a 404 if the reminder does not exist
+/// or a 412 if an `If-Match` header does not match the stored reminder.
+pub async fn complete(
+    Reminders(store, events): Reminders,
+    Path(ItemPath { id }): Path<ItemPath>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    let reminder = set_completed(store.as_ref(), &id, &headers, true).await?;
+    events.emit(EventKind::Completed, reminder);
+
+    Ok(ResponseMessage::from("Completed reminder").into_response())
+}
+
+/// Mark a reminder as not completed.
+///
+/// # Returns
+///
+/// A JSON response with a 200 status code, a 404 if the reminder does not exist
+/// or a 412 if an `If-Match` header does not match the stored reminder.
+pub async fn uncomplete(
+    Reminders(store, events): Reminders,
+    Path(ItemPath { id }): Path<ItemPath>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    let reminder = set_completed(store.as_ref(), &id, &headers, false).await?;
+    events.emit(EventKind::Updated, reminder);
+
+    Ok(ResponseMessage::from("Uncompleted reminder").into_response())
+}
+
+/// Read, update and conditionally write back the completion state of a reminder.
+///
+/// The write is conditional on the version that was read, so a concurrent edit
+/// is reported as a conflict instead of being overwritten.
+///
+/// # Returns
+///
+/// The reminder as it was written.
+async fn set_completed(
+    store: &dyn ReminderStore,
+    id: &str,
+    headers: &HeaderMap,
+    completed: bool,
+) -> crate::store::Result<Reminder> {
+    let current = store.get(id).await?;
+    let etag = if_match(headers).unwrap_or(current.etag);
+    let mut reminder = current.value;
+
+    match completed {
+        true => reminder.complete(crate::time::now()),
+        false => reminder.uncomplete(),
+    }
+
+    store.replace(id, reminder.clone(), Some(&etag)).await?;
+
+    Ok(reminder)
+}
@@ -1,13 +1,13 @@
 //! Delete method
 //!
 //! This module contains the delete method for the reminders API.
-use crate::{
-    models::{generic_response::ResponseMessage, reminder::Reminder, result::Result},
-    SharedState,
-};
+use super::access::{check_ids, ItemPath, Reminders};
+use super::etag::if_match;
+use crate::events::EventKind;
+use crate::models::{generic_response::ResponseMessage, reminder::Reminder, result::Result};
 use axum::{
-    extract::{self, State},
-    http::StatusCode,
+    extract::{self, Path},
+    http::{HeaderMap, StatusCode},
     response::{IntoResponse, Response},
 };
 
@@ -15,9 +15,11 @@ use axum::{
 ///
 /// # Returns
 ///
-/// A JSON response with a 200 response.
+/// A JSON response with a 200 response, a 400 if the id is missing or invalid,
+/// or a 412 if an `If-Match` header does not match the stored reminder.
 pub async fn delete(
-    State(state): State<SharedState>,
+    Reminders(store, events): Reminders,
+    headers: HeaderMap,
     extract::Json(reminder): extract::Json<Reminder>,
 ) -> Result<Response> {
     if reminder.id.is_none() {
@@ -26,10 +28,32 @@ pub async fn delete(
             .into_response());
     }
 
-    let mut db = state.write().await.db.clone();
+    let id = reminder.id.unwrap();
+    check_ids([id.as_str()])?;
+    // Deleting a reminder that does not exist succeeds, but is not an event.
+    let current = store.get(&id).await.ok();
+    store.delete(&id, if_match(&headers).as_deref()).await?;
+    if let Some(current) = current {
+        events.emit(EventKind::Deleted, current.value);
+    }
+
+    Ok(ResponseMessage::from("Deleted reminder").into_response())
+}
 
-    let path = format!("reminders/v2/{}", reminder.id.clone().unwrap());
-    let resp = db.delete(path.as_str()).await;
+/// Delete the reminder identified in the path.
+///
+/// # Returns
+///
+/// A JSON response with a 200 status code, a 404 if the reminder does not exist
+/// or a 412 if an `If-Match` header does not match the stored reminder.
+pub async fn delete_by_id(
+    Reminders(store, events): Reminders,
+    Path(ItemPath { id }): Path<ItemPath>,
+    headers: HeaderMap,
+) -> Result<Response> {
+    let current = store.get(&id).await?;
+    store.delete(&id, if_match(&headers).as_deref()).await?;
+    events.emit(EventKind::Deleted, current.value);
 
-    Ok(resp.map(|_| ResponseMessage::from("Deleted reminder").into_response())?)
+    Ok(ResponseMessage::from("Deleted reminder").into_response())
 }
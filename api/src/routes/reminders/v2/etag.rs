@@ -0,0 +1,49 @@
+//! Entity tag headers for optimistic concurrency.
+use axum::{
+    http::{header, HeaderMap, HeaderValue},
+    response::Response,
+};
+
+/// Read the entity tag from the `If-Match` header.
+///
+/// Returns `None` when the header is missing or is `*`, which only requires the
+/// resource to exist and is checked by the handlers themselves.
+pub fn if_match(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get(header::IF_MATCH)?.to_str().ok()?.trim();
+
+    match value {
+        "*" => None,
+        _ => Some(value.trim_start_matches("W/").trim_matches('"').to_string()),
+    }
+}
+
+/// Attach an entity tag to a response.
+pub fn with_etag(mut response: Response, etag: &str) -> Response {
+    if let Ok(value) = HeaderValue::from_str(&format!("\"{etag}\"")) {
+        response.headers_mut().insert(header::ETAG, value);
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::routes::reminders::v2::etag::if_match;
+    use axum::http::{header, HeaderMap};
+
+    /// Test that quoted, weak and wildcard tags are understood.
+    #[test]
+    fn test_if_match() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(if_match(&headers), None);
+
+        headers.insert(header::IF_MATCH, r#""abc""#.parse().unwrap());
+        assert_eq!(if_match(&headers).as_deref(), Some("abc"));
+
+        headers.insert(header::IF_MATCH, r#"W/"abc""#.parse().unwrap());
+        assert_eq!(if_match(&headers).as_deref(), Some("abc"));
+
+        headers.insert(header::IF_MATCH, "*".parse().unwrap());
+        assert_eq!(if_match(&headers), None);
+    }
+}
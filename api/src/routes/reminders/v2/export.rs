@@ -0,0 +1,31 @@
+//! Export method
+//!
+//! This module contains the endpoint that exports the reminders as iCalendar.
+use super::access::Reminders;
+use crate::{
+    ical,
+    models::result::Result,
+    routes::calendar::{ics, CalendarQuery},
+};
+use axum::{extract::Query, response::Response};
+
+/// Export every reminder as an iCalendar object.
+///
+/// Reminders are written as `VTODO`s, or as `VEVENT`s with
+/// `?component=event`.
+///
+/// # Returns
+///
+/// A `text/calendar` response with a 200 status code.
+pub async fn export(
+    Reminders(store, _): Reminders,
+    Query(query): Query<CalendarQuery>,
+) -> Result<Response> {
+    let reminders = store.list().await?.value;
+
+    Ok(ics(ical::export(
+        &reminders,
+        query.component,
+        crate::time::now(),
+    )))
+}
@@ -1,25 +1,54 @@
 //! Get method
 //!
 //! This module contains the get method for the reminders API.
-use crate::models::{reminder::Reminder, result::Result};
-use crate::SharedState;
+use super::access::{ItemPath, Reminders};
+use super::etag::with_etag;
+use crate::models::{query::ListQuery, result::Result};
 use axum::{
-    extract::State,
+    extract::{Path, Query},
     response::{self, IntoResponse, Response},
 };
-use serde_json::Value;
-use std::collections::HashMap;
 
-type RawReminder = HashMap<String, HashMap<String, Value>>;
+/// Header carrying the cursor for the next page of results.
+const NEXT_CURSOR: &str = "x-next-cursor";
 
-/// Get all reminders.
+/// Get reminders, optionally filtered, sorted and paginated.
 ///
 /// # Returns
 ///
-/// A JSON response with all reminders.
-pub async fn get(State(state): State<SharedState>) -> Result<Response> {
-    let mut db = state.read().await.db.clone();
-    let data: RawReminder = db.get("reminders/v2").await?;
+/// A JSON response with the matching reminders. When there are more results
+/// than `limit`, the cursor for the next page is sent in the `X-Next-Cursor` header.
+/// The `ETag` header carries the entity tag of the whole collection when available.
+pub async fn get(
+    Reminders(store, _): Reminders,
+    Query(query): Query<ListQuery>,
+) -> Result<Response> {
+    let page = store.query(&query).await?;
 
-    Ok(response::Json(Reminder::from_json(data)).into_response())
+    let mut response = response::Json(page.reminders).into_response();
+    if let Some(cursor) = page.next_cursor.and_then(|c| c.parse().ok()) {
+        response.headers_mut().insert(NEXT_CURSOR, cursor);
+    }
+
+    Ok(match page.etag {
+        Some(etag) => with_etag(response, &etag),
+        None => response,
+    })
+}
+
+/// Get a single reminder.
+///
+/// # Returns
+///
+/// A JSON response with the reminder and its `ETag`, or a 404 if it does not exist.
+pub async fn get_by_id(
+    Reminders(store, _): Reminders,
+    Path(ItemPath { id }): Path<ItemPath>,
+) -> Result<Response> {
+    let reminder = store.get(&id).await?;
+
+    Ok(with_etag(
+        response::Json(reminder.value).into_response(),
+        &reminder.etag,
+    ))
 }
@@ -0,0 +1,60 @@
+//! Import method
+//!
+//! This module contains the endpoint that imports reminders from iCalendar.
+use super::access::Reminders;
+use crate::{
+    events::EventKind,
+    ical,
+    models::{calendar::ImportResponse, result::Result},
+};
+use axum::{
+    extract::Query,
+    http::StatusCode,
+    response::{self, IntoResponse, Response},
+};
+use serde::Deserialize;
+
+/// Query parameters of the import route.
+#[derive(Deserialize)]
+pub struct ImportQuery {
+    /// Only report what would be imported.
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Create a reminder for each `VTODO` in an iCalendar file sent as the body.
+///
+/// # Returns
+///
+/// A JSON response with the reminders created and the components skipped and
+/// why, with a 201 status code, or a 200 with the reminders that would be
+/// created for `?dry_run=true`. A 400 if the body is not an iCalendar file.
+pub async fn import(
+    Reminders(store, events): Reminders,
+    Query(query): Query<ImportQuery>,
+    body: String,
+) -> Result<Response> {
+    let (reminders, skipped) = ical::import(&body, crate::time::now())?;
+    if query.dry_run {
+        let response = ImportResponse {
+            dry_run: true,
+            reminders,
+            skipped,
+        };
+        return Ok(response::Json(response).into_response());
+    }
+
+    let mut created = Vec::with_capacity(reminders.len());
+    for reminder in reminders {
+        let reminder = store.create(reminder).await?;
+        events.emit(EventKind::Created, reminder.clone());
+        created.push(reminder);
+    }
+    let response = ImportResponse {
+        dry_run: false,
+        reminders: created,
+        skipped,
+    };
+
+    Ok((StatusCode::CREATED, response::Json(response)).into_response())
+}
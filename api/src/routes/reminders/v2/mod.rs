@@ -1,16 +1,44 @@
 //! Reminders endpoint routing.
+mod access;
+mod complete;
 mod delete;
+mod etag;
+mod export;
 mod get;
+mod import;
 mod patch;
 mod post;
 mod put;
-use crate::AppState;
-use axum::routing::MethodRouter;
+mod stream;
+mod sync;
+mod ws;
+use crate::{AppState, SharedState};
+use axum::{routing::MethodRouter, Router};
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
+/// Returns the reminders routes under `prefix`.
+///
+/// The same routes serve a user's own reminders and, when `prefix` has
+/// `household_id` and `list_id` parameters, the reminders of a household list.
+pub fn routes(prefix: &str) -> Router<SharedState> {
+    Router::new()
+        .route(&format!("{prefix}/"), router())
+        .route(&format!("{prefix}/stream"), stream_router())
+        .route(&format!("{prefix}/ws"), ws_router())
+        .route(&format!("{prefix}/changes"), changes_router())
+        .route(&format!("{prefix}/sync"), sync_router())
+        .route(&format!("{prefix}/export.ics"), export_router())
+        .route(&format!("{prefix}/import"), import_router())
+        .route(&format!("{prefix}/:id"), item_router())
+        .route(&format!("{prefix}/:id/complete"), complete_router())
+        .route(&format!("{prefix}/:id/uncomplete"), uncomplete_router())
+}
+
 /// Returns a router with all the request methods for the reminders.
 /// This is the entry point for the reminders routes.
+///
+/// `PUT` and `DELETE` take the id in the JSON body, as the Flutter client expects.
 pub fn router() -> MethodRouter<Arc<RwLock<AppState>>> {
     axum::routing::get(self::get::get)
         .post(self::post::post)
@@ -18,3 +46,50 @@ pub fn router() -> MethodRouter<Arc<RwLock<AppState>>> {
         .patch(self::patch::patch)
         .delete(self::delete::delete)
 }
+
+/// Returns a router for a single reminder, identified by the `id` path parameter.
+pub fn item_router() -> MethodRouter<Arc<RwLock<AppState>>> {
+    axum::routing::get(self::get::get_by_id)
+        .put(self::put::put_by_id)
+        .delete(self::delete::delete_by_id)
+}
+
+/// Returns a router that marks the reminder in the path as completed.
+pub fn complete_router() -> MethodRouter<Arc<RwLock<AppState>>> {
+    axum::routing::post(self::complete::complete)
+}
+
+/// Returns a router that marks the reminder in the path as not completed.
+pub fn uncomplete_router() -> MethodRouter<Arc<RwLock<AppState>>> {
+    axum::routing::post(self::complete::uncomplete)
+}
+
+/// Returns a router that streams changes to the reminders.
+pub fn stream_router() -> MethodRouter<Arc<RwLock<AppState>>> {
+    axum::routing::get(self::stream::stream)
+}
+
+/// Returns a router that keeps a device in sync over a WebSocket.
+pub fn ws_router() -> MethodRouter<Arc<RwLock<AppState>>> {
+    axum::routing::get(self::ws::ws)
+}
+
+/// Returns a router for the changes to the reminders since a sequence number.
+pub fn changes_router() -> MethodRouter<Arc<RwLock<AppState>>> {
+    axum::routing::get(self::sync::changes)
+}
+
+/// Returns a router that applies a batch of edits made offline.
+pub fn sync_router() -> MethodRouter<Arc<RwLock<AppState>>> {
+    axum::routing::post(self::sync::sync)
+}
+
+/// Returns a router that exports the reminders as iCalendar.
+pub fn export_router() -> MethodRouter<Arc<RwLock<AppState>>> {
+    axum::routing::get(self::export::export)
+}
+
+/// Returns a router that imports reminders from iCalendar.
+pub fn import_router() -> MethodRouter<Arc<RwLock<AppState>>> {
+    axum::routing::post(self::import::import)
+}
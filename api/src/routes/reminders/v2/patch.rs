@@ -1,39 +1,83 @@
 //! Patch method
 //!
 //! This module contains the patch method for the reminders API.
-use crate::models::{generic_response::ResponseMessage, reminder::Reminder, result::Result};
-use crate::SharedState;
+use super::access::{check_ids, Reminders};
+use super::etag::if_match;
+use crate::events::EventKind;
+use crate::models::{
+    bulk::{BulkResponse, ItemResult, ItemStatus},
+    generic_response::ResponseMessage,
+    reminder::Reminder,
+    result::Result,
+};
 use axum::{
-    extract::{self, State},
-    http::StatusCode,
-    response::{IntoResponse, Response},
+    extract,
+    http::{HeaderMap, StatusCode},
+    response::{self, IntoResponse, Response},
 };
+use std::collections::HashMap;
 
 /// Bulk update reminders.
 ///
+/// Only the listed reminders are written; every other reminder is left as is.
+/// An id listed more than once is reported as a duplicate each time, and none
+/// of its reminders are written.
+///
 /// # Returns
 ///
-/// A JSON response with a 200 status code.
+/// A JSON response with a 200 status code and the outcome for each reminder,
+/// a 400 if an id is missing or invalid, or a 412 if an `If-Match` header does
+/// not match the collection's `ETag`.
 pub async fn patch(
-    State(state): State<SharedState>,
+    Reminders(store, events): Reminders,
+    headers: HeaderMap,
     extract::Json(reminders): extract::Json<Vec<Reminder>>,
 ) -> Result<Response> {
-    let errors: Vec<Reminder> = reminders
-        .clone()
-        .into_iter()
-        .filter(move |r| r.id.is_none())
-        .collect();
-
-    if errors.len() > 1 {
+    if reminders.iter().any(|r| r.id.is_none()) {
         return Err(ResponseMessage::from("Reminder is missing id field")
             .with_status(StatusCode::BAD_REQUEST)
             .into_response());
     }
+    let ids: Vec<String> = reminders
+        .iter()
+        .map(|r| r.id.clone().unwrap_or_default())
+        .collect();
+    check_ids(ids.iter().map(String::as_str))?;
 
-    let mut db = state.write().await.db.clone();
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    for id in &ids {
+        *counts.entry(id).or_default() += 1;
+    }
+    let unique: Vec<Reminder> = reminders
+        .into_iter()
+        .zip(&ids)
+        .filter(|(_, id)| counts[id.as_str()] == 1)
+        .map(|(reminder, _)| reminder)
+        .collect();
 
-    let data = crate::models::reminder::reminders_to_firebase(reminders);
-    let resp = db.put("reminders/v2", data).await;
+    let stored = store
+        .update_many(unique.clone(), if_match(&headers).as_deref())
+        .await?;
+    let status = |id: &str| match counts[id] {
+        1 => stored
+            .iter()
+            .find(|result| result.id == id)
+            .map_or(ItemStatus::NotFound, |result| result.status),
+        _ => ItemStatus::Duplicate,
+    };
+    for reminder in unique {
+        if status(reminder.id.as_deref().unwrap_or_default()) == ItemStatus::Updated {
+            events.emit(EventKind::Updated, reminder);
+        }
+    }
+    let results = ids
+        .iter()
+        .map(|id| ItemResult::new(id, status(id)))
+        .collect();
 
-    Ok(resp.map(|_| ResponseMessage::from("Updated reminder").into_response())?)
+    Ok(response::Json(BulkResponse {
+        message: "Updated reminders".into(),
+        results,
+    })
+    .into_response())
 }
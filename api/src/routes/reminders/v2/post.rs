@@ -1,11 +1,12 @@
 //! Post method
 //!
 //! This module contains the post method for the reminders API.
+use super::access::Reminders;
+use crate::events::EventKind;
 use crate::models::{generic_response::ResponseMessage, reminder::Reminder, result::Result};
-use crate::SharedState;
 use axum::{
-    extract::{Json, State},
-    http::StatusCode,
+    extract::Json,
+    http::{header, StatusCode},
     response::{IntoResponse, Response},
 };
 
@@ -13,17 +14,19 @@ use axum::{
 ///
 /// # Returns
 ///
-/// A JSON response with a 201 status code.
+/// A JSON response with a 201 status code, and the new reminder's id as a
+/// relative URL in the `Location` header.
 pub async fn post(
-    State(state): State<SharedState>,
+    Reminders(store, events): Reminders,
     Json(reminder): Json<Reminder>,
 ) -> Result<Response> {
-    let mut db = state.write().await.db.clone();
-    let resp = db.post("reminders/v2", reminder).await;
+    let created = store.create(reminder).await?;
+    let id = created.id.clone().unwrap_or_default();
+    events.emit(EventKind::Created, created);
 
-    Ok(resp.map(|_| {
-        ResponseMessage::from("Created reminder")
-            .with_status(StatusCode::CREATED)
-            .into_response()
-    })?)
+    Ok((
+        [(header::LOCATION, id)],
+        ResponseMessage::from("Created reminder").with_status(StatusCode::CREATED),
+    )
+        .into_response())
 }
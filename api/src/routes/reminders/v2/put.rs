@@ -1,22 +1,26 @@
 //! Put method
 //!
 //! This module contains the put method for the reminders API.
+use super::access::{check_ids, ItemPath, Reminders};
+use super::etag::if_match;
+use crate::events::EventKind;
 use crate::models::{generic_response::ResponseMessage, reminder::Reminder, result::Result};
-use crate::SharedState;
 use axum::{
-    extract::{self, State},
-    http::StatusCode,
+    extract::{self, Path},
+    http::{HeaderMap, StatusCode},
     response::{IntoResponse, Response},
 };
 
-// Update a reminder.
+/// Update a reminder.
 ///
 /// # Returns
 ///
-/// A JSON response with a 200 status code.
+/// A JSON response with a 200 status code, a 400 if the id is missing or
+/// invalid, or a 412 if an `If-Match` header does not match the stored reminder.
 pub async fn put(
-    State(state): State<SharedState>,
-    extract::Json(mut reminder): extract::Json<Reminder>,
+    Reminders(store, events): Reminders,
+    headers: HeaderMap,
+    extract::Json(reminder): extract::Json<Reminder>,
 ) -> Result<Response> {
     if reminder.id.is_none() {
         return Err(ResponseMessage::from("Reminder is missing the id field")
@@ -24,11 +28,43 @@ pub async fn put(
             .into_response());
     }
 
-    let mut db = state.write().await.db.clone();
+    let id = reminder.id.clone().unwrap();
+    check_ids([id.as_str()])?;
+    store
+        .replace(&id, reminder.clone(), if_match(&headers).as_deref())
+        .await?;
+    events.emit(EventKind::Updated, reminder);
 
-    let path = format!("reminders/v2/{}", reminder.id.clone().unwrap());
-    reminder.id = None;
-    let resp = db.put(path.as_str(), reminder).await;
+    Ok(ResponseMessage::from("Updated reminder").into_response())
+}
+
+/// Update the reminder identified in the path.
+///
+/// # Returns
+///
+/// A JSON response with a 200 status code, a 404 if the reminder does not exist
+/// or a 412 if an `If-Match` header does not match the stored reminder.
+pub async fn put_by_id(
+    Reminders(store, events): Reminders,
+    Path(ItemPath { id }): Path<ItemPath>,
+    headers: HeaderMap,
+    extract::Json(reminder): extract::Json<Reminder>,
+) -> Result<Response> {
+    if reminder.id.as_ref().is_some_and(|body_id| *body_id != id) {
+        return Err(ResponseMessage::from("Reminder id does not match the path")
+            .with_status(StatusCode::BAD_REQUEST)
+            .into_response());
+    }
+
+    store.get(&id).await?;
+    let reminder = Reminder {
+        id: Some(id.clone()),
+        ..reminder
+    };
+    store
+        .replace(&id, reminder.clone(), if_match(&headers).as_deref())
+        .await?;
+    events.emit(EventKind::Updated, reminder);
 
-    Ok(resp.map(|_| ResponseMessage::from("Updated reminder").into_response())?)
+    Ok(ResponseMessage::from("Updated reminder").into_response())
 }
@@ -0,0 +1,104 @@
+//! Stream method
+//!
+//! This module contains the endpoint that streams changes to the reminders as
+//! Server-Sent Events.
+use super::access::{Grant, Reminders, RECHECK_INTERVAL};
+use crate::events::Event as ReminderEvent;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use futures_util::stream::{self, Stream};
+use std::convert::Infallible;
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+use tokio::time::{interval_at, Instant, Interval};
+
+/// Stream changes to the reminders as they happen.
+///
+/// Each change is sent as a `created`, `updated` or `deleted` event, whose data
+/// is the reminder as JSON after the change, or before it for `deleted`, and
+/// whose id is its sequence number.
+/// Completing a reminder is sent as `updated`. If the server falls too far
+/// behind to send every change, a `resync` event is sent and the client
+/// should fetch the reminders again.
+///
+/// Events may occasionally be repeated, so clients should apply them as
+/// upserts and deletes by id.
+///
+/// # Returns
+///
+/// A `text/event-stream` response that stays open, with a comment sent
+/// periodically to keep idle connections alive. The stream ends once the
+/// user can no longer read the reminders, or their token is revoked.
+pub async fn stream(
+    grant: Grant,
+    Reminders(_, events): Reminders,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let watch = Watch {
+        scope: events.scope().to_string(),
+        receiver: events.subscribe(),
+        recheck: interval_at(Instant::now() + RECHECK_INTERVAL, RECHECK_INTERVAL),
+        grant,
+    };
+
+    let stream = stream::unfold(watch, |mut watch| async move {
+        let event = watch.next().await?;
+        Some((Ok(event), watch))
+    });
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+/// The state of an open stream.
+struct Watch {
+    scope: String,
+    receiver: Receiver<ReminderEvent>,
+    recheck: Interval,
+    grant: Grant,
+}
+
+impl Watch {
+    /// Wait for the next event to send, checking the user's access every
+    /// [`RECHECK_INTERVAL`] meanwhile.
+    ///
+    /// # Returns
+    ///
+    /// The event, or `None` once the bus has closed or access was lost.
+    async fn next(&mut self) -> Option<Event> {
+        loop {
+            tokio::select! {
+                event = next(&mut self.receiver, &self.scope) => return event,
+                _ = self.recheck.tick() => {
+                    if !self.grant.allows(&self.scope).await {
+                        return None;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Wait for the next change in the scope.
+///
+/// # Returns
+///
+/// The event to send, or `None` once the bus has closed.
+async fn next(receiver: &mut Receiver<ReminderEvent>, scope: &str) -> Option<Event> {
+    loop {
+        let event = match receiver.recv().await {
+            Ok(event) if event.scope == scope => event,
+            Ok(_) => continue,
+            Err(RecvError::Lagged(_)) => return Some(Event::default().event("resync").data("")),
+            Err(RecvError::Closed) => return None,
+        };
+
+        let Some(name) = event.kind.change() else {
+            continue;
+        };
+        let data = serde_json::to_string(&event.reminder).unwrap_or_default();
+
+        return Some(
+            Event::default()
+                .id(event.seq.to_string())
+                .event(name)
+                .data(data),
+        );
+    }
+}
@@ -0,0 +1,94 @@
+//! Sync methods
+//!
+//! This module contains the delta sync endpoints for offline-first clients:
+//! one to get the changes since a sequence number, and one to apply a batch of
+//! edits made offline.
+use super::access::{check_ids, Reminders};
+use crate::{
+    changes,
+    models::{
+        result::Result,
+        sync::{SyncRequest, SyncResponse},
+    },
+    SharedState,
+};
+use axum::{
+    extract::{Json, Query, State},
+    response::{self, IntoResponse, Response},
+};
+use serde::Deserialize;
+
+/// Query parameters of the changes route.
+#[derive(Deserialize)]
+pub struct ChangesQuery {
+    /// Sequence number of the last changes the client has.
+    pub since: Option<u64>,
+}
+
+/// Get the changes to the reminders since a sequence number.
+///
+/// Each change is the reminder as it is now, or a tombstone if it was
+/// deleted. Without `since`, or if the changes since it are no longer known,
+/// every reminder is returned with `complete` set.
+///
+/// # Returns
+///
+/// A JSON response with the changes and the sequence number to ask from next.
+pub async fn changes(
+    State(state): State<SharedState>,
+    Reminders(store, events): Reminders,
+    Query(query): Query<ChangesQuery>,
+) -> Result<Response> {
+    let (docs, log) = {
+        let state = state.read().await;
+        (state.store.clone(), state.changes.clone())
+    };
+
+    log.caught_up(events.latest()).await;
+    let changes = changes::since(
+        docs.as_ref(),
+        store.as_ref(),
+        events.scope(),
+        query.since,
+        log.recorded(),
+    )
+    .await?;
+
+    Ok(response::Json(changes).into_response())
+}
+
+/// Apply a batch of edits the client made offline.
+///
+/// See [`changes::sync`] for how conflicts are resolved.
+///
+/// # Returns
+///
+/// A JSON response with the outcome of each operation, then the changes since
+/// the request's `since` including those the batch made, or a 400 if there
+/// are too many operations or an id is invalid.
+pub async fn sync(
+    State(state): State<SharedState>,
+    Reminders(store, events): Reminders,
+    Json(request): Json<SyncRequest>,
+) -> Result<Response> {
+    check_ids(request.operations.iter().map(|operation| operation.id()))?;
+    let (docs, log) = {
+        let state = state.read().await;
+        (state.store.clone(), state.changes.clone())
+    };
+
+    log.caught_up(events.latest()).await;
+    let results = changes::sync(docs.as_ref(), store.as_ref(), &events, request.operations).await?;
+
+    log.caught_up(events.latest()).await;
+    let changes = changes::since(
+        docs.as_ref(),
+        store.as_ref(),
+        events.scope(),
+        request.since,
+        log.recorded(),
+    )
+    .await?;
+
+    Ok(response::Json(SyncResponse { results, changes }).into_response())
+}
@@ -0,0 +1,295 @@
+//! WebSocket method
+//!
+//! This module contains the endpoint that keeps a device in sync over a single
+//! WebSocket, which carries both the changes to the reminders and the device's
+//! own edits.
+//!
+//! Each message is a JSON object with a `type`. The server sends:
+//!
+//! * `change` - A reminder was `created`, `updated` or `deleted`, with the
+//!   change's sequence number and the reminder.
+//! * `ready` - Every change up to the given sequence number has been sent.
+//! * `resync` - Changes were missed, so the device should fetch the reminders
+//!   again. Changes keep being sent.
+//! * `ack` - The outcome of a command, with its `request_id`, the status code
+//!   and message the HTTP route would have responded with, and the `id` of a
+//!   created reminder.
+//!
+//! The device sends `create`, `update` and `delete` commands, each with a
+//! `request_id` of its choosing. They are handled by the same handlers as the
+//! HTTP routes, so they are checked and answered the same way.
+use super::access::{self, Grant, ItemPath, Reminders, RECHECK_INTERVAL};
+use super::{delete, post, put};
+use crate::{
+    events::{Event, Events},
+    middleware::auth::KeyScopes,
+    models::{
+        api_key::Scope, generic_response::ResponseMessage, household::Role, reminder::Reminder,
+    },
+    store::ReminderStore,
+    SharedState,
+};
+use axum::{
+    extract::{
+        ws::{close_code, CloseFrame, Message, WebSocket, WebSocketUpgrade},
+        Extension, Json, Path, Query, State,
+    },
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::Arc;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::{interval_at, Instant};
+
+/// Query parameters of the WebSocket route.
+#[derive(Deserialize)]
+pub struct SyncQuery {
+    /// Sequence number of the last change the device saw, to resume after.
+    pub since: Option<u64>,
+}
+
+/// A command sent by the device.
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Command {
+    Create {
+        reminder: Reminder,
+    },
+    Update {
+        reminder: Reminder,
+        #[serde(default)]
+        if_match: Option<String>,
+    },
+    Delete {
+        id: String,
+        #[serde(default)]
+        if_match: Option<String>,
+    },
+}
+
+/// A message sent to the device.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Outgoing {
+    Change {
+        seq: u64,
+        change: &'static str,
+        reminder: Reminder,
+    },
+    Ready {
+        seq: u64,
+    },
+    Resync,
+    Ack {
+        request_id: Option<String>,
+        status: u16,
+        message: String,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        id: Option<String>,
+    },
+}
+
+/// Everything a connection needs to handle commands.
+struct Session {
+    /// The credentials and path the WebSocket was opened with.
+    grant: Grant,
+    /// Whether commands may change the reminders. Opening the WebSocket only
+    /// needs read access, so API keys are checked for write access here.
+    writable: bool,
+    store: Arc<dyn ReminderStore>,
+    events: Events,
+}
+
+/// Upgrade to a WebSocket that keeps the device in sync.
+///
+/// Needs the same access as reading the reminders, and each command needs
+/// the same access as the HTTP route it stands for, including the
+/// `reminders:write` scope for API keys. The WebSocket is closed once the
+/// user can no longer read the reminders, or their token is revoked.
+///
+/// # Returns
+///
+/// A 101 response switching to the WebSocket protocol, or the error reading
+/// the reminders would have got.
+pub async fn ws(
+    State(state): State<SharedState>,
+    grant: Grant,
+    scopes: Option<Extension<KeyScopes>>,
+    Query(query): Query<SyncQuery>,
+    Reminders(store, events): Reminders,
+    upgrade: WebSocketUpgrade,
+) -> Response {
+    let max_message_size = state.read().await.config.limits.max_body_bytes;
+    let session = Session {
+        grant,
+        writable: scopes.is_none_or(|Extension(scopes)| scopes.allows(Scope::RemindersWrite)),
+        store,
+        events,
+    };
+
+    upgrade
+        .max_message_size(max_message_size)
+        .on_upgrade(move |socket| session.run(socket, query.since))
+}
+
+impl Session {
+    /// Send the changes the device missed, then relay changes and commands
+    /// until either side closes the connection, or the user's access is lost.
+    async fn run(self, mut socket: WebSocket, since: Option<u64>) {
+        let (missed, mut changes, latest) = self.events.resume(since.unwrap_or(u64::MAX));
+        let mut greeting = match (since, missed) {
+            (None, _) => vec![],
+            (Some(_), None) => vec![Outgoing::Resync],
+            (Some(_), Some(missed)) => missed.iter().filter_map(|e| self.change(e)).collect(),
+        };
+        greeting.push(Outgoing::Ready { seq: latest });
+        for message in greeting {
+            if send(&mut socket, &message).await.is_err() {
+                return;
+            }
+        }
+
+        let mut recheck = interval_at(Instant::now() + RECHECK_INTERVAL, RECHECK_INTERVAL);
+        loop {
+            let outgoing = tokio::select! {
+                received = socket.recv() => match received {
+                    Some(Ok(Message::Text(text))) => self.execute(&text).await,
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => continue,
+                },
+                change = changes.recv() => match change {
+                    Ok(event) => match self.change(&event) {
+                        Some(outgoing) => outgoing,
+                        None => continue,
+                    },
+                    Err(RecvError::Lagged(_)) => Outgoing::Resync,
+                    Err(RecvError::Closed) => break,
+                },
+                _ = recheck.tick() => match self.grant.allows(self.events.scope()).await {
+                    true => continue,
+                    false => {
+                        let close = CloseFrame {
+                            code: close_code::POLICY,
+                            reason: "access revoked".into(),
+                        };
+                        let _ = socket.send(Message::Close(Some(close))).await;
+                        break;
+                    }
+                },
+            };
+            if send(&mut socket, &outgoing).await.is_err() {
+                break;
+            }
+        }
+    }
+
+    /// The message for an event, if it is a change to the reminders the
+    /// device is syncing.
+    fn change(&self, event: &Event) -> Option<Outgoing> {
+        if event.scope != self.events.scope() {
+            return None;
+        }
+
+        Some(Outgoing::Change {
+            seq: event.seq,
+            change: event.kind.change()?,
+            reminder: event.reminder.clone(),
+        })
+    }
+
+    /// Handle a command with the handler of the matching HTTP route.
+    async fn execute(&self, text: &str) -> Outgoing {
+        let value: Value = serde_json::from_str(text).unwrap_or_default();
+        let request_id = value["request_id"].as_str().map(String::from);
+        let command = match serde_json::from_value::<Command>(value) {
+            Ok(command) => command,
+            Err(e) => {
+                return Outgoing::Ack {
+                    request_id,
+                    status: StatusCode::BAD_REQUEST.as_u16(),
+                    message: format!("Invalid command: {e}"),
+                    id: None,
+                }
+            }
+        };
+
+        let response = match self.handle(command).await {
+            Ok(response) | Err(response) => response,
+        };
+
+        ack(request_id, response).await
+    }
+
+    /// Check the user may still change the reminders, then run the command.
+    async fn handle(&self, command: Command) -> crate::models::result::Result<Response> {
+        if !self.writable {
+            return Err(
+                ResponseMessage::from("API key does not have the reminders:write scope")
+                    .with_status(StatusCode::FORBIDDEN)
+                    .into_response(),
+            );
+        }
+        self.grant.scope(Role::Editor).await?;
+        let reminders = || Reminders(self.store.clone(), self.events.clone());
+
+        match command {
+            Command::Create { reminder } => post::post(reminders(), Json(reminder)).await,
+            Command::Update { reminder, if_match } => match reminder.id.clone() {
+                Some(id) => {
+                    access::check_ids([id.as_str()])?;
+                    let path = Path(ItemPath { id });
+                    put::put_by_id(reminders(), path, headers(if_match), Json(reminder)).await
+                }
+                None => put::put(reminders(), headers(if_match), Json(reminder)).await,
+            },
+            Command::Delete { id, if_match } => {
+                access::check_ids([id.as_str()])?;
+                let path = Path(ItemPath { id });
+                delete::delete_by_id(reminders(), path, headers(if_match)).await
+            }
+        }
+    }
+}
+
+/// Headers carrying a command's entity tag, as an HTTP request would.
+fn headers(if_match: Option<String>) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    if let Some(value) = if_match.and_then(|tag| HeaderValue::from_str(&tag).ok()) {
+        headers.insert(header::IF_MATCH, value);
+    }
+
+    headers
+}
+
+/// Acknowledge a command with the response its handler gave.
+async fn ack(request_id: Option<String>, response: Response) -> Outgoing {
+    let status = response.status().as_u16();
+    let id = response
+        .headers()
+        .get(header::LOCATION)
+        .and_then(|value| value.to_str().ok())
+        .map(String::from);
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+        .await
+        .unwrap_or_default();
+    let message = serde_json::from_slice::<Value>(&body)
+        .ok()
+        .and_then(|body| body["message"].as_str().map(String::from))
+        .unwrap_or_default();
+
+    Outgoing::Ack {
+        request_id,
+        status,
+        message,
+        id,
+    }
+}
+
+/// Send a message to the device.
+async fn send(socket: &mut WebSocket, message: &Outgoing) -> Result<(), axum::Error> {
+    let text = serde_json::to_string(message).unwrap_or_default();
+
+    socket.send(Message::Text(text)).await
+}
@@ -0,0 +1,91 @@
+//! # Webhook routes.
+//!
+//! Every webhook route needs a bearer token. Webhooks belong to the user who
+//! added them.
+use crate::{
+    middleware::auth::AuthUser,
+    models::{generic_response::ResponseMessage, result::Result, webhook::NewWebhook},
+    webhooks, SharedState,
+};
+use axum::{
+    extract::{Json, Path, State},
+    http::StatusCode,
+    response::{self, IntoResponse, Response},
+    routing::{delete, get},
+    Router,
+};
+
+/// Returns the webhook routes.
+pub fn router() -> Router<SharedState> {
+    Router::new()
+        .route("/webhooks", get(list).post(create))
+        .route("/webhooks/:id", delete(remove))
+        .route("/webhooks/:id/deliveries", get(deliveries))
+}
+
+/// Get the user's webhooks.
+///
+/// # Returns
+///
+/// A JSON response with each webhook, without its secret.
+pub async fn list(State(state): State<SharedState>, user: AuthUser) -> Result<Response> {
+    let store = state.read().await.store.clone();
+    let webhooks = webhooks::list(store.as_ref(), &user.id).await?;
+
+    Ok(response::Json(webhooks).into_response())
+}
+
+/// Add a webhook for the user.
+///
+/// # Returns
+///
+/// A JSON response with the webhook and its secret and a 201 status code, a
+/// 400 if the webhook is not valid, or a 403 if the user cannot see the
+/// household list it is for.
+pub async fn create(
+    State(state): State<SharedState>,
+    user: AuthUser,
+    Json(body): Json<NewWebhook>,
+) -> Result<Response> {
+    let (store, allow_private) = {
+        let state = state.read().await;
+        (state.store.clone(), state.config.webhooks.allow_private)
+    };
+    let webhook = webhooks::create(store.as_ref(), &user, body, allow_private).await?;
+
+    Ok((StatusCode::CREATED, response::Json(webhook)).into_response())
+}
+
+/// Remove one of the user's webhooks.
+///
+/// # Returns
+///
+/// A JSON response with a 200 status code, or a 404 if the user has no such
+/// webhook.
+pub async fn remove(
+    State(state): State<SharedState>,
+    user: AuthUser,
+    Path(id): Path<String>,
+) -> Result<Response> {
+    let store = state.read().await.store.clone();
+    webhooks::delete(store.as_ref(), &user.id, &id).await?;
+
+    Ok(ResponseMessage::from("Removed webhook").into_response())
+}
+
+/// Get the delivery log of one of the user's webhooks.
+///
+/// # Returns
+///
+/// A JSON response with each delivery and its attempts, newest first, or a
+/// 404 if the user has no such webhook.
+pub async fn deliveries(
+    State(state): State<SharedState>,
+    user: AuthUser,
+    Path(id): Path<String>,
+) -> Result<Response> {
+    let store = state.read().await.store.clone();
+    let deliveries = webhooks::deliveries(store.as_ref(), &user.id, &id).await?;
+
+    Ok(response::Json(deliveries).into_response())
+}
@@ -0,0 +1,574 @@
+//! Fire notifications when reminders fall due.
+//!
+//! The scheduler keeps the due times of open reminders in a queue and sleeps
+//! until the next one. The queue is only a cache: it is rebuilt from storage
+//! when the scheduler starts and every few minutes in case storage was changed
+//! some other way, and a scope's part of it is reloaded whenever a reminder in
+//! the scope is written through [`Watched`] storage.
+//!
+//! Before notifying, the scheduler claims the reminder's due time in the
+//! `notifications` document collection. The claim can only be made once, so a
+//! due time is notified about at most once, even across restarts or with
+//! several servers sharing storage. Due times missed while the server was down
+//! are still notified about if they are within the catch up window.
+//!
+//! The scheduler also sends daily digests, checking for users whose digest is
+//! due every hour.
+use crate::digest;
+use crate::events::{Bus, EventKind};
+use crate::models::{bulk::ItemResult, query::ListQuery, query::Page, reminder::Reminder};
+use crate::notifiers::{self, Notification, Notifier};
+use crate::store::{
+    self, from_document, to_document, DocumentStore, ReminderStore, Storage, Tagged,
+};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeSet;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// Collection of claimed due times.
+const NOTIFICATIONS: &str = "notifications";
+
+/// How often to rebuild the queue from storage even if nothing was written.
+const RESCAN_INTERVAL: u64 = 5 * 60;
+
+/// How long to wait before trying again after storage fails.
+const RETRY_INTERVAL: u64 = 30;
+
+/// How often to delete claims that have left the catch up window.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How often to check for users whose daily digest is due.
+const DIGEST_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// A reminder's due time, ordered by time.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Due {
+    due: u64,
+    scope: String,
+    id: String,
+}
+
+impl Due {
+    /// Id of the claim on this due time.
+    fn key(&self) -> String {
+        crate::accounts::token_key(&format!("{}\n{}\n{}", self.scope, self.id, self.due))
+    }
+}
+
+/// A claimed due time.
+#[derive(Debug, Deserialize, Serialize)]
+struct Claim {
+    scope: String,
+    reminder_id: String,
+    due: u64,
+    fired: u64,
+}
+
+/// Watches reminders and notifies about them when they fall due.
+pub struct Scheduler {
+    store: Arc<dyn Storage>,
+    notifiers: Vec<Arc<dyn Notifier>>,
+    /// Seconds after falling due that a reminder is still notified about.
+    catch_up: u64,
+    queue: BTreeSet<Due>,
+    /// Due times handled by this process that are still in the catch up window.
+    handled: BTreeSet<Due>,
+    /// Hour of the day to send digests at, if they are sent.
+    digest_hour: Option<u64>,
+    /// Where to publish `reminder.due` events, if anywhere.
+    events: Option<Bus>,
+}
+
+impl Scheduler {
+    /// Create a scheduler with an empty queue.
+    ///
+    /// # Arguments
+    ///
+    /// * `store` - The storage to watch, covering every reminders scope.
+    /// * `notifiers` - The backends to notify.
+    /// * `catch_up` - Seconds after falling due that a reminder is still notified about.
+    pub fn new(store: Arc<dyn Storage>, notifiers: Vec<Arc<dyn Notifier>>, catch_up: u64) -> Self {
+        Scheduler {
+            store,
+            notifiers,
+            catch_up,
+            queue: BTreeSet::new(),
+            handled: BTreeSet::new(),
+            digest_hour: None,
+            events: None,
+        }
+    }
+
+    /// Also send daily digests, at the given hour in each user's time zone.
+    pub fn with_digests(mut self, hour: u64) -> Self {
+        self.digest_hour = Some(hour);
+        self
+    }
+
+    /// Also publish an event on the bus when a reminder falls due.
+    pub fn with_events(mut self, bus: Bus) -> Self {
+        self.events = Some(bus);
+        self
+    }
+
+    /// Run the scheduler in the background.
+    ///
+    /// # Arguments
+    ///
+    /// * `wake` - Receives the scope of each write, so its reminders are reloaded.
+    pub fn spawn(
+        mut self,
+        mut wake: mpsc::UnboundedReceiver<String>,
+    ) -> tokio::task::JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut prune = tokio::time::interval(PRUNE_INTERVAL);
+            let mut digests = tokio::time::interval(DIGEST_INTERVAL);
+            let mut rescan_at = 0;
+            let mut changed: BTreeSet<String> = BTreeSet::new();
+            loop {
+                let now = crate::time::now();
+                if now >= rescan_at {
+                    changed.clear();
+                    rescan_at = match self.load(now).await {
+                        Ok(()) => now + RESCAN_INTERVAL,
+                        Err(e) => {
+                            log::error!("failed to load reminders to schedule: {e}");
+                            now + RETRY_INTERVAL
+                        }
+                    };
+                }
+                for scope in std::mem::take(&mut changed) {
+                    if let Err(e) = self.load_scope(&scope, now).await {
+                        log::error!("failed to load reminders to schedule in {scope:?}: {e}");
+                        rescan_at = rescan_at.min(now + RETRY_INTERVAL);
+                    }
+                }
+                match self.run_due(now).await {
+                    Ok(0) => {}
+                    Ok(n) => log::info!("notified about {n} due reminders"),
+                    Err(e) => {
+                        log::error!("failed to notify about due reminders: {e}");
+                        rescan_at = rescan_at.min(now + RETRY_INTERVAL);
+                    }
+                }
+
+                let wait_until = self.next_due().map_or(rescan_at, |due| due.min(rescan_at));
+                let wait = Duration::from_secs(wait_until.saturating_sub(now).max(1));
+                tokio::select! {
+                    _ = tokio::time::sleep(wait) => {}
+                    Some(scope) = wake.recv() => {
+                        changed.insert(scope);
+                        while let Ok(scope) = wake.try_recv() {
+                            changed.insert(scope);
+                        }
+                    }
+                    _ = digests.tick() => {
+                        if let Some(hour) = self.digest_hour {
+                            let now = crate::time::now();
+                            let sent =
+                                digest::run(self.store.as_ref(), &self.notifiers, hour, now).await;
+                            match sent {
+                                Ok(0) => {}
+                                Ok(n) => log::info!("sent {n} daily digests"),
+                                Err(e) => log::error!("failed to send daily digests: {e}"),
+                            }
+                        }
+                    }
+                    _ = prune.tick() => {
+                        let now = crate::time::now();
+                        if let Err(e) = self.prune(now).await {
+                            log::error!("failed to prune notification claims: {e}");
+                        }
+                        if let Err(e) = digest::prune(self.store.as_ref(), now).await {
+                            log::error!("failed to prune digest claims: {e}");
+                        }
+                    }
+                }
+            }
+        })
+    }
+
+    /// Rebuild the queue from the open reminders in storage.
+    ///
+    /// Reminders that fell due before the catch up window, and due times this
+    /// scheduler has already handled, are left out.
+    pub async fn load(&mut self, now: u64) -> store::Result<()> {
+        let cutoff = now.saturating_sub(self.catch_up);
+        self.handled.retain(|due| due.due >= cutoff);
+
+        let mut queue = BTreeSet::new();
+        for scope in store::scopes(self.store.as_ref()).await? {
+            queue.extend(self.scan(&scope, cutoff).await?);
+        }
+        self.queue = queue;
+
+        Ok(())
+    }
+
+    /// Rebuild the part of the queue for one scope, after a write to it.
+    pub async fn load_scope(&mut self, scope: &str, now: u64) -> store::Result<()> {
+        let cutoff = now.saturating_sub(self.catch_up);
+        let queued = self.scan(scope, cutoff).await?;
+        self.queue.retain(|due| due.scope != scope);
+        self.queue.extend(queued);
+
+        Ok(())
+    }
+
+    /// Get the due times in a scope that should be queued.
+    async fn scan(&self, scope: &str, cutoff: u64) -> store::Result<Vec<Due>> {
+        let mut queued = Vec::new();
+        for reminder in self.store.reminders(scope).list().await?.value {
+            let Some(id) = reminder.id.filter(|_| !reminder.completed) else {
+                continue;
+            };
+            let due = Due {
+                due: reminder.due,
+                scope: scope.to_string(),
+                id,
+            };
+            if due.due >= cutoff && !self.handled.contains(&due) {
+                queued.push(due);
+            }
+        }
+
+        Ok(queued)
+    }
+
+    /// The earliest due time in the queue.
+    pub fn next_due(&self) -> Option<u64> {
+        self.queue.first().map(|due| due.due)
+    }
+
+    /// Notify about every queued reminder that is due by `now`.
+    ///
+    /// # Returns
+    ///
+    /// The number of reminders notified about.
+    pub async fn run_due(&mut self, now: u64) -> store::Result<usize> {
+        let mut fired = 0;
+        while let Some(due) = self.queue.first().filter(|due| due.due <= now).cloned() {
+            if self.fire(&due, now).await? {
+                fired += 1;
+            }
+            self.queue.remove(&due);
+            self.handled.insert(due);
+        }
+
+        Ok(fired)
+    }
+
+    /// Claim a due time and notify about it.
+    ///
+    /// # Returns
+    ///
+    /// Whether notifiers were called. They are not if the reminder has since
+    /// changed or someone else already claimed the due time.
+    async fn fire(&self, due: &Due, now: u64) -> store::Result<bool> {
+        let reminder = match self.store.reminders(&due.scope).get(&due.id).await {
+            Ok(reminder) => reminder.value,
+            Err(store::Error::NotFound) => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        if reminder.completed || reminder.due != due.due {
+            return Ok(false);
+        }
+
+        let claim = Claim {
+            scope: due.scope.clone(),
+            reminder_id: due.id.clone(),
+            due: due.due,
+            fired: now,
+        };
+        match self
+            .store
+            .insert_document(NOTIFICATIONS, &due.key(), to_document(&claim)?)
+            .await
+        {
+            Err(store::Error::Conflict) => return Ok(false),
+            result => result?,
+        }
+
+        let notification = Notification {
+            recipients: notifiers::recipients(self.store.as_ref(), &due.scope).await?,
+            scope: due.scope.clone(),
+            reminder,
+        };
+        for notifier in &self.notifiers {
+            if let Err(e) = notifier.notify(&notification).await {
+                log::error!(
+                    "{} failed to notify about reminder {}: {e}",
+                    notifier.name(),
+                    due.id
+                );
+            }
+        }
+        if let Some(events) = &self.events {
+            events.publish(EventKind::Due, &due.scope, notification.reminder);
+        }
+
+        Ok(true)
+    }
+
+    /// Delete the claims on due times that have left the catch up window, as
+    /// they can no longer be notified about.
+    ///
+    /// # Returns
+    ///
+    /// The number of claims deleted.
+    pub async fn prune(&self, now: u64) -> store::Result<usize> {
+        let cutoff = now.saturating_sub(self.catch_up);
+
+        let mut pruned = 0;
+        for (id, claim) in self.store.list_documents(NOTIFICATIONS).await? {
+            if from_document::<Claim>(claim)?.due < cutoff {
+                self.store.delete_document(NOTIFICATIONS, &id).await?;
+                pruned += 1;
+            }
+        }
+
+        Ok(pruned)
+    }
+}
+
+/// Storage that wakes the scheduler whenever a reminder is written.
+pub struct Watched {
+    inner: Arc<dyn Storage>,
+    wake: mpsc::UnboundedSender<String>,
+}
+
+impl Watched {
+    /// Wrap storage so writes through it wake the scheduler with their scope.
+    pub fn new(inner: Arc<dyn Storage>, wake: mpsc::UnboundedSender<String>) -> Self {
+        Watched { inner, wake }
+    }
+}
+
+#[async_trait]
+impl DocumentStore for Watched {
+    async fn get_document(&self, collection: &str, id: &str) -> store::Result<Option<Value>> {
+        self.inner.get_document(collection, id).await
+    }
+
+    async fn list_documents(&self, collection: &str) -> store::Result<Vec<(String, Value)>> {
+        self.inner.list_documents(collection).await
+    }
+
+    async fn insert_document(&self, collection: &str, id: &str, value: Value) -> store::Result<()> {
+        self.inner.insert_document(collection, id, value).await
+    }
+
+    async fn put_document(&self, collection: &str, id: &str, value: Value) -> store::Result<()> {
+        self.inner.put_document(collection, id, value).await
+    }
+
+    async fn delete_document(&self, collection: &str, id: &str) -> store::Result<()> {
+        self.inner.delete_document(collection, id).await
+    }
+}
+
+#[async_trait]
+impl Storage for Watched {
+    fn reminders(&self, scope: &str) -> Arc<dyn ReminderStore> {
+        Arc::new(WatchedReminders {
+            inner: self.inner.reminders(scope),
+            scope: scope.to_string(),
+            wake: self.wake.clone(),
+        })
+    }
+
+    async fn shutdown(&self) -> store::Result<()> {
+        self.inner.shutdown().await
+    }
+
+    fn watch(&self, bus: &Bus) {
+        self.inner.watch(bus);
+    }
+}
+
+/// A reminders collection that wakes the scheduler after each write.
+struct WatchedReminders {
+    inner: Arc<dyn ReminderStore>,
+    scope: String,
+    wake: mpsc::UnboundedSender<String>,
+}
+
+impl WatchedReminders {
+    /// Wake the scheduler if a write succeeded.
+    fn woken<T>(&self, result: store::Result<T>) -> store::Result<T> {
+        if result.is_ok() {
+            // The scheduler only stops with the server.
+            let _ = self.wake.send(self.scope.clone());
+        }
+        result
+    }
+}
+
+#[async_trait]
+impl ReminderStore for WatchedReminders {
+    async fn list(&self) -> store::Result<Tagged<Vec<Reminder>>> {
+        self.inner.list().await
+    }
+
+    async fn query(&self, query: &ListQuery) -> store::Result<Page> {
+        self.inner.query(query).await
+    }
+
+    async fn get(&self, id: &str) -> store::Result<Tagged<Reminder>> {
+        self.inner.get(id).await
+    }
+
+    async fn create(&self, reminder: Reminder) -> store::Result<Reminder> {
+        self.woken(self.inner.create(reminder).await)
+    }
+
+    async fn replace(
+        &self,
+        id: &str,
+        reminder: Reminder,
+        if_match: Option<&str>,
+    ) -> store::Result<()> {
+        self.woken(self.inner.replace(id, reminder, if_match).await)
+    }
+
+    async fn update_many(
+        &self,
+        reminders: Vec<Reminder>,
+        if_match: Option<&str>,
+    ) -> store::Result<Vec<ItemResult>> {
+        self.woken(self.inner.update_many(reminders, if_match).await)
+    }
+
+    async fn delete(&self, id: &str, if_match: Option<&str>) -> store::Result<()> {
+        self.woken(self.inner.delete(id, if_match).await)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::reminder::Reminder;
+    use crate::notifiers::{Notification, Notifier};
+    use crate::scheduler::{Scheduler, Watched};
+    use crate::store::{memory::Memory, Storage};
+    use async_trait::async_trait;
+    use std::sync::{Arc, Mutex};
+    use tokio::sync::mpsc;
+
+    /// Remembers the titles of the reminders it is notified about.
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<String>>);
+
+    #[async_trait]
+    impl Notifier for Recorder {
+        fn name(&self) -> &'static str {
+            "recorder"
+        }
+
+        async fn notify(&self, notification: &Notification) -> Result<(), String> {
+            let title = notification.reminder.title.clone();
+            self.0.lock().unwrap().push(title);
+            Ok(())
+        }
+    }
+
+    fn reminder(title: &str, due: u64) -> Reminder {
+        Reminder {
+            id: None,
+            title: title.into(),
+            due,
+            priority: 0,
+            assignee: None,
+            completed: false,
+            completed_at: None,
+            rrule: None,
+        }
+    }
+
+    /// Test that reminders are notified about once, when due, across restarts.
+    #[tokio::test]
+    async fn test_fires_once() {
+        let store: Arc<dyn Storage> = Arc::new(Memory::default());
+        let reminders = store.reminders("");
+        reminders.create(reminder("Ancient", 100)).await.unwrap();
+        reminders.create(reminder("Missed", 950)).await.unwrap();
+        reminders.create(reminder("Later", 2000)).await.unwrap();
+        let mut done = reminder("Done", 990);
+        done.complete(990);
+        reminders.create(done).await.unwrap();
+
+        let recorder = Arc::new(Recorder::default());
+        let mut scheduler = Scheduler::new(store.clone(), vec![recorder.clone()], 100);
+        scheduler.load(1000).await.unwrap();
+        assert_eq!(scheduler.next_due(), Some(950));
+        assert_eq!(scheduler.run_due(1000).await.unwrap(), 1);
+        assert_eq!(scheduler.next_due(), Some(2000));
+
+        // A restarted scheduler finds the claim and does not notify again.
+        let mut restarted = Scheduler::new(store.clone(), vec![recorder.clone()], 100);
+        restarted.load(1000).await.unwrap();
+        assert_eq!(restarted.run_due(1000).await.unwrap(), 0);
+        assert_eq!(restarted.run_due(2000).await.unwrap(), 1);
+        assert_eq!(*recorder.0.lock().unwrap(), vec!["Missed", "Later"]);
+
+        assert_eq!(restarted.prune(1100).await.unwrap(), 1);
+    }
+
+    /// Test that a reminder moved after it was queued is not notified about early.
+    #[tokio::test]
+    async fn test_moved() {
+        let store: Arc<dyn Storage> = Arc::new(Memory::default());
+        let (wake, mut woken) = mpsc::unbounded_channel();
+        let watched = Watched::new(store.clone(), wake);
+        let reminders = watched.reminders("");
+        let created = reminders.create(reminder("Bins", 500)).await.unwrap();
+        // The write left a wake-up for the scheduler, naming its scope.
+        assert_eq!(woken.recv().await.as_deref(), Some(""));
+
+        let recorder = Arc::new(Recorder::default());
+        let mut scheduler = Scheduler::new(store, vec![recorder.clone()], 100);
+        scheduler.load(400).await.unwrap();
+
+        let id = created.id.clone().unwrap();
+        reminders
+            .replace(&id, reminder("Bins", 800), None)
+            .await
+            .unwrap();
+        assert_eq!(scheduler.run_due(500).await.unwrap(), 0);
+
+        scheduler.load(500).await.unwrap();
+        assert_eq!(scheduler.run_due(800).await.unwrap(), 1);
+        assert_eq!(*recorder.0.lock().unwrap(), vec!["Bins"]);
+    }
+
+    /// Test that reloading a scope after a write leaves other scopes queued.
+    #[tokio::test]
+    async fn test_load_scope() {
+        let store: Arc<dyn Storage> = Arc::new(Memory::default());
+        store
+            .reminders("")
+            .create(reminder("Shared", 500))
+            .await
+            .unwrap();
+        let own = store.reminders("users/abc");
+        let created = own.create(reminder("Own", 600)).await.unwrap();
+
+        let recorder = Arc::new(Recorder::default());
+        let mut scheduler = Scheduler::new(store.clone(), vec![recorder.clone()], 100);
+        scheduler.load(400).await.unwrap();
+        assert_eq!(scheduler.next_due(), Some(500));
+
+        own.delete(created.id.as_deref().unwrap(), None)
+            .await
+            .unwrap();
+        own.create(reminder("Sooner", 450)).await.unwrap();
+        scheduler.load_scope("users/abc", 400).await.unwrap();
+        assert_eq!(scheduler.next_due(), Some(450));
+
+        assert_eq!(scheduler.run_due(1000).await.unwrap(), 2);
+        assert_eq!(*recorder.0.lock().unwrap(), vec!["Sooner", "Shared"]);
+    }
+}
@@ -0,0 +1,431 @@
+//! Firebase Realtime Database implementation of [`Storage`].
+//!
+//! The shared reminders collection lives at `reminders/v2`, and the collection
+//! of scope `<scope>` at `<scope>/reminders/v2`. Documents live under `docs/`.
+//!
+//! List queries are pushed down to Firebase's `orderBy` filters, which need
+//! `".indexOn": ["due", "assignee", "priority"]` in the database rules for
+//! each reminders collection. Without the index the whole collection is
+//! fetched instead.
+use super::{check_id, DocumentStore, Error, ReminderStore, Result, Storage, Tagged};
+use crate::events::Bus;
+use crate::firebase::{self, Firebase};
+use crate::models::{
+    bulk::{ItemResult, ItemStatus},
+    query::{ListQuery, Page, SortField, SortKey, Status},
+    record::ReminderRecord,
+    reminder::Reminder,
+};
+use async_trait::async_trait;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+/// Location of a reminders collection, relative to its scope.
+const PATH: &str = "reminders/v2";
+
+/// Location of the document collections in the database.
+const DOCUMENTS: &str = "docs";
+
+/// How many times to retry writing a reminder that changed after it was read.
+const MAX_RETRIES: usize = 3;
+
+/// Firebase's entity tag for a location that holds no data.
+const NULL_ETAG: &str = "null_etag";
+
+type RawReminders = HashMap<String, Value>;
+
+impl std::convert::From<firebase::Error> for Error {
+    fn from(value: firebase::Error) -> Self {
+        match value {
+            firebase::Error::NotFound => Error::NotFound,
+            firebase::Error::PreconditionFailed => Error::PreconditionFailed,
+            _ => Error::Backend(value.to_string()),
+        }
+    }
+}
+
+/// Translate as much of a query as Firebase can evaluate into query parameters.
+///
+/// Firebase can only order by one child, so the due date range is preferred,
+/// then the assignee, then the priority. Whatever is left is applied in memory.
+fn firebase_params(query: &ListQuery, keys: &[SortKey]) -> Vec<(&'static str, String)> {
+    let mut params = vec![];
+
+    if query.due_before.is_some() || query.due_after.is_some() {
+        params.push(("orderBy", r#""due""#.to_string()));
+        if let Some(after) = query.due_after {
+            params.push(("startAt", (after + 1).to_string()));
+        }
+        if let Some(before) = query.due_before {
+            params.push(("endAt", before.saturating_sub(1).to_string()));
+        }
+    } else if let Some(assignee) = &query.assignee {
+        params.push(("orderBy", r#""assignee""#.to_string()));
+        params.push(("equalTo", Value::String(assignee.clone()).to_string()));
+    } else if let Some(priority) = query.priority {
+        params.push(("orderBy", r#""priority""#.to_string()));
+        params.push(("equalTo", priority.to_string()));
+    }
+
+    // Firebase breaks ties by key, as we do, so when every filter has been
+    // pushed down the first page of an ascending due date sort can be limited.
+    let due_sort = keys
+        == [SortKey {
+            field: SortField::Due,
+            descending: false,
+        }];
+    let due_filters_only =
+        query.assignee.is_none() && query.priority.is_none() && query.status == Status::All;
+    if let (Some(limit), true, true, None) =
+        (query.limit, due_sort, due_filters_only, &query.cursor)
+    {
+        if params.is_empty() {
+            params.push(("orderBy", r#""due""#.to_string()));
+        }
+        params.push(("limitToFirst", (limit + 1).to_string()));
+    }
+
+    params
+}
+
+/// Location of a document collection, or of a document in it.
+///
+/// # Errors
+///
+/// Returns `Error::InvalidId` if the id or a segment of the collection is not
+/// a valid id, so that neither can reach outside the documents.
+fn document_path(collection: &str, id: Option<&str>) -> Result<String> {
+    for segment in collection.split('/').chain(id) {
+        check_id(segment)?;
+    }
+
+    Ok(match id {
+        Some(id) => format!("{DOCUMENTS}/{collection}/{id}"),
+        None => format!("{DOCUMENTS}/{collection}"),
+    })
+}
+
+/// The reminders of one scope.
+struct Collection {
+    db: Firebase,
+    path: String,
+}
+
+#[async_trait]
+impl Storage for Firebase {
+    fn reminders(&self, scope: &str) -> Arc<dyn ReminderStore> {
+        let path = match scope {
+            "" => PATH.to_string(),
+            scope => format!("{scope}/{PATH}"),
+        };
+
+        Arc::new(Collection {
+            db: self.clone(),
+            path,
+        })
+    }
+
+    fn watch(&self, bus: &Bus) {
+        super::relay::spawn(self.clone(), bus.clone());
+    }
+}
+
+#[async_trait]
+impl DocumentStore for Firebase {
+    async fn get_document(&self, collection: &str, id: &str) -> Result<Option<Value>> {
+        let path = document_path(collection, Some(id))?;
+
+        Ok(self.query(&path, &[]).await?)
+    }
+
+    async fn list_documents(&self, collection: &str) -> Result<Vec<(String, Value)>> {
+        let path = document_path(collection, None)?;
+        let data: Option<HashMap<String, Value>> = self.query(&path, &[]).await?;
+
+        Ok(data.unwrap_or_default().into_iter().collect())
+    }
+
+    async fn insert_document(&self, collection: &str, id: &str, value: Value) -> Result<()> {
+        let path = document_path(collection, Some(id))?;
+
+        match self.put_if_match(&path, value, NULL_ETAG).await {
+            Err(firebase::Error::PreconditionFailed) => Err(Error::Conflict),
+            result => Ok(result?),
+        }
+    }
+
+    async fn put_document(&self, collection: &str, id: &str, value: Value) -> Result<()> {
+        let path = document_path(collection, Some(id))?;
+
+        Ok(self.put(&path, value).await?)
+    }
+
+    async fn delete_document(&self, collection: &str, id: &str) -> Result<()> {
+        let path = document_path(collection, Some(id))?;
+
+        Ok(self.delete(&path).await?)
+    }
+}
+
+#[async_trait]
+impl ReminderStore for Collection {
+    async fn list(&self) -> Result<Tagged<Vec<Reminder>>> {
+        let (data, etag): (Option<RawReminders>, _) = self.db.get_with_etag(&self.path).await?;
+
+        Ok(Tagged {
+            value: ReminderRecord::decode_all(data.unwrap_or_default()).logged(),
+            etag,
+        })
+    }
+
+    async fn query(&self, query: &ListQuery) -> Result<Page> {
+        let params = firebase_params(query, &query.sort_keys()?);
+        if params.is_empty() {
+            return self.query_all(query).await;
+        }
+
+        let data: Option<RawReminders> = match self.db.query(&self.path, &params).await {
+            Err(firebase::Error::Query) => {
+                log::warn!("Firebase rejected query {params:?}, is the index defined?");
+                return self.query_all(query).await;
+            }
+            data => data?,
+        };
+
+        query.apply(ReminderRecord::decode_all(data.unwrap_or_default()).logged())
+    }
+
+    async fn get(&self, id: &str) -> Result<Tagged<Reminder>> {
+        let path = self.item_path(id)?;
+        let (data, etag): (Option<Value>, _) = self.db.get_with_etag(&path).await?;
+
+        let (reminder, warnings) = ReminderRecord::decode(id, data.ok_or(Error::NotFound)?);
+        for warning in warnings {
+            log::warn!("{warning}");
+        }
+        let reminder =
+            reminder.ok_or_else(|| Error::Backend(format!("reminder {id} is malformed")))?;
+
+        Ok(Tagged {
+            value: reminder,
+            etag,
+        })
+    }
+
+    async fn create(&self, mut reminder: Reminder) -> Result<Reminder> {
+        let id = self
+            .db
+            .post(&self.path, ReminderRecord::from(reminder.clone()))
+            .await?;
+        reminder.id = Some(id);
+
+        Ok(reminder)
+    }
+
+    async fn replace(&self, id: &str, reminder: Reminder, if_match: Option<&str>) -> Result<()> {
+        let reminder = ReminderRecord::from(reminder);
+        let path = self.item_path(id)?;
+
+        match if_match {
+            Some(etag) => Ok(self.db.put_if_match(&path, reminder, etag).await?),
+            None => Ok(self.db.put(&path, reminder).await?),
+        }
+    }
+
+    async fn update_many(
+        &self,
+        reminders: Vec<Reminder>,
+        if_match: Option<&str>,
+    ) -> Result<Vec<ItemResult>> {
+        for reminder in &reminders {
+            check_id(reminder.id.as_deref().unwrap_or_default())?;
+        }
+        let records = ReminderRecord::encode_all(reminders);
+
+        // Firebase cannot make a PATCH conditional, and an unconditional one
+        // would recreate a reminder deleted since it was read. So each reminder
+        // is replaced with its own conditional PUT, which only succeeds while
+        // it exists, unless the caller needs the whole collection unchanged.
+        if let Some(if_match) = if_match {
+            return self.update_all(&records, if_match).await;
+        }
+
+        let mut results = vec![];
+        for (id, record) in &records {
+            results.push(ItemResult::new(id, self.update_one(id, record).await?));
+        }
+
+        Ok(results)
+    }
+
+    async fn delete(&self, id: &str, if_match: Option<&str>) -> Result<()> {
+        let path = self.item_path(id)?;
+
+        match if_match {
+            Some(etag) => Ok(self.db.delete_if_match(&path, etag).await?),
+            None => Ok(self.db.delete(&path).await?),
+        }
+    }
+}
+
+impl Collection {
+    /// Location of a reminder in the collection.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::InvalidId` if the id is not a valid id.
+    fn item_path(&self, id: &str) -> Result<String> {
+        check_id(id)?;
+
+        Ok(format!("{}/{id}", self.path))
+    }
+
+    /// Replace a reminder if it still exists, reading it again if it changes
+    /// before it is written.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::PreconditionFailed` if the reminder kept changing.
+    async fn update_one(&self, id: &str, record: &ReminderRecord) -> Result<ItemStatus> {
+        let path = self.item_path(id)?;
+        for _ in 0..=MAX_RETRIES {
+            let (data, etag): (Option<Value>, _) = self.db.get_with_etag(&path).await?;
+            if data.is_none() {
+                return Ok(ItemStatus::NotFound);
+            }
+
+            match self.db.put_if_match(&path, record, &etag).await {
+                Err(firebase::Error::PreconditionFailed) => continue,
+                result => {
+                    result?;
+                    return Ok(ItemStatus::Updated);
+                }
+            }
+        }
+
+        Err(Error::PreconditionFailed)
+    }
+
+    /// Replace reminders only if the collection still has the given ETag.
+    ///
+    /// An ETag covers the whole collection, so the collection is rewritten
+    /// as read with the updates merged in, in a single conditional PUT.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::PreconditionFailed` if the collection has changed.
+    async fn update_all(
+        &self,
+        records: &BTreeMap<String, ReminderRecord>,
+        if_match: &str,
+    ) -> Result<Vec<ItemResult>> {
+        let (data, etag): (Option<RawReminders>, _) = self.db.get_with_etag(&self.path).await?;
+        if if_match != etag {
+            return Err(Error::PreconditionFailed);
+        }
+
+        let mut merged = data.unwrap_or_default();
+        let mut results = vec![];
+        for (id, record) in records {
+            match merged.get_mut(id) {
+                Some(existing) => {
+                    *existing = super::to_document(record)?;
+                    results.push(ItemResult::new(id, ItemStatus::Updated));
+                }
+                None => results.push(ItemResult::new(id, ItemStatus::NotFound)),
+            }
+        }
+        if results.iter().any(|r| r.status == ItemStatus::Updated) {
+            self.db.put_if_match(&self.path, merged, &etag).await?;
+        }
+
+        Ok(results)
+    }
+
+    /// Answer a query by fetching the whole collection, tagged with its ETag.
+    async fn query_all(&self, query: &ListQuery) -> Result<Page> {
+        let list = ReminderStore::list(self).await?;
+        let page = query.apply(list.value)?;
+
+        Ok(Page {
+            etag: Some(list.etag),
+            ..page
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::query::{ListQuery, Status};
+    use crate::store::{
+        firebase::{document_path, firebase_params},
+        Error,
+    };
+
+    /// Test that a due date range and limit are pushed down to Firebase.
+    #[test]
+    fn test_due_range_with_limit() {
+        let query = ListQuery {
+            due_after: Some(100),
+            due_before: Some(200),
+            sort: Some("due".into()),
+            limit: Some(10),
+            status: Status::All,
+            ..Default::default()
+        };
+
+        let params = firebase_params(&query, &query.sort_keys().unwrap());
+
+        assert_eq!(
+            params,
+            vec![
+                ("orderBy", r#""due""#.to_string()),
+                ("startAt", "101".to_string()),
+                ("endAt", "199".to_string()),
+                ("limitToFirst", "11".to_string()),
+            ]
+        );
+    }
+
+    /// Test that the limit stays local when other filters are applied in memory.
+    #[test]
+    fn test_limit_not_pushed_with_local_filters() {
+        let query = ListQuery {
+            due_after: Some(100),
+            assignee: Some("Sam".into()),
+            sort: Some("due".into()),
+            limit: Some(10),
+            ..Default::default()
+        };
+
+        let params = firebase_params(&query, &query.sort_keys().unwrap());
+
+        assert_eq!(params.len(), 2);
+        assert!(params.iter().all(|(name, _)| *name != "limitToFirst"));
+    }
+
+    /// Test that ids and collections that could leave the documents are rejected.
+    #[test]
+    fn test_document_path() {
+        assert_eq!(
+            document_path("members/abc", Some("d-e_f")).unwrap(),
+            "docs/members/abc/d-e_f"
+        );
+        assert_eq!(document_path("accounts", None).unwrap(), "docs/accounts");
+
+        for (collection, id) in [
+            ("accounts", ".."),
+            ("accounts", "a%2F.."),
+            ("accounts", "a/b"),
+            ("accounts", ""),
+            ("members/..", "a"),
+            ("members/%2F", "a"),
+        ] {
+            assert!(matches!(
+                document_path(collection, Some(id)),
+                Err(Error::InvalidId(_))
+            ));
+        }
+    }
+}
@@ -0,0 +1,435 @@
+//! In-memory implementation of [`Storage`] for development and tests.
+//!
+//! The store can be seeded from a JSON file in the same shape as a Firebase
+//! export of the `reminders/v2` node, which holds the shared reminders. On
+//! shutdown, the reminders of every scope and every document are persisted to
+//! a JSON snapshot, which can be loaded back the same way.
+use super::{
+    check_etag, content_etag, push_id::PushIds, DocumentStore, Error, ReminderStore, Result,
+    Storage, Tagged,
+};
+use crate::models::{
+    bulk::{ItemResult, ItemStatus},
+    record::ReminderRecord,
+    reminder::{fix_case, Reminder},
+};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tokio::sync::RwLock;
+
+/// In-memory storage backend.
+#[derive(Default)]
+pub struct Memory {
+    collections: Mutex<HashMap<String, Arc<Collection>>>,
+    documents: RwLock<HashMap<String, BTreeMap<String, Value>>>,
+    ids: Arc<PushIds>,
+    persist: Option<PathBuf>,
+}
+
+/// Everything in a store, as persisted on shutdown.
+#[derive(Default, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct Snapshot {
+    /// The stored records of each scope, keyed by scope and then by id.
+    reminders: BTreeMap<String, HashMap<String, Value>>,
+    /// The documents of each collection, keyed by collection and then by id.
+    documents: HashMap<String, BTreeMap<String, Value>>,
+}
+
+/// The reminders of one scope.
+#[derive(Default)]
+struct Collection {
+    reminders: RwLock<BTreeMap<String, Reminder>>,
+    ids: Arc<PushIds>,
+}
+
+impl Memory {
+    /// Create a store seeded from a JSON fixture file.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path to a JSON object of shared reminders keyed by id, or to
+    ///   a snapshot persisted on shutdown.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or parsed.
+    pub fn from_fixture(path: &str) -> Result<Self> {
+        let data = std::fs::read_to_string(path).map_err(|e| Error::Backend(e.to_string()))?;
+        let raw: Value = serde_json::from_str(&data).map_err(|e| Error::Backend(e.to_string()))?;
+        let snapshot = match serde_json::from_value::<Snapshot>(raw.clone()) {
+            Ok(snapshot) => snapshot,
+            Err(_) => Snapshot {
+                reminders: BTreeMap::from([(
+                    String::new(),
+                    serde_json::from_value(raw).map_err(|e| Error::Backend(e.to_string()))?,
+                )]),
+                documents: HashMap::new(),
+            },
+        };
+
+        let memory = Self::default();
+        for (scope, records) in snapshot.reminders {
+            let reminders = ReminderRecord::decode_all(records)
+                .logged()
+                .into_iter()
+                .map(|r| (r.id.clone().unwrap_or_default(), r))
+                .collect();
+            let collection = Collection {
+                reminders: RwLock::new(reminders),
+                ids: memory.ids.clone(),
+            };
+            memory
+                .lock_collections()
+                .insert(scope, Arc::new(collection));
+        }
+
+        Ok(Memory {
+            documents: RwLock::new(snapshot.documents),
+            ..memory
+        })
+    }
+
+    /// Write the store to the given file when the server shuts down.
+    pub fn persist_to(mut self, path: &str) -> Self {
+        self.persist = Some(path.into());
+        self
+    }
+
+    fn lock_collections(&self) -> std::sync::MutexGuard<'_, HashMap<String, Arc<Collection>>> {
+        self.collections.lock().expect("collections lock poisoned")
+    }
+}
+
+/// Copy a stored reminder out, normalised the same way the other backends read it.
+fn read(reminder: &Reminder) -> Reminder {
+    Reminder {
+        title: fix_case(&reminder.title),
+        ..reminder.clone()
+    }
+}
+
+#[async_trait]
+impl Storage for Memory {
+    fn reminders(&self, scope: &str) -> Arc<dyn ReminderStore> {
+        self.lock_collections()
+            .entry(scope.to_string())
+            .or_insert_with(|| {
+                Arc::new(Collection {
+                    ids: self.ids.clone(),
+                    ..Default::default()
+                })
+            })
+            .clone()
+    }
+
+    async fn shutdown(&self) -> Result<()> {
+        let Some(path) = &self.persist else {
+            return Ok(());
+        };
+
+        let collections: Vec<_> = self
+            .lock_collections()
+            .iter()
+            .map(|(scope, collection)| (scope.clone(), collection.clone()))
+            .collect();
+        let mut snapshot = Snapshot {
+            documents: self.documents.read().await.clone(),
+            ..Default::default()
+        };
+        for (scope, collection) in collections {
+            let reminders = collection
+                .reminders
+                .read()
+                .await
+                .values()
+                .cloned()
+                .collect();
+            let records = ReminderRecord::encode_all(reminders)
+                .into_iter()
+                .map(|(id, record)| Ok((id, super::to_document(&record)?)))
+                .collect::<Result<_>>()?;
+            snapshot.reminders.insert(scope, records);
+        }
+        let json =
+            serde_json::to_string_pretty(&snapshot).map_err(|e| Error::Backend(e.to_string()))?;
+
+        tokio::fs::write(path, json)
+            .await
+            .map_err(|e| Error::Backend(e.to_string()))?;
+        log::info!("persisted in-memory store to {}", path.display());
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl DocumentStore for Memory {
+    async fn get_document(&self, collection: &str, id: &str) -> Result<Option<Value>> {
+        let documents = self.documents.read().await;
+
+        Ok(documents.get(collection).and_then(|c| c.get(id)).cloned())
+    }
+
+    async fn list_documents(&self, collection: &str) -> Result<Vec<(String, Value)>> {
+        let documents = self.documents.read().await;
+
+        Ok(documents
+            .get(collection)
+            .map(|c| c.iter().map(|(k, v)| (k.clone(), v.clone())).collect())
+            .unwrap_or_default())
+    }
+
+    async fn insert_document(&self, collection: &str, id: &str, value: Value) -> Result<()> {
+        let mut documents = self.documents.write().await;
+        let collection = documents.entry(collection.to_string()).or_default();
+        if collection.contains_key(id) {
+            return Err(Error::Conflict);
+        }
+
+        collection.insert(id.to_string(), value);
+
+        Ok(())
+    }
+
+    async fn put_document(&self, collection: &str, id: &str, value: Value) -> Result<()> {
+        let mut documents = self.documents.write().await;
+        documents
+            .entry(collection.to_string())
+            .or_default()
+            .insert(id.to_string(), value);
+
+        Ok(())
+    }
+
+    async fn delete_document(&self, collection: &str, id: &str) -> Result<()> {
+        let mut documents = self.documents.write().await;
+        if let Some(collection) = documents.get_mut(collection) {
+            collection.remove(id);
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ReminderStore for Collection {
+    async fn list(&self) -> Result<Tagged<Vec<Reminder>>> {
+        let stored = self.reminders.read().await;
+
+        Ok(Tagged {
+            value: stored.values().map(read).collect(),
+            etag: content_etag(&*stored),
+        })
+    }
+
+    async fn get(&self, id: &str) -> Result<Tagged<Reminder>> {
+        let stored = self.reminders.read().await;
+        let reminder = stored.get(id).ok_or(Error::NotFound)?;
+
+        Ok(Tagged {
+            value: read(reminder),
+            etag: content_etag(reminder),
+        })
+    }
+
+    async fn create(&self, mut reminder: Reminder) -> Result<Reminder> {
+        let id = self.ids.next();
+        reminder.id = Some(id.clone());
+        self.reminders.write().await.insert(id, reminder.clone());
+
+        Ok(reminder)
+    }
+
+    async fn replace(
+        &self,
+        id: &str,
+        mut reminder: Reminder,
+        if_match: Option<&str>,
+    ) -> Result<()> {
+        let mut stored = self.reminders.write().await;
+        check_etag(if_match, stored.get(id))?;
+
+        reminder.id = Some(id.to_string());
+        stored.insert(id.to_string(), reminder);
+
+        Ok(())
+    }
+
+    async fn update_many(
+        &self,
+        reminders: Vec<Reminder>,
+        if_match: Option<&str>,
+    ) -> Result<Vec<ItemResult>> {
+        let mut stored = self.reminders.write().await;
+        check_etag(if_match, Some(&*stored))?;
+
+        Ok(reminders
+            .into_iter()
+            .map(|reminder| {
+                let id = reminder.id.clone().unwrap_or_default();
+                match stored.get_mut(&id) {
+                    Some(existing) => {
+                        *existing = reminder;
+                        ItemResult::new(&id, ItemStatus::Updated)
+                    }
+                    None => ItemResult::new(&id, ItemStatus::NotFound),
+                }
+            })
+            .collect())
+    }
+
+    async fn delete(&self, id: &str, if_match: Option<&str>) -> Result<()> {
+        let mut stored = self.reminders.write().await;
+        check_etag(if_match, stored.get(id))?;
+
+        stored.remove(id);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::reminder::Reminder;
+    use crate::store::{memory::Memory, DocumentStore, Error, Storage};
+    use serde_json::json;
+
+    /// Test that a persisted store can be loaded back as a fixture, with the
+    /// reminders of every scope and every document.
+    #[tokio::test]
+    async fn test_persist_round_trip() {
+        let path = std::env::temp_dir().join(format!("reminders-{}.json", std::process::id()));
+        let path = path.to_str().unwrap();
+
+        let store = Memory::default().persist_to(path);
+        store
+            .reminders("users/abc")
+            .create(Reminder {
+                id: None,
+                title: "Also persisted".into(),
+                due: 1,
+                priority: 0,
+                assignee: None,
+                completed: false,
+                completed_at: None,
+                rrule: None,
+            })
+            .await
+            .unwrap();
+        let created = store
+            .reminders("")
+            .create(Reminder {
+                id: None,
+                title: "Bins".into(),
+                due: 1234,
+                priority: 1,
+                assignee: None,
+                completed: false,
+                completed_at: None,
+                rrule: None,
+            })
+            .await
+            .unwrap();
+        store
+            .put_document("members/h1", "abc", json!({ "role": "owner" }))
+            .await
+            .unwrap();
+        store.shutdown().await.unwrap();
+
+        let loaded = Memory::from_fixture(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        let shared = loaded.reminders("").list().await.unwrap().value;
+        assert_eq!(shared, vec![created]);
+        assert_eq!(shared[0].due, 1234);
+        let own = loaded.reminders("users/abc").list().await.unwrap().value;
+        assert_eq!(own[0].title, "Also persisted");
+        let member = loaded.get_document("members/h1", "abc").await.unwrap();
+        assert_eq!(member, Some(json!({ "role": "owner" })));
+    }
+
+    /// Test that a fixture entry with a bad optional field can still be read,
+    /// with the field left unset.
+    #[tokio::test]
+    async fn test_fixture_invalid_field() {
+        let mut fixture = tempfile::NamedTempFile::new().unwrap();
+        let raw =
+            json!({"abc": {"title": "Bins", "due": 1, "assignee": 123, "rrule": "FREQ=HOURLY"}});
+        std::io::Write::write_all(&mut fixture, raw.to_string().as_bytes()).unwrap();
+
+        let store = Memory::from_fixture(fixture.path().to_str().unwrap()).unwrap();
+        let reminder = store.reminders("").get("abc").await.unwrap().value;
+
+        assert_eq!(reminder.title, "Bins");
+        assert_eq!(reminder.assignee, None);
+        assert_eq!(reminder.rrule, None);
+    }
+
+    /// Test that each scope has its own reminders.
+    #[tokio::test]
+    async fn test_scopes() {
+        let store = Memory::default();
+        let reminder = Reminder {
+            id: None,
+            title: "Bins".into(),
+            due: 1234,
+            priority: 1,
+            assignee: None,
+            completed: false,
+            completed_at: None,
+            rrule: None,
+        };
+        let created = store
+            .reminders("users/a")
+            .create(reminder.clone())
+            .await
+            .unwrap();
+
+        assert_eq!(
+            store.reminders("users/a").list().await.unwrap().value.len(),
+            1
+        );
+        assert!(store
+            .reminders("users/b")
+            .list()
+            .await
+            .unwrap()
+            .value
+            .is_empty());
+        assert!(store
+            .reminders("users/b")
+            .get(created.id.as_deref().unwrap())
+            .await
+            .is_err());
+    }
+
+    /// Test that inserting a document twice is a conflict.
+    #[tokio::test]
+    async fn test_documents() {
+        let store = Memory::default();
+
+        store.insert_document("users", "a", json!(1)).await.unwrap();
+        assert!(matches!(
+            store.insert_document("users", "a", json!(2)).await,
+            Err(Error::Conflict)
+        ));
+        assert_eq!(
+            store.get_document("users", "a").await.unwrap(),
+            Some(json!(1))
+        );
+
+        store.put_document("users", "a", json!(3)).await.unwrap();
+        assert_eq!(
+            store.list_documents("users").await.unwrap(),
+            vec![("a".to_string(), json!(3))]
+        );
+
+        store.delete_document("users", "a").await.unwrap();
+        assert_eq!(store.get_document("users", "a").await.unwrap(), None);
+    }
+}
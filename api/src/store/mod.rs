@@ -0,0 +1,302 @@
+//! Storage backends for reminders.
+//!
+//! Route handlers only talk to the [`Storage`], [`ReminderStore`] and
+//! [`DocumentStore`] traits, so the API can run against any backend that
+//! implements them.
+//!
+//! Reminders are kept in separate collections, one per scope. The empty scope
+//! is the collection shared by everyone holding `AUTH_TOKEN`, which is where
+//! reminders lived before accounts existed.
+mod firebase;
+pub mod memory;
+mod push_id;
+mod relay;
+pub mod sqlite;
+use crate::config::{Backend, StorageConfig};
+use crate::events::Bus;
+use crate::models::{
+    bulk::ItemResult,
+    generic_response::ResponseMessage,
+    query::{ListQuery, Page},
+    reminder::Reminder,
+};
+use async_trait::async_trait;
+use axum::{http::StatusCode, response::IntoResponse};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+/// Errors that can occur when reading or writing reminders.
+#[derive(Debug)]
+pub enum Error {
+    NotFound,
+    InvalidQuery(String),
+    InvalidId(String),
+    PreconditionFailed,
+    Conflict,
+    Backend(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl std::error::Error for Error {}
+
+/// Allow Error to be displayed.
+impl std::fmt::Display for Error {
+    /// Display Error.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::NotFound => write!(f, "Not found"),
+            Error::InvalidQuery(e) => write!(f, "Invalid query: {e}"),
+            Error::InvalidId(id) => write!(f, "Invalid id: {id:?}"),
+            Error::PreconditionFailed => write!(f, "Reminder has been modified"),
+            Error::Conflict => write!(f, "Already exists"),
+            Error::Backend(e) => write!(f, "Storage error: {e}"),
+        }
+    }
+}
+
+impl std::convert::From<Error> for axum::response::Response {
+    fn from(value: Error) -> Self {
+        log::error!("{value}");
+        match value {
+            Error::NotFound => ResponseMessage::from(value)
+                .with_status(StatusCode::NOT_FOUND)
+                .into_response(),
+            Error::InvalidQuery(_) | Error::InvalidId(_) => ResponseMessage::from(value)
+                .with_status(StatusCode::BAD_REQUEST)
+                .into_response(),
+            Error::PreconditionFailed => ResponseMessage::from(value)
+                .with_status(StatusCode::PRECONDITION_FAILED)
+                .into_response(),
+            Error::Conflict => ResponseMessage::from(value)
+                .with_status(StatusCode::CONFLICT)
+                .into_response(),
+            _ => ResponseMessage::from(value)
+                .with_status(StatusCode::INTERNAL_SERVER_ERROR)
+                .into_response(),
+        }
+    }
+}
+
+/// A value read from a store, with the entity tag of the version that was read.
+#[derive(Debug, Clone)]
+pub struct Tagged<T> {
+    pub value: T,
+    pub etag: String,
+}
+
+/// Compute an entity tag from the serialized content of a value.
+///
+/// Used by backends that have no native notion of versions.
+pub fn content_etag<T: serde::Serialize>(value: &T) -> String {
+    let json = serde_json::to_vec(value).expect("value serializes to JSON");
+    let digest = Sha256::digest(json);
+
+    digest[..16].iter().map(|b| format!("{b:02x}")).collect()
+}
+
+/// Check an `If-Match` entity tag against the content of the stored value.
+///
+/// # Errors
+///
+/// Returns `Error::PreconditionFailed` if a tag was given and does not match,
+/// including when nothing is stored.
+pub fn check_etag<T: serde::Serialize>(if_match: Option<&str>, current: Option<&T>) -> Result<()> {
+    match (if_match, current) {
+        (None, _) => Ok(()),
+        (Some(tag), Some(current)) if content_etag(current) == tag => Ok(()),
+        _ => Err(Error::PreconditionFailed),
+    }
+}
+
+/// Whether an id is made only of ASCII letters, digits, `_` and `-`.
+///
+/// Ids end up in paths in some backends, so anything else, such as `..` or
+/// `/`, could reach outside the collection the id belongs to.
+pub fn valid_id(id: &str) -> bool {
+    !id.is_empty()
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Check that an id is a [`valid_id`].
+///
+/// # Errors
+///
+/// Returns `Error::InvalidId` if it is not.
+pub fn check_id(id: &str) -> Result<()> {
+    match valid_id(id) {
+        true => Ok(()),
+        false => Err(Error::InvalidId(id.to_string())),
+    }
+}
+
+/// Convert a value into a document.
+pub fn to_document<T: serde::Serialize>(value: &T) -> Result<Value> {
+    serde_json::to_value(value).map_err(|e| Error::Backend(e.to_string()))
+}
+
+/// Read a value back from a document.
+pub fn from_document<T: serde::de::DeserializeOwned>(value: Value) -> Result<T> {
+    serde_json::from_value(value).map_err(|e| Error::Backend(e.to_string()))
+}
+
+/// Operations the reminders API needs from a storage backend.
+///
+/// Write methods accept an optional `if_match` entity tag. When it is given,
+/// the write only happens if the stored version still has that tag, otherwise
+/// `Error::PreconditionFailed` is returned.
+#[async_trait]
+pub trait ReminderStore: Send + Sync {
+    /// Get every reminder, tagged with the entity tag of the whole collection.
+    async fn list(&self) -> Result<Tagged<Vec<Reminder>>>;
+
+    /// Get one page of reminders matching a query.
+    ///
+    /// The default implementation filters the whole collection in memory;
+    /// backends should override it to push filters down where they can.
+    async fn query(&self, query: &ListQuery) -> Result<Page> {
+        let list = self.list().await?;
+        let page = query.apply(list.value)?;
+
+        Ok(Page {
+            etag: Some(list.etag),
+            ..page
+        })
+    }
+
+    /// Get a single reminder by id.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::NotFound` if no reminder has the given id.
+    async fn get(&self, id: &str) -> Result<Tagged<Reminder>>;
+
+    /// Create a new reminder, ignoring any id it already carries.
+    ///
+    /// # Returns
+    ///
+    /// The stored reminder, including its generated id.
+    async fn create(&self, reminder: Reminder) -> Result<Reminder>;
+
+    /// Replace the reminder with the given id.
+    async fn replace(&self, id: &str, reminder: Reminder, if_match: Option<&str>) -> Result<()>;
+
+    /// Replace each of the given reminders, matched by id.
+    ///
+    /// Reminders that are not listed are left untouched, and listed reminders
+    /// that do not exist are reported as not found rather than created, even
+    /// if they are deleted while the update is made. Each id should be listed
+    /// once. `if_match` is compared with the entity tag of the whole collection.
+    async fn update_many(
+        &self,
+        reminders: Vec<Reminder>,
+        if_match: Option<&str>,
+    ) -> Result<Vec<ItemResult>>;
+
+    /// Delete the reminder with the given id.
+    async fn delete(&self, id: &str, if_match: Option<&str>) -> Result<()>;
+}
+
+/// Schemaless JSON documents, grouped into collections, for everything that
+/// is not a reminder (accounts, sessions and so on).
+#[async_trait]
+pub trait DocumentStore: Send + Sync {
+    /// Get a document, or `None` if there is none with the given id.
+    async fn get_document(&self, collection: &str, id: &str) -> Result<Option<Value>>;
+
+    /// Get every document in a collection, with its id.
+    async fn list_documents(&self, collection: &str) -> Result<Vec<(String, Value)>>;
+
+    /// Store a new document.
+    ///
+    /// # Errors
+    ///
+    /// Returns `Error::Conflict` if a document with the id already exists.
+    async fn insert_document(&self, collection: &str, id: &str, value: Value) -> Result<()>;
+
+    /// Store a document, replacing any document with the same id.
+    async fn put_document(&self, collection: &str, id: &str, value: Value) -> Result<()>;
+
+    /// Delete a document. Deleting a document that does not exist is not an error.
+    async fn delete_document(&self, collection: &str, id: &str) -> Result<()>;
+}
+
+/// A storage backend: reminder collections plus a document store.
+#[async_trait]
+pub trait Storage: DocumentStore {
+    /// The reminders collection for a scope, e.g. `users/<id>`.
+    ///
+    /// The empty scope is the shared collection used with `AUTH_TOKEN`.
+    fn reminders(&self, scope: &str) -> Arc<dyn ReminderStore>;
+
+    /// Flush any state that must outlive the process. Called once on shutdown.
+    async fn shutdown(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Publish changes made other than through this server, such as directly
+    /// in the database, on the bus. Backends that cannot see such changes do
+    /// nothing.
+    fn watch(&self, _bus: &Bus) {}
+}
+
+/// Get every reminders scope: the shared one, each user's and each household list's.
+pub async fn scopes(docs: &dyn DocumentStore) -> Result<Vec<String>> {
+    let mut scopes = vec![String::new()];
+    scopes.extend(
+        crate::accounts::user_ids(docs)
+            .await?
+            .iter()
+            .map(|id| crate::accounts::scope(id)),
+    );
+    scopes.extend(crate::households::scopes(docs).await?);
+
+    Ok(scopes)
+}
+
+/// Open the configured storage backend.
+///
+/// * `firebase` - Firebase Realtime Database at `firebase_uri`.
+/// * `sqlite` - Embedded SQLite database at `sqlite_path`.
+/// * `memory` - In-memory store, optionally seeded from the JSON file at
+///   `memory_fixture` and written to `memory_persist` on shutdown.
+///
+/// # Errors
+///
+/// Returns an error if the backend cannot be opened.
+pub async fn open(config: &StorageConfig) -> std::result::Result<Arc<dyn Storage>, String> {
+    match config.backend {
+        Backend::Firebase => {
+            let uri = config
+                .firebase_uri
+                .clone()
+                .ok_or("storage.firebase_uri is not set")?;
+            let db = crate::firebase::Firebase::new(uri)
+                .await
+                .map_err(|e| e.to_string())?;
+            Ok(Arc::new(db))
+        }
+        Backend::Sqlite => {
+            let path = config.sqlite_path.to_string_lossy();
+            let db = sqlite::Sqlite::open(&path).map_err(|e| e.to_string())?;
+            log::info!("using SQLite database at {path}");
+            Ok(Arc::new(db))
+        }
+        Backend::Memory => {
+            let db = match &config.memory_fixture {
+                Some(path) => memory::Memory::from_fixture(&path.to_string_lossy())
+                    .map_err(|e| e.to_string())?,
+                None => memory::Memory::default(),
+            };
+            let db = match &config.memory_persist {
+                Some(path) => db.persist_to(&path.to_string_lossy()),
+                None => db,
+            };
+            log::warn!("using in-memory storage, reminders are not durable");
+            Ok(Arc::new(db))
+        }
+    }
+}
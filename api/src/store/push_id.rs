@@ -0,0 +1,96 @@
+//! Generate ids in the same style as Firebase push keys.
+//!
+//! A push id is 20 characters long: 8 characters encoding the creation time in
+//! milliseconds followed by 12 random characters. Ids sort lexicographically in
+//! creation order, including ids created within the same millisecond.
+use rand::Rng;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Alphabet used by Firebase, in ASCII order.
+const PUSH_CHARS: &[u8; 64] = b"-0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ_abcdefghijklmnopqrstuvwxyz";
+
+/// Generator for push ids.
+#[derive(Default)]
+pub struct PushIds {
+    last: Mutex<(u64, [u8; 12])>,
+}
+
+impl PushIds {
+    /// Generate a new push id for the current time.
+    pub fn next(&self) -> String {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or_default();
+
+        self.next_at(now)
+    }
+
+    /// Generate a new push id for the given time in milliseconds.
+    fn next_at(&self, now: u64) -> String {
+        let mut last = self.last.lock().expect("push id state poisoned");
+        let (last_time, random) = &mut *last;
+
+        if now == *last_time {
+            // Increment the random part so ids stay ordered within a millisecond.
+            for digit in random.iter_mut().rev() {
+                if *digit < 63 {
+                    *digit += 1;
+                    break;
+                }
+                *digit = 0;
+            }
+        } else {
+            *last_time = now;
+            let mut rng = rand::thread_rng();
+            random.iter_mut().for_each(|d| *d = rng.gen_range(0..64));
+        }
+
+        let mut id = Vec::with_capacity(20);
+        let mut time = now;
+        for _ in 0..8 {
+            id.push(PUSH_CHARS[(time % 64) as usize]);
+            time /= 64;
+        }
+        id.reverse();
+        id.extend(random.iter().map(|&d| PUSH_CHARS[d as usize]));
+
+        String::from_utf8(id).expect("push id is not ASCII")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::store::push_id::{PushIds, PUSH_CHARS};
+
+    /// Test that ids have the expected length and alphabet.
+    #[test]
+    fn test_format() {
+        let id = PushIds::default().next();
+
+        assert_eq!(id.len(), 20);
+        assert!(id.bytes().all(|c| PUSH_CHARS.contains(&c)));
+    }
+
+    /// Test that ids created in the same millisecond still sort in order.
+    #[test]
+    fn test_ordered_within_millisecond() {
+        let ids = PushIds::default();
+        let generated: Vec<String> = (0..100).map(|_| ids.next_at(1_700_000_000_000)).collect();
+
+        let mut sorted = generated.clone();
+        sorted.sort();
+        assert_eq!(generated, sorted);
+        assert!(generated.windows(2).all(|w| w[0] != w[1]));
+    }
+
+    /// Test that later timestamps produce later ids.
+    #[test]
+    fn test_ordered_by_time() {
+        let ids = PushIds::default();
+
+        assert!(ids.next_at(1_700_000_000_000) < ids.next_at(1_700_000_000_001));
+        assert_eq!(&ids.next_at(0)[..8], "--------");
+    }
+}
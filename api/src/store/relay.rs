@@ -0,0 +1,431 @@
+//! Relay changes made directly in Firebase onto the event bus.
+//!
+//! Firebase's REST API can stream the changes under a location as
+//! Server-Sent Events: a `put` replaces the data at a path and a `patch`
+//! replaces some of its children. The relay keeps a mirror of every reminders
+//! collection, fed by those streams, and publishes a reminder event for each
+//! reminder a change touches.
+//!
+//! Changes made through this server are streamed back too. The handlers have
+//! already published those, so the relay also follows the bus and only
+//! publishes a reminder's state if it has not been published already.
+use crate::events::{Bus, Event, EventKind};
+use crate::firebase::Firebase;
+use crate::models::{record::ReminderRecord, reminder::Reminder};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+
+/// Locations to stream, which between them hold every reminders collection
+/// and none of the documents.
+const ROOTS: &[&str] = &["reminders/v2", "users", "households"];
+
+/// Paths of reminders, with `*` for any key: the shared collection, each
+/// user's and each household list's.
+const PATTERNS: &[&[&str]] = &[
+    &["reminders", "v2", "*"],
+    &["users", "*", "reminders", "v2", "*"],
+    &["households", "*", "lists", "*", "reminders", "v2", "*"],
+];
+
+/// How long to wait before reconnecting a stream that ended.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// A reminder, identified by its scope and id.
+type Key = (String, String);
+
+/// The data of a `put` or `patch` event.
+#[derive(Deserialize)]
+struct Change {
+    path: String,
+    data: Value,
+}
+
+/// Stream changes from Firebase and publish them on the bus, in the background.
+pub fn spawn(db: Firebase, bus: Bus) {
+    let mirror = Arc::new(Mutex::new(Mirror::default()));
+
+    for root in ROOTS {
+        let (db, bus, mirror) = (db.clone(), bus.clone(), mirror.clone());
+        tokio::spawn(async move {
+            let mut seeded = false;
+            loop {
+                match listen(&db, root, &mirror, &bus, &mut seeded).await {
+                    Ok(()) => log::info!("reconnecting to the Firebase stream of {root}"),
+                    Err(e) => log::error!("Firebase stream of {root} failed: {e}"),
+                }
+                tokio::time::sleep(RECONNECT_INTERVAL).await;
+            }
+        });
+    }
+
+    tokio::spawn(async move {
+        let mut events = bus.subscribe();
+        loop {
+            match events.recv().await {
+                Ok(event) => mirror.lock().unwrap().record(&event),
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            }
+        }
+    });
+}
+
+/// Apply the changes under one location until the stream ends.
+///
+/// Firebase starts each stream with a `put` of everything at the location.
+/// The first time, that only fills the mirror. After a reconnect, it is
+/// compared like any other change, so changes missed in between are published.
+///
+/// # Returns
+///
+/// `Ok` when Firebase ends the stream to have the token refreshed, or an error
+/// if the stream could not be opened or failed.
+async fn listen(
+    db: &Firebase,
+    root: &str,
+    mirror: &Mutex<Mirror>,
+    bus: &Bus,
+    seeded: &mut bool,
+) -> Result<(), String> {
+    let mut response = db.listen(root).await.map_err(|e| e.to_string())?;
+    let mut parser = Parser::default();
+
+    while let Some(chunk) = response.chunk().await.map_err(|e| e.to_string())? {
+        for (event, data) in parser.feed(&chunk) {
+            let patch = match event.as_str() {
+                "put" => false,
+                "patch" => true,
+                "auth_revoked" => return Ok(()),
+                "cancel" => return Err(format!("Firebase cancelled the stream: {data}")),
+                _ => continue,
+            };
+            let change: Change = serde_json::from_str(&data).map_err(|e| e.to_string())?;
+            let path = format!("{root}{}", change.path.trim_end_matches('/'));
+
+            let changed = mirror.lock().unwrap().apply(&path, change.data, patch);
+            if std::mem::replace(seeded, true) {
+                for (kind, scope, reminder) in changed {
+                    bus.publish(kind, &scope, reminder);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// A copy of the reminders collections in Firebase.
+#[derive(Default)]
+pub struct Mirror {
+    tree: Value,
+    /// The last state published of each reminder, or `None` once deleted.
+    published: HashMap<Key, Option<Value>>,
+}
+
+impl Mirror {
+    /// Apply a change from the stream.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Where the change is, from the root of the database.
+    /// * `data` - The new data, where `null` removes it.
+    /// * `patch` - Whether only the children of `path` in `data` are replaced.
+    ///
+    /// # Returns
+    ///
+    /// An event for each reminder whose change has not been published yet.
+    pub fn apply(
+        &mut self,
+        path: &str,
+        data: Value,
+        patch: bool,
+    ) -> Vec<(EventKind, String, Reminder)> {
+        let path: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        let before = self.reminders(&path);
+
+        match (patch, data) {
+            (true, Value::Object(children)) => {
+                for (key, value) in children {
+                    let mut child = path.clone();
+                    child.push(&key);
+                    set(&mut self.tree, &child, value);
+                }
+            }
+            (_, data) => set(&mut self.tree, &path, data),
+        }
+        let after = self.reminders(&path);
+
+        let mut keys: Vec<&Key> = before.keys().chain(after.keys()).collect();
+        keys.sort();
+        keys.dedup();
+
+        let mut events = Vec::new();
+        for key in keys {
+            let (scope, id) = key;
+            let old = before.get(key).and_then(|value| decode(id, value));
+            let new = after.get(key).and_then(|value| decode(id, value));
+            let kind = match (&old, &new) {
+                (None, None) => continue,
+                (None, Some(_)) => EventKind::Created,
+                (Some(_), None) => EventKind::Deleted,
+                (Some(old), Some(new)) if !old.completed && new.completed => EventKind::Completed,
+                (Some(_), Some(_)) => EventKind::Updated,
+            };
+
+            let state = new.as_ref().and_then(|r| serde_json::to_value(r).ok());
+            if self.published.get(key) == Some(&state) {
+                continue;
+            }
+            self.published.insert(key.clone(), state);
+            if let Some(reminder) = new.or(old) {
+                events.push((kind, scope.clone(), reminder));
+            }
+        }
+
+        events
+    }
+
+    /// Remember a reminder's state was published, so it is not published again.
+    pub fn record(&mut self, event: &Event) {
+        let Some(id) = event.reminder.id.clone() else {
+            return;
+        };
+        let state = match event.kind {
+            EventKind::Due => return,
+            EventKind::Deleted => None,
+            _ => serde_json::to_value(&event.reminder).ok(),
+        };
+
+        self.published.insert((event.scope.clone(), id), state);
+    }
+
+    /// Get the reminders at, under or containing a path, by scope and id.
+    fn reminders(&self, path: &[&str]) -> BTreeMap<Key, Value> {
+        let mut found = BTreeMap::new();
+        for pattern in PATTERNS {
+            collect(&self.tree, pattern, path, &mut Vec::new(), &mut found);
+        }
+
+        found
+    }
+}
+
+/// Find the reminders matching a pattern that are compatible with a path.
+fn collect<'a>(
+    node: &'a Value,
+    pattern: &[&str],
+    path: &[&str],
+    at: &mut Vec<&'a str>,
+    found: &mut BTreeMap<Key, Value>,
+) {
+    let depth = at.len();
+    if depth == pattern.len() {
+        // A reminder's path ends in `reminders/v2/<id>`.
+        let scope = at[..depth - 3].join("/");
+        found.insert((scope, at[depth - 1].to_string()), node.clone());
+        return;
+    }
+
+    let Value::Object(children) = node else {
+        return;
+    };
+    for (key, child) in children {
+        let matches_pattern = pattern[depth] == "*" || pattern[depth] == key;
+        let matches_path = path.get(depth).is_none_or(|segment| segment == key);
+        if matches_pattern && matches_path {
+            at.push(key);
+            collect(child, pattern, path, at, found);
+            at.pop();
+        }
+    }
+}
+
+/// Replace the data at a path, removing it if `value` is `null`.
+fn set(tree: &mut Value, path: &[&str], value: Value) {
+    let Some((last, parents)) = path.split_last() else {
+        *tree = value;
+        return;
+    };
+
+    let mut node = tree;
+    for segment in parents {
+        if !node.is_object() {
+            if value.is_null() {
+                return;
+            }
+            *node = Value::Object(Map::new());
+        }
+        node = node
+            .as_object_mut()
+            .unwrap()
+            .entry(*segment)
+            .or_insert(Value::Null);
+    }
+
+    match (node, value) {
+        (Value::Object(children), Value::Null) => {
+            children.remove(*last);
+        }
+        (_, Value::Null) => {}
+        (node, value) => {
+            if !node.is_object() {
+                *node = Value::Object(Map::new());
+            }
+            node.as_object_mut()
+                .unwrap()
+                .insert(last.to_string(), value);
+        }
+    }
+}
+
+/// Read a stored reminder, or `None` if it cannot be read.
+fn decode(id: &str, value: &Value) -> Option<Reminder> {
+    ReminderRecord::decode(id, value.clone()).0
+}
+
+/// Splits a stream of Server-Sent Events into events.
+#[derive(Default)]
+struct Parser {
+    buffer: Vec<u8>,
+}
+
+impl Parser {
+    /// Add the next chunk of the stream.
+    ///
+    /// # Returns
+    ///
+    /// The name and data of each event the chunk completed.
+    fn feed(&mut self, chunk: &[u8]) -> Vec<(String, String)> {
+        self.buffer.extend_from_slice(chunk);
+
+        let mut events = Vec::new();
+        while let Some(end) = self.buffer.windows(2).position(|w| w == b"\n\n") {
+            let block: Vec<u8> = self.buffer.drain(..end + 2).collect();
+            let (mut name, mut data) = (String::new(), Vec::new());
+            for line in String::from_utf8_lossy(&block).lines() {
+                let line = line.trim_end_matches('\r');
+                if let Some(value) = line.strip_prefix("event:") {
+                    name = value.trim().to_string();
+                } else if let Some(value) = line.strip_prefix("data:") {
+                    data.push(value.strip_prefix(' ').unwrap_or(value).to_string());
+                }
+            }
+            if !name.is_empty() {
+                events.push((name, data.join("\n")));
+            }
+        }
+
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::events::{Bus, EventKind};
+    use crate::store::relay::{Mirror, Parser};
+    use serde_json::json;
+
+    /// Get the kind, scope and title of each event.
+    fn summary(
+        events: Vec<(EventKind, String, crate::models::reminder::Reminder)>,
+    ) -> Vec<(EventKind, String, String)> {
+        events
+            .into_iter()
+            .map(|(kind, scope, reminder)| (kind, scope, reminder.title))
+            .collect()
+    }
+
+    /// Test that puts and patches at every depth become reminder events.
+    #[test]
+    fn test_mirror() {
+        let mut mirror = Mirror::default();
+        let bins = json!({"title": "Bins", "due": 1, "priority": 0});
+
+        let events = mirror.apply(
+            "users",
+            json!({"u1": {"reminders": {"v2": {"a": bins}}}}),
+            false,
+        );
+        assert_eq!(
+            summary(events),
+            [(EventKind::Created, "users/u1".into(), "Bins".into())]
+        );
+
+        let events = mirror.apply("users/u1/reminders/v2/a/title", json!("Recycling"), false);
+        assert_eq!(
+            summary(events),
+            [(EventKind::Updated, "users/u1".into(), "Recycling".into())]
+        );
+
+        let events = mirror.apply(
+            "households/h1/lists/l1/reminders/v2",
+            json!({"b": {"title": "Rent", "due": 2, "priority": 1}}),
+            true,
+        );
+        assert_eq!(
+            summary(events),
+            [(
+                EventKind::Created,
+                "households/h1/lists/l1".into(),
+                "Rent".into()
+            )]
+        );
+
+        let events = mirror.apply(
+            "users/u1/reminders/v2/a",
+            json!({"completed": true, "completed_at": 5}),
+            true,
+        );
+        assert_eq!(summary(events)[0].0, EventKind::Completed);
+
+        let events = mirror.apply("users/u1", json!(null), false);
+        assert_eq!(
+            summary(events),
+            [(EventKind::Deleted, "users/u1".into(), "Recycling".into())]
+        );
+
+        // Data outside a reminders collection is not a reminder.
+        assert!(mirror
+            .apply("users/u2/profile", json!({"a": 1}), false)
+            .is_empty());
+    }
+
+    /// Test that changes the handlers already published are not repeated.
+    #[test]
+    fn test_published() {
+        let mut mirror = Mirror::default();
+        let bus = Bus::default();
+        let mut events = bus.subscribe();
+        let reminder = serde_json::from_value(json!({
+            "id": "a", "title": "bins", "due": 1, "priority": 0, "assignee": null
+        }))
+        .unwrap();
+        bus.publish(EventKind::Created, "", reminder);
+        mirror.record(&events.try_recv().unwrap());
+
+        let bins = json!({"title": "Bins", "due": 1, "priority": 0});
+        assert!(mirror.apply("reminders/v2/a", bins, false).is_empty());
+        let events = mirror.apply("reminders/v2/a/due", json!(2), false);
+        assert_eq!(events.len(), 1);
+    }
+
+    /// Test splitting events that arrive across chunks.
+    #[test]
+    fn test_parser() {
+        let mut parser = Parser::default();
+
+        assert!(parser.feed(b"event: put\ndata: {\"path\":").is_empty());
+        let events = parser.feed(b"\"/\",\"data\":1}\n\nevent: keep-alive\ndata: null\n\n");
+        assert_eq!(
+            events,
+            [
+                ("put".to_string(), "{\"path\":\"/\",\"data\":1}".to_string()),
+                ("keep-alive".to_string(), "null".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,9 @@
+CREATE TABLE reminders (
+    id TEXT PRIMARY KEY NOT NULL,
+    title TEXT NOT NULL,
+    due INTEGER NOT NULL,
+    priority INTEGER NOT NULL,
+    assignee TEXT
+);
+
+CREATE INDEX reminders_due ON reminders (due);
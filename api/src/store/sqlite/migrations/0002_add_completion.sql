@@ -0,0 +1,4 @@
+ALTER TABLE reminders ADD COLUMN completed INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE reminders ADD COLUMN completed_at INTEGER;
+
+CREATE INDEX reminders_completed_at ON reminders (completed, completed_at);
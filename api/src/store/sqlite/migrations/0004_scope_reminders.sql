@@ -0,0 +1,23 @@
+-- Reminders are kept per scope, so ids only need to be unique within a scope.
+CREATE TABLE reminders_scoped (
+    scope TEXT NOT NULL DEFAULT '',
+    id TEXT NOT NULL,
+    title TEXT NOT NULL,
+    due INTEGER NOT NULL,
+    priority INTEGER NOT NULL,
+    assignee TEXT,
+    completed INTEGER NOT NULL DEFAULT 0,
+    completed_at INTEGER,
+    rrule TEXT,
+    PRIMARY KEY (scope, id)
+);
+
+INSERT INTO reminders_scoped
+    (id, title, due, priority, assignee, completed, completed_at, rrule)
+    SELECT id, title, due, priority, assignee, completed, completed_at, rrule FROM reminders;
+
+DROP TABLE reminders;
+ALTER TABLE reminders_scoped RENAME TO reminders;
+
+CREATE INDEX reminders_due ON reminders (scope, due);
+CREATE INDEX reminders_completed_at ON reminders (scope, completed, completed_at);
@@ -0,0 +1,645 @@
+//! Embedded SQLite implementation of [`Storage`].
+//!
+//! The schema is created and upgraded on startup by applying [`MIGRATIONS`]
+//! in order, tracking progress in SQLite's `user_version` pragma.
+use super::{
+    check_etag, content_etag, push_id::PushIds, DocumentStore, Error, ReminderStore, Result,
+    Storage, Tagged,
+};
+use crate::models::{
+    bulk::{ItemResult, ItemStatus},
+    query::{ListQuery, Page, Status},
+    reminder::{fix_case, Reminder},
+};
+use async_trait::async_trait;
+use rusqlite::{
+    params, params_from_iter, types::Value as SqlValue, Connection, OptionalExtension, Row,
+};
+use serde_json::Value;
+use std::sync::{Arc, Mutex};
+
+/// Schema migrations, applied in order. Never edit a released migration, add a new one.
+const MIGRATIONS: &[&str] = &[
+    include_str!("migrations/0001_create_reminders.sql"),
+    include_str!("migrations/0002_add_completion.sql"),
+    include_str!("migrations/0003_add_rrule.sql"),
+    include_str!("migrations/0004_scope_reminders.sql"),
+    include_str!("migrations/0005_create_documents.sql"),
+];
+
+impl std::convert::From<rusqlite::Error> for Error {
+    fn from(value: rusqlite::Error) -> Self {
+        match value {
+            rusqlite::Error::QueryReturnedNoRows => Error::NotFound,
+            rusqlite::Error::SqliteFailure(e, _)
+                if e.code == rusqlite::ErrorCode::ConstraintViolation =>
+            {
+                Error::Conflict
+            }
+            _ => Error::Backend(value.to_string()),
+        }
+    }
+}
+
+/// SQLite storage backend.
+#[derive(Clone)]
+pub struct Sqlite {
+    conn: Arc<Mutex<Connection>>,
+    ids: Arc<PushIds>,
+}
+
+/// The reminders of one scope.
+struct Collection {
+    db: Sqlite,
+    scope: String,
+}
+
+impl Sqlite {
+    /// Open (or create) a database and bring its schema up to date.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - Path to the database file, or `:memory:`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the database cannot be opened or migrated.
+    pub fn open(path: &str) -> Result<Self> {
+        let mut conn = Connection::open(path)?;
+        migrate(&mut conn)?;
+
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+            ids: Arc::new(PushIds::default()),
+        })
+    }
+
+    /// Run a closure against the connection on the blocking thread pool.
+    async fn call<T, E, F>(&self, f: F) -> Result<T>
+    where
+        T: Send + 'static,
+        E: Into<Error> + Send + 'static,
+        F: FnOnce(&mut Connection) -> std::result::Result<T, E> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn.lock().expect("SQLite connection poisoned");
+            f(&mut conn)
+        })
+        .await
+        .map_err(|e| Error::Backend(e.to_string()))?
+        .map_err(Into::into)
+    }
+}
+
+/// Apply any migrations the database has not seen yet.
+fn migrate(conn: &mut Connection) -> rusqlite::Result<()> {
+    let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.pragma_update(None, "user_version", i + 1)?;
+        tx.commit()?;
+        log::info!("applied SQLite migration {}", i + 1);
+    }
+
+    Ok(())
+}
+
+/// Build a Reminder from a row selected with the columns in table order.
+///
+/// A recurrence rule that cannot be parsed is logged and left out, so the
+/// reminder can still be read.
+fn from_row(row: &Row) -> rusqlite::Result<Reminder> {
+    let id: String = row.get("id")?;
+    let rrule = row
+        .get::<_, Option<String>>("rrule")?
+        .and_then(|rule| match rule.parse() {
+            Ok(rule) => Some(rule),
+            Err(e) => {
+                log::warn!("ignored invalid rrule on reminder {id}: {e}");
+                None
+            }
+        });
+
+    Ok(Reminder {
+        id: Some(id),
+        title: fix_case(&row.get::<_, String>("title")?),
+        due: row.get::<_, i64>("due")? as u64,
+        priority: row.get::<_, i64>("priority")? as u64,
+        assignee: row.get("assignee")?,
+        completed: row.get("completed")?,
+        completed_at: row.get::<_, Option<i64>>("completed_at")?.map(|t| t as u64),
+        rrule,
+    })
+}
+
+/// Select every reminder in a scope, ordered by id.
+fn select_all(conn: &Connection, scope: &str) -> rusqlite::Result<Vec<Reminder>> {
+    let mut stmt = conn.prepare("SELECT * FROM reminders WHERE scope = ?1 ORDER BY id")?;
+    let rows = stmt.query_map([scope], from_row)?;
+    rows.collect()
+}
+
+/// Select a single reminder, if it exists.
+fn select_one(conn: &Connection, scope: &str, id: &str) -> rusqlite::Result<Option<Reminder>> {
+    conn.query_row(
+        "SELECT * FROM reminders WHERE scope = ?1 AND id = ?2",
+        [scope, id],
+        from_row,
+    )
+    .optional()
+}
+
+/// Insert a reminder, replacing any existing row with the same id.
+fn upsert(conn: &Connection, scope: &str, id: &str, reminder: &Reminder) -> rusqlite::Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO reminders
+         (scope, id, title, due, priority, assignee, completed, completed_at, rrule)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        params![
+            scope,
+            id,
+            reminder.title,
+            reminder.due as i64,
+            reminder.priority as i64,
+            reminder.assignee,
+            reminder.completed,
+            reminder.completed_at.map(|t| t as i64),
+            reminder.rrule.as_ref().map(ToString::to_string)
+        ],
+    )?;
+
+    Ok(())
+}
+
+#[async_trait]
+impl Storage for Sqlite {
+    fn reminders(&self, scope: &str) -> Arc<dyn ReminderStore> {
+        Arc::new(Collection {
+            db: self.clone(),
+            scope: scope.to_string(),
+        })
+    }
+}
+
+#[async_trait]
+impl DocumentStore for Sqlite {
+    async fn get_document(&self, collection: &str, id: &str) -> Result<Option<Value>> {
+        let (collection, id) = (collection.to_string(), id.to_string());
+        let value = self
+            .call(move |conn| {
+                conn.query_row(
+                    "SELECT value FROM documents WHERE collection = ?1 AND id = ?2",
+                    [collection, id],
+                    |row| row.get::<_, String>(0),
+                )
+                .optional()
+            })
+            .await?;
+
+        value.map(|v| parse_document(&v)).transpose()
+    }
+
+    async fn list_documents(&self, collection: &str) -> Result<Vec<(String, Value)>> {
+        let collection = collection.to_string();
+        let rows = self
+            .call(move |conn| {
+                let mut stmt = conn
+                    .prepare("SELECT id, value FROM documents WHERE collection = ?1 ORDER BY id")?;
+                let rows = stmt.query_map([collection], |row| Ok((row.get(0)?, row.get(1)?)))?;
+                rows.collect::<rusqlite::Result<Vec<(String, String)>>>()
+            })
+            .await?;
+
+        rows.into_iter()
+            .map(|(id, value)| Ok((id, parse_document(&value)?)))
+            .collect()
+    }
+
+    async fn insert_document(&self, collection: &str, id: &str, value: Value) -> Result<()> {
+        let (collection, id) = (collection.to_string(), id.to_string());
+        self.call(move |conn| {
+            conn.execute(
+                "INSERT INTO documents (collection, id, value) VALUES (?1, ?2, ?3)",
+                params![collection, id, value.to_string()],
+            )
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    async fn put_document(&self, collection: &str, id: &str, value: Value) -> Result<()> {
+        let (collection, id) = (collection.to_string(), id.to_string());
+        self.call(move |conn| {
+            conn.execute(
+                "INSERT OR REPLACE INTO documents (collection, id, value) VALUES (?1, ?2, ?3)",
+                params![collection, id, value.to_string()],
+            )
+        })
+        .await?;
+
+        Ok(())
+    }
+
+    async fn delete_document(&self, collection: &str, id: &str) -> Result<()> {
+        let (collection, id) = (collection.to_string(), id.to_string());
+        self.call(move |conn| {
+            conn.execute(
+                "DELETE FROM documents WHERE collection = ?1 AND id = ?2",
+                [collection, id],
+            )
+        })
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// Parse a document stored as JSON text.
+fn parse_document(value: &str) -> Result<Value> {
+    serde_json::from_str(value).map_err(|e| Error::Backend(e.to_string()))
+}
+
+#[async_trait]
+impl ReminderStore for Collection {
+    async fn list(&self) -> Result<Tagged<Vec<Reminder>>> {
+        let scope = self.scope.clone();
+        let reminders = self.db.call(move |conn| select_all(conn, &scope)).await?;
+
+        Ok(Tagged {
+            etag: content_etag(&reminders),
+            value: reminders,
+        })
+    }
+
+    async fn query(&self, query: &ListQuery) -> Result<Page> {
+        let mut clauses = vec![];
+        let mut values: Vec<SqlValue> = vec![];
+
+        if let Some(before) = query.due_before {
+            clauses.push("due < ?");
+            values.push(SqlValue::Integer(before as i64));
+        }
+        if let Some(after) = query.due_after {
+            clauses.push("due > ?");
+            values.push(SqlValue::Integer(after as i64));
+        }
+        if let Some(assignee) = &query.assignee {
+            clauses.push("assignee = ?");
+            values.push(SqlValue::Text(assignee.clone()));
+        }
+        if let Some(priority) = query.priority {
+            clauses.push("priority = ?");
+            values.push(SqlValue::Integer(priority as i64));
+        }
+        match query.status {
+            Status::Open => clauses.push("completed = 0"),
+            Status::Completed => clauses.push("completed = 1"),
+            Status::All => {}
+        }
+
+        if clauses.is_empty() {
+            let list = self.list().await?;
+            let page = query.apply(list.value)?;
+
+            return Ok(Page {
+                etag: Some(list.etag),
+                ..page
+            });
+        }
+
+        clauses.push("scope = ?");
+        values.push(SqlValue::Text(self.scope.clone()));
+
+        let sql = format!("SELECT * FROM reminders WHERE {}", clauses.join(" AND "));
+        let reminders = self
+            .db
+            .call(move |conn| {
+                let mut stmt = conn.prepare(&sql)?;
+                let rows = stmt.query_map(params_from_iter(values), from_row)?;
+                rows.collect::<rusqlite::Result<Vec<Reminder>>>()
+            })
+            .await?;
+
+        query.apply(reminders)
+    }
+
+    async fn get(&self, id: &str) -> Result<Tagged<Reminder>> {
+        let (scope, id) = (self.scope.clone(), id.to_string());
+        let reminder = self
+            .db
+            .call(move |conn| select_one(conn, &scope, &id))
+            .await?
+            .ok_or(Error::NotFound)?;
+
+        Ok(Tagged {
+            etag: content_etag(&reminder),
+            value: reminder,
+        })
+    }
+
+    async fn create(&self, mut reminder: Reminder) -> Result<Reminder> {
+        let id = self.db.ids.next();
+        reminder.id = Some(id.clone());
+        let (scope, row) = (self.scope.clone(), reminder.clone());
+
+        self.db
+            .call(move |conn| upsert(conn, &scope, &id, &row))
+            .await?;
+
+        Ok(reminder)
+    }
+
+    async fn replace(&self, id: &str, reminder: Reminder, if_match: Option<&str>) -> Result<()> {
+        let (scope, id) = (self.scope.clone(), id.to_string());
+        let if_match = if_match.map(String::from);
+
+        self.db
+            .call(move |conn| -> Result<()> {
+                let tx = conn.transaction()?;
+                check_etag(if_match.as_deref(), select_one(&tx, &scope, &id)?.as_ref())?;
+                upsert(&tx, &scope, &id, &reminder)?;
+                Ok(tx.commit()?)
+            })
+            .await
+    }
+
+    async fn update_many(
+        &self,
+        reminders: Vec<Reminder>,
+        if_match: Option<&str>,
+    ) -> Result<Vec<ItemResult>> {
+        let scope = self.scope.clone();
+        let if_match = if_match.map(String::from);
+
+        self.db
+            .call(move |conn| -> Result<Vec<ItemResult>> {
+                let tx = conn.transaction()?;
+                if if_match.is_some() {
+                    check_etag(if_match.as_deref(), Some(&select_all(&tx, &scope)?))?;
+                }
+                let mut results = vec![];
+
+                for reminder in &reminders {
+                    let id = reminder.id.clone().unwrap_or_default();
+                    let updated = tx.execute(
+                        "UPDATE reminders SET title = ?2, due = ?3, priority = ?4, assignee = ?5,
+                     completed = ?6, completed_at = ?7, rrule = ?8
+                     WHERE id = ?1 AND scope = ?9",
+                        params![
+                            id,
+                            reminder.title,
+                            reminder.due as i64,
+                            reminder.priority as i64,
+                            reminder.assignee,
+                            reminder.completed,
+                            reminder.completed_at.map(|t| t as i64),
+                            reminder.rrule.as_ref().map(ToString::to_string),
+                            scope
+                        ],
+                    )?;
+                    let status = match updated {
+                        0 => ItemStatus::NotFound,
+                        _ => ItemStatus::Updated,
+                    };
+                    results.push(ItemResult::new(&id, status));
+                }
+
+                tx.commit()?;
+                Ok(results)
+            })
+            .await
+    }
+
+    async fn delete(&self, id: &str, if_match: Option<&str>) -> Result<()> {
+        let (scope, id) = (self.scope.clone(), id.to_string());
+        let if_match = if_match.map(String::from);
+
+        self.db
+            .call(move |conn| -> Result<()> {
+                let tx = conn.transaction()?;
+                check_etag(if_match.as_deref(), select_one(&tx, &scope, &id)?.as_ref())?;
+                tx.execute(
+                    "DELETE FROM reminders WHERE scope = ?1 AND id = ?2",
+                    [scope, id],
+                )?;
+                Ok(tx.commit()?)
+            })
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::{
+        bulk::{ItemResult, ItemStatus},
+        query::ListQuery,
+        reminder::Reminder,
+    };
+    use crate::store::{sqlite::Sqlite, DocumentStore, Error, Storage};
+    use serde_json::json;
+
+    fn reminder(title: &str, due: u64) -> Reminder {
+        Reminder {
+            id: None,
+            title: title.into(),
+            due,
+            priority: 0,
+            assignee: Some("Sam".into()),
+            completed: false,
+            completed_at: None,
+            rrule: None,
+        }
+    }
+
+    /// Test creating, reading, replacing and deleting a reminder.
+    #[tokio::test]
+    async fn test_crud() {
+        let store = Sqlite::open(":memory:").unwrap().reminders("");
+
+        let created = store.create(reminder("bins", 1234)).await.unwrap();
+        let id = created.id.clone().unwrap();
+        assert_eq!(id.len(), 20);
+
+        let fetched = store.get(&id).await.unwrap().value;
+        assert_eq!(fetched.title, "Bins");
+        assert_eq!(fetched.assignee.as_deref(), Some("Sam"));
+
+        store
+            .replace(&id, reminder("Rent", 99), None)
+            .await
+            .unwrap();
+        assert_eq!(store.get(&id).await.unwrap().value.due, 99);
+
+        store.delete(&id, None).await.unwrap();
+        assert!(matches!(store.get(&id).await, Err(Error::NotFound)));
+    }
+
+    /// Test that filters pushed down to SQL match the in-memory filters.
+    #[tokio::test]
+    async fn test_query() {
+        let store = Sqlite::open(":memory:").unwrap().reminders("");
+        for (title, due) in [("Bins", 1), ("Rent", 2), ("Dog", 3)] {
+            store.create(reminder(title, due)).await.unwrap();
+        }
+
+        let query = ListQuery {
+            due_after: Some(1),
+            assignee: Some("Sam".into()),
+            sort: Some("-due".into()),
+            ..Default::default()
+        };
+        let page = store.query(&query).await.unwrap();
+
+        let titles: Vec<String> = page.reminders.into_iter().map(|r| r.title).collect();
+        assert_eq!(titles, vec!["Dog", "Rent"]);
+    }
+
+    /// Test that a bulk update leaves unlisted reminders alone.
+    #[tokio::test]
+    async fn test_update_many() {
+        let store = Sqlite::open(":memory:").unwrap().reminders("");
+        let mut bins = store.create(reminder("Bins", 1)).await.unwrap();
+        let rent = store.create(reminder("Rent", 2)).await.unwrap();
+
+        bins.priority = 5;
+        let mut missing = reminder("Dog", 3);
+        missing.id = Some("missing".into());
+
+        let results = store
+            .update_many(vec![bins.clone(), missing], None)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            results,
+            vec![
+                ItemResult::new(bins.id.as_ref().unwrap(), ItemStatus::Updated),
+                ItemResult::new("missing", ItemStatus::NotFound),
+            ]
+        );
+        assert_eq!(
+            store
+                .get(bins.id.as_ref().unwrap())
+                .await
+                .unwrap()
+                .value
+                .priority,
+            5
+        );
+        assert_eq!(
+            store
+                .get(rent.id.as_ref().unwrap())
+                .await
+                .unwrap()
+                .value
+                .due,
+            2
+        );
+        assert_eq!(store.list().await.unwrap().value.len(), 2);
+    }
+
+    /// Test that writes with a stale entity tag are rejected.
+    #[tokio::test]
+    async fn test_if_match() {
+        let store = Sqlite::open(":memory:").unwrap().reminders("");
+        let id = store.create(reminder("Bins", 1)).await.unwrap().id.unwrap();
+        let read = store.get(&id).await.unwrap();
+
+        store
+            .replace(&id, reminder("Bins", 2), Some(&read.etag))
+            .await
+            .unwrap();
+
+        let stale = store
+            .replace(&id, reminder("Bins", 3), Some(&read.etag))
+            .await;
+        assert!(matches!(stale, Err(Error::PreconditionFailed)));
+
+        let stale = store.delete(&id, Some(&read.etag)).await;
+        assert!(matches!(stale, Err(Error::PreconditionFailed)));
+
+        let collection = store.list().await.unwrap().etag;
+        store.create(reminder("Rent", 1)).await.unwrap();
+        let stale = store.update_many(vec![], Some(&collection)).await;
+        assert!(matches!(stale, Err(Error::PreconditionFailed)));
+
+        let current = store.get(&id).await.unwrap();
+        assert_eq!(current.value.due, 2);
+        store.delete(&id, Some(&current.etag)).await.unwrap();
+    }
+
+    /// Test that the same id can be used in two scopes without clashing.
+    #[tokio::test]
+    async fn test_scopes() {
+        let db = Sqlite::open(":memory:").unwrap();
+        let (a, b) = (db.reminders("users/a"), db.reminders("users/b"));
+        let id = a.create(reminder("Bins", 1)).await.unwrap().id.unwrap();
+
+        assert!(matches!(b.get(&id).await, Err(Error::NotFound)));
+        b.replace(&id, reminder("Rent", 2), None).await.unwrap();
+
+        assert_eq!(a.get(&id).await.unwrap().value.title, "Bins");
+        assert_eq!(b.get(&id).await.unwrap().value.title, "Rent");
+        assert!(db.reminders("").list().await.unwrap().value.is_empty());
+
+        b.delete(&id, None).await.unwrap();
+        assert!(a.get(&id).await.is_ok());
+    }
+
+    /// Test that reminders created before scopes existed end up in the shared scope.
+    #[tokio::test]
+    async fn test_migrate_to_scopes() {
+        let path = std::env::temp_dir().join(format!("reminders-{}.db", std::process::id()));
+        let conn = rusqlite::Connection::open(&path).unwrap();
+        for migration in &super::MIGRATIONS[..3] {
+            conn.execute_batch(migration).unwrap();
+        }
+        conn.pragma_update(None, "user_version", 3).unwrap();
+        conn.execute(
+            "INSERT INTO reminders (id, title, due, priority) VALUES ('abc', 'Bins', 1, 0)",
+            [],
+        )
+        .unwrap();
+        drop(conn);
+
+        let db = Sqlite::open(path.to_str().unwrap()).unwrap();
+        let reminders = db.reminders("").list().await.unwrap().value;
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(reminders.len(), 1);
+        assert_eq!(reminders[0].id.as_deref(), Some("abc"));
+    }
+
+    /// Test storing, listing and deleting documents.
+    #[tokio::test]
+    async fn test_documents() {
+        let db = Sqlite::open(":memory:").unwrap();
+
+        db.insert_document("users", "a", json!({"name": "Sam"}))
+            .await
+            .unwrap();
+        assert!(matches!(
+            db.insert_document("users", "a", json!({})).await,
+            Err(Error::Conflict)
+        ));
+
+        db.put_document("users", "b", json!(2)).await.unwrap();
+        let ids: Vec<String> = db
+            .list_documents("users")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect();
+        assert_eq!(ids, vec!["a", "b"]);
+
+        db.delete_document("users", "a").await.unwrap();
+        assert_eq!(db.get_document("users", "a").await.unwrap(), None);
+        assert_eq!(db.get_document("users", "b").await.unwrap(), Some(json!(2)));
+    }
+}
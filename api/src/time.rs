@@ -0,0 +1,10 @@
+//! Time helpers.
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The current time in seconds since the Unix epoch.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
@@ -0,0 +1,958 @@
+//! Outgoing webhooks.
+//!
+//! A user can add webhooks for their own reminders or a household list they
+//! belong to. Each reminder event on the [`Bus`] is posted to every webhook
+//! that wants it, signed with the webhook's secret. Failed deliveries are
+//! retried with exponential backoff, and every attempt is recorded so users
+//! can see what was sent and why it failed.
+//!
+//! Webhooks are kept in the `webhooks` document collection, and their
+//! deliveries in `webhook_deliveries/<webhook id>`.
+use crate::config::WebhooksConfig;
+use crate::events::{Bus, Event};
+use crate::households;
+use crate::middleware::auth::AuthUser;
+use crate::models::{
+    generic_response::ResponseMessage,
+    household::Role,
+    webhook::{
+        Attempt, CreatedWebhook, Delivery, DeliveryStatus, NewWebhook, Webhook, WebhookResponse,
+    },
+};
+use crate::store::{self, from_document, to_document, DocumentStore, Storage};
+use axum::{http::StatusCode, response::IntoResponse};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::collections::BTreeSet;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+
+const WEBHOOKS: &str = "webhooks";
+
+const MAX_WEBHOOKS: usize = 20;
+const MAX_URL_LENGTH: usize = 2048;
+const MIN_SECRET_LENGTH: usize = 16;
+
+/// How long finished deliveries are kept in the log, in seconds.
+const DELIVERY_RETENTION: u64 = 7 * 24 * 60 * 60;
+
+/// How often to prune the delivery log.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How long to wait before trying again when the store fails.
+const RETRY_INTERVAL: u64 = 60;
+
+/// Longest a response body is kept in the delivery log for.
+const MAX_ERROR_LENGTH: usize = 200;
+
+/// Errors that can occur when managing webhooks.
+#[derive(Debug)]
+pub enum Error {
+    NotFound,
+    InvalidWebhook(&'static str),
+    TooMany,
+    Household(households::Error),
+    Store(store::Error),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl std::error::Error for Error {}
+
+/// Allow Error to be displayed.
+impl std::fmt::Display for Error {
+    /// Display Error.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::NotFound => write!(f, "Webhook not found"),
+            Error::InvalidWebhook(e) => write!(f, "Invalid webhook: {e}"),
+            Error::TooMany => write!(f, "You can have at most {MAX_WEBHOOKS} webhooks"),
+            Error::Household(e) => write!(f, "{e}"),
+            Error::Store(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::convert::From<store::Error> for Error {
+    fn from(value: store::Error) -> Self {
+        Error::Store(value)
+    }
+}
+
+impl std::convert::From<households::Error> for Error {
+    fn from(value: households::Error) -> Self {
+        Error::Household(value)
+    }
+}
+
+impl std::convert::From<Error> for axum::response::Response {
+    fn from(value: Error) -> Self {
+        let status = match value {
+            Error::NotFound => StatusCode::NOT_FOUND,
+            Error::InvalidWebhook(_) | Error::TooMany => StatusCode::BAD_REQUEST,
+            Error::Household(e) => return e.into(),
+            Error::Store(e) => return e.into(),
+        };
+
+        ResponseMessage::from(value.to_string())
+            .with_status(status)
+            .into_response()
+    }
+}
+
+/// Add a webhook for a user.
+///
+/// # Arguments
+///
+/// * `user` - The user adding the webhook.
+/// * `new` - The webhook. If it names a household list, the user must be able
+///   to see the list's reminders.
+/// * `allow_private` - Whether the URL may name a loopback, private,
+///   link-local or unspecified address.
+///
+/// # Returns
+///
+/// The webhook, with its secret.
+///
+/// # Errors
+///
+/// Returns `Error::InvalidWebhook` if the URL or secret are not valid,
+/// `Error::TooMany` if the user already has too many webhooks, or
+/// `Error::Household` if the user cannot see the list.
+pub async fn create(
+    docs: &dyn DocumentStore,
+    user: &AuthUser,
+    new: NewWebhook,
+    allow_private: bool,
+) -> Result<CreatedWebhook> {
+    validate_url(&new.url, allow_private)?;
+    let secret = match new.secret {
+        Some(secret) if secret.len() < MIN_SECRET_LENGTH => {
+            return Err(Error::InvalidWebhook("secret is too short"))
+        }
+        Some(secret) => secret,
+        None => crate::random::token(),
+    };
+
+    let scope = match (&new.household_id, &new.list_id) {
+        (Some(household_id), Some(list_id)) => {
+            households::list_access(docs, &user.id, household_id, list_id, Role::Viewer).await?
+        }
+        (None, None) => user.scope(),
+        _ => {
+            return Err(Error::InvalidWebhook(
+                "household_id and list_id must be given together",
+            ))
+        }
+    };
+
+    if webhooks(docs, &user.id).await?.len() >= MAX_WEBHOOKS {
+        return Err(Error::TooMany);
+    }
+
+    let mut events = new.events;
+    events.sort();
+    events.dedup();
+    let webhook = Webhook {
+        id: crate::random::id(),
+        user_id: user.id.clone(),
+        url: new.url,
+        secret: secret.clone(),
+        events,
+        household_id: new.household_id,
+        list_id: new.list_id,
+        scope,
+        created: crate::time::now(),
+    };
+    docs.insert_document(WEBHOOKS, &webhook.id, to_document(&webhook)?)
+        .await?;
+
+    Ok(CreatedWebhook {
+        webhook: webhook.into(),
+        secret,
+    })
+}
+
+/// Get a user's webhooks.
+pub async fn list(docs: &dyn DocumentStore, user_id: &str) -> Result<Vec<WebhookResponse>> {
+    let mut webhooks = webhooks(docs, user_id).await?;
+    webhooks.sort_by_key(|webhook| webhook.created);
+
+    Ok(webhooks.into_iter().map(Into::into).collect())
+}
+
+/// Remove one of a user's webhooks, along with its delivery log.
+///
+/// # Errors
+///
+/// Returns `Error::NotFound` if the user has no webhook with the id.
+pub async fn delete(docs: &dyn DocumentStore, user_id: &str, id: &str) -> Result<()> {
+    get(docs, user_id, id).await?;
+
+    for (delivery_id, _) in docs.list_documents(&deliveries_collection(id)).await? {
+        docs.delete_document(&deliveries_collection(id), &delivery_id)
+            .await?;
+    }
+
+    Ok(docs.delete_document(WEBHOOKS, id).await?)
+}
+
+/// Get the delivery log of one of a user's webhooks, newest first.
+///
+/// # Errors
+///
+/// Returns `Error::NotFound` if the user has no webhook with the id.
+pub async fn deliveries(
+    docs: &dyn DocumentStore,
+    user_id: &str,
+    id: &str,
+) -> Result<Vec<Delivery>> {
+    get(docs, user_id, id).await?;
+
+    let mut deliveries = stored_deliveries(docs, id).await?;
+    deliveries.sort_by_key(|delivery| std::cmp::Reverse(delivery.created));
+
+    Ok(deliveries)
+}
+
+/// Sign a delivery, so the receiver can check it came from us.
+///
+/// # Arguments
+///
+/// * `secret` - The webhook's secret.
+/// * `timestamp` - When the delivery was sent, in seconds since the epoch.
+/// * `body` - The body of the delivery.
+///
+/// # Returns
+///
+/// The hex-encoded HMAC-SHA256 of `<timestamp>.<body>`.
+pub fn sign(secret: &str, timestamp: u64, body: &[u8]) -> String {
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any length");
+    mac.update(format!("{timestamp}.").as_bytes());
+    mac.update(body);
+
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+/// Get one of a user's webhooks.
+async fn get(docs: &dyn DocumentStore, user_id: &str, id: &str) -> Result<Webhook> {
+    docs.get_document(WEBHOOKS, id)
+        .await?
+        .map(from_document::<Webhook>)
+        .transpose()?
+        .filter(|webhook| webhook.user_id == user_id)
+        .ok_or(Error::NotFound)
+}
+
+/// Get a user's webhooks, including their secrets.
+async fn webhooks(docs: &dyn DocumentStore, user_id: &str) -> store::Result<Vec<Webhook>> {
+    Ok(all(docs)
+        .await?
+        .into_iter()
+        .filter(|webhook| webhook.user_id == user_id)
+        .collect())
+}
+
+/// Get every webhook.
+async fn all(docs: &dyn DocumentStore) -> store::Result<Vec<Webhook>> {
+    docs.list_documents(WEBHOOKS)
+        .await?
+        .into_iter()
+        .map(|(_, webhook)| from_document(webhook))
+        .collect()
+}
+
+/// Get every delivery of a webhook.
+async fn stored_deliveries(
+    docs: &dyn DocumentStore,
+    webhook_id: &str,
+) -> store::Result<Vec<Delivery>> {
+    docs.list_documents(&deliveries_collection(webhook_id))
+        .await?
+        .into_iter()
+        .map(|(_, delivery)| from_document(delivery))
+        .collect()
+}
+
+/// Check a URL is somewhere events may be posted.
+///
+/// Host names are only checked for `localhost` here, as what they resolve to
+/// can change. [`Dispatcher`] checks the addresses again before each post.
+fn validate_url(url: &str, allow_private: bool) -> Result<()> {
+    if url.len() > MAX_URL_LENGTH {
+        return Err(Error::InvalidWebhook("url is too long"));
+    }
+
+    let parsed = reqwest::Url::parse(url).map_err(|_| Error::InvalidWebhook("url is not a URL"))?;
+    if !matches!(parsed.scheme(), "https" | "http") {
+        return Err(Error::InvalidWebhook("url must use HTTP or HTTPS"));
+    }
+
+    let local = private(&parsed).ok_or(Error::InvalidWebhook("url has no host"))?;
+    match local && !allow_private {
+        true => Err(Error::InvalidWebhook("url must not be a private address")),
+        false => Ok(()),
+    }
+}
+
+/// Whether a URL names the server's own network, by a `localhost` name or a
+/// non-public address, or `None` if it has no host.
+///
+/// Other names are not resolved, as what they resolve to can change; see
+/// [`pinned`] for the check made before connecting.
+pub(crate) fn private(url: &reqwest::Url) -> Option<bool> {
+    Some(match host(url)? {
+        Err(domain) => {
+            let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+            domain == "localhost" || domain.ends_with(".localhost")
+        }
+        Ok(ip) => !public(ip),
+    })
+}
+
+/// The host of a URL, as an address if it is one or else as a name.
+fn host(url: &reqwest::Url) -> Option<std::result::Result<IpAddr, &str>> {
+    let host = url.host_str()?;
+    let address = host.trim_start_matches('[').trim_end_matches(']');
+
+    Some(address.parse().map_err(|_| host))
+}
+
+/// Whether an address is outside the server's own network: not loopback,
+/// private, link-local or unspecified.
+fn public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                // Shared address space, used by carrier-grade NAT.
+                || (ip.octets()[0] == 100 && (ip.octets()[1] & 0xc0) == 64))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => public(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    // Unique local addresses, fc00::/7.
+                    || (first & 0xfe00) == 0xfc00
+                    // Link-local addresses, fe80::/10.
+                    || (first & 0xffc0) == 0xfe80)
+            }
+        },
+    }
+}
+
+/// Start a client that can only connect to the addresses a URL's host
+/// resolves to now, after checking they are public unless `allow_private`.
+///
+/// Resolving the host here rather than when connecting means a name cannot
+/// pass the check and then resolve somewhere else. Redirects are not
+/// followed, as they could lead anywhere.
+///
+/// # Errors
+///
+/// Returns why the URL cannot be connected to.
+pub(crate) async fn pinned(
+    url: &str,
+    allow_private: bool,
+) -> std::result::Result<reqwest::ClientBuilder, String> {
+    let url = reqwest::Url::parse(url).map_err(|e| e.to_string())?;
+    let port = url.port_or_known_default().unwrap_or(80);
+    let (domain, addresses): (_, Vec<SocketAddr>) = match host(&url) {
+        Some(Err(domain)) => (
+            Some(domain),
+            tokio::net::lookup_host((domain, port))
+                .await
+                .map_err(|e| format!("failed to resolve {domain}: {e}"))?
+                .collect(),
+        ),
+        Some(Ok(ip)) => (None, vec![SocketAddr::new(ip, port)]),
+        None => return Err("url has no host".into()),
+    };
+    if !allow_private {
+        if let Some(address) = addresses.iter().find(|address| !public(address.ip())) {
+            return Err(format!("url resolves to private address {}", address.ip()));
+        }
+    }
+
+    let mut builder = reqwest::Client::builder().redirect(reqwest::redirect::Policy::none());
+    if let Some(domain) = domain {
+        builder = builder.resolve_to_addrs(domain, &addresses);
+    }
+
+    Ok(builder)
+}
+
+/// The collection of a webhook's deliveries, keyed by delivery id.
+fn deliveries_collection(webhook_id: &str) -> String {
+    format!("webhook_deliveries/{webhook_id}")
+}
+
+/// A pending delivery, ordered by when it should next be attempted.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Queued {
+    at: u64,
+    webhook_id: String,
+    delivery_id: String,
+}
+
+/// Posts events to webhooks, retrying failed deliveries.
+pub struct Dispatcher {
+    store: Arc<dyn Storage>,
+    timeout: Duration,
+    allow_private: bool,
+    max_attempts: u32,
+    initial_backoff: u64,
+    queue: BTreeSet<Queued>,
+}
+
+impl Dispatcher {
+    /// Create a dispatcher with an empty queue.
+    ///
+    /// # Arguments
+    ///
+    /// * `store` - The storage webhooks and their deliveries are kept in.
+    /// * `config` - How many times to try, how long to wait between tries, how
+    ///   long to wait for a response and whether private addresses are allowed.
+    pub fn new(store: Arc<dyn Storage>, config: &WebhooksConfig) -> Self {
+        Dispatcher {
+            store,
+            timeout: Duration::from_secs(config.timeout_secs),
+            allow_private: config.allow_private,
+            max_attempts: config.max_attempts,
+            initial_backoff: config.initial_backoff_secs,
+            queue: BTreeSet::new(),
+        }
+    }
+
+    /// Deliver events from the bus in the background, resuming any pending
+    /// deliveries left from before a restart.
+    pub fn spawn(mut self, bus: &Bus) -> tokio::task::JoinHandle<()> {
+        let mut events = bus.subscribe();
+        tokio::spawn(async move {
+            if let Err(e) = self.load().await {
+                log::error!("failed to load pending webhook deliveries: {e}");
+            }
+
+            let mut prune = tokio::time::interval(PRUNE_INTERVAL);
+            loop {
+                let now = crate::time::now();
+                if let Err(e) = self.run_due(now).await {
+                    log::error!("failed to deliver webhooks: {e}");
+                }
+
+                let wait = self.queue.first().map_or(PRUNE_INTERVAL, |queued| {
+                    Duration::from_secs(queued.at.saturating_sub(now))
+                });
+                tokio::select! {
+                    received = events.recv() => match received {
+                        Ok(event) => {
+                            if let Err(e) = self.handle(&event, crate::time::now()).await {
+                                log::error!("failed to queue webhook deliveries for event {}: {e}", event.id);
+                            }
+                        }
+                        Err(RecvError::Lagged(n)) => {
+                            log::warn!("webhooks fell behind and missed {n} events");
+                        }
+                        Err(RecvError::Closed) => break,
+                    },
+                    _ = tokio::time::sleep(wait) => {}
+                    _ = prune.tick() => {
+                        match self.prune(crate::time::now()).await {
+                            Ok(0) => {}
+                            Ok(n) => log::info!("pruned {n} webhook deliveries"),
+                            Err(e) => log::error!("failed to prune webhook deliveries: {e}"),
+                        }
+                    }
+                }
+            }
+        })
+    }
+
+    /// Queue the pending deliveries of every webhook.
+    pub async fn load(&mut self) -> store::Result<()> {
+        for webhook in all(self.store.as_ref()).await? {
+            for delivery in stored_deliveries(self.store.as_ref(), &webhook.id).await? {
+                if let Some(at) = delivery.next_attempt {
+                    self.queue.insert(Queued {
+                        at,
+                        webhook_id: webhook.id.clone(),
+                        delivery_id: delivery.id,
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Record a delivery of an event for each webhook that wants it, to be
+    /// attempted straight away.
+    ///
+    /// Webhooks on a household list only get events while their user can
+    /// still see the list.
+    ///
+    /// # Returns
+    ///
+    /// The number of deliveries queued.
+    pub async fn handle(&mut self, event: &Event, now: u64) -> store::Result<usize> {
+        let docs = self.store.as_ref();
+        let mut queued = 0;
+        for webhook in all(docs).await? {
+            if !webhook.wants(event) {
+                continue;
+            }
+            if let (Some(household_id), Some(list_id)) = (&webhook.household_id, &webhook.list_id) {
+                let access = households::list_access(
+                    docs,
+                    &webhook.user_id,
+                    household_id,
+                    list_id,
+                    Role::Viewer,
+                )
+                .await;
+                match access {
+                    Ok(_) => {}
+                    Err(households::Error::Store(e)) => return Err(e),
+                    Err(_) => continue,
+                }
+            }
+
+            let delivery = Delivery {
+                id: crate::random::id(),
+                webhook_id: webhook.id.clone(),
+                event: event.clone(),
+                status: DeliveryStatus::Pending,
+                attempts: Vec::new(),
+                next_attempt: Some(now),
+                created: now,
+            };
+            docs.insert_document(
+                &deliveries_collection(&webhook.id),
+                &delivery.id,
+                to_document(&delivery)?,
+            )
+            .await?;
+            self.queue.insert(Queued {
+                at: now,
+                webhook_id: webhook.id,
+                delivery_id: delivery.id,
+            });
+            queued += 1;
+        }
+
+        Ok(queued)
+    }
+
+    /// Attempt every delivery that is due by `now`.
+    ///
+    /// # Returns
+    ///
+    /// The number of deliveries attempted.
+    pub async fn run_due(&mut self, now: u64) -> store::Result<usize> {
+        let mut attempted = 0;
+        while let Some(queued) = self.queue.first().filter(|q| q.at <= now).cloned() {
+            self.queue.remove(&queued);
+            match self.attempt(&queued, now).await {
+                Ok(true) => attempted += 1,
+                Ok(false) => {}
+                Err(e) => {
+                    self.queue.insert(Queued {
+                        at: now + RETRY_INTERVAL,
+                        ..queued
+                    });
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(attempted)
+    }
+
+    /// Remove finished deliveries older than the retention window.
+    ///
+    /// # Returns
+    ///
+    /// The number of deliveries removed.
+    pub async fn prune(&self, now: u64) -> store::Result<usize> {
+        let docs = self.store.as_ref();
+        let cutoff = now.saturating_sub(DELIVERY_RETENTION);
+        let mut pruned = 0;
+        for webhook in all(docs).await? {
+            for delivery in stored_deliveries(docs, &webhook.id).await? {
+                if delivery.status != DeliveryStatus::Pending && delivery.created < cutoff {
+                    docs.delete_document(&deliveries_collection(&webhook.id), &delivery.id)
+                        .await?;
+                    pruned += 1;
+                }
+            }
+        }
+
+        Ok(pruned)
+    }
+
+    /// Try to deliver an event, then record how it went and when to try again.
+    ///
+    /// # Returns
+    ///
+    /// Whether the delivery was attempted. It is not if the webhook or the
+    /// delivery has since been removed.
+    async fn attempt(&mut self, queued: &Queued, now: u64) -> store::Result<bool> {
+        let docs = self.store.as_ref();
+        let collection = deliveries_collection(&queued.webhook_id);
+        let Some(webhook) = docs.get_document(WEBHOOKS, &queued.webhook_id).await? else {
+            return Ok(false);
+        };
+        let webhook: Webhook = from_document(webhook)?;
+        let Some(delivery) = docs.get_document(&collection, &queued.delivery_id).await? else {
+            return Ok(false);
+        };
+        let mut delivery: Delivery = from_document(delivery)?;
+        if delivery.status != DeliveryStatus::Pending {
+            return Ok(false);
+        }
+
+        let attempt = self.post(&webhook, &delivery, now).await;
+        let succeeded = attempt.error.is_none();
+        delivery.attempts.push(attempt);
+        let tries = delivery.attempts.len() as u32;
+        delivery.next_attempt = None;
+        delivery.status = if succeeded {
+            DeliveryStatus::Succeeded
+        } else if tries >= self.max_attempts {
+            log::warn!(
+                "giving up delivering event {} to webhook {} after {tries} attempts",
+                delivery.event.id,
+                webhook.id
+            );
+            DeliveryStatus::Failed
+        } else {
+            let at = now + self.backoff(tries);
+            delivery.next_attempt = Some(at);
+            self.queue.insert(Queued {
+                at,
+                ..queued.clone()
+            });
+            DeliveryStatus::Pending
+        };
+        docs.put_document(&collection, &delivery.id, to_document(&delivery)?)
+            .await?;
+
+        Ok(true)
+    }
+
+    /// Post a delivery to its webhook.
+    async fn post(&self, webhook: &Webhook, delivery: &Delivery, now: u64) -> Attempt {
+        let client = match self.client(&webhook.url).await {
+            Ok(client) => client,
+            Err(error) => {
+                return Attempt {
+                    time: now,
+                    status_code: None,
+                    error: Some(error),
+                }
+            }
+        };
+
+        let body = serde_json::to_vec(&delivery.event).unwrap_or_default();
+        let event = serde_json::to_value(delivery.event.kind).unwrap_or_default();
+        let result = client
+            .post(&webhook.url)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header("X-Reminders-Event", event.as_str().unwrap_or_default())
+            .header("X-Reminders-Delivery", &delivery.id)
+            .header("X-Reminders-Timestamp", now.to_string())
+            .header(
+                "X-Reminders-Signature",
+                format!("sha256={}", sign(&webhook.secret, now, &body)),
+            )
+            .body(body)
+            .send()
+            .await;
+
+        let (status_code, error) = match result {
+            Ok(response) if response.status().is_success() => {
+                (Some(response.status().as_u16()), None)
+            }
+            Ok(response) => {
+                let status = response.status();
+                let mut text = response.text().await.unwrap_or_default();
+                if let Some((end, _)) = text.char_indices().nth(MAX_ERROR_LENGTH) {
+                    text.truncate(end);
+                }
+                (Some(status.as_u16()), Some(format!("{status}: {text}")))
+            }
+            Err(e) => (None, Some(e.to_string())),
+        };
+
+        Attempt {
+            time: now,
+            status_code,
+            error,
+        }
+    }
+
+    /// Build a client for posting to a webhook's URL.
+    ///
+    /// # Errors
+    ///
+    /// Returns why the URL cannot be posted to.
+    async fn client(&self, url: &str) -> std::result::Result<reqwest::Client, String> {
+        pinned(url, self.allow_private)
+            .await?
+            .timeout(self.timeout)
+            .user_agent(concat!("reminders/", env!("CARGO_PKG_VERSION")))
+            .build()
+            .map_err(|e| e.to_string())
+    }
+
+    /// How long to wait after the given number of failed attempts, in seconds.
+    fn backoff(&self, tries: u32) -> u64 {
+        self.initial_backoff
+            .saturating_mul(1 << (tries - 1).min(20))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::WebhooksConfig;
+    use crate::events::{Bus, EventKind};
+    use crate::middleware::auth::AuthUser;
+    use crate::models::{
+        reminder::Reminder,
+        webhook::{DeliveryStatus, NewWebhook},
+    };
+    use crate::store::memory::Memory;
+    use crate::webhooks::{self, Dispatcher, Error};
+    use axum::{
+        http::{HeaderMap, StatusCode},
+        routing::post,
+        Router,
+    };
+    use std::sync::{Arc, Mutex};
+
+    const SECRET: &str = "a secret that is long enough";
+
+    fn user(id: &str) -> AuthUser {
+        AuthUser {
+            id: id.into(),
+            username: id.into(),
+        }
+    }
+
+    fn webhook(url: &str, events: Vec<EventKind>) -> NewWebhook {
+        NewWebhook {
+            url: url.into(),
+            secret: Some(SECRET.into()),
+            events,
+            household_id: None,
+            list_id: None,
+        }
+    }
+
+    fn reminder(title: &str) -> Reminder {
+        Reminder {
+            id: Some("r1".into()),
+            title: title.into(),
+            due: 1,
+            priority: 0,
+            assignee: None,
+            completed: false,
+            completed_at: None,
+            rrule: None,
+        }
+    }
+
+    /// Test signing against a signature computed independently.
+    #[test]
+    fn test_sign() {
+        assert_eq!(
+            webhooks::sign("secret", 1_700_000_000, b"{}"),
+            "b8569b78799ff9e3cbff0fc2d63a33a2b57f3282abd07c37ae5e8e7d79a5f163"
+        );
+    }
+
+    /// Test adding, listing and removing webhooks.
+    #[tokio::test]
+    async fn test_webhooks() {
+        let docs = Memory::default();
+        let hook = webhook("https://example.com/hook", vec![]);
+
+        let created = webhooks::create(&docs, &user("u1"), hook, false)
+            .await
+            .unwrap();
+        assert_eq!(created.secret, SECRET);
+        assert_eq!(webhooks::list(&docs, "u1").await.unwrap().len(), 1);
+        assert!(webhooks::list(&docs, "u2").await.unwrap().is_empty());
+
+        let bad_url = webhook("ftp://example.com/", vec![]);
+        let result = webhooks::create(&docs, &user("u1"), bad_url, false).await;
+        assert!(matches!(result, Err(Error::InvalidWebhook(_))));
+        let mut short = webhook("https://example.com/hook", vec![]);
+        short.secret = Some("short".into());
+        let result = webhooks::create(&docs, &user("u1"), short, false).await;
+        assert!(matches!(result, Err(Error::InvalidWebhook(_))));
+
+        let result = webhooks::delete(&docs, "u2", &created.webhook.id).await;
+        assert!(matches!(result, Err(Error::NotFound)));
+        webhooks::delete(&docs, "u1", &created.webhook.id)
+            .await
+            .unwrap();
+        assert!(webhooks::list(&docs, "u1").await.unwrap().is_empty());
+    }
+
+    /// Test that a failed delivery is retried after the backoff, and that
+    /// every attempt is logged.
+    #[tokio::test]
+    async fn test_retry() {
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let receiver = received.clone();
+        let app = Router::new().route(
+            "/hook",
+            post(move |headers: HeaderMap, body: String| async move {
+                let mut received = receiver.lock().unwrap();
+                received.push((headers, body));
+                match received.len() {
+                    1 => StatusCode::INTERNAL_SERVER_ERROR,
+                    _ => StatusCode::NO_CONTENT,
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let store = Arc::new(Memory::default());
+        let only_completed = webhook(&url, vec![EventKind::Completed]);
+        let hook = webhooks::create(store.as_ref(), &user("u1"), only_completed, true)
+            .await
+            .unwrap()
+            .webhook;
+        let config = WebhooksConfig {
+            allow_private: true,
+            ..Default::default()
+        };
+        let mut dispatcher = Dispatcher::new(store.clone(), &config);
+
+        let bus = Bus::default();
+        let mut events = bus.subscribe();
+        bus.publish(EventKind::Updated, "users/u1", reminder("Bins"));
+        bus.publish(EventKind::Completed, "users/u2", reminder("Bins"));
+        bus.publish(EventKind::Completed, "users/u1", reminder("Bins"));
+        let mut queued = 0;
+        for _ in 0..3 {
+            let event = events.recv().await.unwrap();
+            queued += dispatcher.handle(&event, 1000).await.unwrap();
+        }
+        assert_eq!(queued, 1);
+
+        assert_eq!(dispatcher.run_due(1000).await.unwrap(), 1);
+        assert_eq!(dispatcher.run_due(1000 + 29).await.unwrap(), 0);
+        assert_eq!(dispatcher.run_due(1000 + 30).await.unwrap(), 1);
+
+        let log = webhooks::deliveries(store.as_ref(), "u1", &hook.id)
+            .await
+            .unwrap();
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0].status, DeliveryStatus::Succeeded);
+        assert_eq!(log[0].attempts.len(), 2);
+        assert_eq!(log[0].attempts[0].status_code, Some(500));
+        assert!(log[0].attempts[0].error.is_some());
+        assert_eq!(log[0].attempts[1].status_code, Some(204));
+        assert_eq!(log[0].next_attempt, None);
+
+        let received = received.lock().unwrap().clone();
+        let (headers, body) = &received[1];
+        assert_eq!(headers["x-reminders-event"], "reminder.completed");
+        assert_eq!(headers["x-reminders-delivery"], log[0].id.as_str());
+        assert_eq!(headers["x-reminders-timestamp"], "1030");
+        let signature = format!("sha256={}", webhooks::sign(SECRET, 1030, body.as_bytes()));
+        assert_eq!(headers["x-reminders-signature"], signature.as_str());
+        let event: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!(event["type"], "reminder.completed");
+        assert_eq!(event["reminder"]["title"], "Bins");
+
+        assert_eq!(dispatcher.prune(1000 + 8 * 24 * 60 * 60).await.unwrap(), 1);
+    }
+
+    /// Test that webhooks cannot post to the server's own network unless
+    /// allowed to, whether the URL names the address or a redirect does.
+    #[tokio::test]
+    async fn test_private_urls() {
+        let docs = Memory::default();
+        for url in [
+            "http://127.0.0.1/hook",
+            "http://localhost:8080/hook",
+            "http://Foo.localhost./hook",
+            "http://10.0.0.1/hook",
+            "http://192.168.1.1/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://0.0.0.0/hook",
+            "http://[::1]/hook",
+            "http://[fd00::1]/hook",
+            "http://[::ffff:127.0.0.1]/hook",
+        ] {
+            let result = webhooks::create(&docs, &user("u1"), webhook(url, vec![]), false).await;
+            assert!(matches!(result, Err(Error::InvalidWebhook(_))), "{url}");
+        }
+
+        let hits = Arc::new(Mutex::new(0));
+        let counter = hits.clone();
+        let app = Router::new()
+            .route(
+                "/hook",
+                post(|| async { axum::response::Redirect::temporary("/other") }),
+            )
+            .route(
+                "/other",
+                post(move || async move {
+                    *counter.lock().unwrap() += 1;
+                    StatusCode::NO_CONTENT
+                }),
+            );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hook", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let store = Arc::new(Memory::default());
+        let hook = webhooks::create(store.as_ref(), &user("u1"), webhook(&url, vec![]), true)
+            .await
+            .unwrap()
+            .webhook;
+        let bus = Bus::default();
+        let mut events = bus.subscribe();
+        bus.publish(EventKind::Updated, "users/u1", reminder("Bins"));
+        let event = events.recv().await.unwrap();
+
+        let mut dispatcher = Dispatcher::new(store.clone(), &WebhooksConfig::default());
+        dispatcher.handle(&event, 1000).await.unwrap();
+        dispatcher.run_due(1000).await.unwrap();
+        let allowed = WebhooksConfig {
+            allow_private: true,
+            ..Default::default()
+        };
+        let mut dispatcher = Dispatcher::new(store.clone(), &allowed);
+        dispatcher.handle(&event, 1000).await.unwrap();
+        dispatcher.run_due(1000).await.unwrap();
+
+        let mut log = webhooks::deliveries(store.as_ref(), "u1", &hook.id)
+            .await
+            .unwrap();
+        log.sort_by_key(|delivery| delivery.attempts[0].status_code);
+        let error = log[0].attempts[0].error.as_deref().unwrap();
+        assert!(error.contains("private address"), "{error}");
+        assert_eq!(log[1].attempts[0].status_code, Some(307));
+        assert_eq!(*hits.lock().unwrap(), 0);
+    }
+}
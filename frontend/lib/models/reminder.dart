@@ -14,6 +14,7 @@ class Reminder {
   int due;
   int priority;
   String? assignee;
+  String? rrule;
 
   Reminder({
     this.id,
@@ -21,6 +22,7 @@ class Reminder {
     required this.due,
     required this.priority,
     this.assignee,
+    this.rrule,
   });
 
   DateTime get dueDate =>
@@ -44,7 +46,8 @@ class Reminder {
         title = json['title'],
         due = json['due'],
         priority = json['priority'],
-        assignee = json['assignee'];
+        assignee = json['assignee'],
+        rrule = json['rrule'];
 
   Map<String, Object?> toMap() => {
         'id': id,
@@ -52,6 +55,7 @@ class Reminder {
         'due': due,
         'priority': priority,
         'assignee': assignee,
+        'rrule': rrule,
       };
 
   String toJson() => jsonEncode(toMap());
@@ -111,7 +111,7 @@ class _HomeState extends State<Home> {
               _confetti.play();
               final Reminder r = _reminders[index];
               _reminders.removeAt(index);
-              reminders.delete(r).then(
+              reminders.complete(r).then(
                     (_) => _getReminders(),
                   );
             },
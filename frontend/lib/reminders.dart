@@ -112,3 +112,20 @@ Future<void> delete(Reminder reminder) async {
     throw Exception('Failed to delete reminder.');
   }
 }
+
+Future<void> complete(Reminder reminder) async {
+  final response = await http.post(
+    (await url).resolve('${reminder.id}/complete'),
+    headers: {
+      'Authorization': 'Bearer $accessToken',
+    },
+  );
+
+  if (response.statusCode == 401) {
+    throw RemindersApiNotAuthorizedException();
+  }
+
+  if (response.statusCode != 200) {
+    throw Exception('Failed to complete reminder.');
+  }
+}